@@ -20,16 +20,24 @@
 mod http_resources {
     //! API resources
 
+    pub mod get_cluster;
+    pub mod get_export;
     pub mod get_object;
+    pub mod get_partitions;
+    pub mod get_state_transfers;
+    pub mod post_import;
     pub mod put_object;
 }
 mod common {
     //! Common RESP API resources and utils.
 
+    mod admin_auth;
     mod api_error_mapper;
 
+    pub use admin_auth::*;
     pub use api_error_mapper::*;
 }
+mod reverse_proxy;
 
 use actix_web::App;
 use actix_web::HttpResponse;
@@ -39,64 +47,93 @@ use actix_web::get;
 use actix_web::http::header::ContentType;
 use actix_web::web;
 use clacheless::DistributedCache;
+use reverse_proxy::ReverseProxy;
 use std::sync::Arc;
 use tyst_api_rest_health::AppHealth;
 use tyst_api_rest_health::health_resources;
+use utoipa::Modify;
 use utoipa::OpenApi;
-
-/// Number of parallel requests the can be served for each assigned CPU core.
-const WORKERS_PER_CORE: usize = 1024;
+use utoipa::openapi::security::Http;
+use utoipa::openapi::security::HttpAuthScheme;
+use utoipa::openapi::security::SecurityScheme;
 
 /// Shared state between requests.
 #[derive(Clone)]
 struct AppState {
     dc: Arc<DistributedCache>,
+    reverse_proxy: Option<Arc<ReverseProxy>>,
+    admin_token: Option<Arc<str>>,
 }
 
-/// Simple health check that gets the provider instance.
+/// Health check that reflects the state of the distributed cache.
 pub struct AppHealthImpl {
-    _app: Arc<DistributedCache>,
+    app: Arc<DistributedCache>,
 }
 impl AppHealthImpl {
     fn with_app(app: &Arc<DistributedCache>) -> Arc<dyn AppHealth> {
         Arc::new(Self {
-            _app: Arc::clone(app),
+            app: Arc::clone(app),
         })
     }
 }
 impl AppHealth for AppHealthImpl {
     fn is_health_started(&self) -> bool {
-        true
+        self.app.is_started()
     }
     fn is_health_ready(&self) -> bool {
-        true
+        self.app.is_ready()
     }
     fn is_health_live(&self) -> bool {
-        true
+        self.app.is_live()
     }
 }
 
 /// Run HTTP server.
+///
+/// When `proxy_upstream_base_url` is set, all requests outside of the API are
+/// served by a caching reverse proxy of the upstream server.
+///
+/// The admin resources require `admin_token` as bearer token and are disabled
+/// when it is `None`.
 pub async fn run_http_server(
     dc: &Arc<DistributedCache>,
     bind_address: &str,
     bind_port: u16,
+    proxy_upstream_base_url: Option<&str>,
+    admin_token: Option<&str>,
 ) -> Result<(), Box<dyn core::error::Error>> {
     let workers = std::thread::available_parallelism()
         .map(|non_zero| non_zero.get())
         .unwrap_or(1);
-    let max_connections = WORKERS_PER_CORE * workers;
+    // Number of parallel requests that can be served for each assigned CPU core
+    let max_connections = dc.config().workers_per_core() * workers;
     log::info!(
         "API described by http://{bind_address}:{bind_port}/openapi.json allows {max_connections} concurrent connections."
     );
-    let app_state: AppState = AppState { dc: Arc::clone(dc) };
+    let reverse_proxy = proxy_upstream_base_url
+        .map(ReverseProxy::new)
+        .transpose()?
+        .map(Arc::new);
+    if let Some(proxy_upstream_base_url) = proxy_upstream_base_url {
+        log::info!("Requests outside of the API are proxied to '{proxy_upstream_base_url}'.");
+    }
+    let app_state: AppState = AppState {
+        dc: Arc::clone(dc),
+        reverse_proxy,
+        admin_token: admin_token.map(Arc::from),
+    };
     let app_data = web::Data::<AppState>::new(app_state);
     let app_health = web::Data::<Arc<dyn AppHealth>>::new(AppHealthImpl::with_app(dc));
 
     HttpServer::new(move || {
         let scope = web::scope("/api/v1")
             .service(get_openapi)
+            .service(http_resources::get_cluster::get_cluster)
+            .service(http_resources::get_export::get_export)
             .service(http_resources::get_object::get_object)
+            .service(http_resources::get_partitions::get_partitions)
+            .service(http_resources::get_state_transfers::get_state_transfers)
+            .service(http_resources::post_import::post_import)
             .service(http_resources::put_object::put_object);
         App::new()
             .app_data(app_data.clone())
@@ -108,6 +145,7 @@ pub async fn run_http_server(
             .service(health_resources::health_live)
             .service(health_resources::health_ready)
             .service(health_resources::health_started)
+            .default_service(web::to(reverse_proxy::proxy))
     })
     .workers(workers)
     .backlog(u32::try_from(max_connections / 2).unwrap()) // Default is 2048
@@ -135,14 +173,33 @@ pub fn openapi_as_string() -> String {
     #[openapi(
         // Use Cargo.toml as source for the "info" section
         paths(
+            http_resources::get_cluster::get_cluster,
+            http_resources::get_export::get_export,
             http_resources::get_object::get_object,
+            http_resources::get_partitions::get_partitions,
+            http_resources::get_state_transfers::get_state_transfers,
+            http_resources::post_import::post_import,
             http_resources::put_object::put_object,
             health_resources::health,
             health_resources::health_live,
             health_resources::health_ready,
             health_resources::health_started,
-        )
+        ),
+        modifiers(&AdminTokenSecurity),
     )]
     struct ApiDoc;
+    /// Describes the bearer token of the admin resources.
+    struct AdminTokenSecurity;
+    impl Modify for AdminTokenSecurity {
+        fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+            openapi
+                .components
+                .get_or_insert_default()
+                .add_security_scheme(
+                    "admin_token",
+                    SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+                );
+        }
+    }
     ApiDoc::openapi().to_pretty_json().unwrap()
 }
@@ -0,0 +1,100 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Authorization of administrative API requests.
+
+use actix_web::Error;
+use actix_web::HttpRequest;
+use actix_web::error;
+use actix_web::http::header;
+
+/// Authorization of administrative API requests with a static bearer token.
+pub struct AdminAuth {}
+
+impl AdminAuth {
+    /// Fail unless the request has an `Authorization: Bearer <token>` header
+    /// with the configured admin token.
+    ///
+    /// The admin API is disabled when no token is configured.
+    pub fn authorize(admin_token: Option<&str>, req: &HttpRequest) -> Result<(), Error> {
+        let Some(admin_token) = admin_token else {
+            // HTTP 403
+            return Err(error::ErrorForbidden("The admin API is disabled."));
+        };
+        let presented = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match presented {
+            Some(presented) if Self::constant_time_eq(presented, admin_token) => Ok(()),
+            // HTTP 401
+            _ => Err(error::ErrorUnauthorized("Invalid admin token.")),
+        }
+    }
+
+    /// Compare without leaking the length of the common prefix.
+    fn constant_time_eq(a: &str, b: &str) -> bool {
+        a.len() == b.len()
+            && a.bytes()
+                .zip(b.bytes())
+                .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+mod test {
+    //! Admin authorization tests.
+
+    #[test]
+    fn test_admin_token_is_required() {
+        use super::AdminAuth;
+        use actix_web::http::StatusCode;
+        use actix_web::test::TestRequest;
+
+        let status_of = |admin_token: Option<&str>, authorization: Option<&str>| {
+            let mut req = TestRequest::default();
+            if let Some(authorization) = authorization {
+                req = req.insert_header(("Authorization", authorization));
+            }
+            AdminAuth::authorize(admin_token, &req.to_http_request())
+                .err()
+                .map(|e| e.as_response_error().status_code())
+        };
+        assert_eq!(
+            status_of(None, Some("Bearer secret")),
+            Some(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(
+            status_of(Some("secret"), None),
+            Some(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            status_of(Some("secret"), Some("Bearer secreT")),
+            Some(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            status_of(Some("secret"), Some("Bearer secret2")),
+            Some(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            status_of(Some("secret"), Some("secret")),
+            Some(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(status_of(Some("secret"), Some("Bearer secret")), None);
+    }
+}
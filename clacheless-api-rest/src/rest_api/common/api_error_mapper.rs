@@ -41,6 +41,10 @@ impl ApiErrorMapper {
                 // HTTP 404
                 error::ErrorNotFound(e.to_string())
             }
+            ClachelessErrorKind::Unavailable => {
+                // HTTP 503
+                error::ErrorServiceUnavailable(e.to_string())
+            }
             _other => {
                 // HTTP 500
                 error::ErrorInternalServerError(e.to_string())
@@ -0,0 +1,166 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! API resource for retrieving the cluster status.
+
+use super::get_state_transfers::StateTransferResponse;
+use crate::rest_api::AppState;
+use actix_web::HttpResponse;
+use actix_web::get;
+use actix_web::web::Data;
+use clacheless::ClusterStatus;
+use clacheless::OriginStatus;
+use clacheless::PeerStatus;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// A remote node that is known to be alive or that is down while updates are
+/// kept for it.
+#[derive(Serialize, ToSchema)]
+pub struct PeerResponse {
+    /// Ordinal of the remote node.
+    node_ordinal: u32,
+    /// When the remote node was last heard from in epoch microseconds or when
+    /// it was first considered down if it is no longer known to be alive.
+    last_seen_micros: u64,
+    /// When the remote node was first considered down in epoch microseconds
+    /// or `null` if updates are sent to it.
+    down_since_micros: Option<u64>,
+    /// Number of updates waiting to be replicated to the remote node.
+    replication_queue_depth: u64,
+    /// Number of updates that were not replicated to the remote node because
+    /// its replication queue was full.
+    replication_dropped_entries: u64,
+    /// `true` if updates were dropped and the remote node has not yet been
+    /// told to recover them with a state transfer.
+    needs_state_transfer: bool,
+    /// Number of updates kept while the remote node was considered down,
+    /// waiting to be replayed.
+    hinted_entries: u64,
+    /// Number of bytes of the updates waiting to be replayed.
+    hinted_bytes: u64,
+    /// Estimate of how far the clock of the remote node is ahead of this
+    /// node's clock (negative if behind) in microseconds.
+    clock_offset_micros: Option<i64>,
+    /// Estimated round-trip time to the remote node in microseconds.
+    round_trip_micros: Option<u64>,
+    /// `true` if the clock offset exceeds the maximum skew.
+    clock_skewed: bool,
+}
+
+impl From<&PeerStatus> for PeerResponse {
+    fn from(value: &PeerStatus) -> Self {
+        Self {
+            node_ordinal: value.node_ordinal(),
+            last_seen_micros: value.last_seen_micros(),
+            down_since_micros: value.down_since_micros(),
+            replication_queue_depth: value.replication_queue_depth(),
+            replication_dropped_entries: value.replication_dropped_entries(),
+            needs_state_transfer: value.needs_state_transfer(),
+            hinted_entries: value.hinted_entries(),
+            hinted_bytes: value.hinted_bytes(),
+            clock_offset_micros: value.clock_offset_micros(),
+            round_trip_micros: value.round_trip_micros(),
+            clock_skewed: value.clock_skewed(),
+        }
+    }
+}
+
+/// How far this node has synchronized the updates of an origin node.
+#[derive(Serialize, ToSchema)]
+pub struct OriginResponse {
+    /// Identifier of the node where the updates were first recieved.
+    origin_node_id: u64,
+    /// Sequence number up to which all updates have been recieved.
+    baseline_seq: u64,
+    /// Latest known sequence number of the origin node.
+    latest_seq: u64,
+}
+
+impl From<&OriginStatus> for OriginResponse {
+    fn from(value: &OriginStatus) -> Self {
+        Self {
+            origin_node_id: value.origin_node_id(),
+            baseline_seq: value.baseline_seq(),
+            latest_seq: value.latest_seq(),
+        }
+    }
+}
+
+/// Cluster as seen by this node.
+#[derive(Serialize, ToSchema)]
+pub struct ClusterResponse {
+    /// Ordinal of this node.
+    local_node_ordinal: u32,
+    /// Identifier of this node.
+    local_node_id: u64,
+    /// Remote nodes that are known to be alive or are down while updates are
+    /// kept for them.
+    peers: Vec<PeerResponse>,
+    /// Synchronization state of each origin node, including this node.
+    origins: Vec<OriginResponse>,
+    /// Running and recently finished state transfers to this node.
+    state_transfers: Vec<StateTransferResponse>,
+    /// Number of entries in the local cache.
+    local_entries: u64,
+    /// Number of object bytes in the local cache.
+    local_bytes: u64,
+    /// `true` if this node's clock is skewed compared to the majority of the
+    /// peers.
+    local_clock_skewed: bool,
+    /// Local membership epoch. Increases every time a node joins or is lost.
+    membership_epoch: u64,
+}
+
+impl From<&ClusterStatus> for ClusterResponse {
+    fn from(value: &ClusterStatus) -> Self {
+        Self {
+            local_node_ordinal: value.local_node_ordinal(),
+            local_node_id: value.local_node_id(),
+            peers: value.peers().iter().map(PeerResponse::from).collect(),
+            origins: value.origins().iter().map(OriginResponse::from).collect(),
+            state_transfers: value
+                .state_transfers()
+                .iter()
+                .map(StateTransferResponse::from)
+                .collect(),
+            local_entries: value.local_entries(),
+            local_bytes: value.local_bytes(),
+            local_clock_skewed: value.local_clock_skewed(),
+            membership_epoch: value.membership_epoch(),
+        }
+    }
+}
+
+/// Retrieve the known peers, synchronization state and local cache size of
+/// this node.
+#[utoipa::path(
+    tag = "cluster",
+    responses(
+        (
+            status = 200,
+            description = "Return the cluster status.",
+            body = ClusterResponse,
+        ),
+        (status = 500, description = "Internal server error."),
+    ),
+)]
+#[get("/cluster")]
+pub async fn get_cluster(app_state: Data<AppState>) -> HttpResponse {
+    let cluster_status = app_state.dc.cluster_status().await;
+    HttpResponse::Ok().json(ClusterResponse::from(&cluster_status))
+}
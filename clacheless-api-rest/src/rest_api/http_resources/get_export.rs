@@ -0,0 +1,85 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! API resource for exporting all cached items.
+
+use crate::rest_api::AppState;
+use crate::rest_api::common::AdminAuth;
+use actix_web::Error;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::get;
+use actix_web::http::header::ContentType;
+use actix_web::web::BytesMut;
+use actix_web::web::Data;
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+
+/// Size of the buffer between the exporting task and the response body.
+const EXPORT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Export all non-expired cached items with full meta data.
+///
+/// The response body uses the binary snapshot format of the library: the
+/// magic bytes `CLACHSNP`, the format version as a big-endian 32-bit integer
+/// and then length-delimited protobuf messages (a header followed by one
+/// message per cached item and a trailer with the number of items).
+///
+/// Requires the admin token as bearer token.
+#[utoipa::path(
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (
+            status = 200,
+            description = "Stream of all cached items.",
+            content_type = "application/octet-stream",
+        ),
+        (status = 401, description = "Unauthorized."),
+        (status = 403, description = "The admin API is disabled."),
+        (status = 500, description = "Internal server error."),
+    ),
+)]
+#[get("/admin/export")]
+pub async fn get_export(
+    app_state: Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    AdminAuth::authorize(app_state.admin_token.as_deref(), &req)?;
+    let (mut writer, reader) = tokio::io::duplex(EXPORT_BUFFER_SIZE);
+    let dc = Arc::clone(&app_state.dc);
+    actix_web::rt::spawn(async move {
+        match dc.export(&mut writer).await {
+            Ok(count) => log::info!("Exported {count} entries."),
+            Err(e) => log::warn!("Export failed: {e}"),
+        }
+    });
+    let body = futures::stream::unfold(reader, |mut reader| async move {
+        let mut buf = BytesMut::with_capacity(EXPORT_BUFFER_SIZE);
+        match reader.read_buf(&mut buf).await {
+            Ok(0) => None,
+            Ok(_len) => Some((Ok::<_, actix_web::Error>(buf.freeze()), reader)),
+            Err(e) => {
+                log::warn!("Export stream failed: {e}");
+                None
+            }
+        }
+    });
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::octet_stream())
+        .streaming(body))
+}
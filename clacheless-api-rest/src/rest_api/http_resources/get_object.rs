@@ -54,6 +54,7 @@ pub async fn get_object(
     let object = app_state
         .dc
         .get_string(&cache_key)
+        .await
         .inspect_err(|e| log::info!("Request for '{cache_key}' failed: {e}"))
         .map_err(ApiErrorMapper::from_error)?;
     Ok(HttpResponse::build(StatusCode::OK).body(object))
@@ -0,0 +1,126 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! API resource for retrieving reports of healed partitions.
+
+use crate::rest_api::AppState;
+use actix_web::HttpResponse;
+use actix_web::get;
+use actix_web::web::Data;
+use clacheless::ConflictResolution;
+use clacheless::KeyConflict;
+use clacheless::PartitionReport;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// A key that was written on both sides of a partition.
+#[derive(Serialize, ToSchema)]
+pub struct KeyConflictResponse {
+    /// The key that was written on both sides.
+    key: String,
+    /// One of `local_won` or `remote_won`.
+    resolution: String,
+    /// When the write on the local side happened in epoch microseconds.
+    local_update_micros: u64,
+    /// Node id where the write on the local side was recieved.
+    local_origin_node_id: u64,
+    /// When the write on the remote side happened in epoch microseconds.
+    remote_update_micros: u64,
+    /// Node id where the write on the remote side was recieved.
+    remote_origin_node_id: u64,
+}
+
+impl From<&KeyConflict> for KeyConflictResponse {
+    fn from(value: &KeyConflict) -> Self {
+        Self {
+            key: value.key().to_string(),
+            resolution: match value.resolution() {
+                ConflictResolution::LocalWon => "local_won",
+                ConflictResolution::RemoteWon => "remote_won",
+            }
+            .to_string(),
+            local_update_micros: value.local_update_micros(),
+            local_origin_node_id: value.local_origin_node_id(),
+            remote_update_micros: value.remote_update_micros(),
+            remote_origin_node_id: value.remote_origin_node_id(),
+        }
+    }
+}
+
+/// A partition between this node and a remote node that has healed.
+#[derive(Serialize, ToSchema)]
+pub struct PartitionResponse {
+    /// Ordinal of the remote node.
+    node_ordinal: u32,
+    /// Identifier of the remote node.
+    node_id: u64,
+    /// When the remote node was last heard from before the partition in epoch
+    /// microseconds.
+    split_micros: u64,
+    /// When the sides were merged in epoch microseconds.
+    healed_micros: u64,
+    /// Local membership epoch when the remote node was lost.
+    split_epoch: u64,
+    /// Local membership epoch when the remote node rejoined.
+    healed_epoch: u64,
+    /// Number of keys that were written on both sides.
+    conflict_count: u64,
+    /// Keys that were written on both sides (limited for large partitions).
+    conflicts: Vec<KeyConflictResponse>,
+}
+
+impl From<&PartitionReport> for PartitionResponse {
+    fn from(value: &PartitionReport) -> Self {
+        Self {
+            node_ordinal: value.node_ordinal(),
+            node_id: value.node_id(),
+            split_micros: value.split_micros(),
+            healed_micros: value.healed_micros(),
+            split_epoch: value.split_epoch(),
+            healed_epoch: value.healed_epoch(),
+            conflict_count: value.conflict_count() as u64,
+            conflicts: value
+                .conflicts()
+                .iter()
+                .map(KeyConflictResponse::from)
+                .collect(),
+        }
+    }
+}
+
+/// Retrieve recently healed partitions between this node and other nodes.
+#[utoipa::path(
+    tag = "cluster",
+    responses(
+        (
+            status = 200,
+            description = "Return reports of healed partitions, oldest first.",
+            body = Vec<PartitionResponse>,
+        ),
+        (status = 500, description = "Internal server error."),
+    ),
+)]
+#[get("/cluster/partitions")]
+pub async fn get_partitions(app_state: Data<AppState>) -> HttpResponse {
+    let partitions = app_state
+        .dc
+        .partition_reports()
+        .iter()
+        .map(PartitionResponse::from)
+        .collect::<Vec<_>>();
+    HttpResponse::Ok().json(partitions)
+}
@@ -0,0 +1,95 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! API resource for retrieving the progress of state transfers.
+
+use crate::rest_api::AppState;
+use actix_web::HttpResponse;
+use actix_web::get;
+use actix_web::web::Data;
+use clacheless::StateTransferProgress;
+use clacheless::StateTransferState;
+use serde::Serialize;
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+/// Progress of a state transfer to this node.
+#[derive(Serialize, ToSchema)]
+pub struct StateTransferResponse {
+    /// Locally unique identifier of the transfer session.
+    session_id: u64,
+    /// Ordinal of the node that sends the entries.
+    sender_node_ordinal: u32,
+    /// One of `running`, `completed` or `failed`.
+    state: String,
+    /// How many times the transfer has been started or resumed.
+    attempts: u32,
+    /// When the transfer started in epoch microseconds.
+    started_micros: u64,
+    /// When the transfer last made progress in epoch microseconds.
+    updated_micros: u64,
+    /// Number of entries recieved so far.
+    received_entries: u64,
+    /// Number of object bytes recieved so far.
+    received_bytes: u64,
+    /// Highest recieved update sequence number by origin node id.
+    checkpoints: HashMap<u64, u64>,
+}
+
+impl From<&StateTransferProgress> for StateTransferResponse {
+    fn from(value: &StateTransferProgress) -> Self {
+        Self {
+            session_id: value.session_id(),
+            sender_node_ordinal: value.sender_node_ordinal(),
+            state: match value.state() {
+                StateTransferState::Running => "running",
+                StateTransferState::Completed => "completed",
+                StateTransferState::Failed => "failed",
+            }
+            .to_string(),
+            attempts: value.attempts(),
+            started_micros: value.started_micros(),
+            updated_micros: value.updated_micros(),
+            received_entries: value.received_entries(),
+            received_bytes: value.received_bytes(),
+            checkpoints: value.checkpoints().clone(),
+        }
+    }
+}
+
+/// Retrieve running and recently finished state transfers to this node.
+#[utoipa::path(
+    tag = "cluster",
+    responses(
+        (
+            status = 200,
+            description = "Return the progress of state transfers.",
+            body = Vec<StateTransferResponse>,
+        ),
+        (status = 500, description = "Internal server error."),
+    ),
+)]
+#[get("/cluster/transfers")]
+pub async fn get_state_transfers(app_state: Data<AppState>) -> HttpResponse {
+    let state_transfers = app_state
+        .dc
+        .state_transfers()
+        .iter()
+        .map(StateTransferResponse::from)
+        .collect::<Vec<_>>();
+    HttpResponse::Ok().json(state_transfers)
+}
@@ -0,0 +1,93 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! API resource for importing cached items.
+
+use crate::rest_api::AppState;
+use crate::rest_api::common::AdminAuth;
+use crate::rest_api::common::ApiErrorMapper;
+use actix_web::Error;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::error;
+use actix_web::post;
+use actix_web::web::Data;
+use actix_web::web::Payload;
+use futures::StreamExt;
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use utoipa::ToSchema;
+
+/// Size of the buffer between the request body and the importing task.
+const IMPORT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Result of an import.
+#[derive(Serialize, ToSchema)]
+pub struct ImportResponse {
+    /// Number of imported items that were newer than the existing ones.
+    imported_entries: usize,
+}
+
+/// Import cached items in the format produced by `GET /api/v1/admin/export`.
+///
+/// Imported items keep their original timestamps, so an item is only replaced
+/// if the imported one is more recent. Expired items are skipped. A truncated
+/// stream is rejected, but the items read until then stay imported.
+///
+/// Requires the admin token as bearer token.
+#[utoipa::path(
+    tag = "admin",
+    security(("admin_token" = [])),
+    request_body(
+        content = Vec<u8>,
+        content_type = "application/octet-stream",
+        description = "Stream of cached items.",
+    ),
+    responses(
+        (status = 200, description = "Items were imported.", body = ImportResponse),
+        (status = 400, description = "Bad Request."),
+        (status = 401, description = "Unauthorized."),
+        (status = 403, description = "The admin API is disabled."),
+        (status = 500, description = "Internal server error."),
+    ),
+)]
+#[post("/admin/import")]
+pub async fn post_import(
+    app_state: Data<AppState>,
+    req: HttpRequest,
+    mut payload: Payload,
+) -> Result<HttpResponse, Error> {
+    AdminAuth::authorize(app_state.admin_token.as_deref(), &req)?;
+    let (mut writer, mut reader) = tokio::io::duplex(IMPORT_BUFFER_SIZE);
+    let feed = async move {
+        while let Some(chunk) = payload.next().await {
+            writer
+                .write_all(&chunk?)
+                .await
+                .map_err(error::ErrorBadRequest)?;
+        }
+        // Dropping the writer signals the end of the import
+        Ok::<_, Error>(())
+    };
+    // The reader is dropped as soon as the import ends, so feeding never
+    // blocks on an aborted import.
+    let import = async move { app_state.dc.import(&mut reader).await };
+    let (feed_res, import_res) = futures::join!(feed, import);
+    let imported_entries = import_res.map_err(ApiErrorMapper::from_error)?;
+    feed_res?;
+    Ok(HttpResponse::Ok().json(ImportResponse { imported_entries }))
+}
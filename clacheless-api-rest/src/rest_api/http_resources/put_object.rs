@@ -19,6 +19,7 @@
 
 use crate::rest_api::AppState;
 use crate::rest_api::common::ApiErrorMapper;
+use crate::rest_api::reverse_proxy::ReverseProxy;
 use actix_web::Error;
 use actix_web::HttpRequest;
 use actix_web::HttpResponse;
@@ -31,10 +32,9 @@ use actix_web::web::Path;
 use actix_web::web::Payload;
 use futures::StreamExt;
 
-/// Limit payload size to 5 MiB.
-const MAX_DOCUMENT_SIZE: usize = 5 * 1024 * 1024;
-
 /// Storing a cached item by key.
+///
+/// Keys starting with `proxy|` are reserved for the caching reverse proxy.
 #[utoipa::path(
     tag = "cache",
     params(
@@ -54,8 +54,17 @@ pub async fn put_object(
     http_request: HttpRequest,
 ) -> Result<HttpResponse, Error> {
     let cache_key = path.into_inner();
-    let content_length_estimate = assert_declared_content_length(&http_request, MAX_DOCUMENT_SIZE)?;
-    let raw_cache_value = read_full_body_text(content_length_estimate, payload).await?;
+    if cache_key.starts_with(ReverseProxy::CACHE_KEY_PREFIX) {
+        Err(error::ErrorBadRequest(format!(
+            "Keys starting with '{}' are reserved.",
+            ReverseProxy::CACHE_KEY_PREFIX
+        )))?;
+    }
+    // Limit payload size to the configured maximum
+    let max_document_size = app_state.dc.config().max_document_size();
+    let content_length_estimate = assert_declared_content_length(&http_request, max_document_size)?;
+    let raw_cache_value =
+        read_full_body_text(content_length_estimate, max_document_size, payload).await?;
     app_state
         .dc
         .put_string(&cache_key, &raw_cache_value)
@@ -85,15 +94,16 @@ fn assert_declared_content_length(
 
 async fn read_full_body_text(
     content_length_estimate: usize,
+    max_size: usize,
     mut payload: Payload,
 ) -> Result<String, Error> {
     let mut body = web::BytesMut::with_capacity(content_length_estimate);
     while let Some(chunk) = payload.next().await {
         let chunk = chunk?;
         // limit max size of in-memory payload
-        if (body.len() + chunk.len()) > MAX_DOCUMENT_SIZE {
+        if (body.len() + chunk.len()) > max_size {
             Err(error::ErrorBadRequest(format!(
-                "Message body exceeded {MAX_DOCUMENT_SIZE} bytes."
+                "Message body exceeded {max_size} bytes."
             )))?;
         }
         body.extend_from_slice(&chunk);
@@ -0,0 +1,612 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Caching reverse proxy in front of an upstream HTTP server.
+
+mod cached_response;
+
+use self::cached_response::CacheRecord;
+use self::cached_response::CachedResponse;
+use crate::rest_api::AppState;
+use actix_web::Error;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::HttpResponseBuilder;
+use actix_web::error;
+use actix_web::http::Method;
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
+use actix_web::web::BytesMut;
+use actix_web::web::Data;
+use actix_web::web::Payload;
+use clacheless::DistributedCache;
+use futures::StreamExt;
+
+/// Headers that only apply to a single connection and are never forwarded.
+const HOP_BY_HOP_HEADERS: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "proxy-connection",
+];
+
+/// Body of an upstream response.
+enum UpstreamBody {
+    /// The complete body.
+    Complete(Bytes),
+    /// The start of a body that is too large to store and the response to
+    /// stream the rest from.
+    Oversize(Bytes, reqwest::Response),
+}
+
+/** Caching reverse proxy of a configured upstream base URL.
+
+Responses to `GET` requests are stored in the distributed cache including
+status, headers and body for as long as `Cache-Control` or `Expires` allows.
+Stale responses with an `ETag` or `Last-Modified` validator are kept for the
+default time to live of cached items and revalidated with a conditional request
+before they are served again. Responses with a `Vary` header are stored per
+combination of the listed request header values.
+
+Responses to requests with an `Authorization` header are only stored and served
+from the cache if they are explicitly shareable (`public`, `s-maxage` or
+`must-revalidate`). `Set-Cookie` headers are never stored.
+
+Response bodies larger than the configured maximum document size are streamed
+to the client without being stored.
+
+All other requests are forwarded to the upstream server as is.
+*/
+pub struct ReverseProxy {
+    upstream_base_url: String,
+    client: reqwest::Client,
+}
+
+impl ReverseProxy {
+    /// Prefix of all cache keys used by the proxy.
+    ///
+    /// The object API rejects keys with this prefix, so clients can't plant
+    /// responses.
+    pub const CACHE_KEY_PREFIX: &str = "proxy|";
+    /// Maximum size of a forwarded request body.
+    const MAX_REQUEST_BODY_BYTES: usize = 16 * 1024 * 1024;
+
+    /// Return a new instance that proxies requests to `upstream_base_url`.
+    pub fn new(upstream_base_url: &str) -> Result<Self, Box<dyn core::error::Error>> {
+        let client = reqwest::Client::builder()
+            // Redirects are returned to the client
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        Ok(Self {
+            upstream_base_url: upstream_base_url.trim_end_matches('/').to_string(),
+            client,
+        })
+    }
+
+    /// Serve the request from the cache or the upstream server.
+    async fn serve(
+        &self,
+        dc: &DistributedCache,
+        http_request: &HttpRequest,
+        body: Bytes,
+    ) -> Result<HttpResponse, Error> {
+        let path_and_query = http_request
+            .uri()
+            .path_and_query()
+            .map(|path_and_query| path_and_query.as_str())
+            .unwrap_or("/");
+        let url = self.upstream_base_url.to_owned() + path_and_query;
+        if http_request.method() != Method::GET {
+            let upstream_response = self.forward(http_request, &url, body, None).await?;
+            let (status, headers) = Self::upstream_status_and_headers(&upstream_response);
+            return Ok(Self::respond_streaming(
+                status,
+                &headers,
+                Bytes::new(),
+                upstream_response,
+                "PASS",
+            ));
+        }
+        let base_key = Self::CACHE_KEY_PREFIX.to_owned() + path_and_query;
+        let authorized = http_request.headers().contains_key("authorization");
+        let cached = Self::lookup(dc, &base_key, http_request)
+            .await
+            .filter(|cached| {
+                !authorized || cached_response::is_shareable_when_authorized(&cached.headers)
+            });
+        let now_micros = dc.config().clock().now_micros();
+        if let Some(cached) = &cached
+            && cached.is_fresh(now_micros)
+            && !Self::requires_revalidation(http_request)
+        {
+            return Ok(Self::respond_cached(
+                http_request,
+                cached,
+                now_micros,
+                "HIT",
+            ));
+        }
+        let upstream_response = self
+            .forward(http_request, &url, body, cached.as_ref())
+            .await?;
+        let (status, headers) = Self::upstream_status_and_headers(&upstream_response);
+        let max_body_bytes = dc.config().max_document_size();
+        let body = Self::read_upstream_body(upstream_response, max_body_bytes).await?;
+        let now_micros = dc.config().clock().now_micros();
+        if status == StatusCode::NOT_MODIFIED.as_u16()
+            && let Some(mut cached) = cached
+        {
+            cached.update_headers(headers);
+            cached.stored_micros = now_micros;
+            if let Some(fresh_for_micros) = cached_response::fresh_for_micros(
+                cached.status,
+                &cached.headers,
+                now_micros,
+                authorized,
+            ) {
+                cached.fresh_until_micros = now_micros.saturating_add(fresh_for_micros);
+                Self::store(dc, &base_key, http_request, &cached, now_micros).await;
+            }
+            return Ok(Self::respond_cached(
+                http_request,
+                &cached,
+                now_micros,
+                "REVALIDATED",
+            ));
+        }
+        let body = match body {
+            UpstreamBody::Complete(body) => body,
+            UpstreamBody::Oversize(start, upstream_response) => {
+                return Ok(Self::respond_streaming(
+                    status,
+                    &headers,
+                    start,
+                    upstream_response,
+                    "MISS",
+                ));
+            }
+        };
+        if let Some(fresh_for_micros) =
+            cached_response::fresh_for_micros(status, &headers, now_micros, authorized)
+        {
+            let response = CachedResponse {
+                status,
+                headers,
+                body: body.to_vec(),
+                stored_micros: now_micros,
+                fresh_until_micros: now_micros.saturating_add(fresh_for_micros),
+            };
+            Self::store(dc, &base_key, http_request, &response, now_micros).await;
+            return Ok(Self::respond_cached(
+                http_request,
+                &response,
+                now_micros,
+                "MISS",
+            ));
+        }
+        Ok(Self::respond(status, &headers, body, "MISS", None))
+    }
+
+    /// Return `true` if the client asked for a response validated by the
+    /// upstream server.
+    fn requires_revalidation(http_request: &HttpRequest) -> bool {
+        let headers = Self::request_headers(http_request);
+        cached_response::header_list(&headers, "cache-control")
+            .iter()
+            .any(|directive| {
+                directive.eq_ignore_ascii_case("no-cache")
+                    || directive.eq_ignore_ascii_case("max-age=0")
+            })
+            || cached_response::header_list(&headers, "pragma")
+                .iter()
+                .any(|directive| directive.eq_ignore_ascii_case("no-cache"))
+    }
+
+    /// Return the stored response that matches the request, if any.
+    async fn lookup(
+        dc: &DistributedCache,
+        base_key: &str,
+        http_request: &HttpRequest,
+    ) -> Option<CachedResponse> {
+        match Self::get_record(dc, base_key).await? {
+            CacheRecord::Response(response) => Some(response),
+            CacheRecord::Variants(header_names) => {
+                let variant_key = Self::variant_key(base_key, &header_names, http_request);
+                match Self::get_record(dc, &variant_key).await? {
+                    CacheRecord::Response(response) => Some(response),
+                    CacheRecord::Variants(_) => None,
+                }
+            }
+        }
+    }
+
+    /// Return the decoded record stored under the key, if any.
+    async fn get_record(dc: &DistributedCache, cache_key: &str) -> Option<CacheRecord> {
+        let bytes = dc
+            .get_bytes(cache_key)
+            .await
+            .inspect_err(|e| {
+                if log::log_enabled!(log::Level::Trace) {
+                    log::trace!("No cached response for '{cache_key}': {e}");
+                }
+            })
+            .ok()?;
+        CacheRecord::decode(&bytes).or_else(|| {
+            log::debug!("Ignoring malformed cached response for '{cache_key}'.");
+            None
+        })
+    }
+
+    /// Return the cache key of the variant selected by the request.
+    fn variant_key(base_key: &str, header_names: &[String], http_request: &HttpRequest) -> String {
+        let headers = Self::request_headers(http_request);
+        header_names.iter().fold(base_key.to_owned(), |key, name| {
+            key + "|" + name + "=" + &cached_response::header_list(&headers, name).join(",")
+        })
+    }
+
+    /// Store the response in the distributed cache.
+    async fn store(
+        dc: &DistributedCache,
+        base_key: &str,
+        http_request: &HttpRequest,
+        response: &CachedResponse,
+        now_micros: u64,
+    ) {
+        let mut ttl_micros = response.fresh_until_micros.saturating_sub(now_micros);
+        if response.has_validators() {
+            // Keep stale responses around for revalidation
+            ttl_micros = ttl_micros.saturating_add(dc.cache_item_ttl_micros());
+        }
+        if ttl_micros == 0 {
+            return;
+        }
+        let header_names = cached_response::header_list(&response.headers, "vary")
+            .into_iter()
+            .map(str::to_ascii_lowercase)
+            .collect::<Vec<_>>();
+        let cache_key = if header_names.is_empty() {
+            base_key.to_owned()
+        } else {
+            let variant_key = Self::variant_key(base_key, &header_names, http_request);
+            let variants = CacheRecord::Variants(header_names);
+            dc.put_bytes_with_ttl(base_key, &variants.encode(), ttl_micros)
+                .await
+                .inspect_err(|e| log::info!("Failed to cache variants of '{base_key}': {e}"))
+                .ok();
+            variant_key
+        };
+        let record = CacheRecord::Response(CachedResponse {
+            status: response.status,
+            // Cookies are meant for a single client
+            headers: response
+                .headers
+                .iter()
+                .filter(|(name, _value)| !name.eq_ignore_ascii_case("set-cookie"))
+                .cloned()
+                .collect(),
+            body: response.body.clone(),
+            stored_micros: response.stored_micros,
+            fresh_until_micros: response.fresh_until_micros,
+        });
+        dc.put_bytes_with_ttl(&cache_key, &record.encode(), ttl_micros)
+            .await
+            .inspect_err(|e| log::info!("Failed to cache response for '{cache_key}': {e}"))
+            .ok();
+    }
+
+    /// Forward the request to the upstream server.
+    ///
+    /// Validators of the `cached` response are added to `GET` requests and
+    /// conditional headers of the client are then replaced, since the cache
+    /// answers those itself.
+    async fn forward(
+        &self,
+        http_request: &HttpRequest,
+        url: &str,
+        body: Bytes,
+        cached: Option<&CachedResponse>,
+    ) -> Result<reqwest::Response, Error> {
+        let method = reqwest::Method::from_bytes(http_request.method().as_str().as_bytes())
+            .map_err(error::ErrorBadRequest)?;
+        let is_get = method == reqwest::Method::GET;
+        let mut request_builder = self.client.request(method, url);
+        for (name, value) in Self::request_headers(http_request) {
+            let skipped = name == "host"
+                || name == "content-length"
+                || (is_get && (name == "if-none-match" || name == "if-modified-since"));
+            if !skipped {
+                request_builder = request_builder.header(name, value);
+            }
+        }
+        if let Some(cached) = cached {
+            if let Some(etag) = cached.header("etag") {
+                request_builder = request_builder.header("if-none-match", etag);
+            }
+            if let Some(last_modified) = cached.header("last-modified") {
+                request_builder = request_builder.header("if-modified-since", last_modified);
+            }
+        }
+        if let Some(host) = http_request.headers().get("host") {
+            request_builder = request_builder.header("x-forwarded-host", host.as_bytes());
+        }
+        if let Some(peer_addr) = http_request.peer_addr() {
+            request_builder = request_builder.header("x-forwarded-for", peer_addr.ip().to_string());
+        }
+        request_builder
+            .body(body)
+            .send()
+            .await
+            .inspect_err(|e| log::info!("Request to upstream '{url}' failed: {e}"))
+            .map_err(error::ErrorBadGateway)
+    }
+
+    /// Return the lower case names and values of the end-to-end request
+    /// headers.
+    fn request_headers(http_request: &HttpRequest) -> Vec<(String, String)> {
+        Self::end_to_end_headers(
+            http_request
+                .headers()
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_bytes())),
+        )
+    }
+
+    /// Return the status and end-to-end headers of the upstream response.
+    fn upstream_status_and_headers(
+        upstream_response: &reqwest::Response,
+    ) -> (u16, Vec<(String, String)>) {
+        let status = upstream_response.status().as_u16();
+        let headers = Self::end_to_end_headers(
+            upstream_response
+                .headers()
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_bytes())),
+        );
+        (status, headers)
+    }
+
+    /// Read the body of the upstream response.
+    ///
+    /// Reading stops as soon as the body is known to be larger than
+    /// `max_body_bytes`, either from the declared `Content-Length` or the
+    /// chunks recieved so far.
+    async fn read_upstream_body(
+        mut upstream_response: reqwest::Response,
+        max_body_bytes: usize,
+    ) -> Result<UpstreamBody, Error> {
+        if upstream_response
+            .content_length()
+            .is_some_and(|content_length| content_length > max_body_bytes as u64)
+        {
+            return Ok(UpstreamBody::Oversize(Bytes::new(), upstream_response));
+        }
+        let mut body = BytesMut::new();
+        while let Some(chunk) = upstream_response
+            .chunk()
+            .await
+            .inspect_err(|e| log::info!("Reading upstream response failed: {e}"))
+            .map_err(error::ErrorBadGateway)?
+        {
+            body.extend_from_slice(&chunk);
+            if body.len() > max_body_bytes {
+                return Ok(UpstreamBody::Oversize(body.freeze(), upstream_response));
+            }
+        }
+        Ok(UpstreamBody::Complete(body.freeze()))
+    }
+
+    /// Return headers with lower case names, without hop-by-hop headers and
+    /// values that are not valid UTF-8.
+    fn end_to_end_headers<'a>(
+        headers: impl Iterator<Item = (&'a str, &'a [u8])>,
+    ) -> Vec<(String, String)> {
+        headers
+            .filter(|(name, _value)| !HOP_BY_HOP_HEADERS.contains(name))
+            .filter_map(|(name, value)| {
+                str::from_utf8(value)
+                    .ok()
+                    .map(|value| (name.to_ascii_lowercase(), value.to_string()))
+            })
+            .collect()
+    }
+
+    /// Respond with a stored response or `304 Not Modified` if the client
+    /// already has the current version.
+    fn respond_cached(
+        http_request: &HttpRequest,
+        cached: &CachedResponse,
+        now_micros: u64,
+        cache_status: &str,
+    ) -> HttpResponse {
+        let age_seconds = now_micros.saturating_sub(cached.stored_micros) / 1_000_000;
+        let request_headers = Self::request_headers(http_request);
+        let if_none_match = cached_response::header_list(&request_headers, "if-none-match");
+        let not_modified = cached.status == StatusCode::OK.as_u16()
+            && cached.header("etag").is_some_and(|etag| {
+                if_none_match
+                    .iter()
+                    .any(|candidate| *candidate == "*" || *candidate == etag)
+            });
+        if not_modified {
+            return Self::respond(
+                StatusCode::NOT_MODIFIED.as_u16(),
+                &cached.headers,
+                Bytes::new(),
+                cache_status,
+                Some(age_seconds),
+            );
+        }
+        Self::respond(
+            cached.status,
+            &cached.headers,
+            Bytes::copy_from_slice(&cached.body),
+            cache_status,
+            Some(age_seconds),
+        )
+    }
+
+    /// Build the response to the client that starts with `start` and streams
+    /// the rest of the upstream body.
+    fn respond_streaming(
+        status: u16,
+        headers: &[(String, String)],
+        start: Bytes,
+        upstream_response: reqwest::Response,
+        cache_status: &str,
+    ) -> HttpResponse {
+        let rest = futures::stream::unfold(Some(upstream_response), |upstream_response| async {
+            let mut upstream_response = upstream_response?;
+            match upstream_response.chunk().await {
+                Ok(Some(chunk)) => Some((Ok(chunk), Some(upstream_response))),
+                Ok(None) => None,
+                Err(e) => {
+                    log::info!("Streaming upstream response failed: {e}");
+                    Some((Err(e), None))
+                }
+            }
+        });
+        let mut response_builder = Self::response_builder(status, headers, cache_status, None);
+        if status == StatusCode::NOT_MODIFIED.as_u16() {
+            return response_builder.finish();
+        }
+        response_builder.streaming(futures::stream::once(async { Ok(start) }).chain(rest))
+    }
+
+    /// Build the response to the client.
+    fn respond(
+        status: u16,
+        headers: &[(String, String)],
+        body: Bytes,
+        cache_status: &str,
+        age_seconds: Option<u64>,
+    ) -> HttpResponse {
+        let mut response_builder =
+            Self::response_builder(status, headers, cache_status, age_seconds);
+        if status == StatusCode::NOT_MODIFIED.as_u16() {
+            response_builder.finish()
+        } else {
+            response_builder.body(body)
+        }
+    }
+
+    /// Return a builder of the response to the client with status and
+    /// headers set.
+    fn response_builder(
+        status: u16,
+        headers: &[(String, String)],
+        cache_status: &str,
+        age_seconds: Option<u64>,
+    ) -> HttpResponseBuilder {
+        let mut response_builder =
+            HttpResponse::build(StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_GATEWAY));
+        headers
+            .iter()
+            .filter(|(name, _value)| name != "content-length" && name != "age")
+            .for_each(|(name, value)| {
+                response_builder.append_header((name.as_str(), value.as_str()));
+            });
+        if let Some(age_seconds) = age_seconds {
+            response_builder.insert_header(("age", age_seconds.to_string()));
+        }
+        response_builder.insert_header(("x-cache", cache_status));
+        response_builder
+    }
+}
+
+/// Serve any request that is not handled by the API from the cache or the
+/// upstream server.
+pub async fn proxy(
+    app_state: Data<AppState>,
+    http_request: HttpRequest,
+    payload: Payload,
+) -> Result<HttpResponse, Error> {
+    let Some(reverse_proxy) = &app_state.reverse_proxy else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let body = payload
+        .to_bytes_limited(ReverseProxy::MAX_REQUEST_BODY_BYTES)
+        .await
+        .map_err(|_e| error::ErrorPayloadTooLarge("Request body is too large."))??;
+    reverse_proxy
+        .serve(&app_state.dc, &http_request, body)
+        .await
+}
+
+mod test {
+    //! Reverse proxy tests.
+
+    /// Serve a single raw HTTP response on a local port and return its URL.
+    #[cfg(test)]
+    fn serve_once(raw_response: &'static [u8]) -> String {
+        use std::io::Read;
+        use std::io::Write;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _peer_addr) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request);
+            let _ = stream.write_all(raw_response);
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_oversize_upstream_body_is_not_buffered() {
+        use super::ReverseProxy;
+        use super::UpstreamBody;
+
+        let client = reqwest::Client::new();
+        let url = serve_once(b"HTTP/1.1 200 OK\r\ncontent-length: 11\r\n\r\nhello world");
+        let upstream_response = client.get(url).send().await.unwrap();
+        let body = ReverseProxy::read_upstream_body(upstream_response, 5)
+            .await
+            .unwrap();
+        let UpstreamBody::Oversize(start, mut upstream_response) = body else {
+            panic!("Declared oversize body was read.");
+        };
+        assert!(start.is_empty());
+        assert_eq!(
+            upstream_response.chunk().await.unwrap().unwrap().as_ref(),
+            b"hello world"
+        );
+        let url = serve_once(
+            b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n4\r\nabcd\r\n4\r\nefgh\r\n4\r\nijkl\r\n0\r\n\r\n",
+        );
+        let upstream_response = client.get(url).send().await.unwrap();
+        let body = ReverseProxy::read_upstream_body(upstream_response, 5)
+            .await
+            .unwrap();
+        let UpstreamBody::Oversize(start, _upstream_response) = body else {
+            panic!("Chunked oversize body was read to the end.");
+        };
+        assert!(start.len() < 12);
+        let url = serve_once(b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nhello");
+        let upstream_response = client.get(url).send().await.unwrap();
+        let body = ReverseProxy::read_upstream_body(upstream_response, 5)
+            .await
+            .unwrap();
+        assert!(matches!(body, UpstreamBody::Complete(body) if body.as_ref() == b"hello"));
+    }
+}
@@ -0,0 +1,368 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Upstream responses stored in the distributed cache.
+
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// Status codes that are cacheable by default according to RFC 9110.
+const HEURISTICALLY_CACHEABLE: [u16; 10] = [200, 203, 204, 300, 301, 404, 405, 410, 414, 501];
+
+/// Record stored in the distributed cache for a proxied resource.
+#[derive(Debug, PartialEq)]
+pub enum CacheRecord {
+    /// The stored upstream response.
+    Response(CachedResponse),
+    /// Lower case names of the request headers that select the stored
+    /// variant of the resource (from the `Vary` response header).
+    Variants(Vec<String>),
+}
+
+impl CacheRecord {
+    const FORMAT_VERSION: u8 = 1;
+    const KIND_RESPONSE: u8 = 1;
+    const KIND_VARIANTS: u8 = 2;
+
+    /// Serialize the record.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut ret = vec![Self::FORMAT_VERSION];
+        match self {
+            Self::Response(response) => {
+                ret.push(Self::KIND_RESPONSE);
+                ret.extend_from_slice(&response.status.to_be_bytes());
+                ret.extend_from_slice(&response.stored_micros.to_be_bytes());
+                ret.extend_from_slice(&response.fresh_until_micros.to_be_bytes());
+                ret.extend_from_slice(
+                    &u32::try_from(response.headers.len()).unwrap().to_be_bytes(),
+                );
+                for (name, value) in &response.headers {
+                    encode_bytes(&mut ret, name.as_bytes());
+                    encode_bytes(&mut ret, value.as_bytes());
+                }
+                ret.extend_from_slice(&response.body);
+            }
+            Self::Variants(header_names) => {
+                ret.push(Self::KIND_VARIANTS);
+                ret.extend_from_slice(&u32::try_from(header_names.len()).unwrap().to_be_bytes());
+                for name in header_names {
+                    encode_bytes(&mut ret, name.as_bytes());
+                }
+            }
+        }
+        ret
+    }
+
+    /// Deserialize a record or return `None` if it is malformed.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut decoder = Decoder { bytes };
+        if decoder.u8()? != Self::FORMAT_VERSION {
+            return None;
+        }
+        match decoder.u8()? {
+            Self::KIND_RESPONSE => {
+                let status = u16::from_be_bytes(decoder.array()?);
+                let stored_micros = u64::from_be_bytes(decoder.array()?);
+                let fresh_until_micros = u64::from_be_bytes(decoder.array()?);
+                let header_count = u32::from_be_bytes(decoder.array()?);
+                let headers = (0..header_count)
+                    .map(|_| Some((decoder.string()?, decoder.string()?)))
+                    .collect::<Option<Vec<_>>>()?;
+                Some(Self::Response(CachedResponse {
+                    status,
+                    headers,
+                    body: decoder.bytes.to_vec(),
+                    stored_micros,
+                    fresh_until_micros,
+                }))
+            }
+            Self::KIND_VARIANTS => {
+                let name_count = u32::from_be_bytes(decoder.array()?);
+                (0..name_count)
+                    .map(|_| decoder.string())
+                    .collect::<Option<Vec<_>>>()
+                    .map(Self::Variants)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Append length prefixed bytes.
+fn encode_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(&u32::try_from(bytes.len()).unwrap().to_be_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+/// Reader of the serialized form of a [CacheRecord].
+struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl Decoder<'_> {
+    fn u8(&mut self) -> Option<u8> {
+        self.array::<1>().map(|array| array[0])
+    }
+
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (head, tail) = self.bytes.split_first_chunk::<N>()?;
+        self.bytes = tail;
+        Some(*head)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = usize::try_from(u32::from_be_bytes(self.array()?)).ok()?;
+        let (head, tail) = self.bytes.split_at_checked(len)?;
+        self.bytes = tail;
+        String::from_utf8(head.to_vec()).ok()
+    }
+}
+
+/// Upstream response with the information needed to decide if it can be
+/// served without contacting the upstream server.
+#[derive(Debug, PartialEq)]
+pub struct CachedResponse {
+    /// HTTP status code.
+    pub status: u16,
+    /// End-to-end response headers.
+    pub headers: Vec<(String, String)>,
+    /// Response body.
+    pub body: Vec<u8>,
+    /// When the response was received or last revalidated.
+    pub stored_micros: u64,
+    /// When the response becomes stale.
+    pub fresh_until_micros: u64,
+}
+
+impl CachedResponse {
+    /// Return the first value of the header with the (case-insensitive) name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        header(&self.headers, name)
+    }
+
+    /// Return `true` if the response can be served without revalidation.
+    pub fn is_fresh(&self, now_micros: u64) -> bool {
+        now_micros < self.fresh_until_micros
+    }
+
+    /// Return `true` if the response can be revalidated with the upstream
+    /// server.
+    pub fn has_validators(&self) -> bool {
+        self.header("etag").is_some() || self.header("last-modified").is_some()
+    }
+
+    /// Replace headers with the ones from a `304 Not Modified` response.
+    pub fn update_headers(&mut self, headers: Vec<(String, String)>) {
+        self.headers.retain(|(name, _value)| {
+            !headers
+                .iter()
+                .any(|(updated_name, _value)| updated_name.eq_ignore_ascii_case(name))
+        });
+        self.headers.extend(headers);
+    }
+}
+
+/// Return the first value of the header with the (case-insensitive) name.
+pub fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(header_name, _value)| header_name.eq_ignore_ascii_case(name))
+        .map(|(_name, value)| value.as_str())
+}
+
+/// Return the comma separated values of all headers with the
+/// (case-insensitive) name.
+pub fn header_list<'a>(headers: &'a [(String, String)], name: &str) -> Vec<&'a str> {
+    headers
+        .iter()
+        .filter(|(header_name, _value)| header_name.eq_ignore_ascii_case(name))
+        .flat_map(|(_name, value)| value.split(','))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+/// Return the lower case names and the values of the `Cache-Control`
+/// directives.
+fn cache_control_directives(headers: &[(String, String)]) -> Vec<(String, &str)> {
+    header_list(headers, "cache-control")
+        .into_iter()
+        .map(|directive| {
+            let (name, value) = directive.split_once('=').unwrap_or((directive, ""));
+            (
+                name.trim().to_ascii_lowercase(),
+                value.trim().trim_matches('"'),
+            )
+        })
+        .collect()
+}
+
+/// Return `true` if the response may be shared with other clients although the
+/// request had an `Authorization` header.
+pub fn is_shareable_when_authorized(headers: &[(String, String)]) -> bool {
+    cache_control_directives(headers)
+        .iter()
+        .any(|(name, _value)| name == "public" || name == "s-maxage" || name == "must-revalidate")
+}
+
+/// Return for how many microseconds a response is fresh or `None` if it must
+/// not be stored.
+///
+/// Freshness is derived from `Cache-Control` `s-maxage` or `max-age` and
+/// falls back to `Expires`. Responses without explicit freshness are only
+/// stored if they can be revalidated. Responses to `authorized` requests are
+/// only stored if they are explicitly shareable.
+pub fn fresh_for_micros(
+    status: u16,
+    headers: &[(String, String)],
+    now_micros: u64,
+    authorized: bool,
+) -> Option<u64> {
+    let directives = cache_control_directives(headers);
+    let directive = |name: &str| {
+        directives
+            .iter()
+            .find(|(directive_name, _value)| directive_name == name)
+            .map(|(_name, value)| *value)
+    };
+    if directive("no-store").is_some()
+        || directive("private").is_some()
+        || header_list(headers, "vary").contains(&"*")
+        || (authorized && !is_shareable_when_authorized(headers))
+    {
+        return None;
+    }
+    let explicit = directive("s-maxage")
+        .or(directive("max-age"))
+        .map(|seconds| {
+            seconds
+                .parse::<u64>()
+                .unwrap_or(0)
+                .saturating_mul(1_000_000)
+        });
+    let has_explicit = explicit.is_some() || header(headers, "expires").is_some();
+    if !has_explicit && !HEURISTICALLY_CACHEABLE.contains(&status) {
+        return None;
+    }
+    let fresh_for_micros = explicit.unwrap_or_else(|| {
+        header(headers, "expires")
+            .and_then(|expires| httpdate::parse_http_date(expires).ok())
+            .map(|expires| micros_since_epoch(expires).saturating_sub(now_micros))
+            .unwrap_or(0)
+    });
+    let has_validators =
+        header(headers, "etag").is_some() || header(headers, "last-modified").is_some();
+    if directive("no-cache").is_some() {
+        return has_validators.then_some(0);
+    }
+    (fresh_for_micros > 0 || has_validators).then_some(fresh_for_micros)
+}
+
+/// Return the number of microseconds since the Unix epoch.
+pub fn micros_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| u64::try_from(duration.as_micros()).unwrap_or(u64::MAX))
+        .unwrap_or(0)
+}
+
+mod test {
+    //! Cached response tests.
+
+    #[cfg(test)]
+    fn headers(headers: &[(&str, &str)]) -> Vec<(String, String)> {
+        headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let record = super::CacheRecord::Response(super::CachedResponse {
+            status: 200,
+            headers: headers(&[("ETag", "\"v1\""), ("Content-Type", "text/plain")]),
+            body: b"hello".to_vec(),
+            stored_micros: 1,
+            fresh_until_micros: 2,
+        });
+        assert_eq!(super::CacheRecord::decode(&record.encode()), Some(record));
+        let record = super::CacheRecord::Variants(vec!["accept-language".to_string()]);
+        assert_eq!(super::CacheRecord::decode(&record.encode()), Some(record));
+        assert_eq!(super::CacheRecord::decode(&[1, 1, 0]), None);
+    }
+
+    #[test]
+    fn test_freshness() {
+        let now_micros = 1_700_000_000_000_000;
+        let fresh_for = |status, h: &[(&str, &str)]| {
+            super::fresh_for_micros(status, &headers(h), now_micros, false)
+        };
+        assert_eq!(
+            fresh_for(200, &[("Cache-Control", "public, max-age=60")]),
+            Some(60_000_000)
+        );
+        assert_eq!(
+            fresh_for(200, &[("cache-control", "max-age=60, s-maxage=10")]),
+            Some(10_000_000)
+        );
+        assert_eq!(fresh_for(200, &[("Cache-Control", "no-store")]), None);
+        assert_eq!(
+            fresh_for(200, &[("Cache-Control", "private, max-age=60")]),
+            None
+        );
+        assert_eq!(fresh_for(200, &[("Cache-Control", "no-cache")]), None);
+        assert_eq!(
+            fresh_for(200, &[("Cache-Control", "no-cache"), ("ETag", "\"a\"")]),
+            Some(0)
+        );
+        assert_eq!(fresh_for(200, &[]), None);
+        assert_eq!(fresh_for(200, &[("ETag", "\"a\"")]), Some(0));
+        assert_eq!(fresh_for(500, &[("ETag", "\"a\"")]), None);
+        assert_eq!(
+            fresh_for(200, &[("Cache-Control", "max-age=60"), ("Vary", "*")]),
+            None
+        );
+        let expires = httpdate::fmt_http_date(
+            std::time::UNIX_EPOCH + std::time::Duration::from_micros(now_micros + 120_000_000),
+        );
+        let fresh = fresh_for(200, &[("Expires", &expires)]).unwrap();
+        assert_eq!(fresh, 120_000_000);
+        assert_eq!(
+            fresh_for(200, &[("Expires", "Thu, 01 Jan 1970 00:00:00 GMT")]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_authorized_responses_are_only_stored_if_shareable() {
+        let fresh_for = |h: &[(&str, &str)]| super::fresh_for_micros(200, &headers(h), 0, true);
+        assert_eq!(fresh_for(&[("Cache-Control", "max-age=60")]), None);
+        assert_eq!(fresh_for(&[("ETag", "\"a\"")]), None);
+        assert_eq!(
+            fresh_for(&[("Cache-Control", "public, max-age=60")]),
+            Some(60_000_000)
+        );
+        assert_eq!(
+            fresh_for(&[("Cache-Control", "s-maxage=10")]),
+            Some(10_000_000)
+        );
+        assert_eq!(
+            fresh_for(&[("Cache-Control", "must-revalidate, max-age=5")]),
+            Some(5_000_000)
+        );
+    }
+}
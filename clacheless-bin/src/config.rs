@@ -17,6 +17,14 @@
 
 //! Configuration parsing.
 
+use clacheless::ClachelessConfig;
+use clacheless::ClachelessError;
+use clacheless::ClachelessErrorKind;
+use clacheless::NodeRole;
+use clacheless::ReplicationOverflowPolicy;
+use std::fmt::Display;
+use std::str::FromStr;
+
 /// Return the address template where the literal String `ORDINAL` will be
 /// replaced by the target node's id.
 pub fn address_template() -> String {
@@ -33,11 +41,258 @@ pub fn local_node_id() -> u32 {
 }
 
 /// Return for how many microseconds a checked item will be kept.
-pub fn cache_item_time_to_live_micros() -> u64 {
-    env_or_default("CLACHELESS_TTL", "3600")
+pub fn cache_item_time_to_live_micros() -> Result<u64, ClachelessError> {
+    env_secs_or_default("CLACHELESS_TTL", 3_600_000_000)
+}
+
+/// Return the library configuration or an error describing invalid values.
+pub fn clacheless_config() -> Result<ClachelessConfig, ClachelessError> {
+    let config = ClachelessConfig::default()
+        .with_state_broadcast_interval_micros(state_broadcast_interval_micros()?)
+        .with_alive_margin_micros(alive_margin_micros()?)
+        .with_purge_interval_micros(purge_interval_micros()?)
+        .with_peer_token_validity_micros(peer_token_validity_micros()?)
+        .with_max_document_size(max_document_size()?)
+        .with_workers_per_core(workers_per_core()?)
+        .with_probe_window(probe_window()?)
+        .with_anti_entropy_interval_micros(anti_entropy_interval_micros()?)
+        .with_anti_entropy_cpu_budget_percent(anti_entropy_cpu_budget_percent()?)
+        .with_initial_sync_timeout_micros(initial_sync_timeout_micros()?)
+        .with_replication_queue_capacity(replication_queue_capacity()?)
+        .with_replication_overflow_policy(replication_overflow_policy()?)
+        .with_node_role(node_role()?)
+        .with_hinted_handoff_max_bytes(hinted_handoff_max_bytes()?)
+        .with_hinted_handoff_max_age_micros(hinted_handoff_max_age_micros()?)
+        .with_max_clock_skew_micros(max_clock_skew_micros()?);
+    if let Some(snapshot_path) = snapshot_path() {
+        config
+            .with_snapshot_path(snapshot_path)
+            .with_snapshot_interval_micros(snapshot_interval_micros()?)
+    } else {
+        config
+    }
+    .validate()
+}
+
+/// Return the base URL of the upstream server to act as a caching reverse
+/// proxy for or `None` if the proxy mode is disabled.
+pub fn proxy_upstream_base_url() -> Option<String> {
+    std::env::var("CLACHELESS_PROXY_UPSTREAM")
+        .ok()
+        .filter(|proxy_upstream_base_url| !proxy_upstream_base_url.is_empty())
+}
+
+/// Return the bearer token that protects the admin API or `None` if the admin
+/// API is disabled.
+pub fn admin_token() -> Option<String> {
+    std::env::var("CLACHELESS_ADMIN_TOKEN")
+        .ok()
+        .filter(|admin_token| !admin_token.is_empty())
+}
+
+/// Return how long a graceful leave of the cluster may take at shutdown.
+pub fn shutdown_timeout_micros() -> Result<u64, ClachelessError> {
+    env_secs_or_default("CLACHELESS_SHUTDOWN_TIMEOUT", 10_000_000)
+}
+
+/// Return the number of microseconds between pushes of the cluster view to
+/// other nodes.
+fn state_broadcast_interval_micros() -> Result<u64, ClachelessError> {
+    env_micros_or_default(
+        "CLACHELESS_STATE_BROADCAST_INTERVAL_MS",
+        ClachelessConfig::DEFAULT_STATE_BROADCAST_INTERVAL_MICROS,
+    )
+}
+
+/// Return how many microseconds longer than the state broadcast interval a node
+/// may be silent before it is considered lost.
+fn alive_margin_micros() -> Result<u64, ClachelessError> {
+    env_micros_or_default(
+        "CLACHELESS_ALIVE_MARGIN_MS",
+        ClachelessConfig::DEFAULT_ALIVE_MARGIN_MICROS,
+    )
+}
+
+/// Return the number of microseconds between purges of expired entries.
+fn purge_interval_micros() -> Result<u64, ClachelessError> {
+    env_micros_or_default(
+        "CLACHELESS_PURGE_INTERVAL_MS",
+        ClachelessConfig::DEFAULT_PURGE_INTERVAL_MICROS,
+    )
+}
+
+/// Return for how many microseconds a peer authentication token is accepted.
+fn peer_token_validity_micros() -> Result<u64, ClachelessError> {
+    env_micros_or_default(
+        "CLACHELESS_PEER_TOKEN_VALIDITY_MS",
+        ClachelessConfig::DEFAULT_PEER_TOKEN_VALIDITY_MICROS,
+    )
+}
+
+/// Return the maximum size in bytes of an object written through the API.
+fn max_document_size() -> Result<usize, ClachelessError> {
+    env_parsed_or_default(
+        "CLACHELESS_MAX_DOCUMENT_SIZE",
+        ClachelessConfig::DEFAULT_MAX_DOCUMENT_SIZE,
+    )
+}
+
+/// Return the number of concurrent API connections per CPU core.
+fn workers_per_core() -> Result<usize, ClachelessError> {
+    env_parsed_or_default(
+        "CLACHELESS_WORKERS_PER_CORE",
+        ClachelessConfig::DEFAULT_WORKERS_PER_CORE,
+    )
+}
+
+/// Return how many node ordinals above the highest known one to probe for new
+/// nodes.
+fn probe_window() -> Result<u32, ClachelessError> {
+    env_parsed_or_default(
+        "CLACHELESS_PROBE_WINDOW",
+        ClachelessConfig::DEFAULT_PROBE_WINDOW,
+    )
+}
+
+/// Return the number of microseconds between anti-entropy rounds or `0` to
+/// disable anti-entropy.
+fn anti_entropy_interval_micros() -> Result<u64, ClachelessError> {
+    env_secs_or_default(
+        "CLACHELESS_ANTI_ENTROPY_INTERVAL",
+        ClachelessConfig::DEFAULT_ANTI_ENTROPY_INTERVAL_MICROS,
+    )
+}
+
+/// Return the share of a CPU core in percent that anti-entropy may use.
+fn anti_entropy_cpu_budget_percent() -> Result<u8, ClachelessError> {
+    env_parsed_or_default(
+        "CLACHELESS_ANTI_ENTROPY_CPU_PERCENT",
+        ClachelessConfig::DEFAULT_ANTI_ENTROPY_CPU_BUDGET_PERCENT,
+    )
+}
+
+/// Return how long a starting node waits for the initial synchronization
+/// before it reports itself as ready anyway.
+fn initial_sync_timeout_micros() -> Result<u64, ClachelessError> {
+    env_secs_or_default(
+        "CLACHELESS_INITIAL_SYNC_TIMEOUT",
+        ClachelessConfig::DEFAULT_INITIAL_SYNC_TIMEOUT_MICROS,
+    )
+}
+
+/// Return the maximum number of updates waiting to be sent to each peer.
+fn replication_queue_capacity() -> Result<usize, ClachelessError> {
+    env_parsed_or_default(
+        "CLACHELESS_REPLICATION_QUEUE_CAPACITY",
+        ClachelessConfig::DEFAULT_REPLICATION_QUEUE_CAPACITY,
+    )
+}
+
+/// Return what happens to a write when the replication queue of a peer is
+/// full: `drop` (and let the peer recover with a state transfer), `block` or
+/// `fail`.
+fn replication_overflow_policy() -> Result<ReplicationOverflowPolicy, ClachelessError> {
+    let name = "CLACHELESS_REPLICATION_OVERFLOW";
+    match env_or_default(name, "drop").as_str() {
+        "drop" => Ok(ReplicationOverflowPolicy::DropAndResync),
+        "block" => Ok(ReplicationOverflowPolicy::Block),
+        "fail" => Ok(ReplicationOverflowPolicy::FailPut),
+        value => Err(invalid_value_error(name, value)),
+    }
+}
+
+/// Return the role of the local node: `member` (default) or `observer` for a
+/// read-only replica.
+fn node_role() -> Result<NodeRole, ClachelessError> {
+    let name = "CLACHELESS_NODE_ROLE";
+    match env_or_default(name, "member").as_str() {
+        "member" => Ok(NodeRole::Member),
+        "observer" => Ok(NodeRole::Observer),
+        value => Err(invalid_value_error(name, value)),
+    }
+}
+
+/// Return the maximum size in bytes of the updates kept for each peer that is
+/// down or `0` to disable hinted handoff.
+fn hinted_handoff_max_bytes() -> Result<usize, ClachelessError> {
+    env_parsed_or_default(
+        "CLACHELESS_HINTED_HANDOFF_MAX_BYTES",
+        ClachelessConfig::DEFAULT_HINTED_HANDOFF_MAX_BYTES,
+    )
+}
+
+/// Return for how many microseconds updates are kept for a peer that is down.
+fn hinted_handoff_max_age_micros() -> Result<u64, ClachelessError> {
+    env_secs_or_default(
+        "CLACHELESS_HINTED_HANDOFF_MAX_AGE",
+        ClachelessConfig::DEFAULT_HINTED_HANDOFF_MAX_AGE_MICROS,
+    )
+}
+
+/// Return the snapshot file or `None` if snapshots are disabled.
+fn snapshot_path() -> Option<String> {
+    std::env::var("CLACHELESS_SNAPSHOT_PATH")
+        .ok()
+        .filter(|snapshot_path| !snapshot_path.is_empty())
+}
+
+/// Return the number of microseconds between snapshots or `0` to only write a
+/// snapshot at shutdown.
+fn snapshot_interval_micros() -> Result<u64, ClachelessError> {
+    env_secs_or_default(
+        "CLACHELESS_SNAPSHOT_INTERVAL",
+        ClachelessConfig::DEFAULT_SNAPSHOT_INTERVAL_MICROS,
+    )
+}
+
+/// Return the maximum clock offset to a peer in microseconds.
+fn max_clock_skew_micros() -> Result<u64, ClachelessError> {
+    env_micros_or_default(
+        "CLACHELESS_MAX_CLOCK_SKEW_MS",
+        ClachelessConfig::DEFAULT_MAX_CLOCK_SKEW_MICROS,
+    )
+}
+
+/// Get environment variable in milliseconds by name as microseconds or return
+/// the default value if the variable isn't set.
+fn env_micros_or_default(name: &str, default_value_micros: u64) -> Result<u64, ClachelessError> {
+    env_scaled_or_default(name, default_value_micros, 1_000)
+}
+
+/// Get environment variable in seconds by name as microseconds or return the
+/// default value if the variable isn't set.
+fn env_secs_or_default(name: &str, default_value_micros: u64) -> Result<u64, ClachelessError> {
+    env_scaled_or_default(name, default_value_micros, 1_000_000)
+}
+
+/// Get environment variable in units of `micros_per_unit` by name as
+/// microseconds or return the default value if the variable isn't set.
+fn env_scaled_or_default(
+    name: &str,
+    default_value_micros: u64,
+    micros_per_unit: u64,
+) -> Result<u64, ClachelessError> {
+    let value = env_parsed_or_default(name, default_value_micros / micros_per_unit)?;
+    value
+        .checked_mul(micros_per_unit)
+        .ok_or_else(|| invalid_value_error(name, &value.to_string()))
+}
+
+/// Get environment variable by name parsed as `T` or return the default value
+/// if the variable isn't set.
+fn env_parsed_or_default<T: FromStr + Display>(
+    name: &str,
+    default_value: T,
+) -> Result<T, ClachelessError> {
+    let value = env_or_default(name, &default_value.to_string());
+    value
         .parse()
-        .unwrap_or(3600)
-        * 1_000_000
+        .map_err(|_e| invalid_value_error(name, &value))
+}
+
+/// Return an error describing the invalid value of an environment variable.
+fn invalid_value_error(name: &str, value: &str) -> ClachelessError {
+    ClachelessErrorKind::Malformed.error_with_msg(format!("Invalid value '{value}' of env.{name}."))
 }
 
 /// Get environment variable by name or return a default value if the variable
@@ -21,8 +21,10 @@
 
 mod config;
 
+use clacheless::ClachelessConfig;
 use clacheless::DistributedCache;
 use std::process::ExitCode;
+use std::sync::Arc;
 use tokio::signal::unix::SignalKind;
 use tokio::signal::unix::signal;
 
@@ -32,6 +34,20 @@ fn main() -> ExitCode {
         println!("Failed to initialize logging: {e:?}");
         return ExitCode::FAILURE;
     }
+    let clacheless_config = match config::clacheless_config() {
+        Ok(clacheless_config) => clacheless_config,
+        Err(e) => {
+            log::error!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let cache_item_ttl_micros = match config::cache_item_time_to_live_micros() {
+        Ok(cache_item_ttl_micros) => cache_item_ttl_micros,
+        Err(e) => {
+            log::error!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
     // Defaults to using one thread per core when no limit is set.
     tokio::runtime::Builder::new_multi_thread()
         .enable_all()
@@ -40,9 +56,11 @@ fn main() -> ExitCode {
         .block_on(run_async(
             &config::address_template(),
             config::local_node_id(),
-            config::cache_item_time_to_live_micros(),
+            cache_item_ttl_micros,
+            clacheless_config,
             "0.0.0.0",
             8080,
+            config::proxy_upstream_base_url().as_deref(),
         ))
 }
 
@@ -71,14 +89,42 @@ pub async fn run_async(
     address_template: &str,
     local_node_id: u32,
     cache_item_ttl_micros: u64,
+    clacheless_config: ClachelessConfig,
     http_bind_address: &str,
     http_bind_port: u16,
+    proxy_upstream_base_url: Option<&str>,
 ) -> ExitCode {
-    let dc = DistributedCache::new(address_template, local_node_id, cache_item_ttl_micros).await;
+    let shutdown_timeout_micros = match config::shutdown_timeout_micros() {
+        Ok(shutdown_timeout_micros) => shutdown_timeout_micros,
+        Err(e) => {
+            log::error!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let dc = match DistributedCache::new_with_config(
+        address_template,
+        local_node_id,
+        cache_item_ttl_micros,
+        clacheless_config,
+    )
+    .await
+    {
+        Ok(dc) => dc,
+        Err(e) => {
+            log::error!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
     let dc_future = dc.run();
-    let app_future =
-        clacheless_api_rest::rest_api::run_http_server(&dc, http_bind_address, http_bind_port);
-    let signals_future = block_until_signaled();
+    let admin_token = config::admin_token();
+    let app_future = clacheless_api_rest::rest_api::run_http_server(
+        &dc,
+        http_bind_address,
+        http_bind_port,
+        proxy_upstream_base_url,
+        admin_token.as_deref(),
+    );
+    let signals_future = block_until_signaled(&dc, shutdown_timeout_micros);
     let res = tokio::select! {
         res = app_future => {
             log::trace!("app_future finished");
@@ -104,12 +150,24 @@ pub async fn run_async(
 }
 
 /// Block until SIGTERM or SIGINT is recieved.
-async fn block_until_signaled() {
+///
+/// When SIGTERM is recieved, the node leaves the cluster gracefully within
+/// `shutdown_timeout_micros` and a final snapshot of the cache is written (if
+/// enabled).
+async fn block_until_signaled(dc: &Arc<DistributedCache>, shutdown_timeout_micros: u64) {
     let mut sigint = signal(SignalKind::interrupt()).unwrap();
     let mut sigterm = signal(SignalKind::terminate()).unwrap();
     tokio::select! {
         _ = sigterm.recv() => {
-            log::trace!("SIGTERM recieved.")
+            log::trace!("SIGTERM recieved.");
+            dc.leave(shutdown_timeout_micros)
+                .await
+                .inspect_err(|e| log::warn!("Failed to leave the cluster gracefully: {e}"))
+                .ok();
+            dc.write_snapshot()
+                .await
+                .inspect_err(|e| log::warn!("Failed to write final snapshot: {e}"))
+                .ok();
         },
         _ = sigint.recv() => {
             log::trace!("SIGINT recieved.")
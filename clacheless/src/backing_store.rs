@@ -0,0 +1,70 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Persistent storage behind the distributed cache.
+
+mod file_system;
+mod in_memory;
+
+pub use self::file_system::FileSystemBackingStore;
+pub use self::in_memory::InMemoryBackingStore;
+
+use crate::ClachelessError;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// When locally originated writes reach the [BackingStore].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WritePolicy {
+    /// Store the object before the cache write returns. A failed store fails
+    /// the cache write (after the object has been cached).
+    WriteThrough,
+    /// Queue the object and store it in batches in the background, retrying
+    /// failed batches with exponential backoff.
+    WriteBehind,
+}
+
+/** Slow, persistent storage that a [DistributedCache](crate::DistributedCache)
+can be placed in front of.
+
+Cache misses are loaded from the store and writes are stored according to the
+configured [WritePolicy]. Only the node where a write was recieved stores it,
+so each write reaches the store once regardless of the cluster size. The same
+node removes the object once the cache entry expires.
+*/
+#[async_trait]
+pub trait BackingStore: Send + Sync {
+    /// Return the stored object or `None` if there is no object for the key.
+    async fn load(&self, key: &str) -> Result<Option<Vec<u8>>, ClachelessError>;
+
+    /// Store the object for the key, replacing any existing object.
+    async fn store(&self, key: &str, value: &[u8]) -> Result<(), ClachelessError>;
+
+    /// Remove the object for the key if present.
+    async fn delete(&self, key: &str) -> Result<(), ClachelessError>;
+
+    /// Store a batch of objects.
+    ///
+    /// The default implementation stores one object at the time. Stores with
+    /// support for bulk writes should override this.
+    async fn store_batch(&self, entries: &[(String, Arc<Vec<u8>>)]) -> Result<(), ClachelessError> {
+        for (key, value) in entries {
+            self.store(key, value).await?;
+        }
+        Ok(())
+    }
+}
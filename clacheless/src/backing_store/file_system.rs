@@ -0,0 +1,154 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! File system backing store.
+
+use super::BackingStore;
+use crate::ClachelessError;
+use crate::ClachelessErrorKind;
+use async_trait::async_trait;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+/** [BackingStore] that keeps each object in a file of a directory.
+
+File names are the hex encoded keys, so any key is safe to use. Encoded keys
+that exceed the file name limit of common file systems are split into nested
+directories. Objects are written to a temporary file first and then renamed,
+so readers never see a partially written object.
+*/
+pub struct FileSystemBackingStore {
+    dir: PathBuf,
+    last_tmp_id: AtomicU64,
+}
+
+impl FileSystemBackingStore {
+    /// Maximum length of a path segment of an encoded key.
+    ///
+    /// Leaves room for the suffix of temporary files within the common limit
+    /// of 255 bytes per file name.
+    const SEGMENT_MAX_LEN: usize = 200;
+    /// Suffix of the directories of long keys, so they never clash with the
+    /// (pure hex) files.
+    const DIR_SUFFIX: &str = ".d";
+
+    /// Return a new instance that stores objects in `dir`, creating the
+    /// directory if needed.
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self, ClachelessError> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir).map_err(|e| {
+            ClachelessErrorKind::Unspecified.error_with_msg(format!(
+                "Failed to create directory '{}': {e}",
+                dir.display()
+            ))
+        })?;
+        Ok(Self {
+            dir,
+            last_tmp_id: AtomicU64::default(),
+        })
+    }
+
+    /// Return the file of the key.
+    fn path_for(&self, key: &str) -> PathBuf {
+        let encoded = key
+            .as_bytes()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+        let mut path = self.dir.clone();
+        let mut rest = encoded.as_str();
+        while rest.len() > Self::SEGMENT_MAX_LEN {
+            let (segment, tail) = rest.split_at(Self::SEGMENT_MAX_LEN);
+            path.push(format!("{segment}{}", Self::DIR_SUFFIX));
+            rest = tail;
+        }
+        path.push(rest);
+        path
+    }
+
+    fn io_error(key: &str, e: std::io::Error) -> ClachelessError {
+        ClachelessErrorKind::Unspecified
+            .error_with_msg(format!("File system access for key '{key}' failed: {e}"))
+    }
+}
+
+#[async_trait]
+impl BackingStore for FileSystemBackingStore {
+    async fn load(&self, key: &str) -> Result<Option<Vec<u8>>, ClachelessError> {
+        match tokio::fs::read(self.path_for(key)).await {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Self::io_error(key, e)),
+        }
+    }
+
+    async fn store(&self, key: &str, value: &[u8]) -> Result<(), ClachelessError> {
+        let path = self.path_for(key);
+        if let Some(parent) = path.parent()
+            && parent != self.dir
+        {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| Self::io_error(key, e))?;
+        }
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(format!(
+            ".{}.tmp",
+            self.last_tmp_id.fetch_add(1, Ordering::Relaxed)
+        ));
+        tokio::fs::write(&tmp_path, value)
+            .await
+            .map_err(|e| Self::io_error(key, e))?;
+        tokio::fs::rename(&tmp_path, &path)
+            .await
+            .map_err(|e| Self::io_error(key, e))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), ClachelessError> {
+        match tokio::fs::remove_file(self.path_for(key)).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(Self::io_error(key, e)),
+        }
+    }
+}
+
+mod test {
+    //! File system backing store tests.
+
+    #[tokio::test]
+    async fn test_long_keys() {
+        use super::BackingStore;
+        use super::FileSystemBackingStore;
+
+        let dir = std::env::temp_dir().join(format!("clacheless-fs-test-{}", std::process::id()));
+        let backing_store = FileSystemBackingStore::new(&dir).unwrap();
+        let short_key = "k".repeat(100);
+        let long_key = "k".repeat(1000);
+        backing_store.store(&short_key, b"short").await.unwrap();
+        backing_store.store(&long_key, b"long").await.unwrap();
+        let short_value = backing_store.load(&short_key).await.unwrap();
+        let long_value = backing_store.load(&long_key).await.unwrap();
+        backing_store.delete(&long_key).await.unwrap();
+        let deleted_value = backing_store.load(&long_key).await.unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+        assert_eq!(short_value, Some(b"short".to_vec()));
+        assert_eq!(long_value, Some(b"long".to_vec()));
+        assert_eq!(deleted_value, None);
+    }
+}
@@ -0,0 +1,57 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! In-memory backing store.
+
+use super::BackingStore;
+use crate::ClachelessError;
+use async_trait::async_trait;
+use crossbeam_skiplist::SkipMap;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+/// [BackingStore] that keeps objects in memory, intended for tests.
+#[derive(Default)]
+pub struct InMemoryBackingStore {
+    objects: SkipMap<String, Vec<u8>>,
+    store_count: AtomicU64,
+}
+
+impl InMemoryBackingStore {
+    /// Return the number of objects stored so far.
+    pub fn store_count(&self) -> u64 {
+        self.store_count.load(Ordering::Relaxed)
+    }
+}
+
+#[async_trait]
+impl BackingStore for InMemoryBackingStore {
+    async fn load(&self, key: &str) -> Result<Option<Vec<u8>>, ClachelessError> {
+        Ok(self.objects.get(key).map(|entry| entry.value().to_owned()))
+    }
+
+    async fn store(&self, key: &str, value: &[u8]) -> Result<(), ClachelessError> {
+        self.objects.insert(key.to_owned(), value.to_vec());
+        self.store_count.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), ClachelessError> {
+        self.objects.remove(key);
+        Ok(())
+    }
+}
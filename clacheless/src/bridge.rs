@@ -0,0 +1,404 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+/*! Asynchronous replication of key prefixes between two clusters.
+
+The peer protocol within a cluster assumes a full mesh of peers with low
+latency. Clusters in different regions are instead connected by a [Bridge] on
+one node of each cluster, that replicates the entries of selected key prefixes
+over a single long-lived stream.
+
+```no_run
+# use clacheless::DistributedCache;
+# use clacheless::bridge::Bridge;
+# use clacheless::bridge::BridgeConfig;
+# async fn example(dc: std::sync::Arc<DistributedCache>) {
+let bridge = Bridge::new(
+    &dc,
+    BridgeConfig::new("eu-north", b"shared secret").with_key_prefix("session/"),
+);
+loop {
+    // The other cluster's bridge accepts the connection and runs it the same way
+    if let Ok(stream) = tokio::net::TcpStream::connect("bridge.us-east.example:9100").await {
+        if let Err(e) = bridge.run(stream).await {
+            log::warn!("Bridge failed: {e}");
+        }
+    }
+    tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+}
+# }
+```
+
+Each side proves knowledge of the shared secret by answering the other side's
+random challenge. The stream itself is not encrypted, so use a TLS stream when
+crossing untrusted networks.
+
+All replicated keys are sent when a bridge connects and local updates are then
+streamed as they happen. Updates wait in a bounded lag buffer while the other
+side is slow, and all replicated keys are sent again if it overflows.
+
+Entries recieved from the other cluster are written on this node if the
+[BridgeConflictPolicy] accepts them and replicated within the local cluster.
+An accepted entry gets an update timestamp past the local entry if needed, so
+that it replaces the local entry on all nodes. Such entries are marked as
+bridged on all nodes and are not sent back unless they are updated locally, so
+run a single bridge between each pair of clusters.
+*/
+
+mod bridge_config;
+mod bridge_status;
+mod bridge_stream;
+mod lag_buffer;
+
+pub use self::bridge_config::BridgeConfig;
+pub use self::bridge_config::BridgeConflictPolicy;
+pub use self::bridge_status::BridgeStatus;
+
+use self::bridge_stream::BridgeReader;
+use self::bridge_stream::BridgeWriter;
+use self::lag_buffer::LagBuffer;
+use crate::CacheUpdate;
+use crate::ClachelessError;
+use crate::ClachelessErrorKind;
+use crate::DistributedCache;
+use crate::proto::bridge::BridgeEntry;
+use crate::proto::bridge::BridgeHello;
+use crate::proto::bridge::BridgeMessage;
+use crate::proto::bridge::BridgeProof;
+use crate::proto::bridge::bridge_message::Message;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Weak;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::io::BufWriter;
+use tokio::sync::Notify;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::Duration;
+use tyst::Tyst;
+
+/// Replicates entries of selected key prefixes to and from another cluster.
+///
+/// See the [module documentation](self).
+pub struct Bridge {
+    dc: Arc<DistributedCache>,
+    config: BridgeConfig,
+    remote_cluster_id: Mutex<Option<String>>,
+    lag_buffer: Mutex<LagBuffer>,
+    lag_notify: Notify,
+    resyncs: AtomicU64,
+    sent_entries: AtomicU64,
+    received_entries: AtomicU64,
+    rejected_entries: AtomicU64,
+}
+
+impl Bridge {
+    const CHALLENGE_BYTES: usize = 32;
+    const RESYNC_BATCH_SIZE: usize = 1024;
+
+    /// Return a new instance that replicates entries of `dc`.
+    ///
+    /// Local updates are collected from now on, but nothing is sent until
+    /// [Self::run] is called with a stream to the other cluster.
+    pub fn new(dc: &Arc<DistributedCache>, config: BridgeConfig) -> Arc<Self> {
+        let cache_updates = dc.cache_updates();
+        let ret = Arc::new(Self {
+            dc: Arc::clone(dc),
+            lag_buffer: Mutex::new(LagBuffer::new(config.max_lag_entries())),
+            config,
+            remote_cluster_id: Mutex::default(),
+            lag_notify: Notify::new(),
+            resyncs: AtomicU64::default(),
+            sent_entries: AtomicU64::default(),
+            received_entries: AtomicU64::default(),
+            rejected_entries: AtomicU64::default(),
+        });
+        let weak = Arc::downgrade(&ret);
+        tokio::spawn(async move { Self::collect_updates(weak, cache_updates).await });
+        ret
+    }
+
+    /// Queue local updates of replicated keys while connected.
+    async fn collect_updates(
+        weak: Weak<Self>,
+        mut cache_updates: tokio::sync::broadcast::Receiver<CacheUpdate>,
+    ) {
+        loop {
+            let res = cache_updates.recv().await;
+            let Some(bridge) = weak.upgrade() else {
+                break;
+            };
+            if !bridge.is_connected() {
+                // Everything is sent when the other side connects
+                continue;
+            }
+            match res {
+                Ok(update) => {
+                    if update.is_bridged() || !bridge.config.is_replicated(update.key()) {
+                        continue;
+                    }
+                    bridge.lag_buffer.lock().unwrap().push(update);
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    log::warn!("Bridge missed {missed} local updates. Resynchronizing.");
+                    bridge.lag_buffer.lock().unwrap().request_resync();
+                }
+                Err(RecvError::Closed) => break,
+            }
+            bridge.lag_notify.notify_one();
+        }
+    }
+
+    /// Return the configuration this instance was created with.
+    pub fn config(&self) -> &BridgeConfig {
+        &self.config
+    }
+
+    /// Return `true` if a stream to the other cluster is running.
+    pub fn is_connected(&self) -> bool {
+        self.remote_cluster_id.lock().unwrap().is_some()
+    }
+
+    /// Return the current status.
+    pub fn status(&self) -> BridgeStatus {
+        BridgeStatus {
+            remote_cluster_id: self.remote_cluster_id.lock().unwrap().clone(),
+            lag_entries: self.lag_buffer.lock().unwrap().len(),
+            resyncs: self.resyncs.load(Ordering::Relaxed),
+            sent_entries: self.sent_entries.load(Ordering::Relaxed),
+            received_entries: self.received_entries.load(Ordering::Relaxed),
+            rejected_entries: self.rejected_entries.load(Ordering::Relaxed),
+        }
+    }
+
+    /** Replicate over `stream` until it fails or the other side closes it.
+
+    `stream` can be a connection initiated by either side. The sides
+    authenticate each other, send all replicated keys and then stream updates
+    in both directions.
+
+    Only one stream can run at a time. Call again with a new stream to
+    reconnect.
+    */
+    pub async fn run(
+        &self,
+        stream: impl AsyncRead + AsyncWrite + Send,
+    ) -> Result<(), ClachelessError> {
+        let (reader, writer) = tokio::io::split(stream);
+        let mut reader = BridgeReader::new(reader);
+        let mut writer = BridgeWriter::new(BufWriter::new(writer));
+        let remote_cluster_id = tokio::time::timeout(
+            Duration::from_micros(self.config.handshake_timeout_micros()),
+            self.authenticate(&mut reader, &mut writer),
+        )
+        .await
+        .map_err(|_| {
+            ClachelessErrorKind::Connection.error_with_msg("Bridge authentication timed out.")
+        })??;
+        {
+            let mut connected_cluster_id = self.remote_cluster_id.lock().unwrap();
+            if let Some(connected_cluster_id) = connected_cluster_id.as_ref() {
+                return Err(ClachelessErrorKind::Unavailable.error_with_msg(format!(
+                    "Bridge is already connected to cluster '{connected_cluster_id}'."
+                )));
+            }
+            *connected_cluster_id = Some(remote_cluster_id.to_owned());
+            self.lag_buffer.lock().unwrap().request_resync();
+        }
+        log::info!("Bridge connected to cluster '{remote_cluster_id}'.");
+        let res = tokio::select! {
+            res = self.send_updates(&mut writer) => res,
+            res = self.receive_entries(&mut reader, &remote_cluster_id) => res,
+        };
+        *self.remote_cluster_id.lock().unwrap() = None;
+        log::info!("Bridge disconnected from cluster '{remote_cluster_id}'.");
+        res
+    }
+
+    /// Prove knowledge of the shared secret to the other side and verify its
+    /// proof. Returns the other side's cluster id.
+    async fn authenticate(
+        &self,
+        reader: &mut BridgeReader<impl AsyncRead + Unpin>,
+        writer: &mut BridgeWriter<impl AsyncWrite + Unpin>,
+    ) -> Result<String, ClachelessError> {
+        let auth_error = |msg: &str| ClachelessErrorKind::Connection.error_with_msg(msg);
+        let challenge = Tyst::instance().prng_get_random_bytes(None, Self::CHALLENGE_BYTES);
+        writer
+            .send(&BridgeMessage {
+                message: Some(Message::Hello(BridgeHello {
+                    cluster_id: self.config.cluster_id().to_owned(),
+                    challenge: challenge.to_owned(),
+                })),
+            })
+            .await?;
+        writer.flush().await?;
+        let Some(Message::Hello(hello)) = reader.next_message().await?.and_then(|m| m.message)
+        else {
+            return Err(auth_error("Expected bridge hello."));
+        };
+        if hello.cluster_id == self.config.cluster_id() {
+            return Err(auth_error("Bridge is connected to its own cluster."));
+        }
+        if hello.challenge.len() != Self::CHALLENGE_BYTES {
+            return Err(auth_error("Bridge challenge has the wrong size."));
+        }
+        writer
+            .send(&BridgeMessage {
+                message: Some(Message::Proof(BridgeProof {
+                    mac: bridge_stream::create_proof(
+                        self.config.shared_secret(),
+                        self.config.cluster_id(),
+                        &hello.challenge,
+                    ),
+                })),
+            })
+            .await?;
+        writer.flush().await?;
+        let Some(Message::Proof(proof)) = reader.next_message().await?.and_then(|m| m.message)
+        else {
+            return Err(auth_error("Expected bridge proof."));
+        };
+        if !bridge_stream::verify_proof(
+            self.config.shared_secret(),
+            &hello.cluster_id,
+            &challenge,
+            &proof.mac,
+        ) {
+            return Err(auth_error(&format!(
+                "Cluster '{}' failed to authenticate.",
+                hello.cluster_id
+            )));
+        }
+        reader.set_authenticated();
+        Ok(hello.cluster_id)
+    }
+
+    /// Send all replicated keys when requested and queued updates as they
+    /// arrive.
+    async fn send_updates(
+        &self,
+        writer: &mut BridgeWriter<impl AsyncWrite + Unpin>,
+    ) -> Result<(), ClachelessError> {
+        loop {
+            if self.lag_buffer.lock().unwrap().take_resync() {
+                self.resyncs.fetch_add(1, Ordering::Relaxed);
+                self.send_replicated_keys(writer).await?;
+            }
+            loop {
+                let update = self.lag_buffer.lock().unwrap().pop();
+                let Some(update) = update else {
+                    break;
+                };
+                self.send_update(writer, &update).await?;
+            }
+            writer.flush().await?;
+            self.lag_notify.notified().await;
+        }
+    }
+
+    async fn send_replicated_keys(
+        &self,
+        writer: &mut BridgeWriter<impl AsyncWrite + Unpin>,
+    ) -> Result<(), ClachelessError> {
+        for key_prefix in self.config.key_prefixes() {
+            let mut after_key = None;
+            loop {
+                let batch = self.dc.latest_updates_with_prefix(
+                    key_prefix,
+                    after_key.as_deref(),
+                    Self::RESYNC_BATCH_SIZE,
+                );
+                for update in batch.iter().filter(|update| !update.is_bridged()) {
+                    self.send_update(writer, update).await?;
+                }
+                if batch.len() < Self::RESYNC_BATCH_SIZE {
+                    break;
+                }
+                after_key = batch.last().map(|update| update.key().to_owned());
+            }
+        }
+        Ok(())
+    }
+
+    async fn send_update(
+        &self,
+        writer: &mut BridgeWriter<impl AsyncWrite + Unpin>,
+        update: &CacheUpdate,
+    ) -> Result<(), ClachelessError> {
+        writer
+            .send(&BridgeMessage {
+                message: Some(Message::Entry(BridgeEntry {
+                    key: update.key().to_owned(),
+                    object_bytes: update.object_bytes().to_vec(),
+                    this_update_micros: update.this_update_micros(),
+                    expires_micros: update.expires_micros(),
+                    origin_cluster_id: self.config.cluster_id().to_owned(),
+                })),
+            })
+            .await?;
+        self.sent_entries.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Apply entries from the other side until it closes the stream.
+    async fn receive_entries(
+        &self,
+        reader: &mut BridgeReader<impl AsyncRead + Unpin>,
+        remote_cluster_id: &str,
+    ) -> Result<(), ClachelessError> {
+        while let Some(message) = reader.next_message().await? {
+            let Some(Message::Entry(entry)) = message.message else {
+                return Err(ClachelessErrorKind::Malformed
+                    .error_with_msg("Expected bridge entry after authentication."));
+            };
+            self.received_entries.fetch_add(1, Ordering::Relaxed);
+            if entry.origin_cluster_id == self.config.cluster_id()
+                || !self.config.is_replicated(&entry.key)
+            {
+                self.rejected_entries.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            let remote = CacheUpdate::new(
+                entry.key,
+                Arc::new(entry.object_bytes),
+                entry.this_update_micros,
+                entry.expires_micros,
+            );
+            let local = self.dc.latest_update(remote.key());
+            if self.config.conflict_policy().accepts(
+                local.as_ref(),
+                &remote,
+                self.config.cluster_id(),
+                remote_cluster_id,
+            ) {
+                self.dc.put_bridged(&remote).await?;
+            } else {
+                if log::log_enabled!(log::Level::Debug) {
+                    log::debug!(
+                        "Kept local entry for key '{}' over entry from cluster '{remote_cluster_id}'.",
+                        remote.key()
+                    );
+                }
+                self.rejected_entries.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Configuration of a bridge to another cluster.
+
+use crate::CacheUpdate;
+use std::fmt;
+use std::sync::Arc;
+
+/// Which write of a key wins when both clusters have written it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BridgeConflictPolicy {
+    /// The write with the most recent update timestamp wins. Writes with the
+    /// same timestamp are decided by the greatest cluster id, so both clusters
+    /// agree on the outcome.
+    #[default]
+    NewestWins,
+    /// Entries recieved from the other cluster never replace an existing
+    /// entry.
+    LocalWins,
+    /// Entries recieved from the other cluster always replace the existing
+    /// entry.
+    RemoteWins,
+}
+
+impl BridgeConflictPolicy {
+    /// Return `true` if the `remote` entry should replace the `local` one.
+    pub(crate) fn accepts(
+        &self,
+        local: Option<&CacheUpdate>,
+        remote: &CacheUpdate,
+        local_cluster_id: &str,
+        remote_cluster_id: &str,
+    ) -> bool {
+        let Some(local) = local else {
+            return true;
+        };
+        if local.this_update_micros() == remote.this_update_micros()
+            && local.object_bytes() == remote.object_bytes()
+        {
+            // Same write that has already been bridged
+            return false;
+        }
+        match self {
+            Self::NewestWins => {
+                (remote.this_update_micros(), remote_cluster_id)
+                    > (local.this_update_micros(), local_cluster_id)
+            }
+            Self::LocalWins => false,
+            Self::RemoteWins => true,
+        }
+    }
+}
+
+/** Configuration of a [Bridge](super::Bridge).
+
+Both sides of a bridge need the same shared secret and unique cluster ids. Use
+[BridgeConflictPolicy::NewestWins] on both sides, or
+[BridgeConflictPolicy::LocalWins] on the authoritative side and
+[BridgeConflictPolicy::RemoteWins] on the other.
+*/
+#[derive(Clone)]
+pub struct BridgeConfig {
+    cluster_id: String,
+    shared_secret: Arc<Vec<u8>>,
+    key_prefixes: Vec<String>,
+    max_lag_entries: usize,
+    conflict_policy: BridgeConflictPolicy,
+    handshake_timeout_micros: u64,
+}
+
+impl fmt::Debug for BridgeConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BridgeConfig")
+            .field("cluster_id", &self.cluster_id)
+            .field("key_prefixes", &self.key_prefixes)
+            .field("max_lag_entries", &self.max_lag_entries)
+            .field("conflict_policy", &self.conflict_policy)
+            .field("handshake_timeout_micros", &self.handshake_timeout_micros)
+            .finish_non_exhaustive()
+    }
+}
+
+impl BridgeConfig {
+    /// Return a new instance for the local cluster identified by `cluster_id`
+    /// that authenticates the other side with `shared_secret`.
+    ///
+    /// No keys are replicated until a prefix is added with
+    /// [Self::with_key_prefix].
+    pub fn new(cluster_id: &str, shared_secret: &[u8]) -> Self {
+        Self {
+            cluster_id: cluster_id.to_owned(),
+            shared_secret: Arc::new(shared_secret.to_vec()),
+            key_prefixes: Vec::default(),
+            max_lag_entries: 65_536,
+            conflict_policy: BridgeConflictPolicy::default(),
+            handshake_timeout_micros: 10_000_000,
+        }
+    }
+
+    /// Replicate keys starting with `key_prefix` in both directions.
+    ///
+    /// May be called multiple times. An empty prefix replicates all keys.
+    pub fn with_key_prefix(mut self, key_prefix: &str) -> Self {
+        self.key_prefixes.push(key_prefix.to_owned());
+        self
+    }
+
+    /// Maximum number of local updates waiting to be sent before they are
+    /// dropped in favour of a resynchronization of all replicated keys.
+    pub fn with_max_lag_entries(mut self, max_lag_entries: usize) -> Self {
+        self.max_lag_entries = max_lag_entries.max(1);
+        self
+    }
+
+    /// How to resolve writes of the same key in both clusters.
+    pub fn with_conflict_policy(mut self, conflict_policy: BridgeConflictPolicy) -> Self {
+        self.conflict_policy = conflict_policy;
+        self
+    }
+
+    /// Maximum time for the other side to complete the authentication.
+    pub fn with_handshake_timeout_micros(mut self, handshake_timeout_micros: u64) -> Self {
+        self.handshake_timeout_micros = handshake_timeout_micros;
+        self
+    }
+
+    /// Return the identifier of the local cluster.
+    pub fn cluster_id(&self) -> &str {
+        &self.cluster_id
+    }
+
+    /// Return the secret shared with the other side.
+    pub(crate) fn shared_secret(&self) -> &[u8] {
+        &self.shared_secret
+    }
+
+    /// Return the replicated key prefixes.
+    pub fn key_prefixes(&self) -> &[String] {
+        &self.key_prefixes
+    }
+
+    /// Return `true` if the key is replicated.
+    pub fn is_replicated(&self, key: &str) -> bool {
+        self.key_prefixes
+            .iter()
+            .any(|key_prefix| key.starts_with(key_prefix.as_str()))
+    }
+
+    /// Return the maximum number of local updates waiting to be sent.
+    pub fn max_lag_entries(&self) -> usize {
+        self.max_lag_entries
+    }
+
+    /// Return how writes of the same key in both clusters are resolved.
+    pub fn conflict_policy(&self) -> BridgeConflictPolicy {
+        self.conflict_policy
+    }
+
+    /// Return the maximum time for the other side to authenticate.
+    pub fn handshake_timeout_micros(&self) -> u64 {
+        self.handshake_timeout_micros
+    }
+}
@@ -0,0 +1,63 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Point in time status of a bridge.
+
+/// Status of a [Bridge](super::Bridge).
+#[derive(Clone, Debug)]
+pub struct BridgeStatus {
+    pub(super) remote_cluster_id: Option<String>,
+    pub(super) lag_entries: usize,
+    pub(super) resyncs: u64,
+    pub(super) sent_entries: u64,
+    pub(super) received_entries: u64,
+    pub(super) rejected_entries: u64,
+}
+
+impl BridgeStatus {
+    /// Identifier of the connected cluster or `None` when disconnected.
+    pub fn remote_cluster_id(&self) -> Option<&str> {
+        self.remote_cluster_id.as_deref()
+    }
+
+    /// Number of local updates waiting to be sent.
+    pub fn lag_entries(&self) -> usize {
+        self.lag_entries
+    }
+
+    /// Number of times all replicated keys were sent because the other side
+    /// connected or the lag buffer overflowed.
+    pub fn resyncs(&self) -> u64 {
+        self.resyncs
+    }
+
+    /// Number of entries sent to the other cluster.
+    pub fn sent_entries(&self) -> u64 {
+        self.sent_entries
+    }
+
+    /// Number of entries recieved from the other cluster.
+    pub fn received_entries(&self) -> u64 {
+        self.received_entries
+    }
+
+    /// Number of recieved entries that lost against the local entry or were
+    /// not replicated.
+    pub fn rejected_entries(&self) -> u64 {
+        self.rejected_entries
+    }
+}
@@ -0,0 +1,247 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Length-delimited bridge messages over a byte stream.
+
+use crate::ClachelessError;
+use crate::ClachelessErrorKind;
+use crate::proto::bridge::BridgeMessage;
+use prost::Message;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWrite;
+use tokio::io::AsyncWriteExt;
+use tyst::traits::mac::ToMacKey;
+
+/// Reads [BridgeMessage]s from the other side.
+pub struct BridgeReader<R> {
+    reader: R,
+    max_message_bytes: usize,
+}
+
+impl<R: AsyncRead + Unpin> BridgeReader<R> {
+    /// Maximum size of a single encoded message before the other side has
+    /// authenticated.
+    const MAX_HANDSHAKE_MESSAGE_BYTES: usize = 4 * 1024;
+    /// Maximum size of a single encoded message.
+    const MAX_MESSAGE_BYTES: usize = 64 * 1024 * 1024;
+
+    /// Return a new instance that only accepts handshake sized messages.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            max_message_bytes: Self::MAX_HANDSHAKE_MESSAGE_BYTES,
+        }
+    }
+
+    /// Accept messages of any size up to the limit once the other side has
+    /// authenticated.
+    pub fn set_authenticated(&mut self) {
+        self.max_message_bytes = Self::MAX_MESSAGE_BYTES;
+    }
+
+    /// Read the next message or return `None` if the other side closed the
+    /// stream at a message boundary.
+    pub async fn next_message(&mut self) -> Result<Option<BridgeMessage>, ClachelessError> {
+        // Varint length prefix
+        let mut len = 0usize;
+        for shift in (0..64).step_by(7) {
+            let mut byte = [0u8; 1];
+            if self.reader.read(&mut byte).await.map_err(io_error)? == 0 {
+                if shift == 0 {
+                    return Ok(None);
+                }
+                return Err(truncated_error());
+            }
+            len |= usize::from(byte[0] & 0x7f) << shift;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        if len > self.max_message_bytes {
+            return Err(ClachelessErrorKind::Malformed
+                .error_with_msg(format!("Bridge message of {len} bytes is too large.")));
+        }
+        let mut buf = vec![0u8; len];
+        self.reader.read_exact(&mut buf).await.map_err(|e| {
+            if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                truncated_error()
+            } else {
+                io_error(e)
+            }
+        })?;
+        BridgeMessage::decode(buf.as_slice())
+            .map(Some)
+            .map_err(|e| {
+                ClachelessErrorKind::Malformed.error_with_msg(format!("Bad bridge message: {e}"))
+            })
+    }
+}
+
+/// Writes [BridgeMessage]s to the other side.
+pub struct BridgeWriter<W> {
+    writer: W,
+}
+
+impl<W: AsyncWrite + Unpin> BridgeWriter<W> {
+    /// Return a new instance.
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Write a message without flushing.
+    pub async fn send(&mut self, message: &BridgeMessage) -> Result<(), ClachelessError> {
+        self.writer
+            .write_all(&message.encode_length_delimited_to_vec())
+            .await
+            .map_err(io_error)
+    }
+
+    /// Flush written messages to the other side.
+    pub async fn flush(&mut self) -> Result<(), ClachelessError> {
+        self.writer.flush().await.map_err(io_error)
+    }
+}
+
+/// Create a HMAC-SHA3-256 that proves that the side with `cluster_id` knows
+/// `shared_secret` and has seen `challenge`.
+pub fn create_proof(shared_secret: &[u8], cluster_id: &str, challenge: &[u8]) -> Vec<u8> {
+    let message = [challenge, cluster_id.as_bytes()].concat();
+    tyst::Tyst::instance()
+        .macs()
+        .by_oid(&tyst::encdec::oid::as_string(
+            tyst::oids::mac::HMAC_SHA3_256,
+        ))
+        .map(|mut mac_impl| mac_impl.mac(shared_secret.to_vec().to_mac_key().as_ref(), &message))
+        .unwrap_or_default()
+}
+
+/// Return `true` if `proof` was created by [create_proof] with the same
+/// arguments.
+///
+/// The comparison does not leak the length of the common prefix.
+pub fn verify_proof(
+    shared_secret: &[u8],
+    cluster_id: &str,
+    challenge: &[u8],
+    proof: &[u8],
+) -> bool {
+    let expected = create_proof(shared_secret, cluster_id, challenge);
+    !expected.is_empty()
+        && expected.len() == proof.len()
+        && expected
+            .iter()
+            .zip(proof)
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn io_error(e: std::io::Error) -> ClachelessError {
+    ClachelessErrorKind::Connection.error_with_msg(format!("Bridge stream failed: {e}"))
+}
+
+fn truncated_error() -> ClachelessError {
+    ClachelessErrorKind::Connection.error_with_msg("Bridge stream ended within a message.")
+}
+
+mod test {
+    //! Bridge stream tests.
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        use super::BridgeReader;
+        use super::BridgeWriter;
+        use crate::proto::bridge::BridgeEntry;
+        use crate::proto::bridge::BridgeMessage;
+        use crate::proto::bridge::bridge_message::Message;
+
+        let (client, server) = tokio::io::duplex(64);
+        let message = BridgeMessage {
+            message: Some(Message::Entry(BridgeEntry {
+                key: "key".to_owned(),
+                object_bytes: vec![7; 1000],
+                this_update_micros: 1,
+                expires_micros: 2,
+                origin_cluster_id: "east".to_owned(),
+            })),
+        };
+        let sent = message.clone();
+        let writer = tokio::spawn(async move {
+            let mut bridge_writer = BridgeWriter::new(client);
+            bridge_writer.send(&sent).await.unwrap();
+            bridge_writer.flush().await.unwrap();
+        });
+        let mut bridge_reader = BridgeReader::new(server);
+        assert_eq!(bridge_reader.next_message().await.unwrap(), Some(message));
+        writer.await.unwrap();
+        assert_eq!(bridge_reader.next_message().await.unwrap(), None);
+    }
+
+    #[test]
+    fn test_proof_depends_on_secret_and_challenge() {
+        use super::create_proof;
+        use super::verify_proof;
+
+        let proof = create_proof(b"secret", "east", b"challenge");
+        assert!(!proof.is_empty());
+        assert_eq!(proof, create_proof(b"secret", "east", b"challenge"));
+        assert_ne!(proof, create_proof(b"other", "east", b"challenge"));
+        assert_ne!(proof, create_proof(b"secret", "west", b"challenge"));
+        assert_ne!(proof, create_proof(b"secret", "east", b"replayed"));
+        assert!(verify_proof(b"secret", "east", b"challenge", &proof));
+        assert!(!verify_proof(b"other", "east", b"challenge", &proof));
+        assert!(!verify_proof(b"secret", "east", b"challenge", &proof[1..]));
+        assert!(!verify_proof(b"secret", "east", b"challenge", &[]));
+    }
+
+    #[tokio::test]
+    async fn test_large_message_requires_authentication() {
+        use super::BridgeReader;
+        use super::BridgeWriter;
+        use crate::ClachelessErrorKind;
+        use crate::proto::bridge::BridgeEntry;
+        use crate::proto::bridge::BridgeMessage;
+        use crate::proto::bridge::bridge_message::Message;
+
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let message = BridgeMessage {
+            message: Some(Message::Entry(BridgeEntry {
+                key: "key".to_owned(),
+                object_bytes: vec![7; 5000],
+                this_update_micros: 1,
+                expires_micros: 2,
+                origin_cluster_id: "east".to_owned(),
+            })),
+        };
+        let mut bridge_writer = BridgeWriter::new(client);
+        bridge_writer.send(&message).await.unwrap();
+        bridge_writer.send(&message).await.unwrap();
+        bridge_writer.flush().await.unwrap();
+        let mut bridge_reader = BridgeReader::new(server);
+        assert_eq!(
+            bridge_reader.next_message().await.unwrap_err().kind(),
+            &ClachelessErrorKind::Malformed
+        );
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let mut bridge_writer = BridgeWriter::new(client);
+        bridge_writer.send(&message).await.unwrap();
+        bridge_writer.flush().await.unwrap();
+        let mut bridge_reader = BridgeReader::new(server);
+        bridge_reader.set_authenticated();
+        assert_eq!(bridge_reader.next_message().await.unwrap(), Some(message));
+    }
+}
@@ -0,0 +1,109 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Bounded queue of local updates that the other cluster has not seen yet.
+
+use crate::CacheUpdate;
+use std::collections::VecDeque;
+
+/// Bounded queue of local updates waiting to be sent.
+///
+/// When the queue overflows, the queued updates are dropped and a
+/// resynchronization of all replicated keys is requested instead.
+pub struct LagBuffer {
+    max_entries: usize,
+    updates: VecDeque<CacheUpdate>,
+    resync_requested: bool,
+}
+
+impl LagBuffer {
+    /// Return a new instance that requests a resynchronization.
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            updates: VecDeque::default(),
+            resync_requested: true,
+        }
+    }
+
+    /// Queue an update unless a resynchronization will cover it.
+    pub fn push(&mut self, update: CacheUpdate) {
+        if self.resync_requested {
+            return;
+        }
+        if self.updates.len() >= self.max_entries {
+            log::warn!(
+                "Bridge lagged more than {} entries behind. Resynchronizing.",
+                self.max_entries
+            );
+            self.request_resync();
+            return;
+        }
+        self.updates.push_back(update);
+    }
+
+    /// Drop all queued updates and request a resynchronization.
+    pub fn request_resync(&mut self) {
+        self.updates.clear();
+        self.resync_requested = true;
+    }
+
+    /// Return `true` and clear the request if a resynchronization was
+    /// requested. Updates queued after this call are sent after the
+    /// resynchronization.
+    pub fn take_resync(&mut self) -> bool {
+        std::mem::take(&mut self.resync_requested)
+    }
+
+    /// Return the oldest queued update.
+    pub fn pop(&mut self) -> Option<CacheUpdate> {
+        self.updates.pop_front()
+    }
+
+    /// Return the number of queued updates.
+    pub fn len(&self) -> usize {
+        self.updates.len()
+    }
+}
+
+mod test {
+    //! Lag buffer tests.
+
+    #[test]
+    fn test_overflow_requests_resync() {
+        use super::CacheUpdate;
+        use super::LagBuffer;
+        use std::sync::Arc;
+
+        let update = |key: &str| CacheUpdate::new(key.to_owned(), Arc::default(), 1, 2);
+        let mut lag_buffer = LagBuffer::new(2);
+        assert!(lag_buffer.take_resync());
+        assert!(!lag_buffer.take_resync());
+        lag_buffer.push(update("a"));
+        lag_buffer.push(update("b"));
+        assert_eq!(lag_buffer.len(), 2);
+        lag_buffer.push(update("c"));
+        assert_eq!(lag_buffer.len(), 0);
+        // Covered by the pending resynchronization
+        lag_buffer.push(update("d"));
+        assert_eq!(lag_buffer.len(), 0);
+        assert!(lag_buffer.take_resync());
+        lag_buffer.push(update("e"));
+        assert_eq!(lag_buffer.pop().as_ref().map(CacheUpdate::key), Some("e"));
+        assert!(lag_buffer.pop().is_none());
+    }
+}
@@ -0,0 +1,587 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Library configuration.
+
+use crate::ClachelessError;
+use crate::ClachelessErrorKind;
+use crate::InMemoryNetwork;
+use crate::PartitionReport;
+use crate::backing_store::BackingStore;
+use crate::backing_store::WritePolicy;
+use crate::time::Clock;
+use crate::time::SystemClock;
+use std::fmt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/** Tuning parameters of a [DistributedCache](crate::DistributedCache).
+
+Start from [ClachelessConfig::default()], override individual values and check
+the result with [ClachelessConfig::validate]:
+
+```
+let config = clacheless::ClachelessConfig::default()
+    .with_probe_window(4)
+    .validate()
+    .unwrap();
+assert_eq!(config.probe_window(), 4);
+```
+*/
+#[derive(Clone, Debug)]
+pub struct ClachelessConfig {
+    state_broadcast_interval_micros: u64,
+    alive_margin_micros: u64,
+    purge_interval_micros: u64,
+    peer_token_validity_micros: u64,
+    max_document_size: usize,
+    workers_per_core: usize,
+    backing_store: Option<BackingStoreSetup>,
+    probe_window: u32,
+    anti_entropy_interval_micros: u64,
+    anti_entropy_cpu_budget_percent: u8,
+    snapshot_path: Option<PathBuf>,
+    snapshot_interval_micros: u64,
+    initial_sync_timeout_micros: u64,
+    replication_queue_capacity: usize,
+    replication_overflow_policy: ReplicationOverflowPolicy,
+    node_role: NodeRole,
+    hinted_handoff_max_bytes: usize,
+    hinted_handoff_max_age_micros: u64,
+    max_clock_skew_micros: u64,
+    in_memory_network: Option<Arc<InMemoryNetwork>>,
+    clock: Arc<dyn Clock>,
+    partition_healed_callback: Option<PartitionHealedCallbackSetup>,
+}
+
+/// What happens to a write when the replication queue of a peer is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplicationOverflowPolicy {
+    /// Skip the peer for this write and mark it for state transfer. The peer
+    /// recovers the missing updates from the local node once it learns about
+    /// the gap.
+    DropAndResync,
+    /// Wait for free space in the queue before the write is cached. A slow
+    /// peer slows down all writes, for up to a few seconds per write, after
+    /// which the update is dropped for that peer like with
+    /// [Self::DropAndResync].
+    Block,
+    /// Fail the write before it is cached.
+    FailPut,
+}
+
+/// Role of the local node in the cluster.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeRole {
+    /// Accept writes and replicate them to the other members.
+    Member,
+    /// Read-only replica that catches up with the members through state
+    /// transfers. Writes are rejected and the node is not part of the
+    /// membership of the other nodes, so writers don't replicate to it.
+    ///
+    /// Observers must use node ordinals that don't collide with the members'
+    /// ordinals and be reachable through the address template.
+    Observer,
+}
+
+/// Backing store and how writes reach it.
+#[derive(Clone)]
+struct BackingStoreSetup {
+    backing_store: Arc<dyn BackingStore>,
+    write_policy: WritePolicy,
+}
+
+impl fmt::Debug for BackingStoreSetup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BackingStoreSetup")
+            .field("write_policy", &self.write_policy)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Function that is invoked with the report of each healed partition.
+pub type PartitionHealedCallback = Arc<dyn Fn(&PartitionReport) + Send + Sync>;
+
+/// Callback for healed partitions.
+#[derive(Clone)]
+struct PartitionHealedCallbackSetup(PartitionHealedCallback);
+
+impl fmt::Debug for PartitionHealedCallbackSetup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PartitionHealedCallbackSetup")
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for ClachelessConfig {
+    fn default() -> Self {
+        Self {
+            state_broadcast_interval_micros: Self::DEFAULT_STATE_BROADCAST_INTERVAL_MICROS,
+            alive_margin_micros: Self::DEFAULT_ALIVE_MARGIN_MICROS,
+            purge_interval_micros: Self::DEFAULT_PURGE_INTERVAL_MICROS,
+            peer_token_validity_micros: Self::DEFAULT_PEER_TOKEN_VALIDITY_MICROS,
+            max_document_size: Self::DEFAULT_MAX_DOCUMENT_SIZE,
+            workers_per_core: Self::DEFAULT_WORKERS_PER_CORE,
+            backing_store: None,
+            probe_window: Self::DEFAULT_PROBE_WINDOW,
+            anti_entropy_interval_micros: Self::DEFAULT_ANTI_ENTROPY_INTERVAL_MICROS,
+            anti_entropy_cpu_budget_percent: Self::DEFAULT_ANTI_ENTROPY_CPU_BUDGET_PERCENT,
+            snapshot_path: None,
+            snapshot_interval_micros: Self::DEFAULT_SNAPSHOT_INTERVAL_MICROS,
+            initial_sync_timeout_micros: Self::DEFAULT_INITIAL_SYNC_TIMEOUT_MICROS,
+            replication_queue_capacity: Self::DEFAULT_REPLICATION_QUEUE_CAPACITY,
+            replication_overflow_policy: ReplicationOverflowPolicy::DropAndResync,
+            node_role: NodeRole::Member,
+            hinted_handoff_max_bytes: Self::DEFAULT_HINTED_HANDOFF_MAX_BYTES,
+            hinted_handoff_max_age_micros: Self::DEFAULT_HINTED_HANDOFF_MAX_AGE_MICROS,
+            max_clock_skew_micros: Self::DEFAULT_MAX_CLOCK_SKEW_MICROS,
+            in_memory_network: None,
+            clock: Arc::new(SystemClock),
+            partition_healed_callback: None,
+        }
+    }
+}
+
+impl ClachelessConfig {
+    /// Default time between pushes of the cluster view to other nodes in
+    /// microseconds.
+    pub const DEFAULT_STATE_BROADCAST_INTERVAL_MICROS: u64 = 2_000_000;
+    /// Default extra time a node may be silent before it is considered lost in
+    /// microseconds.
+    pub const DEFAULT_ALIVE_MARGIN_MICROS: u64 = 500_000;
+    /// Default time between purges of expired entries in microseconds.
+    pub const DEFAULT_PURGE_INTERVAL_MICROS: u64 = 30_000_000;
+    /// Default validity of peer authentication tokens in microseconds.
+    pub const DEFAULT_PEER_TOKEN_VALIDITY_MICROS: u64 = 1_000_000;
+    /// Default maximum size of a cached object in bytes.
+    pub const DEFAULT_MAX_DOCUMENT_SIZE: usize = 5 * 1024 * 1024;
+    /// Default number of concurrent API connections per CPU core.
+    pub const DEFAULT_WORKERS_PER_CORE: usize = 1024;
+    /// Default number of node ordinals above the highest known one to probe.
+    pub const DEFAULT_PROBE_WINDOW: u32 = 2;
+    /// Default time between anti-entropy rounds in microseconds.
+    pub const DEFAULT_ANTI_ENTROPY_INTERVAL_MICROS: u64 = 60_000_000;
+    /// Default share of a CPU core that anti-entropy may use in percent.
+    pub const DEFAULT_ANTI_ENTROPY_CPU_BUDGET_PERCENT: u8 = 10;
+    /// Default time between snapshots in microseconds.
+    pub const DEFAULT_SNAPSHOT_INTERVAL_MICROS: u64 = 300_000_000;
+    /// Default time to wait for the initial synchronization in microseconds.
+    pub const DEFAULT_INITIAL_SYNC_TIMEOUT_MICROS: u64 = 10_000_000;
+    /// Default maximum number of updates waiting to be sent to each peer.
+    pub const DEFAULT_REPLICATION_QUEUE_CAPACITY: usize = 4096;
+    /// Default maximum size of the updates kept for each peer that is down.
+    pub const DEFAULT_HINTED_HANDOFF_MAX_BYTES: usize = 16 * 1024 * 1024;
+    /// Default time to keep updates for a peer that is down in microseconds.
+    pub const DEFAULT_HINTED_HANDOFF_MAX_AGE_MICROS: u64 = 600_000_000;
+    /// Default maximum clock offset to a peer in microseconds.
+    pub const DEFAULT_MAX_CLOCK_SKEW_MICROS: u64 = 500_000;
+
+    /// Set the time between pushes of the cluster view to other nodes in
+    /// microseconds.
+    ///
+    /// This also drives node discovery and failure detection.
+    pub fn with_state_broadcast_interval_micros(
+        mut self,
+        state_broadcast_interval_micros: u64,
+    ) -> Self {
+        self.state_broadcast_interval_micros = state_broadcast_interval_micros;
+        self
+    }
+
+    /// Return the time between pushes of the cluster view to other nodes in
+    /// microseconds.
+    pub fn state_broadcast_interval_micros(&self) -> u64 {
+        self.state_broadcast_interval_micros
+    }
+
+    /// Set how much longer than the state broadcast interval a node may be
+    /// silent before it is considered lost in microseconds.
+    pub fn with_alive_margin_micros(mut self, alive_margin_micros: u64) -> Self {
+        self.alive_margin_micros = alive_margin_micros;
+        self
+    }
+
+    /// Return how much longer than the state broadcast interval a node may be
+    /// silent before it is considered lost in microseconds.
+    pub fn alive_margin_micros(&self) -> u64 {
+        self.alive_margin_micros
+    }
+
+    /// Set the time between purges of expired entries from the local cache in
+    /// microseconds.
+    pub fn with_purge_interval_micros(mut self, purge_interval_micros: u64) -> Self {
+        self.purge_interval_micros = purge_interval_micros;
+        self
+    }
+
+    /// Return the time between purges of expired entries from the local cache
+    /// in microseconds.
+    pub fn purge_interval_micros(&self) -> u64 {
+        self.purge_interval_micros
+    }
+
+    /// Set for how long a peer authentication token is accepted after it was
+    /// created in microseconds.
+    ///
+    /// This must cover network latency and clock differences between nodes.
+    pub fn with_peer_token_validity_micros(mut self, peer_token_validity_micros: u64) -> Self {
+        self.peer_token_validity_micros = peer_token_validity_micros;
+        self
+    }
+
+    /// Return for how long a peer authentication token is accepted after it
+    /// was created in microseconds.
+    pub fn peer_token_validity_micros(&self) -> u64 {
+        self.peer_token_validity_micros
+    }
+
+    /// Set the maximum size in bytes of an object written through the API.
+    pub fn with_max_document_size(mut self, max_document_size: usize) -> Self {
+        self.max_document_size = max_document_size;
+        self
+    }
+
+    /// Return the maximum size in bytes of an object written through the API.
+    pub fn max_document_size(&self) -> usize {
+        self.max_document_size
+    }
+
+    /// Set the number of concurrent API connections that can be served for
+    /// each available CPU core.
+    pub fn with_workers_per_core(mut self, workers_per_core: usize) -> Self {
+        self.workers_per_core = workers_per_core;
+        self
+    }
+
+    /// Return the number of concurrent API connections that can be served for
+    /// each available CPU core.
+    pub fn workers_per_core(&self) -> usize {
+        self.workers_per_core
+    }
+
+    /// Return the configuration if all values are consistent or an error
+    /// describing every problem.
+    pub fn validate(self) -> Result<Self, ClachelessError> {
+        let mut problems = vec![];
+        if self.state_broadcast_interval_micros == 0 {
+            problems.push("the state broadcast interval must be greater than zero".to_string());
+        }
+        if self.alive_margin_micros == 0
+            || self.alive_margin_micros >= self.state_broadcast_interval_micros
+        {
+            problems.push(format!(
+                "the alive margin ({} micros) must be greater than zero and smaller than the state broadcast interval ({} micros)",
+                self.alive_margin_micros, self.state_broadcast_interval_micros
+            ));
+        }
+        if self.purge_interval_micros == 0 {
+            problems.push("the purge interval must be greater than zero".to_string());
+        }
+        if self.peer_token_validity_micros == 0 {
+            problems.push("the peer token validity must be greater than zero".to_string());
+        }
+        if self.max_document_size == 0 {
+            problems.push("the maximum document size must be greater than zero".to_string());
+        }
+        if self.workers_per_core == 0 {
+            problems.push("the workers per core must be greater than zero".to_string());
+        }
+        if self.initial_sync_timeout_micros < self.state_broadcast_interval_micros {
+            problems.push(format!(
+                "the initial sync timeout ({} micros) must be at least the state broadcast interval ({} micros)",
+                self.initial_sync_timeout_micros, self.state_broadcast_interval_micros
+            ));
+        }
+        if problems.is_empty() {
+            Ok(self)
+        } else {
+            Err(ClachelessErrorKind::Malformed
+                .error_with_msg(format!("Invalid configuration: {}.", problems.join("; "))))
+        }
+    }
+
+    /// Set how many node ordinals above the highest known node ordinal that
+    /// will be actively probed to discover new nodes.
+    ///
+    /// Use `0` to only rely on new nodes announcing themselves.
+    pub fn with_probe_window(mut self, probe_window: u32) -> Self {
+        self.probe_window = probe_window;
+        self
+    }
+
+    /// Return how many node ordinals above the highest known node ordinal that
+    /// will be actively probed to discover new nodes.
+    pub fn probe_window(&self) -> u32 {
+        self.probe_window
+    }
+
+    /// Set the time between anti-entropy rounds in microseconds.
+    ///
+    /// In each round the content of the local cache is compared with one of
+    /// the other nodes and differing entries are repaired. Use `0` to disable
+    /// anti-entropy.
+    pub fn with_anti_entropy_interval_micros(mut self, anti_entropy_interval_micros: u64) -> Self {
+        self.anti_entropy_interval_micros = anti_entropy_interval_micros;
+        self
+    }
+
+    /// Return the time between anti-entropy rounds in microseconds or `0` if
+    /// disabled.
+    pub fn anti_entropy_interval_micros(&self) -> u64 {
+        self.anti_entropy_interval_micros
+    }
+
+    /// Set the share of a CPU core in percent (`1..=100`) that anti-entropy
+    /// may use while hashing the local cache.
+    pub fn with_anti_entropy_cpu_budget_percent(
+        mut self,
+        anti_entropy_cpu_budget_percent: u8,
+    ) -> Self {
+        self.anti_entropy_cpu_budget_percent = anti_entropy_cpu_budget_percent.clamp(1, 100);
+        self
+    }
+
+    /// Return the share of a CPU core in percent that anti-entropy may use.
+    pub fn anti_entropy_cpu_budget_percent(&self) -> u8 {
+        self.anti_entropy_cpu_budget_percent
+    }
+
+    /// Enable snapshots of the local cache to the file at `snapshot_path`.
+    ///
+    /// An existing snapshot is loaded at startup.
+    pub fn with_snapshot_path(mut self, snapshot_path: impl Into<PathBuf>) -> Self {
+        self.snapshot_path = Some(snapshot_path.into());
+        self
+    }
+
+    /// Return the snapshot file or `None` if snapshots are disabled.
+    pub fn snapshot_path(&self) -> Option<&Path> {
+        self.snapshot_path.as_deref()
+    }
+
+    /// Set the time between periodic snapshots in microseconds.
+    ///
+    /// Use `0` to only write snapshots on demand (e.g. at shutdown).
+    pub fn with_snapshot_interval_micros(mut self, snapshot_interval_micros: u64) -> Self {
+        self.snapshot_interval_micros = snapshot_interval_micros;
+        self
+    }
+
+    /// Return the time between periodic snapshots in microseconds or `0` if
+    /// disabled.
+    pub fn snapshot_interval_micros(&self) -> u64 {
+        self.snapshot_interval_micros
+    }
+
+    /// Set how long a starting node waits for the initial synchronization with
+    /// other nodes before it reports itself as ready anyway.
+    ///
+    /// This is also how long a single node waits before it considers itself
+    /// alone.
+    pub fn with_initial_sync_timeout_micros(mut self, initial_sync_timeout_micros: u64) -> Self {
+        self.initial_sync_timeout_micros = initial_sync_timeout_micros;
+        self
+    }
+
+    /// Return how long a starting node waits for the initial synchronization
+    /// in microseconds.
+    pub fn initial_sync_timeout_micros(&self) -> u64 {
+        self.initial_sync_timeout_micros
+    }
+
+    /// Set the maximum number of updates waiting to be sent to each peer.
+    pub fn with_replication_queue_capacity(mut self, replication_queue_capacity: usize) -> Self {
+        self.replication_queue_capacity = replication_queue_capacity.max(1);
+        self
+    }
+
+    /// Return the maximum number of updates waiting to be sent to each peer.
+    pub fn replication_queue_capacity(&self) -> usize {
+        self.replication_queue_capacity
+    }
+
+    /// Set what happens to a write when the replication queue of a peer is
+    /// full.
+    pub fn with_replication_overflow_policy(
+        mut self,
+        replication_overflow_policy: ReplicationOverflowPolicy,
+    ) -> Self {
+        self.replication_overflow_policy = replication_overflow_policy;
+        self
+    }
+
+    /// Return what happens to a write when the replication queue of a peer is
+    /// full.
+    pub fn replication_overflow_policy(&self) -> ReplicationOverflowPolicy {
+        self.replication_overflow_policy
+    }
+
+    /// Set the role of the local node in the cluster.
+    pub fn with_node_role(mut self, node_role: NodeRole) -> Self {
+        self.node_role = node_role;
+        self
+    }
+
+    /// Return the role of the local node in the cluster.
+    pub fn node_role(&self) -> NodeRole {
+        self.node_role
+    }
+
+    /// Set the maximum size in bytes of the updates kept for each peer that is
+    /// down.
+    ///
+    /// The updates are replayed when the peer is back. If the limit is
+    /// exceeded, the peer has to recover the updates with a state transfer.
+    /// Use `0` to disable hinted handoff.
+    pub fn with_hinted_handoff_max_bytes(mut self, hinted_handoff_max_bytes: usize) -> Self {
+        self.hinted_handoff_max_bytes = hinted_handoff_max_bytes;
+        self
+    }
+
+    /// Return the maximum size in bytes of the updates kept for each peer that
+    /// is down.
+    pub fn hinted_handoff_max_bytes(&self) -> usize {
+        self.hinted_handoff_max_bytes
+    }
+
+    /// Set for how long updates are kept for a peer that is down in
+    /// microseconds, before the peer is assumed to be gone for good.
+    pub fn with_hinted_handoff_max_age_micros(
+        mut self,
+        hinted_handoff_max_age_micros: u64,
+    ) -> Self {
+        self.hinted_handoff_max_age_micros = hinted_handoff_max_age_micros;
+        self
+    }
+
+    /// Return for how long updates are kept for a peer that is down in
+    /// microseconds.
+    pub fn hinted_handoff_max_age_micros(&self) -> u64 {
+        self.hinted_handoff_max_age_micros
+    }
+
+    /// Set the maximum clock offset to a peer in microseconds before a
+    /// warning is logged and the peer is reported as skewed. `0` disables
+    /// clock skew detection.
+    ///
+    /// Concurrent writes to the same key are ordered by wall clock time, so a
+    /// node with a skewed clock can win (or lose) against writes it should
+    /// not. Peer authentication tokens also fail when the skew approaches
+    /// [Self::peer_token_validity_micros].
+    pub fn with_max_clock_skew_micros(mut self, max_clock_skew_micros: u64) -> Self {
+        self.max_clock_skew_micros = max_clock_skew_micros;
+        self
+    }
+
+    /// Return the maximum clock offset to a peer in microseconds.
+    pub fn max_clock_skew_micros(&self) -> u64 {
+        self.max_clock_skew_micros
+    }
+
+    /// Place the cache in front of a [BackingStore].
+    ///
+    /// Cache misses are loaded from the store and writes reach the store
+    /// according to the `write_policy`.
+    pub fn with_backing_store(
+        mut self,
+        backing_store: Arc<dyn BackingStore>,
+        write_policy: WritePolicy,
+    ) -> Self {
+        self.backing_store = Some(BackingStoreSetup {
+            backing_store,
+            write_policy,
+        });
+        self
+    }
+
+    /// Return the backing store or `None` if the cache is standalone.
+    pub fn backing_store(&self) -> Option<&Arc<dyn BackingStore>> {
+        self.backing_store
+            .as_ref()
+            .map(|backing_store_setup| &backing_store_setup.backing_store)
+    }
+
+    /// Return how writes reach the backing store (if any).
+    pub fn backing_store_write_policy(&self) -> Option<WritePolicy> {
+        self.backing_store
+            .as_ref()
+            .map(|backing_store_setup| backing_store_setup.write_policy)
+    }
+
+    /// Connect to other nodes over an [InMemoryNetwork] instead of gRPC.
+    ///
+    /// This allows a whole cluster to run in a single process, e.g. in tests.
+    pub fn with_in_memory_network(mut self, in_memory_network: Arc<InMemoryNetwork>) -> Self {
+        self.in_memory_network = Some(in_memory_network);
+        self
+    }
+
+    /// Return the in-memory network or `None` if other nodes are reached over
+    /// gRPC.
+    pub fn in_memory_network(&self) -> Option<&Arc<InMemoryNetwork>> {
+        self.in_memory_network.as_ref()
+    }
+
+    /// Use `clock` instead of the system clock for all timing decisions.
+    ///
+    /// See [ManualClock](crate::time::ManualClock) for deterministic tests.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Return the source of the current time.
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    /// Invoke `callback` with the report of each healed partition.
+    ///
+    /// The callback runs on an async worker and should return quickly.
+    pub fn with_partition_healed_callback(mut self, callback: PartitionHealedCallback) -> Self {
+        self.partition_healed_callback = Some(PartitionHealedCallbackSetup(callback));
+        self
+    }
+
+    /// Return the function to invoke when a partition has healed (if any).
+    pub fn partition_healed_callback(&self) -> Option<&PartitionHealedCallback> {
+        self.partition_healed_callback
+            .as_ref()
+            .map(|partition_healed_callback| &partition_healed_callback.0)
+    }
+}
+
+mod test {
+    //! Configuration tests.
+
+    #[test]
+    fn test_validate_reports_all_problems() {
+        use super::ClachelessConfig;
+
+        assert!(ClachelessConfig::default().validate().is_ok());
+        let res = ClachelessConfig::default()
+            .with_state_broadcast_interval_micros(1_000_000)
+            .with_alive_margin_micros(1_000_000)
+            .with_max_document_size(0)
+            .validate();
+        let msg = res.unwrap_err().to_string();
+        assert!(msg.contains("alive margin"), "{msg}");
+        assert!(msg.contains("maximum document size"), "{msg}");
+        assert!(!msg.contains("purge interval"), "{msg}");
+    }
+}
@@ -31,6 +31,8 @@ pub enum ClachelessErrorKind {
     NotFound,
     /// The object is not in the expected format.
     Malformed,
+    /// The request can't be served right now (e.g. during shutdown).
+    Unavailable,
 }
 
 impl ClachelessErrorKind {
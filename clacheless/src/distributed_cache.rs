@@ -17,48 +17,191 @@
 
 //! Distributed cache.
 
+mod anti_entropy;
+mod backing_store_writer;
+mod cache_update;
+mod clock_skew;
+mod cluster_status;
 mod cluster_view;
 mod grpc_client;
+mod grpc_client_pool;
 mod grpc_server;
+mod hinted_handoff;
+mod in_memory_network;
 mod local_cache;
+mod membership_event;
+mod node_health;
+mod node_prober;
+mod partition_report;
+mod partition_tracker;
 mod peer_authenticator;
+mod peer_replicator;
+mod peer_service;
+mod peer_transport;
+mod protocol;
+mod retry_backoff;
+mod snapshot;
+mod state_transfer;
 
+use self::anti_entropy::AntiEntropy;
+use self::anti_entropy::HashTree;
+use self::backing_store_writer::BackingStoreWriter;
+use self::clock_skew::ClockSkew;
+use self::clock_skew::PeerClock;
 use self::cluster_view::ClusterStateView;
+use self::cluster_view::PeerIdentity;
+use self::cluster_view::SequenceRanges;
 use self::grpc_client::GrpcClient;
+use self::grpc_client_pool::GrpcClientPool;
+use self::in_memory_network::InMemoryTransport;
+use self::local_cache::CacheEntry;
+use self::local_cache::CacheEntryAndKey;
 use self::local_cache::LocalCache;
+use self::local_cache::Overwrite;
+use self::node_health::NodeHealth;
+use self::node_prober::NodeProber;
+use self::partition_tracker::PartitionTracker;
+use self::peer_replicator::PeerReplicator;
+use self::peer_replicator::QueueSlot;
+use self::peer_transport::PeerTransport;
+use self::protocol::Capability;
+use self::protocol::PeerProtocols;
+use self::retry_backoff::RetryBackoff;
+use self::snapshot::Snapshot;
+use self::snapshot::SnapshotReader;
+use self::state_transfer::StateTransferSession;
+use self::state_transfer::StateTransfers;
+use crate::ClachelessConfig;
 use crate::ClachelessError;
 use crate::ClachelessErrorKind;
+use crate::NodeRole;
+use crate::ReplicationOverflowPolicy;
+use crate::backing_store::WritePolicy;
 use crossbeam_skiplist::SkipMap;
 use crossbeam_skiplist::map::Entry;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tokio::sync::broadcast;
+use tokio::sync::mpsc;
+use tyst::Tyst;
+
+pub use self::cache_update::CacheUpdate;
+pub use self::cluster_status::ClusterStatus;
+pub use self::cluster_status::OriginStatus;
+pub use self::cluster_status::PeerStatus;
+pub use self::in_memory_network::InMemoryNetwork;
+pub use self::membership_event::MembershipEvent;
+pub use self::partition_report::ConflictResolution;
+pub use self::partition_report::KeyConflict;
+pub use self::partition_report::PartitionReport;
+pub use self::state_transfer::StateTransferProgress;
+pub use self::state_transfer::StateTransferState;
 
 /** Distributed cache between `Pod`s in a `StatefulSet`.
 
 [Self] maintains connectivity to other `Pod`s in the `StatefulSet` and holds
 the local copy of the distributed cache.
 
-Node identifiers are unique (for 136 years) and calculated at starup as
-`local_node_id = now_seconds & 0xffff_ffff << 32 | node_ordinal`.
+Node identifiers are calculated at startup as
+`local_node_id = random_incarnation << 32 | node_ordinal`, so a restarted node
+(or a node of another cluster on the same network) is told apart from earlier
+incarnations of the same ordinal. Should a node id still be reused, this is
+detected by the sequence of the node id going backwards and the affected
+baselines are reset.
+
+Nodes with higher ordinals than the highest known one are discovered by
+probing a window of ordinals (see [ClachelessConfig::with_probe_window]) with
+`Join` requests. Failed probes are retried with exponential backoff.
+
+Nodes advertise their wire protocol version and optional features with every
+cluster view update, and features that a node hasn't advertised are replaced
+by their initial protocol equivalents (or skipped) when talking to it. This
+keeps clusters with mixed versions working during rolling upgrades.
+
+A single long-lived gRPC connection is kept to each node and is dropped after
+a failed request or when unused for a while.
+
+Local writes are replicated through a bounded, ordered queue per node where
+updates are sent in batches.
+
+A node that is lagging behind requests a state transfer session from a node
+that is more up to date. An interrupted transfer is resumed from the last
+recieved update of each origin node and only one transfer at the time is
+requested for each origin node. See [Self::state_transfers].
+
+Divergence that sequence numbers can't reveal is repaired by periodic
+anti-entropy rounds (see [ClachelessConfig::with_anti_entropy_interval_micros]).
+Each round compares hash trees of the cache content with one node at the time
+and pulls the entries where the remote has a newer version.
 
+With [ClachelessConfig::with_snapshot_path], the local cache and the cluster
+state baselines are periodically written to disk and restored at startup.
+
+A node that is shut down with [Self::leave] tells the other nodes to drop it
+right away and hands off the entries it originated to a surviving node.
+
+Each peer has a bounded replication queue. What happens to writes when a slow
+or unreachable peer lets its queue fill up is controlled by
+[ClachelessConfig::with_replication_overflow_policy]. Updates for a peer that
+is down are kept within [ClachelessConfig::with_hinted_handoff_max_bytes] and
+replayed when the peer is heard from again.
+
+With [ClachelessConfig::with_backing_store], cache misses are loaded from the
+backing store and writes recieved by this node are stored there. Once such an
+entry expires, it is removed from the backing store.
 */
 pub struct DistributedCache {
-    address_template: String,
     local_node_ordinal: u32,
     cache_item_ttl_micros: u64,
     local_node_id: u64,
+    config: ClachelessConfig,
     known_node_ordinals_with_last_seen: SkipMap<u32, u64>,
+    membership_lock: std::sync::Mutex<()>,
+    observer_node_ordinals_with_last_seen: SkipMap<u32, u64>,
+    node_prober: NodeProber,
+    peer_transport: Arc<dyn PeerTransport>,
+    peer_replicators: SkipMap<u32, PeerReplicator>,
+    view_pushes_in_flight: SkipMap<u32, ()>,
+    peer_protocols: Arc<PeerProtocols>,
+    clock_skew: ClockSkew,
+    local_clock_skewed: AtomicBool,
+    broadcast_lock: Mutex<()>,
+    state_transfers: StateTransfers,
+    anti_entropy: AntiEntropy,
+    partition_tracker: PartitionTracker,
+    membership_events: broadcast::Sender<MembershipEvent>,
+    cache_updates: broadcast::Sender<CacheUpdate>,
+    snapshot_lock: Mutex<()>,
+    backing_store_writer: Option<BackingStoreWriter>,
+    node_health: NodeHealth,
+    leaving: AtomicBool,
     local_cache: Arc<LocalCache>,
     cluster_view: Arc<ClusterStateView>,
 }
 
 impl DistributedCache {
-    const STATE_BROADCAST_INTERVAL_MICROS: u64 = 2_000_000;
-    const ALIVE_MARGIN_MICROS: u64 = 500_000;
-    const MAX_AGE_BEFORE_IGNORED_MICROS: u64 =
-        Self::STATE_BROADCAST_INTERVAL_MICROS + Self::ALIVE_MARGIN_MICROS;
+    const PROBE_MAX_BACKOFF_MICROS: u64 = 60_000_000;
+    const HEARTBEAT_MAX_MISSED_INTERVALS: u64 = 5;
+    const GRPC_CLIENT_MAX_IDLE_MICROS: u64 = 60_000_000;
+    const STATE_TRANSFER_MAX_ATTEMPTS: u32 = 5;
+    const STATE_TRANSFER_IDLE_TIMEOUT_MICROS: u64 = 30_000_000;
+    const ANTI_ENTROPY_MAX_LEAVES_PER_ROUND: usize = 64;
+    const ANTI_ENTROPY_FETCH_BATCH_SIZE: usize = 256;
+    const PARTITION_HEALING_MAX_WAIT_INTERVALS: u32 = 10;
+    const PARTITION_MAX_LOGGED_CONFLICTS: usize = 32;
+    const MEMBERSHIP_EVENTS_CAPACITY: usize = 256;
+    const REPLICATION_BLOCK_MAX_WAIT: tokio::time::Duration = tokio::time::Duration::from_secs(5);
+    const CACHE_UPDATES_CAPACITY: usize = 4096;
+    const EXPORT_BATCH_SIZE: usize = 1024;
+    const HANDOFF_BATCH_SIZE: usize = 1024;
 
-    /// Return a new instance.
+    /// Return a new instance with default configuration.
     ///
     /// `address_template` should be in the form a `fqdn:port` with the literal
     /// string `ORDINAL` present.
@@ -67,101 +210,544 @@ impl DistributedCache {
         local_node_ordinal: u32,
         cache_item_ttl_micros: u64,
     ) -> Arc<Self> {
-        let now_seconds = crate::time::get_timestamp_micros() / 1_000_000;
-        let local_node_id = (now_seconds & 0xffff_ffff) << 32 | u64::from(local_node_ordinal);
+        Self::new_with_valid_config(
+            address_template,
+            local_node_ordinal,
+            cache_item_ttl_micros,
+            ClachelessConfig::default(),
+        )
+        .await
+    }
+
+    /// Return a new instance or an error if the `config` is invalid.
+    ///
+    /// `address_template` should be in the form a `fqdn:port` with the literal
+    /// string `ORDINAL` present.
+    pub async fn new_with_config(
+        address_template: &str,
+        local_node_ordinal: u32,
+        cache_item_ttl_micros: u64,
+        config: ClachelessConfig,
+    ) -> Result<Arc<Self>, ClachelessError> {
+        let config = config.validate()?;
+        Ok(Self::new_with_valid_config(
+            address_template,
+            local_node_ordinal,
+            cache_item_ttl_micros,
+            config,
+        )
+        .await)
+    }
+
+    async fn new_with_valid_config(
+        address_template: &str,
+        local_node_ordinal: u32,
+        cache_item_ttl_micros: u64,
+        config: ClachelessConfig,
+    ) -> Arc<Self> {
+        let local_node_id = Self::new_node_id(local_node_ordinal);
+        let (expired_sender, expired_receiver) = match config.backing_store() {
+            Some(_backing_store) => {
+                let (sender, receiver) = mpsc::unbounded_channel();
+                (Some(sender), Some(receiver))
+            }
+            None => (None, None),
+        };
         Arc::new(Self {
-            address_template: address_template.to_string(),
             local_node_ordinal,
             cache_item_ttl_micros,
             local_node_id,
+            config: config.clone(),
             known_node_ordinals_with_last_seen: SkipMap::default(),
-            local_cache: LocalCache::new().await,
-            cluster_view: ClusterStateView::new(local_node_id),
+            membership_lock: std::sync::Mutex::default(),
+            observer_node_ordinals_with_last_seen: SkipMap::default(),
+            node_prober: NodeProber::new(
+                config.state_broadcast_interval_micros(),
+                Self::PROBE_MAX_BACKOFF_MICROS,
+            ),
+            peer_transport: match config.in_memory_network() {
+                Some(network) => Arc::new(InMemoryTransport::new(network, local_node_ordinal)),
+                None => Arc::new(GrpcClientPool::new(
+                    address_template,
+                    config.clock(),
+                    GrpcClient::max_message_bytes(config.max_document_size()),
+                )),
+            },
+            peer_replicators: SkipMap::default(),
+            view_pushes_in_flight: SkipMap::default(),
+            peer_protocols: Arc::default(),
+            clock_skew: ClockSkew::new(config.max_clock_skew_micros()),
+            local_clock_skewed: AtomicBool::default(),
+            broadcast_lock: Mutex::default(),
+            state_transfers: StateTransfers::new(config.clock()),
+            anti_entropy: AntiEntropy::new(config.anti_entropy_cpu_budget_percent()),
+            partition_tracker: PartitionTracker::default(),
+            membership_events: broadcast::Sender::new(Self::MEMBERSHIP_EVENTS_CAPACITY),
+            cache_updates: broadcast::Sender::new(Self::CACHE_UPDATES_CAPACITY),
+            snapshot_lock: Mutex::default(),
+            backing_store_writer: config
+                .backing_store()
+                .filter(|_| config.backing_store_write_policy() == Some(WritePolicy::WriteBehind))
+                .map(|backing_store| BackingStoreWriter::new(Arc::clone(backing_store))),
+            node_health: NodeHealth::new(config.initial_sync_timeout_micros()),
+            leaving: AtomicBool::default(),
+            local_cache: LocalCache::new(
+                config.purge_interval_micros(),
+                config.clock(),
+                expired_sender,
+            )
+            .await,
+            cluster_view: ClusterStateView::new(local_node_id, config.clock()),
         })
-        .init()
+        .init(expired_receiver)
         .await
     }
 
-    async fn init(self: Arc<Self>) -> Arc<Self> {
+    /// Return a node id with a random incarnation in the upper 32 bits and the
+    /// node ordinal in the lower 32 bits.
+    ///
+    /// Panics if the PRNG does not return the requested number of bytes, since
+    /// a constant incarnation would collide with earlier runs of the node.
+    fn new_node_id(local_node_ordinal: u32) -> u64 {
+        let random_bytes = Tyst::instance().prng_get_random_bytes(None, 4);
+        let incarnation = u32::from_be_bytes(
+            random_bytes
+                .try_into()
+                .expect("PRNG returned the wrong number of bytes for the node incarnation."),
+        );
+        u64::from(incarnation) << 32 | u64::from(local_node_ordinal)
+    }
+
+    async fn init(
+        self: Arc<Self>,
+        expired_receiver: Option<mpsc::UnboundedReceiver<CacheEntryAndKey>>,
+    ) -> Arc<Self> {
+        self.restore_snapshot().await;
         let self_clone = Arc::clone(&self);
         tokio::spawn(async move { self_clone.remove_expired_other_nodes().await });
+        if let Some(expired_receiver) = expired_receiver {
+            let self_clone = Arc::clone(&self);
+            tokio::spawn(async move {
+                self_clone
+                    .delete_expired_from_backing_store(expired_receiver)
+                    .await
+            });
+        }
         self
     }
 
-    /// Start publishing local state to other nodes and start local gRPC server.
+    /// Start publishing local state to other nodes and start serving requests
+    /// from other nodes.
     ///
     /// This function will not return for as long as the server is running.
     pub async fn run(self: &Arc<Self>) -> Result<(), ClachelessError> {
+        self.node_health
+            .on_run_started(self.config.clock().now_micros());
         let self_clone = Arc::clone(self);
         tokio::spawn(async move { self_clone.notify_other_nodes().await });
-        let port = self.get_address_template_port();
-        grpc_server::run_grpc_server(self, port).await
-    }
-
-    fn get_address_for_node_ordinal(&self, node_ordinal: u32) -> String {
-        self.address_template
-            .replacen("ORDINAL", &node_ordinal.to_string(), 1)
-    }
-
-    /// Extract gRPC address port from template or default to 9000.
-    fn get_address_template_port(&self) -> u16 {
-        self.address_template
-            .match_indices(':')
-            .next_back()
-            .map(|(last_dash_index, _)| self.address_template.split_at(last_dash_index + 1).1)
-            .and_then(|ordinal_string| {
-                ordinal_string
-                    .parse::<u16>()
-                    .inspect_err(|e| log::debug!("Failed to parse ordinal '{ordinal_string}': {e}"))
-                    .ok()
-            })
-            .unwrap_or(9000)
+        if self.config.anti_entropy_interval_micros() > 0 {
+            let self_clone = Arc::clone(self);
+            tokio::spawn(async move { self_clone.run_anti_entropy().await });
+        }
+        if self.config.snapshot_path().is_some() && self.config.snapshot_interval_micros() > 0 {
+            let self_clone = Arc::clone(self);
+            tokio::spawn(async move { self_clone.run_snapshots().await });
+        }
+        self.peer_transport.clone().serve(self).await
     }
 
     /// Periodically notify all other nodes about this node's ClusterStateView.
     async fn notify_other_nodes(self: &Arc<Self>) {
         loop {
-            for node_ordinal in 0..=self.get_highest_known_node_ordinal() {
-                if node_ordinal != self.local_node_ordinal {
-                    let address = self.get_address_for_node_ordinal(node_ordinal);
+            self.node_health.on_heartbeat(
+                "notify_other_nodes",
+                self.config.clock().now_micros(),
+                self.heartbeat_max_interval_micros(),
+            );
+            let node_ordinals = if self.is_observer() {
+                // Unknown members are found by probing, not by pushing to every
+                // ordinal below the observer's own
+                self.members()
+            } else {
+                // Observers catch up through the pushed views
+                let highest_node_ordinal = self.get_highest_known_node_ordinal();
+                let observer_node_ordinals = self
+                    .observer_node_ordinals_with_last_seen
+                    .iter()
+                    .map(|entry| *entry.key())
+                    .filter(|node_ordinal| *node_ordinal > highest_node_ordinal);
+                (0..=highest_node_ordinal)
+                    .chain(observer_node_ordinals)
+                    .collect::<Vec<_>>()
+            };
+            for node_ordinal in node_ordinals {
+                if node_ordinal != self.local_node_ordinal && !self.is_leaving() {
+                    if self.view_pushes_in_flight.contains_key(&node_ordinal) {
+                        // Don't pile up pushes to a node that doesn't respond
+                        continue;
+                    }
+                    self.view_pushes_in_flight.insert(node_ordinal, ());
                     if log::log_enabled!(log::Level::Trace) {
-                        log::trace!("Pushing view to '{address}'.");
+                        log::trace!(
+                            "Pushing view to '{}'.",
+                            self.peer_transport.address_for_node_ordinal(node_ordinal)
+                        );
                     }
                     let self_clone = Arc::clone(self);
-                    let _res = tokio::spawn(async move {
-                        let grpc_client = GrpcClient::new(&address).await?;
-                        grpc_client
-                            .push_state_view(
-                                self_clone.local_node_ordinal,
-                                self_clone.cluster_view.as_map().await,
-                            )
-                            .await
-                            .inspect_err(|e| log::debug!("Push failed: {e}"))
+                    tokio::spawn(async move {
+                        self_clone.push_state_view(node_ordinal).await.ok();
+                        self_clone.view_pushes_in_flight.remove(&node_ordinal);
                     });
                 }
             }
+            self.probe_unknown_nodes();
             tokio::time::sleep(tokio::time::Duration::from_micros(
-                Self::STATE_BROADCAST_INTERVAL_MICROS,
+                self.config.state_broadcast_interval_micros(),
             ))
             .await;
         }
     }
 
+    /// Push the local cluster view to a single node.
+    async fn push_state_view(&self, node_ordinal: u32) -> Result<(), ClachelessError> {
+        let cluster_view = self.cluster_view.as_map().await;
+        let sent_micros = self.config.clock().now_micros();
+        self.peer_transport
+            .client(node_ordinal)?
+            .push_state_view(
+                self.local_node_ordinal,
+                self.local_node_id,
+                sent_micros,
+                self.is_observer(),
+                cluster_view,
+            )
+            .await
+            .map(|response| {
+                self.peer_protocols
+                    .on_advertised(node_ordinal, response.peer_protocol);
+                if response.timestamp_micros > 0 && !self.is_observer_node_ordinal(node_ordinal) {
+                    let changed = self.clock_skew.on_round_trip(
+                        node_ordinal,
+                        sent_micros,
+                        response.timestamp_micros,
+                        self.config.clock().now_micros(),
+                    );
+                    self.on_clock_estimate(node_ordinal, changed);
+                }
+                // The view reveals any updates that were dropped from the queue
+                if let Some(entry) = self.peer_replicators.get(&node_ordinal) {
+                    entry.value().on_state_view_pushed();
+                }
+            })
+            .inspect_err(|e| {
+                log::debug!("Push failed: {e}");
+                self.peer_transport
+                    .evict_on_connection_error(node_ordinal, e);
+            })
+    }
+
+    /// Send `Join` requests to node ordinals above the highest known one and
+    /// to nodes reported by other nodes, unless they are backing off from a
+    /// previous failed probe.
+    ///
+    /// Observers use ordinals outside of the members' range, so they probe
+    /// from the lowest ordinal instead.
+    fn probe_unknown_nodes(self: &Arc<Self>) {
+        let (probe_window_start, highest_node_ordinal) = if self.is_observer() {
+            (0, self.members().last().copied().unwrap_or_default())
+        } else {
+            let highest_node_ordinal = self
+                .get_highest_known_node_ordinal()
+                .max(self.local_node_ordinal);
+            (highest_node_ordinal + 1, highest_node_ordinal)
+        };
+        let probe_window_end = highest_node_ordinal.saturating_add(self.config.probe_window());
+        let now_micros = self.config.clock().now_micros();
+        let candidates = self
+            .node_prober
+            .take_candidates()
+            .into_iter()
+            .filter(|node_ordinal| *node_ordinal > probe_window_end);
+        for node_ordinal in (probe_window_start..=probe_window_end).chain(candidates) {
+            if node_ordinal == self.local_node_ordinal
+                || self.is_known_node_ordinal(node_ordinal, now_micros)
+                || self.is_observer_node_ordinal(node_ordinal)
+                || !self.node_prober.is_due(node_ordinal, now_micros)
+            {
+                continue;
+            }
+            let self_clone = Arc::clone(self);
+            tokio::spawn(async move { self_clone.probe_node(node_ordinal).await });
+        }
+    }
+
+    /// Announce this node to the remote node and learn about other members.
+    async fn probe_node(self: &Arc<Self>, node_ordinal: u32) {
+        if log::log_enabled!(log::Level::Trace) {
+            log::trace!(
+                "Probing '{}'.",
+                self.peer_transport.address_for_node_ordinal(node_ordinal)
+            );
+        }
+        let res = match self.peer_transport.client(node_ordinal) {
+            Ok(grpc_client) => {
+                grpc_client
+                    .join(self.local_node_ordinal, self.is_observer())
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok(join_response) => {
+                self.node_prober.on_success(node_ordinal);
+                if join_response.observer {
+                    self.on_observer_seen(node_ordinal);
+                } else {
+                    self.on_node_seen(node_ordinal);
+                }
+                let now_micros = self.config.clock().now_micros();
+                join_response
+                    .member_node_ordinals
+                    .into_iter()
+                    .filter(|member_node_ordinal| {
+                        *member_node_ordinal != self.local_node_ordinal
+                            && !self.is_known_node_ordinal(*member_node_ordinal, now_micros)
+                    })
+                    .for_each(|member_node_ordinal| {
+                        self.node_prober.add_candidate(member_node_ordinal)
+                    });
+            }
+            Err(e) => {
+                if log::log_enabled!(log::Level::Trace) {
+                    log::trace!("Probe failed: {e}");
+                }
+                self.peer_transport
+                    .evict_on_connection_error(node_ordinal, &e);
+                self.node_prober
+                    .on_failure(node_ordinal, self.config.clock().now_micros());
+            }
+        }
+    }
+
+    /// Invoked when a remote node announced itself to this node.
+    ///
+    /// Returns the ordinals of all members known to be alive, including this
+    /// one unless it is an observer.
+    fn on_join(&self, sender_ordinal: u32, sender_observer: bool) -> Vec<u32> {
+        if sender_observer {
+            self.on_observer_seen(sender_ordinal);
+        } else {
+            self.on_node_seen(sender_ordinal);
+        }
+        let last_seen_threshold = self
+            .config
+            .clock()
+            .now_micros()
+            .saturating_sub(self.max_age_before_ignored_micros());
+        let mut member_node_ordinals = self
+            .known_node_ordinals_with_last_seen
+            .iter()
+            .filter(|entry| *entry.value() > last_seen_threshold)
+            .map(|entry| *entry.key())
+            .collect::<Vec<_>>();
+        if !self.is_observer() {
+            member_node_ordinals.push(self.local_node_ordinal);
+        }
+        member_node_ordinals
+    }
+
+    /// Invoked when a remote node announced that it is leaving the cluster.
+    fn on_leave(&self, sender_ordinal: u32) {
+        if self
+            .observer_node_ordinals_with_last_seen
+            .remove(&sender_ordinal)
+            .is_some()
+        {
+            self.peer_transport.evict(sender_ordinal);
+            self.peer_protocols.remove(sender_ordinal);
+            log::debug!("Observer node with ordinal '{sender_ordinal}' left.");
+            return;
+        }
+        let was_member = {
+            let _membership_guard = self.membership_lock.lock().unwrap();
+            self.known_node_ordinals_with_last_seen
+                .remove(&sender_ordinal)
+                .is_some()
+        };
+        self.peer_replicators.remove(&sender_ordinal);
+        self.peer_transport.evict(sender_ordinal);
+        self.peer_protocols.remove(sender_ordinal);
+        self.clock_skew.remove(sender_ordinal);
+        // Don't rediscover the node before it is replaced
+        self.node_prober
+            .on_failure(sender_ordinal, self.config.clock().now_micros());
+        if was_member {
+            self.publish_membership_event(MembershipEvent::Left {
+                node_ordinal: sender_ordinal,
+            });
+        }
+        log::info!("Distributed cache node with ordinal '{sender_ordinal}' left the cluster.");
+    }
+
+    /// Record that a remote observer node has been heard from.
+    ///
+    /// Observers only keep track of the members.
+    fn on_observer_seen(&self, node_ordinal: u32) {
+        if self.is_observer() {
+            return;
+        }
+        let is_new = !self.is_observer_node_ordinal(node_ordinal);
+        self.observer_node_ordinals_with_last_seen
+            .insert(node_ordinal, self.config.clock().now_micros());
+        if is_new {
+            log::info!("Observer node with ordinal '{node_ordinal}' detected.");
+        }
+    }
+
+    /// Return `true` if the local node is a read-only observer.
+    fn is_observer(&self) -> bool {
+        self.config.node_role() == NodeRole::Observer
+    }
+
+    /// Return `true` if the remote node is a known observer.
+    fn is_observer_node_ordinal(&self, node_ordinal: u32) -> bool {
+        self.observer_node_ordinals_with_last_seen
+            .contains_key(&node_ordinal)
+    }
+
+    /// Return `true` if the node ordinal has checked in recently.
+    fn is_known_node_ordinal(&self, node_ordinal: u32, now_micros: u64) -> bool {
+        self.known_node_ordinals_with_last_seen
+            .get(&node_ordinal)
+            .as_ref()
+            .map(Entry::value)
+            .is_some_and(|last_seen_micros| {
+                *last_seen_micros >= now_micros.saturating_sub(self.max_age_before_ignored_micros())
+            })
+    }
+
+    /// Return the time since a node was last heard from before it is
+    /// considered lost.
+    fn max_age_before_ignored_micros(&self) -> u64 {
+        self.config
+            .state_broadcast_interval_micros()
+            .saturating_add(self.config.alive_margin_micros())
+    }
+
+    /// Return the longest time a background loop may go without a heartbeat
+    /// before the node is considered unhealthy.
+    fn heartbeat_max_interval_micros(&self) -> u64 {
+        Self::HEARTBEAT_MAX_MISSED_INTERVALS * self.config.state_broadcast_interval_micros()
+    }
+
+    /// Return the delays between attempts to resume a state transfer.
+    fn state_transfer_retry_backoff(&self) -> RetryBackoff {
+        RetryBackoff::new(
+            tokio::time::Duration::from_micros(self.config.state_broadcast_interval_micros()),
+            tokio::time::Duration::from_micros(Self::PROBE_MAX_BACKOFF_MICROS),
+        )
+    }
+
+    /// Record that a remote node has been heard from.
+    ///
+    /// Returns `true` if the node was previously unknown.
+    fn on_node_seen(&self, node_ordinal: u32) -> bool {
+        let now_micros = self.config.clock().now_micros();
+        // Only a node that is absent from the membership is new, so that each
+        // join is paired with exactly one removal
+        let is_new = {
+            let _membership_guard = self.membership_lock.lock().unwrap();
+            let is_new = !self
+                .known_node_ordinals_with_last_seen
+                .contains_key(&node_ordinal);
+            self.known_node_ordinals_with_last_seen
+                .insert(node_ordinal, now_micros);
+            is_new
+        };
+        if is_new {
+            self.node_prober.on_success(node_ordinal);
+            self.partition_tracker.on_membership_changed();
+            self.publish_membership_event(MembershipEvent::Joined { node_ordinal });
+            log::info!("New distributed cache node with ordinal '{node_ordinal}' detected.");
+        }
+        is_new
+    }
+
     /// Periodically check if other nodes has disappeared.
     async fn remove_expired_other_nodes(self: &Arc<Self>) {
         loop {
-            let now_micros = crate::time::get_timestamp_micros();
+            let now_micros = self.config.clock().now_micros();
+            self.node_health.on_heartbeat(
+                "remove_expired_other_nodes",
+                now_micros,
+                self.heartbeat_max_interval_micros(),
+            );
             for entry in self.known_node_ordinals_with_last_seen.iter() {
-                if *entry.value() < now_micros - Self::MAX_AGE_BEFORE_IGNORED_MICROS {
-                    entry.remove();
+                // The entry is not removed if the node was seen again meanwhile
+                let removed = {
+                    let _membership_guard = self.membership_lock.lock().unwrap();
+                    *entry.value() < now_micros.saturating_sub(self.max_age_before_ignored_micros())
+                        && entry.remove()
+                };
+                if removed {
+                    self.partition_tracker.on_membership_changed();
+                    if let Some(node_id) = self.cluster_view.announced_node_id(*entry.key()) {
+                        self.partition_tracker.on_peer_lost(
+                            *entry.key(),
+                            node_id,
+                            *entry.value(),
+                            self.cluster_view.as_map().await,
+                        );
+                    }
+                    self.get_peer_replicator(*entry.key())
+                        .value()
+                        .on_peer_lost(now_micros);
+                    self.peer_transport.evict(*entry.key());
+                    self.peer_protocols.remove(*entry.key());
+                    self.clock_skew.remove(*entry.key());
+                    self.publish_membership_event(MembershipEvent::Left {
+                        node_ordinal: *entry.key(),
+                    });
                     log::info!(
                         "Lost connectivity to distributed cache node with ordinal '{}'.",
                         entry.key()
                     );
                 }
             }
+            for entry in self.observer_node_ordinals_with_last_seen.iter() {
+                if *entry.value() < now_micros.saturating_sub(self.max_age_before_ignored_micros())
+                {
+                    entry.remove();
+                    self.peer_transport.evict(*entry.key());
+                    self.peer_protocols.remove(*entry.key());
+                    log::debug!(
+                        "Lost connectivity to observer node with ordinal '{}'.",
+                        entry.key()
+                    );
+                }
+            }
+            // Stop keeping updates for nodes that seem to be gone for good
+            for entry in self.peer_replicators.iter() {
+                if entry
+                    .value()
+                    .peer_down_since_micros()
+                    .is_some_and(|since_micros| {
+                        since_micros
+                            < now_micros.saturating_sub(self.config.hinted_handoff_max_age_micros())
+                    })
+                {
+                    log::info!(
+                        "Discarding updates kept for node ordinal {} that has been down too long.",
+                        entry.key()
+                    );
+                    self.partition_tracker.discard(*entry.key());
+                    entry.remove();
+                }
+            }
+            self.peer_transport
+                .evict_idle(now_micros.saturating_sub(Self::GRPC_CLIENT_MAX_IDLE_MICROS));
             tokio::time::sleep(tokio::time::Duration::from_micros(
-                Self::STATE_BROADCAST_INTERVAL_MICROS,
+                self.config.state_broadcast_interval_micros(),
             ))
             .await;
         }
@@ -171,50 +757,930 @@ impl DistributedCache {
     ///
     /// If the remote node has more up to date data than this node, a state
     /// transfer will be requested from the remote node for the delta.
-    async fn on_state_view(&self, sender_ordinal: u32, view: HashMap<u64, u64>) {
+    async fn on_state_view(
+        self: &Arc<Self>,
+        sender_ordinal: u32,
+        sender_node_id: u64,
+        sender_timestamp_micros: u64,
+        sender_observer: bool,
+        view: HashMap<u64, u64>,
+    ) {
         log::trace!("Got state update: {view:?}");
-        let now_micros = crate::time::get_timestamp_micros();
-        let is_new = self
-            .known_node_ordinals_with_last_seen
-            .get(&sender_ordinal)
-            .as_ref()
-            .map(Entry::value)
-            .filter(|last_seen_micros| {
-                **last_seen_micros >= now_micros - Self::MAX_AGE_BEFORE_IGNORED_MICROS
-            })
-            .is_none();
-        self.known_node_ordinals_with_last_seen
-            .insert(sender_ordinal, now_micros);
-        let data_origin_id_and_baseline = self
-            .cluster_view
-            .get_out_of_sync_node_id_and_baselines(view)
-            .await;
-        if !data_origin_id_and_baseline.is_empty() {
-            log::debug!(
-                "This node is lagging behind and need a state transfer: {data_origin_id_and_baseline:?}"
+        if self.is_leaving() {
+            return;
+        }
+        if sender_observer {
+            // Observers never have updates that members are missing
+            self.on_observer_seen(sender_ordinal);
+            return;
+        }
+        if sender_node_id == self.local_node_id {
+            log::warn!(
+                "Node ordinal {sender_ordinal} uses the same node id {sender_node_id} as this node. Ignoring its cluster view."
             );
-            let address = self.get_address_for_node_ordinal(sender_ordinal);
-            if let Ok(grpc_client) = GrpcClient::new(&address)
-                .await
-                .inspect_err(|e| log::info!("Failed to connect: {e}"))
+            return;
+        }
+        self.check_identities(sender_ordinal, sender_node_id, &view);
+        if sender_timestamp_micros > 0 {
+            let changed = self.clock_skew.on_one_way(
+                sender_ordinal,
+                sender_timestamp_micros,
+                self.config.clock().now_micros(),
+            );
+            self.on_clock_estimate(sender_ordinal, changed);
+        }
+        self.on_node_seen(sender_ordinal);
+        if let Some(peer_replicator) = self.peer_replicators.get(&sender_ordinal) {
+            peer_replicator.value().on_peer_alive();
+        }
+        // A peer without data proves nothing about what this node is missing
+        if !view.is_empty() && self.is_up_to_date_with(&view).await {
+            self.node_health.on_synchronized(sender_ordinal);
+        }
+        if self.partition_tracker.is_separated(sender_ordinal)
+            && self.partition_tracker.on_peer_view(
+                sender_ordinal,
+                sender_node_id,
+                &view,
+                &self.cluster_view.as_map().await,
+            )
+        {
+            log::warn!(
+                "Node ordinal {sender_ordinal} accepted writes while it was unreachable. Merging the partitioned sides."
+            );
+            let self_clone = Arc::clone(self);
+            let view = view.clone();
+            tokio::spawn(async move { self_clone.heal_partition(sender_ordinal, &view).await });
+        }
+        let data_origin_id_and_ranges = self.cluster_view.get_missing_ranges(view).await;
+        if !data_origin_id_and_ranges.is_empty()
+            && !self
+                .peer_protocols
+                .supports(sender_ordinal, Capability::StreamStateTransfer)
+        {
+            self.request_legacy_state_transfer(sender_ordinal, &data_origin_id_and_ranges)
+                .await;
+        } else if !data_origin_id_and_ranges.is_empty() {
+            if let Some(session) = self
+                .state_transfers
+                .start(sender_ordinal, data_origin_id_and_ranges)
+            {
+                log::debug!(
+                    "This node is missing updates and need a state transfer (session {}): {:?}",
+                    session.session_id(),
+                    session.ranges()
+                );
+                let self_clone = Arc::clone(self);
+                tokio::spawn(async move { self_clone.run_state_transfer(&session).await });
+            } else if log::log_enabled!(log::Level::Trace) {
+                log::trace!("This node is lagging behind, but a state transfer is in progress.");
+            }
+        }
+    }
+
+    /// Warn when the clock of a peer, or the local clock, becomes skewed.
+    fn on_clock_estimate(&self, node_ordinal: u32, changed: Option<PeerClock>) {
+        match changed {
+            Some(peer_clock) if peer_clock.skewed => log::warn!(
+                "Clock of node ordinal {node_ordinal} is {} ms off from the local clock (round trip {} ms), which exceeds the maximum skew of {} ms. Concurrent writes may be resolved in the wrong order.",
+                peer_clock.offset_micros / 1_000,
+                peer_clock.round_trip_micros / 1_000,
+                self.config.max_clock_skew_micros() / 1_000,
+            ),
+            Some(peer_clock) => log::info!(
+                "Clock of node ordinal {node_ordinal} is back within the maximum skew ({} ms off).",
+                peer_clock.offset_micros / 1_000
+            ),
+            None => return,
+        }
+        let local_clock_skewed = self.clock_skew.is_local_clock_skewed();
+        if self
+            .local_clock_skewed
+            .swap(local_clock_skewed, Ordering::Relaxed)
+            != local_clock_skewed
+        {
+            if local_clock_skewed {
+                log::warn!(
+                    "The local clock is skewed compared to the majority of the peers. This node is not ready until its clock is corrected."
+                );
+            } else {
+                log::info!("The local clock agrees with the majority of the peers again.");
+            }
+        }
+    }
+
+    /// Detect node ids that have been reused by new incarnations.
+    ///
+    /// Nodes that predate incarnations don't announce their node id and are
+    /// only checked for reuse of the local node id.
+    fn check_identities(&self, sender_ordinal: u32, sender_node_id: u64, view: &HashMap<u64, u64>) {
+        if sender_node_id != 0 {
+            let announced_seq = view.get(&sender_node_id).copied().unwrap_or(0);
+            match self
+                .cluster_view
+                .on_peer_announced(sender_ordinal, sender_node_id, announced_seq)
             {
+                PeerIdentity::Unchanged => {}
+                PeerIdentity::NewIncarnation { previous_node_id } => log::info!(
+                    "Node ordinal {sender_ordinal} restarted with node id {sender_node_id} (previously {previous_node_id})."
+                ),
+                PeerIdentity::Reused => log::warn!(
+                    "Node ordinal {sender_ordinal} reused node id {sender_node_id} and started its sequence over. The baseline of the node id was reset."
+                ),
+            }
+        }
+        if let Some(local_baseline_seq) = view.get(&self.local_node_id)
+            && self
+                .cluster_view
+                .on_local_baseline_seen(*local_baseline_seq)
+        {
+            log::warn!(
+                "Node id {} was used by an earlier incarnation up to sequence {local_baseline_seq}. Local updates continue after it.",
+                self.local_node_id
+            );
+        }
+    }
+
+    /// Request the remote node to push all entries newer than the first
+    /// missing update of each origin node.
+    ///
+    /// Used with nodes that don't support streamed state transfers. Entries
+    /// are pushed one by one and are accounted for as they arrive.
+    async fn request_legacy_state_transfer(
+        &self,
+        sender_ordinal: u32,
+        data_origin_id_and_ranges: &HashMap<u64, SequenceRanges>,
+    ) {
+        let data_origin_id_and_baseline = data_origin_id_and_ranges
+            .iter()
+            .filter_map(|(origin_node_id, ranges)| {
+                ranges
+                    .iter()
+                    .next()
+                    .map(|range| (*origin_node_id, range.start() - 1))
+            })
+            .collect();
+        log::debug!(
+            "This node is missing updates and requests a legacy state transfer from node ordinal {sender_ordinal}."
+        );
+        match self.peer_transport.client(sender_ordinal) {
+            Ok(grpc_client) => {
                 grpc_client
-                    .request_state_transfer(self.local_node_ordinal, data_origin_id_and_baseline)
+                    .init_state_transfer(self.local_node_ordinal, data_origin_id_and_baseline)
                     .await
-                    .inspect_err(|e| log::info!("Failed to request state transfer: {e}"))
-                    .ok();
             }
+            Err(e) => Err(e),
         }
-        if is_new {
-            log::info!("New distributed cache node with ordinal '{sender_ordinal}' detected.");
+        .inspect_err(|e| {
+            log::info!("Legacy state transfer request failed: {e}");
+            self.peer_transport
+                .evict_on_connection_error(sender_ordinal, e);
+        })
+        .ok();
+    }
+
+    /// Return `true` if the local node has recieved all updates that the
+    /// remote node's view of the cluster contains.
+    async fn is_up_to_date_with(&self, view: &HashMap<u64, u64>) -> bool {
+        let local_view = self.cluster_view.as_map().await;
+        view.iter().all(|(node_id, baseline_seq)| {
+            *node_id == self.local_node_id
+                || local_view.get(node_id).copied().unwrap_or(0) >= *baseline_seq
+        })
+    }
+
+    /// Recieve a state transfer and resume it from the last recieved entry of
+    /// each origin node if the transfer is interrupted.
+    async fn run_state_transfer(&self, session: &StateTransferSession) {
+        self.publish_membership_event(MembershipEvent::SyncStarted {
+            node_ordinal: session.sender_node_ordinal(),
+        });
+        loop {
+            let attempt = session.on_attempt();
+            match self.receive_state_transfer(session).await {
+                Ok(()) => {
+                    self.cluster_view
+                        .on_state_transfer_completed(session.ranges())
+                        .await;
+                    session.finish(StateTransferState::Completed);
+                    self.node_health
+                        .on_synchronized(session.sender_node_ordinal());
+                    self.publish_membership_event(MembershipEvent::SyncCompleted {
+                        node_ordinal: session.sender_node_ordinal(),
+                        succeeded: true,
+                    });
+                    log::debug!(
+                        "State transfer session {} from node ordinal {} completed.",
+                        session.session_id(),
+                        session.sender_node_ordinal()
+                    );
+                    return;
+                }
+                Err(e) if attempt < Self::STATE_TRANSFER_MAX_ATTEMPTS => {
+                    log::info!(
+                        "State transfer session {} attempt {attempt} failed and will be resumed: {e}",
+                        session.session_id()
+                    );
+                    self.peer_transport
+                        .evict_on_connection_error(session.sender_node_ordinal(), &e);
+                    self.state_transfer_retry_backoff().sleep(attempt).await;
+                }
+                Err(e) => {
+                    log::info!(
+                        "State transfer session {} failed after {attempt} attempts: {e}",
+                        session.session_id()
+                    );
+                    session.finish(StateTransferState::Failed);
+                    self.publish_membership_event(MembershipEvent::SyncCompleted {
+                        node_ordinal: session.sender_node_ordinal(),
+                        succeeded: false,
+                    });
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Apply all entries of a streamed state transfer.
+    ///
+    /// Requested entries that the sender no longer has are either expired or
+    /// superseded, so a completed transfer covers all the requested ranges.
+    async fn receive_state_transfer(
+        &self,
+        session: &StateTransferSession,
+    ) -> Result<(), ClachelessError> {
+        let mut stream = self
+            .peer_transport
+            .client(session.sender_node_ordinal())?
+            .stream_state_transfer(
+                session.session_id(),
+                self.local_node_ordinal,
+                session.remaining_ranges(),
+            )
+            .await?;
+        loop {
+            let chunk = tokio::time::timeout(
+                tokio::time::Duration::from_micros(Self::STATE_TRANSFER_IDLE_TIMEOUT_MICROS),
+                stream.next_chunk(),
+            )
+            .await
+            .map_err(|_e| {
+                ClachelessErrorKind::Connection.error_with_msg("State transfer stalled.")
+            })??
+            .ok_or_else(|| {
+                ClachelessErrorKind::Connection
+                    .error_with_msg("State transfer ended before it was completed.")
+            })?;
+            for ur in chunk.entries {
+                let entry = CacheEntryAndKey::from(ur);
+                let (origin_node_id, origin_node_update_seq) =
+                    (entry.ce.origin_node_id, entry.ce.origin_node_update_seq);
+                let bytes = entry.ce.object_bytes.len();
+                self.put_raw_from_remote_origin(entry).await?;
+                session.on_entry_applied(origin_node_id, origin_node_update_seq, bytes);
+            }
+            if chunk.completed {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Periodically compare the cache content with one other node at the time
+    /// and repair entries where the other node has a newer version.
+    async fn run_anti_entropy(&self) {
+        loop {
+            tokio::time::sleep(tokio::time::Duration::from_micros(
+                self.config.anti_entropy_interval_micros(),
+            ))
+            .await;
+            let local_tree = self.anti_entropy.rebuild(&self.local_cache).await;
+            let now_micros = self.config.clock().now_micros();
+            let node_ordinals = self
+                .known_node_ordinals_with_last_seen
+                .iter()
+                .map(|entry| *entry.key())
+                .filter(|node_ordinal| {
+                    self.is_known_node_ordinal(*node_ordinal, now_micros)
+                        && self
+                            .peer_protocols
+                            .supports(*node_ordinal, Capability::AntiEntropy)
+                })
+                .collect::<Vec<_>>();
+            let Some(node_ordinal) = self.anti_entropy.next_peer(&node_ordinals) else {
+                continue;
+            };
+            match self
+                .anti_entropy_round(
+                    node_ordinal,
+                    &local_tree,
+                    Self::ANTI_ENTROPY_MAX_LEAVES_PER_ROUND,
+                )
+                .await
+            {
+                Ok(0) => {
+                    if log::log_enabled!(log::Level::Trace) {
+                        log::trace!("Cache content is in sync with node ordinal {node_ordinal}.");
+                    }
+                }
+                Ok(repaired) => log::info!(
+                    "Anti-entropy repaired {repaired} entries from node ordinal {node_ordinal}."
+                ),
+                Err(e) => {
+                    log::debug!("Anti-entropy with node ordinal {node_ordinal} failed: {e}");
+                    self.peer_transport
+                        .evict_on_connection_error(node_ordinal, &e);
+                }
+            }
+        }
+    }
+
+    /// Descend the hash trees of both nodes where they differ and pull the
+    /// entries of at most `max_leaves` differing leaves where the remote has a
+    /// newer version.
+    ///
+    /// Returns the number of repaired entries. Entries where the local version
+    /// is newer are repaired when the remote compares with this node.
+    async fn anti_entropy_round(
+        &self,
+        node_ordinal: u32,
+        local_tree: &HashTree,
+        max_leaves: usize,
+    ) -> Result<usize, ClachelessError> {
+        let grpc_client = self.peer_transport.client(node_ordinal)?;
+        let mut indexes = vec![0];
+        for level in 0..=HashTree::DEPTH {
+            if level > 0 {
+                indexes = HashTree::children(&indexes);
+            }
+            let remote_digests = grpc_client
+                .anti_entropy_digests(level, indexes.clone())
+                .await?;
+            if remote_digests.len() != indexes.len() {
+                // The remote has not built a hash tree yet
+                return Ok(0);
+            }
+            let local_digests = local_tree.digests(level, &indexes);
+            indexes = indexes
+                .into_iter()
+                .zip(local_digests.into_iter().zip(remote_digests))
+                .filter_map(|(index, (local, remote))| (local != remote).then_some(index))
+                .collect();
+            if indexes.is_empty() {
+                return Ok(0);
+            }
+        }
+        indexes.truncate(max_leaves);
+        let mut repaired = 0;
+        for indexes_batch in indexes.chunks(Self::ANTI_ENTROPY_MAX_LEAVES_PER_ROUND) {
+            let keys = grpc_client
+                .anti_entropy_versions(indexes_batch.to_vec())
+                .await?
+                .into_iter()
+                .filter(|version| {
+                    let remote_version = (
+                        version.this_update_micros,
+                        version.origin_node_id,
+                        version.origin_node_update_seq,
+                    );
+                    self.local_cache.get_entry(&version.key).is_none_or(|ce| {
+                        self.partition_tracker.on_versions_compared(
+                            &version.key,
+                            ce.version(),
+                            remote_version,
+                        );
+                        ce.version() < remote_version
+                    })
+                })
+                .map(|version| version.key)
+                .collect::<Vec<_>>();
+            for mut keys_batch in keys.chunks(Self::ANTI_ENTROPY_FETCH_BATCH_SIZE) {
+                while !keys_batch.is_empty() {
+                    let reply = grpc_client.fetch_entries(keys_batch.to_vec()).await?;
+                    // The remote only handles as many keys as fit in the reply
+                    let handled_count = match usize::try_from(reply.handled_count) {
+                        Ok(0) | Err(_) => keys_batch.len(),
+                        Ok(handled_count) => handled_count.min(keys_batch.len()),
+                    };
+                    keys_batch = &keys_batch[handled_count..];
+                    for ur in reply.entries {
+                        self.put_raw_from_remote_origin(CacheEntryAndKey::from(ur))
+                            .await?;
+                        repaired += 1;
+                    }
+                }
+            }
+        }
+        Ok(repaired)
+    }
+
+    /// Merge the two sides of a partition with a remote node and report the
+    /// keys that were written on both sides.
+    ///
+    /// The writes of the remote side arrive through the regular state
+    /// transfer. A full anti-entropy comparison then catches what the state
+    /// transfer can't tell, like keys where the local write won.
+    async fn heal_partition(&self, node_ordinal: u32, view: &HashMap<u64, u64>) {
+        for _ in 0..Self::PARTITION_HEALING_MAX_WAIT_INTERVALS {
+            if self.is_up_to_date_with(view).await {
+                break;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_micros(
+                self.config.state_broadcast_interval_micros(),
+            ))
+            .await;
+        }
+        if self
+            .peer_protocols
+            .supports(node_ordinal, Capability::AntiEntropy)
+        {
+            let local_tree = self.anti_entropy.rebuild(&self.local_cache).await;
+            match self
+                .anti_entropy_round(node_ordinal, &local_tree, usize::MAX)
+                .await
+            {
+                Ok(repaired) => log::debug!(
+                    "Anti-entropy repaired {repaired} entries from partitioned node ordinal {node_ordinal}."
+                ),
+                Err(e) => log::info!(
+                    "Anti-entropy with partitioned node ordinal {node_ordinal} failed. Conflicts may be under-reported: {e}"
+                ),
+            }
+        }
+        let Some(report) = self
+            .partition_tracker
+            .finish_healing(node_ordinal, self.config.clock().now_micros())
+        else {
+            return;
+        };
+        log::warn!(
+            "Partition with node ordinal {node_ordinal} has healed (membership epoch {} to {}). {} keys were written on both sides.",
+            report.split_epoch(),
+            report.healed_epoch(),
+            report.conflict_count(),
+        );
+        for conflict in report
+            .conflicts()
+            .iter()
+            .take(Self::PARTITION_MAX_LOGGED_CONFLICTS)
+        {
+            log::info!(
+                "Key '{}' was written on both sides of the partition: {:?} (local {} on node id {}, remote {} on node id {}).",
+                conflict.key(),
+                conflict.resolution(),
+                conflict.local_update_micros(),
+                conflict.local_origin_node_id(),
+                conflict.remote_update_micros(),
+                conflict.remote_origin_node_id(),
+            );
+        }
+        if let Some(callback) = self.config.partition_healed_callback() {
+            callback(&report);
+        }
+    }
+
+    /// Periodically write a snapshot of the local cache.
+    async fn run_snapshots(&self) {
+        loop {
+            tokio::time::sleep(tokio::time::Duration::from_micros(
+                self.config.snapshot_interval_micros(),
+            ))
+            .await;
+            self.write_snapshot()
+                .await
+                .inspect_err(|e| log::warn!("Failed to write snapshot: {e}"))
+                .ok();
+        }
+    }
+
+    /// Write a snapshot of the local cache and the cluster state baselines if
+    /// snapshots are enabled.
+    pub async fn write_snapshot(&self) -> Result<(), ClachelessError> {
+        let Some(snapshot_path) = self.config.snapshot_path() else {
+            return Ok(());
+        };
+        let _snapshot_guard = self.snapshot_lock.lock().await;
+        // Baselines must be captured before the entries are collected
+        let data_origin_id_and_baseline = self.cluster_view.as_map().await;
+        let snapshot = Snapshot {
+            created_micros: self.config.clock().now_micros(),
+            data_origin_id_and_baseline,
+            entries: self.local_cache.entries_after(None, usize::MAX),
+        };
+        let count = snapshot.entries.len();
+        snapshot.write_to_file(snapshot_path).await?;
+        log::info!(
+            "Wrote snapshot of {count} entries to '{}'.",
+            snapshot_path.display()
+        );
+        Ok(())
+    }
+
+    /// Load the snapshot (if enabled and present) into the local cache.
+    async fn restore_snapshot(&self) {
+        let Some(snapshot_path) = self.config.snapshot_path() else {
+            return;
+        };
+        let snapshot =
+            match Snapshot::read_from_file(snapshot_path, self.config.clock().now_micros()).await {
+                Ok(Some(snapshot)) => snapshot,
+                Ok(None) => {
+                    log::info!("No snapshot found at '{}'.", snapshot_path.display());
+                    return;
+                }
+                Err(e) => {
+                    log::warn!("Ignoring snapshot at '{}': {e}", snapshot_path.display());
+                    return;
+                }
+            };
+        let count = snapshot.entries.len();
+        for entry in snapshot.entries {
+            self.local_cache.put_entry(entry.key, entry.ce).ok();
+        }
+        let restored_ranges = snapshot
+            .data_origin_id_and_baseline
+            .into_iter()
+            .filter(|(_node_id, baseline)| *baseline > 0)
+            .map(|(node_id, baseline)| (node_id, SequenceRanges::from_iter([1..=baseline])))
+            .collect::<HashMap<_, _>>();
+        self.cluster_view
+            .on_state_transfer_completed(&restored_ranges)
+            .await;
+        log::info!(
+            "Restored {count} entries from snapshot at '{}'.",
+            snapshot_path.display()
+        );
+    }
+
+    /// Return the progress of running and recently finished state transfers
+    /// to this node.
+    pub fn state_transfers(&self) -> Vec<StateTransferProgress> {
+        self.state_transfers.progress()
+    }
+
+    /// Subscribe to changes of the cluster membership.
+    ///
+    /// Only events that happen after the call are recieved. Subscribe before
+    /// taking a snapshot with [Self::members] to not miss any changes. A
+    /// receiver that falls too far behind gets
+    /// [RecvError::Lagged](broadcast::error::RecvError::Lagged) and should take
+    /// a new snapshot.
+    pub fn membership_events(&self) -> broadcast::Receiver<MembershipEvent> {
+        self.membership_events.subscribe()
+    }
+
+    /// Return the ordinals of the local node and all remote nodes that are
+    /// known to be alive, in ascending order. Observers are not members.
+    pub fn members(&self) -> Vec<u32> {
+        let now_micros = self.config.clock().now_micros();
+        let mut members = self
+            .known_node_ordinals_with_last_seen
+            .iter()
+            .map(|entry| *entry.key())
+            .filter(|node_ordinal| self.is_known_node_ordinal(*node_ordinal, now_micros))
+            .chain((!self.is_observer()).then_some(self.local_node_ordinal))
+            .collect::<Vec<_>>();
+        members.sort_unstable();
+        members
+    }
+
+    fn publish_membership_event(&self, membership_event: MembershipEvent) {
+        // Sending only fails when there are no subscribers
+        self.membership_events.send(membership_event).ok();
+    }
+
+    /// Subscribe to entries written on this node.
+    ///
+    /// Both writes recieved by this node and updates replicated from other
+    /// nodes are published, but only if they replaced the local entry. Entries
+    /// loaded from the backing store on a cache miss are not. A receiver that
+    /// falls too far behind gets
+    /// [RecvError::Lagged](broadcast::error::RecvError::Lagged) and has missed
+    /// updates.
+    pub fn cache_updates(&self) -> broadcast::Receiver<CacheUpdate> {
+        self.cache_updates.subscribe()
+    }
+
+    fn publish_cache_update(&self, entry: &CacheEntryAndKey) {
+        if self.cache_updates.receiver_count() > 0 {
+            self.cache_updates.send(Self::cache_update_from(entry)).ok();
+        }
+    }
+
+    fn cache_update_from(entry: &CacheEntryAndKey) -> CacheUpdate {
+        CacheUpdate {
+            bridged: entry.ce.bridged,
+            ..CacheUpdate::new(
+                entry.key.to_owned(),
+                Arc::clone(&entry.ce.object_bytes),
+                entry.ce.this_update_micros,
+                entry.ce.expires_micros,
+            )
+        }
+    }
+
+    /// Return the non-expired entry of the key as a [CacheUpdate].
+    pub(crate) fn latest_update(&self, cache_key: &str) -> Option<CacheUpdate> {
+        self.local_cache.get_entry(cache_key).map(|ce| {
+            Self::cache_update_from(&CacheEntryAndKey {
+                key: cache_key.to_owned(),
+                ce,
+            })
+        })
+    }
+
+    /// Return up to `limit` non-expired entries with keys starting with
+    /// `prefix` as [CacheUpdate]s, ordered by key and starting after
+    /// `after_key`.
+    pub(crate) fn latest_updates_with_prefix(
+        &self,
+        prefix: &str,
+        after_key: Option<&str>,
+        limit: usize,
+    ) -> Vec<CacheUpdate> {
+        self.local_cache
+            .entries_with_prefix_after(prefix, after_key, limit)
+            .iter()
+            .map(Self::cache_update_from)
+            .collect()
+    }
+
+    /// Write an entry recieved from another cluster that the conflict policy
+    /// has accepted.
+    ///
+    /// The entry is re-originated from this node like an imported entry: an
+    /// origin node id from another cluster would leave gaps in the update
+    /// sequences that no node here can fill. It keeps its expiration, but the
+    /// update timestamp is moved past the local entry if needed, so that the
+    /// accepted entry replaces it.
+    ///
+    /// The entry is marked as bridged on all nodes, so that no bridge sends it
+    /// back to another cluster.
+    pub(crate) async fn put_bridged(&self, update: &CacheUpdate) -> Result<(), ClachelessError> {
+        self.ensure_accepting_writes()?;
+        if update.expires_micros <= self.config.clock().now_micros() {
+            return Ok(());
+        }
+        let Some(entry) = self
+            .put_originated(
+                &update.key,
+                Arc::clone(&update.object_bytes),
+                update.this_update_micros,
+                update.expires_micros,
+                Overwrite::Always,
+                true,
+            )
+            .await?
+        else {
+            return Ok(());
+        };
+        self.publish_cache_update(&entry);
+        self.write_to_backing_store(entry).await
+    }
+
+    /// Return the reports of recently healed partitions, oldest first.
+    pub fn partition_reports(&self) -> Vec<PartitionReport> {
+        self.partition_tracker.reports()
+    }
+
+    /// Return `true` if the node accepts connections from other nodes.
+    pub fn is_started(&self) -> bool {
+        self.node_health.is_started()
+    }
+
+    /// Return `true` if the node is started and has synchronized with the
+    /// other nodes (or timed out waiting for them).
+    ///
+    /// See [ClachelessConfig::with_initial_sync_timeout_micros].
+    ///
+    /// A node whose clock is skewed compared to the majority of (at least
+    /// two) peers is not ready. See [ClachelessConfig::with_max_clock_skew_micros].
+    /// Neither is a node that is leaving the cluster.
+    pub fn is_ready(&self) -> bool {
+        self.node_health.is_ready(self.config.clock().now_micros())
+            && !self.clock_skew.is_local_clock_skewed()
+            && !self.is_leaving()
+    }
+
+    /// Return `true` if the gRPC server and the background loops are running.
+    pub fn is_live(&self) -> bool {
+        self.node_health.is_live(self.config.clock().now_micros())
+    }
+
+    /// Return `true` if the node is leaving the cluster.
+    pub fn is_leaving(&self) -> bool {
+        self.leaving.load(Ordering::Relaxed)
+    }
+
+    /// Fail if the node no longer accepts writes.
+    fn ensure_accepting_writes(&self) -> Result<(), ClachelessError> {
+        if self.is_observer() {
+            Err(ClachelessErrorKind::Unavailable
+                .error_with_msg("Observer nodes don't accept writes."))?;
+        }
+        if self.is_leaving() {
+            Err(ClachelessErrorKind::Unavailable.error_with_msg("Node is leaving the cluster."))?;
+        }
+        Ok(())
+    }
+
+    /// Leave the cluster gracefully within `timeout_micros`.
+    ///
+    /// New writes are rejected, queued replication is flushed and the other
+    /// nodes are told to drop this node immediately. Entries that originated
+    /// at this node are then handed off to a surviving node, so that they are
+    /// not lost even if some replication failed.
+    pub async fn leave(&self, timeout_micros: u64) -> Result<(), ClachelessError> {
+        if self.leaving.swap(true, Ordering::Relaxed) {
+            return Ok(());
+        }
+        log::info!("Leaving the cluster.");
+        tokio::time::timeout(
+            tokio::time::Duration::from_micros(timeout_micros),
+            self.leave_and_handoff(),
+        )
+        .await
+        .map_err(|_e| {
+            ClachelessErrorKind::Unspecified
+                .error_with_msg("Leaving the cluster did not complete in time.")
+        })?
+    }
+
+    async fn leave_and_handoff(&self) -> Result<(), ClachelessError> {
+        // Let writes in progress finish. Later ones see the leaving flag once
+        // they hold the lock and fail right away.
+        drop(self.broadcast_lock.lock().await);
+        for entry in self.peer_replicators.iter() {
+            entry
+                .value()
+                .flush()
+                .await
+                .inspect_err(|e| log::debug!("Flushing replication failed: {e}"))
+                .ok();
+        }
+        let now_micros = self.config.clock().now_micros();
+        let mut node_ordinals = self
+            .known_node_ordinals_with_last_seen
+            .iter()
+            .filter(|entry| self.is_known_node_ordinal(*entry.key(), now_micros))
+            .map(|entry| *entry.key())
+            .collect::<Vec<_>>();
+        for node_ordinal in node_ordinals.iter().filter(|node_ordinal| {
+            self.peer_protocols
+                .supports(**node_ordinal, Capability::Leave)
+        }) {
+            match self.peer_transport.client(*node_ordinal) {
+                Ok(grpc_client) => grpc_client.leave(self.local_node_ordinal).await,
+                Err(e) => Err(e),
+            }
+            .inspect_err(|e| log::debug!("Failed to announce leave: {e}"))
+            .ok();
+        }
+        if self.is_observer() {
+            // Nothing originated here
+            return Ok(());
+        }
+        // StatefulSets replace and remove Pods from the highest ordinal and
+        // down, so nodes above this one are most likely to stay around.
+        node_ordinals.sort_unstable_by_key(|node_ordinal| {
+            (
+                *node_ordinal < self.local_node_ordinal,
+                node_ordinal.abs_diff(self.local_node_ordinal),
+            )
+        });
+        let Some(successor_ordinal) = node_ordinals.first().copied() else {
+            log::info!("No other node to hand off entries to.");
+            return Ok(());
+        };
+        let count = self.handoff(successor_ordinal).await?;
+        log::info!("Handed off {count} entries to node ordinal '{successor_ordinal}'.");
+        Ok(())
+    }
+
+    /// Send all entries that originated at this node to the remote node in
+    /// update sequence order.
+    async fn handoff(&self, node_ordinal: u32) -> Result<usize, ClachelessError> {
+        let grpc_client = self.peer_transport.client(node_ordinal)?;
+        let local_seq = self.cluster_view.current_local_update_seq();
+        let data_origin_id_and_ranges = HashMap::from([(
+            self.local_node_id,
+            SequenceRanges::from_iter([1..=local_seq]),
+        )]);
+        let supports_replicate_entries = self
+            .peer_protocols
+            .supports(node_ordinal, Capability::ReplicateEntries);
+        let entries = self
+            .local_cache
+            .iter(&data_origin_id_and_ranges)
+            .collect::<Vec<_>>();
+        let mut remaining = entries.as_slice();
+        while !remaining.is_empty() {
+            let (batch, rest) = remaining.split_at(PeerReplicator::batch_len(
+                remaining,
+                Self::HANDOFF_BATCH_SIZE,
+            ));
+            if supports_replicate_entries {
+                grpc_client.replicate_entries(batch.to_vec()).await?;
+            } else {
+                grpc_client.send_updates(batch.to_vec()).await?;
+            }
+            remaining = rest;
+        }
+        Ok(entries.len())
+    }
+
+    /// Return the known live nodes, how far this node has synchronized the
+    /// updates of each origin node, state transfers to this node and the size
+    /// of the local cache.
+    pub async fn cluster_status(&self) -> ClusterStatus {
+        let now_micros = self.config.clock().now_micros();
+        let alive_peers = self
+            .known_node_ordinals_with_last_seen
+            .iter()
+            .filter(|entry| self.is_known_node_ordinal(*entry.key(), now_micros))
+            .map(|entry| (*entry.key(), *entry.value()))
+            .collect::<Vec<_>>();
+        // Also show nodes that are gone, but still have updates kept for them
+        let down_peers = self
+            .peer_replicators
+            .iter()
+            .filter(|entry| {
+                !self.is_known_node_ordinal(*entry.key(), now_micros)
+                    && entry.value().hinted_size().0 > 0
+            })
+            .filter_map(|entry| {
+                entry
+                    .value()
+                    .peer_down_since_micros()
+                    .map(|down_since_micros| (*entry.key(), down_since_micros))
+            })
+            .collect::<Vec<_>>();
+        let mut peers = alive_peers
+            .into_iter()
+            .chain(down_peers)
+            .map(|(node_ordinal, last_seen_micros)| {
+                let peer_replicator = self.peer_replicators.get(&node_ordinal);
+                let peer_replicator = peer_replicator.as_ref().map(Entry::value);
+                let (hinted_entries, hinted_bytes) = peer_replicator
+                    .map(PeerReplicator::hinted_size)
+                    .unwrap_or_default();
+                let peer_clock = self.clock_skew.estimate(node_ordinal);
+                PeerStatus {
+                    node_ordinal,
+                    last_seen_micros,
+                    down_since_micros: peer_replicator
+                        .and_then(PeerReplicator::peer_down_since_micros),
+                    replication_queue_depth: peer_replicator
+                        .map(PeerReplicator::queue_depth)
+                        .unwrap_or_default() as u64,
+                    replication_dropped_entries: peer_replicator
+                        .map(PeerReplicator::dropped_entries)
+                        .unwrap_or_default(),
+                    needs_state_transfer: peer_replicator
+                        .is_some_and(PeerReplicator::needs_state_transfer),
+                    hinted_entries: hinted_entries as u64,
+                    hinted_bytes: hinted_bytes as u64,
+                    clock_offset_micros: peer_clock.map(|peer_clock| peer_clock.offset_micros),
+                    round_trip_micros: peer_clock
+                        .map(|peer_clock| peer_clock.round_trip_micros)
+                        .filter(|round_trip_micros| *round_trip_micros > 0),
+                    clock_skewed: peer_clock.is_some_and(|peer_clock| peer_clock.skewed),
+                }
+            })
+            .collect::<Vec<_>>();
+        peers.sort_unstable_by_key(|peer| peer.node_ordinal);
+        let origins = self
+            .cluster_view
+            .sequences()
+            .await
+            .into_iter()
+            .map(|(origin_node_id, baseline_seq, latest_seq)| OriginStatus {
+                origin_node_id,
+                baseline_seq,
+                latest_seq,
+            })
+            .collect();
+        let (local_entries, local_bytes) = self.local_cache.size();
+        ClusterStatus {
+            local_node_ordinal: self.local_node_ordinal,
+            local_node_id: self.local_node_id,
+            peers,
+            origins,
+            state_transfers: self.state_transfers.progress(),
+            local_entries,
+            local_bytes,
+            local_clock_skewed: self.clock_skew.is_local_clock_skewed(),
+            membership_epoch: self.partition_tracker.membership_epoch(),
         }
     }
 
     /// Return the highest known `node_ordinal` that is confirmed to be alive
     /// (has checked in).
     fn get_highest_known_node_ordinal(&self) -> u32 {
-        let last_seen_threshold =
-            crate::time::get_timestamp_micros() - Self::MAX_AGE_BEFORE_IGNORED_MICROS;
+        let last_seen_threshold = self
+            .config
+            .clock()
+            .now_micros()
+            .saturating_sub(self.max_age_before_ignored_micros());
         *self
             .known_node_ordinals_with_last_seen
             .iter()
@@ -236,58 +1702,153 @@ impl DistributedCache {
         reciever_node_ordinal: u32,
         data_origin_id_and_baseline: HashMap<u64, u64>,
     ) -> Result<(), ClachelessError> {
-        let address = self.get_address_for_node_ordinal(reciever_node_ordinal);
-        let grpc_client = GrpcClient::new(&address)
-            .await
+        let grpc_client = self
+            .peer_transport
+            .client(reciever_node_ordinal)
             .inspect_err(|e| log::debug!("Failed to connect: {e}"))?;
+        let data_origin_id_and_ranges = data_origin_id_and_baseline
+            .into_iter()
+            .filter_map(|(origin_node_id, baseline)| {
+                // Nothing can be newer than the last possible sequence number
+                let first_missing = baseline.checked_add(1)?;
+                Some((
+                    origin_node_id,
+                    SequenceRanges::from_iter([first_missing..=u64::MAX]),
+                ))
+            })
+            .collect::<HashMap<_, _>>();
         let self_clone = Arc::clone(self);
         tokio::spawn(async move {
-            for fcde in self_clone.local_cache.iter(&data_origin_id_and_baseline) {
+            for fcde in self_clone.local_cache.iter(&data_origin_id_and_ranges) {
                 grpc_client
-                    .send_update(
-                        fcde.key,
-                        fcde.ce.this_update_micros,
-                        fcde.ce.expires_micros,
-                        fcde.ce.object_bytes.to_vec(),
-                        fcde.ce.origin_node_id,
-                        fcde.ce.origin_node_update_seq,
-                    )
+                    .send_update(fcde)
                     .await
-                    .inspect_err(|e| log::info!("Failed to send update: {e}"))
+                    .inspect_err(|e| {
+                        log::info!("Failed to send update: {e}");
+                        self_clone
+                            .peer_transport
+                            .evict_on_connection_error(reciever_node_ordinal, e);
+                    })
                     .ok();
             }
         });
         Ok(())
     }
 
-    /// Send cache item to all known nodes.
-    async fn broadcast_update(
-        &self,
-        key: String,
-        this_update_micros: u64,
-        expires: u64,
-        object_bytes: Vec<u8>,
-        origin_node_id: u64,
-        update_seq: u64,
-    ) -> Result<(), ClachelessError> {
-        for node_ordinal in 0..=self.get_highest_known_node_ordinal() {
-            if node_ordinal != self.local_node_ordinal {
-                let address = self.get_address_for_node_ordinal(node_ordinal);
-                let key = key.to_owned();
-                let object_bytes = object_bytes.to_owned();
-                let _res = tokio::spawn(async move {
-                    let grpc_client = GrpcClient::new(&address).await?;
-                    grpc_client
-                        .send_update(
-                            key,
-                            this_update_micros,
-                            expires,
-                            object_bytes,
-                            origin_node_id,
-                            update_seq,
-                        )
-                        .await
-                });
+    /// Queue cache item for replication to all known nodes.
+    ///
+    /// Each node has a single ordered queue, so entries are sent in the order
+    /// this method is invoked.
+    ///
+    /// A full queue is handled according to the configured
+    /// [ReplicationOverflowPolicy]. With [ReplicationOverflowPolicy::FailPut]
+    /// the caller is expected to have checked the queues with
+    /// [Self::ensure_replication_capacity] first, so entries are only dropped
+    /// here if that was not done.
+    fn broadcast_update(&self, entry: &CacheEntryAndKey, mut queue_slots: HashMap<u32, QueueSlot>) {
+        for node_ordinal in self.replicated_node_ordinals() {
+            if let Some(queue_slot) = queue_slots.remove(&node_ordinal) {
+                queue_slot.send(entry.clone());
+                continue;
+            }
+            self.get_peer_replicator(node_ordinal)
+                .value()
+                .enqueue_or_drop(entry.clone())
+                .inspect_err(|e| {
+                    log::debug!("Failed to queue update for node ordinal {node_ordinal}: {e}")
+                })
+                .ok();
+        }
+    }
+
+    /// Return the ordinals of all nodes that locally originated entries are
+    /// replicated to.
+    fn replicated_node_ordinals(&self) -> Vec<u32> {
+        // Include nodes that are down, but still keep updates for hinted
+        // handoff. Nodes not seen for longer than hints are kept are skipped
+        // until they are seen again.
+        let seen_after_micros = self
+            .config
+            .clock()
+            .now_micros()
+            .saturating_sub(self.config.hinted_handoff_max_age_micros());
+        let mut node_ordinals = self
+            .known_node_ordinals_with_last_seen
+            .iter()
+            .filter(|entry| *entry.value() >= seen_after_micros)
+            .map(|entry| *entry.key())
+            .chain(self.peer_replicators.iter().map(|entry| *entry.key()))
+            .filter(|node_ordinal| {
+                *node_ordinal != self.local_node_ordinal
+                    && !self.is_observer_node_ordinal(*node_ordinal)
+            })
+            .collect::<Vec<_>>();
+        node_ordinals.sort_unstable();
+        node_ordinals.dedup();
+        node_ordinals
+    }
+
+    /// Reserve room in the replication queue of each node if the replication
+    /// overflow policy is [ReplicationOverflowPolicy::Block].
+    ///
+    /// This waits for slow nodes before [Self::broadcast_lock] is taken, so a
+    /// single slow node doesn't hold up writers that already have room. Nodes
+    /// that don't make room within [Self::REPLICATION_BLOCK_MAX_WAIT] are
+    /// treated as with [ReplicationOverflowPolicy::DropAndResync].
+    async fn reserve_queue_slots(&self) -> HashMap<u32, QueueSlot> {
+        let mut queue_slots = HashMap::new();
+        if self.config.replication_overflow_policy() != ReplicationOverflowPolicy::Block {
+            return queue_slots;
+        }
+        let deadline = tokio::time::Instant::now() + Self::REPLICATION_BLOCK_MAX_WAIT;
+        for node_ordinal in self.replicated_node_ordinals() {
+            let peer_replicator = self.get_peer_replicator(node_ordinal);
+            match tokio::time::timeout_at(deadline, peer_replicator.value().reserve()).await {
+                Ok(Ok(queue_slot)) => {
+                    queue_slots.insert(node_ordinal, queue_slot);
+                }
+                Ok(Err(e)) => {
+                    log::debug!("Failed to queue update for node ordinal {node_ordinal}: {e}")
+                }
+                Err(_elapsed) => log::debug!(
+                    "Replication queue of node ordinal {node_ordinal} stayed full. Dropping update."
+                ),
+            }
+        }
+        queue_slots
+    }
+
+    /// Return the replication queue of the node, creating it if needed.
+    fn get_peer_replicator(&self, node_ordinal: u32) -> Entry<'_, u32, PeerReplicator> {
+        self.peer_replicators.get_or_insert_with(node_ordinal, || {
+            PeerReplicator::new(
+                node_ordinal,
+                &self.peer_transport,
+                &self.peer_protocols,
+                &self.config,
+            )
+        })
+    }
+
+    /// Fail with [ClachelessErrorKind::Unavailable] if the replication
+    /// overflow policy is [ReplicationOverflowPolicy::FailPut] and the
+    /// replication queue of any node the update would be queued for is full.
+    ///
+    /// Must be called while holding the broadcast lock, so that no other write
+    /// can fill up a queue before the update is queued.
+    fn ensure_replication_capacity(&self) -> Result<(), ClachelessError> {
+        if self.config.replication_overflow_policy() != ReplicationOverflowPolicy::FailPut {
+            return Ok(());
+        }
+        for node_ordinal in self.replicated_node_ordinals() {
+            if self
+                .peer_replicators
+                .get(&node_ordinal)
+                .is_some_and(|entry| entry.value().is_full())
+            {
+                Err(ClachelessErrorKind::Unavailable.error_with_msg(format!(
+                    "Replication queue of node ordinal {node_ordinal} is full."
+                )))?;
             }
         }
         Ok(())
@@ -297,29 +1858,35 @@ impl DistributedCache {
     /// cluster view.
     async fn put_raw_from_remote_origin(
         &self,
-        cache_key: String,
-        cache_value: Vec<u8>,
-        this_update_micros: u64,
-        expires_micros: u64,
-        origin_node_id: u64,
-        origin_node_update_seq: u64,
+        entry: CacheEntryAndKey,
     ) -> Result<(), ClachelessError> {
+        let origin_node_id = entry.ce.origin_node_id;
         if log::log_enabled!(log::Level::Debug) {
             log::debug!(
-                "Got update for key '{cache_key}' created on node_id {origin_node_id} (ordinal: {}).",
+                "Got update for key '{}' created on node_id {origin_node_id} (ordinal: {}).",
+                entry.key,
                 origin_node_id & 0xffff_ffff
             );
         }
-        self.local_cache.put(
-            cache_key,
-            cache_value,
-            this_update_micros,
-            origin_node_id,
-            origin_node_update_seq,
-            expires_micros,
-        )?;
+        let incoming_version = entry.ce.version();
+        let existing_version = self
+            .local_cache
+            .get_entry(&entry.key)
+            .map(|ce| ce.version());
+        if let Some(existing_version) = existing_version {
+            self.partition_tracker.on_versions_compared(
+                &entry.key,
+                existing_version,
+                incoming_version,
+            );
+        }
+        self.local_cache
+            .put_entry(entry.key.to_owned(), Arc::clone(&entry.ce))?;
+        if existing_version.is_none_or(|existing_version| existing_version < incoming_version) {
+            self.publish_cache_update(&entry);
+        }
         self.cluster_view
-            .on_recieved_cache_entry_from_other(origin_node_id, origin_node_update_seq)
+            .on_recieved_cache_entry_from_other(origin_node_id, entry.ce.origin_node_update_seq)
             .await;
         Ok(())
     }
@@ -330,28 +1897,232 @@ impl DistributedCache {
         cache_key: &str,
         cache_value: &[u8],
     ) -> Result<(), ClachelessError> {
+        self.put_bytes_with_ttl(cache_key, cache_value, self.cache_item_ttl_micros)
+            .await
+    }
+
+    /// Insert item in cache that expires after `ttl_micros` instead of the
+    /// default time to live and broadcast update to all other known nodes.
+    pub async fn put_bytes_with_ttl(
+        &self,
+        cache_key: &str,
+        cache_value: &[u8],
+        ttl_micros: u64,
+    ) -> Result<(), ClachelessError> {
+        self.ensure_accepting_writes()?;
+        let this_update_micros = self.config.clock().now_micros();
+        if let Some(entry) = self
+            .put_originated(
+                cache_key,
+                Arc::new(cache_value.to_vec()),
+                this_update_micros,
+                this_update_micros.saturating_add(ttl_micros),
+                Overwrite::UnlessNewer,
+                false,
+            )
+            .await?
+        {
+            self.publish_cache_update(&entry);
+            self.write_to_backing_store(entry).await?;
+        }
+        Ok(())
+    }
+
+    /// Store a locally originated entry in the backing store (if any)
+    /// according to the configured write policy.
+    async fn write_to_backing_store(&self, entry: CacheEntryAndKey) -> Result<(), ClachelessError> {
+        if let Some(backing_store_writer) = &self.backing_store_writer {
+            backing_store_writer
+                .enqueue(entry.key, Arc::clone(&entry.ce.object_bytes))
+                .await
+        } else if let Some(backing_store) = self.config.backing_store() {
+            backing_store
+                .store(&entry.key, &entry.ce.object_bytes)
+                .await
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Remove expired entries from the backing store.
+    ///
+    /// Like with writes, only the node where the entry was recieved removes
+    /// it. Entries that were written again since they expired are kept.
+    async fn delete_expired_from_backing_store(
+        &self,
+        mut expired_receiver: mpsc::UnboundedReceiver<CacheEntryAndKey>,
+    ) {
+        while let Some(entry) = expired_receiver.recv().await {
+            if entry.ce.origin_node_id & 0xffff_ffff != u64::from(self.local_node_ordinal)
+                || self.local_cache.get_entry(&entry.key).is_some()
+            {
+                continue;
+            }
+            let res = if let Some(backing_store_writer) = &self.backing_store_writer {
+                backing_store_writer
+                    .enqueue_delete(entry.key.to_owned())
+                    .await
+            } else if let Some(backing_store) = self.config.backing_store() {
+                backing_store.delete(&entry.key).await
+            } else {
+                Ok(())
+            };
+            if let Err(e) = res {
+                log::warn!(
+                    "Failed to remove expired key '{}' from backing store: {e}",
+                    entry.key
+                );
+            }
+        }
+    }
+
+    /// Insert an entry originating from this node in the local cache and
+    /// broadcast it to all other known nodes.
+    ///
+    /// The `overwrite` decides how the entry relates to the existing entry.
+    /// `bridged` entries were recieved from another cluster.
+    /// Returns the entry if it was inserted. Entries that were not inserted are
+    /// not broadcast.
+    async fn put_originated(
+        &self,
+        cache_key: &str,
+        object_bytes: Arc<Vec<u8>>,
+        this_update_micros: u64,
+        expires_micros: u64,
+        overwrite: Overwrite,
+        bridged: bool,
+    ) -> Result<Option<CacheEntryAndKey>, ClachelessError> {
+        let queue_slots = self.reserve_queue_slots().await;
+        // Queue updates in sequence order to avoid gaps at the other nodes
+        let _broadcast_guard = self.broadcast_lock.lock().await;
+        // The node may have started leaving while waiting for the lock
+        self.ensure_accepting_writes()?;
+        let existing = self.local_cache.get_entry(cache_key);
+        let Some(this_update_micros) =
+            overwrite.update_micros(this_update_micros, existing.as_deref())
+        else {
+            return Ok(None);
+        };
+        self.ensure_replication_capacity()?;
         let update_seq = self.cluster_view.next_local_update_seq();
-        let this_update_micros = crate::time::get_timestamp_micros();
-        let expires = this_update_micros + self.cache_item_ttl_micros;
-        self.broadcast_update(
-            cache_key.to_owned(),
-            this_update_micros,
-            expires,
-            cache_value.to_owned(),
-            self.local_node_id,
-            update_seq,
-        )
-        .await
-        .inspect_err(|e| log::debug!("Failed to broadcast update: {e}"))
-        .ok();
-        self.local_cache.put(
-            cache_key.to_string(),
-            cache_value.to_vec(),
-            this_update_micros,
-            self.local_node_id,
-            update_seq,
-            expires,
-        )
+        let entry = CacheEntryAndKey {
+            key: cache_key.to_owned(),
+            ce: Arc::new(CacheEntry {
+                this_update_micros,
+                origin_node_id: self.local_node_id,
+                origin_node_update_seq: update_seq,
+                expires_micros,
+                object_bytes,
+                bridged,
+            }),
+        };
+        if !self
+            .local_cache
+            .put_entry(entry.key.to_owned(), Arc::clone(&entry.ce))?
+        {
+            // A newer entry was replicated meanwhile
+            return Ok(None);
+        }
+        self.broadcast_update(&entry, queue_slots);
+        Ok(Some(entry))
+    }
+
+    /** Write all non-expired entries with full meta data to `writer`.
+
+    The output uses the binary snapshot format: the magic bytes `CLACHSNP`, the
+    format version as a big-endian `u32` and then length-delimited protobuf
+    messages, starting with a `SnapshotHeader` followed by a `SnapshotRecord`
+    with the entry for each cache entry and a final `SnapshotRecord` with the
+    trailer that holds the number of entries (see `proto/snapshot.proto`).
+
+    Returns the number of exported entries.
+    */
+    pub async fn export(
+        &self,
+        writer: &mut (impl AsyncWrite + Unpin),
+    ) -> Result<usize, ClachelessError> {
+        let io_error = |e: std::io::Error| {
+            ClachelessErrorKind::Unspecified.error_with_msg(format!("Export failed: {e}"))
+        };
+        writer
+            .write_all(&Snapshot::encode_header(
+                self.config.clock().now_micros(),
+                &HashMap::default(),
+            ))
+            .await
+            .map_err(io_error)?;
+        let mut count = 0;
+        let mut after_key = None;
+        loop {
+            let batch = self
+                .local_cache
+                .entries_after(after_key.as_deref(), Self::EXPORT_BATCH_SIZE);
+            for entry in &batch {
+                writer
+                    .write_all(&Snapshot::encode_entry(entry))
+                    .await
+                    .map_err(io_error)?;
+            }
+            count += batch.len();
+            if batch.len() < Self::EXPORT_BATCH_SIZE {
+                break;
+            }
+            after_key = batch.last().map(|entry| entry.key.to_owned());
+        }
+        writer
+            .write_all(&Snapshot::encode_trailer(count))
+            .await
+            .map_err(io_error)?;
+        writer.flush().await.map_err(io_error)?;
+        Ok(count)
+    }
+
+    /** Read entries in the format written by [Self::export] from `reader`.
+
+    Imported entries keep their original update and expiration timestamps, so
+    the most recent write of a key still wins. Each imported entry that is
+    newer than the local one is re-originated from this node, replicated to
+    the other nodes and stored in the backing store (if any). Expired entries
+    are skipped.
+
+    Entries are imported as they are read, so memory use doesn't grow with the
+    size of the input. An error is returned if the input is incomplete, i.e.
+    it doesn't end with the trailer or the trailer doesn't match the number of
+    entries, but the entries read until then stay imported. Importing the
+    complete input again is safe, since entries that are not newer than the
+    local ones are skipped.
+
+    Returns the number of imported entries.
+    */
+    pub async fn import(
+        &self,
+        reader: &mut (impl AsyncRead + Unpin),
+    ) -> Result<usize, ClachelessError> {
+        let (mut snapshot_reader, _baselines) = SnapshotReader::new(reader).await?;
+        let mut count = 0;
+        while let Some(entry) = snapshot_reader.next_entry().await? {
+            self.ensure_accepting_writes()?;
+            if entry.ce.expires_micros <= self.config.clock().now_micros() {
+                continue;
+            }
+            if let Some(entry) = self
+                .put_originated(
+                    &entry.key,
+                    Arc::clone(&entry.ce.object_bytes),
+                    entry.ce.this_update_micros,
+                    entry.ce.expires_micros,
+                    Overwrite::OnlyIfOlder,
+                    false,
+                )
+                .await?
+            {
+                self.publish_cache_update(&entry);
+                self.write_to_backing_store(entry).await?;
+                count += 1;
+            }
+        }
+        log::info!("Imported {count} entries.");
+        Ok(count)
     }
 
     /// Insert item in cache and broadcast update to all other known nodes.
@@ -363,14 +2134,60 @@ impl DistributedCache {
         self.put_bytes(cache_key, cache_value.as_bytes()).await
     }
 
+    /// Return the default time to live of cached items in microseconds.
+    pub fn cache_item_ttl_micros(&self) -> u64 {
+        self.cache_item_ttl_micros
+    }
+
+    /// Return the configuration this instance was created with.
+    pub fn config(&self) -> &ClachelessConfig {
+        &self.config
+    }
+
     /// Get object bytes from cache.
-    pub fn get_bytes(&self, cache_key: &str) -> Result<Arc<Vec<u8>>, ClachelessError> {
-        self.local_cache.get(cache_key)
+    ///
+    /// Cache misses are loaded from the backing store (if any) and cached
+    /// across the cluster. Observers and leaving nodes only return what they
+    /// loaded.
+    pub async fn get_bytes(&self, cache_key: &str) -> Result<Arc<Vec<u8>>, ClachelessError> {
+        let res = self.local_cache.get(cache_key);
+        if let Err(e) = &res
+            && e.kind() == &ClachelessErrorKind::NotFound
+            && let Some(backing_store) = self.config.backing_store()
+        {
+            // Taken before the load, so a concurrent put always wins
+            let this_update_micros = self.config.clock().now_micros();
+            let Some(object_bytes) = backing_store.load(cache_key).await? else {
+                return res;
+            };
+            let object_bytes = Arc::new(object_bytes);
+            if self.ensure_accepting_writes().is_err() {
+                // Only nodes that accept writes originate updates
+                return Ok(object_bytes);
+            }
+            // Already present in the backing store, so it is not written back
+            self.put_originated(
+                cache_key,
+                Arc::clone(&object_bytes),
+                this_update_micros,
+                this_update_micros.saturating_add(self.cache_item_ttl_micros),
+                Overwrite::OnlyIfOlder,
+                false,
+            )
+            .await
+            .inspect_err(|e| log::debug!("Failed to cache loaded object of '{cache_key}': {e}"))
+            .ok();
+            return Ok(object_bytes);
+        }
+        res
     }
 
     /// Get string object from cache.
-    pub fn get_string(&self, cache_key: &str) -> Result<String, ClachelessError> {
-        let cached_content = self.get_bytes(cache_key)?;
+    ///
+    /// Cache misses are loaded from the backing store (if any) and cached
+    /// across the cluster.
+    pub async fn get_string(&self, cache_key: &str) -> Result<String, ClachelessError> {
+        let cached_content = self.get_bytes(cache_key).await?;
         String::from_utf8(cached_content.to_vec()).map_err(|e| {
             ClachelessErrorKind::Malformed.error_with_msg(format!(
                 "Entry for {cache_key} was not an UTF-8 string: {e}"
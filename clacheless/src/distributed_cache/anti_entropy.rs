@@ -0,0 +1,242 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Detection of diverged cache content by comparing hash trees.
+
+use super::local_cache::CacheEntryAndKey;
+use super::local_cache::LocalCache;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
+use tokio::time::Instant;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Continue a 64-bit FNV-1a hash over `bytes`.
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/** Hash tree over the non-expired content of a [LocalCache].
+
+Keys are assigned to leaves by a stable hash of the key, so all nodes agree on
+which key range each leaf covers. A leaf digest is the order independent sum of
+the digests of its entries and every inner node is the hash of its two
+children.
+*/
+pub struct HashTree {
+    /// Digests of each level, where `levels[0]` holds the root.
+    levels: Vec<Vec<u64>>,
+}
+
+impl HashTree {
+    /// Number of levels below the root.
+    pub const DEPTH: u32 = 10;
+
+    /// Return the index of the leaf that covers the key.
+    pub fn leaf_index(key: &str) -> u32 {
+        u32::try_from(fnv1a(FNV_OFFSET_BASIS, key.as_bytes()) >> (64 - Self::DEPTH)).unwrap()
+    }
+
+    /// Return the digest of a single version of an entry.
+    fn entry_digest(entry: &CacheEntryAndKey) -> u64 {
+        let (this_update_micros, origin_node_id, origin_node_update_seq) = entry.ce.version();
+        [this_update_micros, origin_node_id, origin_node_update_seq]
+            .iter()
+            .fold(
+                fnv1a(FNV_OFFSET_BASIS, entry.key.as_bytes()),
+                |hash, value| fnv1a(hash, &value.to_be_bytes()),
+            )
+    }
+
+    /// Return a new instance from the digests of the leaves.
+    fn from_leaves(leaves: Vec<u64>) -> Self {
+        let mut levels = vec![leaves];
+        while levels[0].len() > 1 {
+            let parents = levels[0]
+                .chunks(2)
+                .map(|children| {
+                    children.iter().fold(FNV_OFFSET_BASIS, |hash, child| {
+                        fnv1a(hash, &child.to_be_bytes())
+                    })
+                })
+                .collect();
+            levels.insert(0, parents);
+        }
+        Self { levels }
+    }
+
+    /// Return the digests of the nodes at `level` with the given indexes.
+    ///
+    /// Indexes outside of the tree have a digest of `0`.
+    pub fn digests(&self, level: u32, indexes: &[u32]) -> Vec<u64> {
+        let Some(digests) = usize::try_from(level)
+            .ok()
+            .and_then(|level| self.levels.get(level))
+        else {
+            return vec![0; indexes.len()];
+        };
+        indexes
+            .iter()
+            .map(|index| {
+                usize::try_from(*index)
+                    .ok()
+                    .and_then(|index| digests.get(index))
+                    .copied()
+                    .unwrap_or(0)
+            })
+            .collect()
+    }
+
+    /// Return the indexes of the children of the nodes with the given indexes.
+    pub fn children(indexes: &[u32]) -> Vec<u32> {
+        indexes
+            .iter()
+            .flat_map(|index| [index * 2, index * 2 + 1])
+            .collect()
+    }
+}
+
+/** Anti-entropy state of the local node.
+
+Scanning the local cache is done in batches with pauses in between, so that the
+scan on average uses no more than the configured share of a CPU core. Scans
+for the local hash tree and for requests of other nodes take turns, so that
+they share the budget.
+*/
+pub struct AntiEntropy {
+    cpu_budget_percent: u8,
+    scan_lock: tokio::sync::Mutex<()>,
+    tree: Mutex<Option<Arc<HashTree>>>,
+    last_peer_node_ordinal: AtomicU32,
+}
+
+impl AntiEntropy {
+    /// Number of cache entries to process between pauses.
+    const SCAN_BATCH_SIZE: usize = 1024;
+
+    /// Return a new instance.
+    pub fn new(cpu_budget_percent: u8) -> Self {
+        Self {
+            cpu_budget_percent: cpu_budget_percent.clamp(1, 100),
+            scan_lock: tokio::sync::Mutex::default(),
+            tree: Mutex::default(),
+            last_peer_node_ordinal: AtomicU32::new(u32::MAX),
+        }
+    }
+
+    /// Return the most recently built hash tree, if any.
+    pub fn tree(&self) -> Option<Arc<HashTree>> {
+        self.tree.lock().unwrap().clone()
+    }
+
+    /// Build a new hash tree of the local cache and make it available to
+    /// other nodes.
+    pub async fn rebuild(&self, local_cache: &LocalCache) -> Arc<HashTree> {
+        let mut leaves = vec![0u64; 1 << HashTree::DEPTH];
+        self.scan(local_cache, |entry| {
+            let leaf = &mut leaves[usize::try_from(HashTree::leaf_index(&entry.key)).unwrap()];
+            *leaf = leaf.wrapping_add(HashTree::entry_digest(entry));
+        })
+        .await;
+        let tree = Arc::new(HashTree::from_leaves(leaves));
+        *self.tree.lock().unwrap() = Some(Arc::clone(&tree));
+        tree
+    }
+
+    /// Return all non-expired entries covered by the leaves.
+    pub async fn entries_in_leaves(
+        &self,
+        local_cache: &LocalCache,
+        leaf_indexes: &[u32],
+    ) -> Vec<CacheEntryAndKey> {
+        let leaf_indexes = leaf_indexes.iter().copied().collect::<HashSet<_>>();
+        let mut ret = vec![];
+        self.scan(local_cache, |entry| {
+            if leaf_indexes.contains(&HashTree::leaf_index(&entry.key)) {
+                ret.push(entry.clone());
+            }
+        })
+        .await;
+        ret
+    }
+
+    /// Return the next node ordinal to compare with in round-robin order.
+    pub fn next_peer(&self, node_ordinals: &[u32]) -> Option<u32> {
+        let last = self.last_peer_node_ordinal.load(Ordering::Relaxed);
+        let next = node_ordinals
+            .iter()
+            .copied()
+            .filter(|node_ordinal| last == u32::MAX || *node_ordinal > last)
+            .min()
+            .or_else(|| node_ordinals.iter().copied().min())?;
+        self.last_peer_node_ordinal.store(next, Ordering::Relaxed);
+        Some(next)
+    }
+
+    /// Invoke `f` for every non-expired entry of the local cache while
+    /// staying within the CPU budget.
+    async fn scan(&self, local_cache: &LocalCache, mut f: impl FnMut(&CacheEntryAndKey)) {
+        let _scan_guard = self.scan_lock.lock().await;
+        let mut after_key = None;
+        loop {
+            let batch_start = Instant::now();
+            let batch = local_cache.entries_after(after_key.as_deref(), Self::SCAN_BATCH_SIZE);
+            batch.iter().for_each(&mut f);
+            if batch.len() < Self::SCAN_BATCH_SIZE {
+                return;
+            }
+            after_key = batch.last().map(|entry| entry.key.to_owned());
+            let busy = batch_start.elapsed();
+            let budget_percent = u32::from(self.cpu_budget_percent);
+            tokio::time::sleep(busy * (100 - budget_percent) / budget_percent).await;
+        }
+    }
+}
+
+mod test {
+    //! Hash tree tests.
+
+    #[test]
+    fn test_trees_differ_only_along_changed_leaf() {
+        let mut leaves = vec![0u64; 1 << super::HashTree::DEPTH];
+        let tree = super::HashTree::from_leaves(leaves.clone());
+        leaves[5] = 42;
+        let changed_tree = super::HashTree::from_leaves(leaves);
+        assert_eq!(
+            tree.levels.len(),
+            usize::try_from(super::HashTree::DEPTH).unwrap() + 1
+        );
+        assert_ne!(tree.digests(0, &[0]), changed_tree.digests(0, &[0]));
+        let mut indexes = vec![0];
+        for level in 1..=super::HashTree::DEPTH {
+            indexes = super::HashTree::children(&indexes)
+                .into_iter()
+                .filter(|index| {
+                    tree.digests(level, &[*index]) != changed_tree.digests(level, &[*index])
+                })
+                .collect();
+            assert_eq!(indexes.len(), 1);
+        }
+        assert_eq!(indexes, vec![5]);
+    }
+}
@@ -0,0 +1,166 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Batched background writes to a backing store.
+
+use crate::ClachelessError;
+use crate::ClachelessErrorKind;
+use crate::backing_store::BackingStore;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+use tokio::time::Instant;
+
+/** Write-behind queue of a [BackingStore].
+
+A dedicated task drains the bounded queue. Objects that arrive within a short
+time window are stored as a single batch where only the most recent object of
+each key is kept. Failed batches are retried with exponential backoff.
+
+Deletes are queued as well, so they are applied in order with the writes of
+the same key.
+*/
+pub struct BackingStoreWriter {
+    sender: mpsc::Sender<(String, Option<Arc<Vec<u8>>>)>,
+}
+
+impl BackingStoreWriter {
+    /// Maximum number of objects waiting to be stored.
+    const QUEUE_CAPACITY: usize = 4096;
+    /// How long to wait for more objects before storing a batch.
+    const BATCH_WINDOW: Duration = Duration::from_millis(50);
+    /// Maximum number of objects in a batch.
+    const BATCH_MAX_ENTRIES: usize = 256;
+    /// Delay before the first retry of a failed batch.
+    const RETRY_BASE_DELAY: Duration = Duration::from_millis(100);
+    /// Maximum delay between retries of a failed batch.
+    const RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+    /// Number of attempts before a batch is dropped.
+    const MAX_ATTEMPTS: u32 = 10;
+
+    /// Return a new instance and start the writing task.
+    pub fn new(backing_store: Arc<dyn BackingStore>) -> Self {
+        let (sender, receiver) = mpsc::channel(Self::QUEUE_CAPACITY);
+        tokio::spawn(async move { Self::run(backing_store, receiver).await });
+        Self { sender }
+    }
+
+    /// Queue the object to be stored.
+    ///
+    /// Waits for free space in the queue if the store is falling behind.
+    pub async fn enqueue(&self, key: String, value: Arc<Vec<u8>>) -> Result<(), ClachelessError> {
+        self.send(key, Some(value)).await
+    }
+
+    /// Queue the removal of the object.
+    ///
+    /// Waits for free space in the queue if the store is falling behind.
+    pub async fn enqueue_delete(&self, key: String) -> Result<(), ClachelessError> {
+        self.send(key, None).await
+    }
+
+    async fn send(&self, key: String, value: Option<Arc<Vec<u8>>>) -> Result<(), ClachelessError> {
+        self.sender.send((key, value)).await.map_err(|_e| {
+            ClachelessErrorKind::Unspecified.error_with_msg("Write-behind queue is closed.")
+        })
+    }
+
+    /// Store batches of queued objects until the queue is closed.
+    async fn run(
+        backing_store: Arc<dyn BackingStore>,
+        mut receiver: mpsc::Receiver<(String, Option<Arc<Vec<u8>>>)>,
+    ) {
+        while let Some(first) = receiver.recv().await {
+            let batch = Self::collect_batch(first, &mut receiver).await;
+            let mut retry_delay = Self::RETRY_BASE_DELAY;
+            for attempt in 1..=Self::MAX_ATTEMPTS {
+                match Self::apply_batch(&backing_store, &batch).await {
+                    Ok(()) => {
+                        if log::log_enabled!(log::Level::Trace) {
+                            log::trace!("Stored {} objects in backing store.", batch.len());
+                        }
+                        break;
+                    }
+                    Err(e) if attempt < Self::MAX_ATTEMPTS => {
+                        log::debug!(
+                            "Storing {} objects failed (attempt {attempt}): {e}",
+                            batch.len()
+                        );
+                        tokio::time::sleep(retry_delay).await;
+                        retry_delay = (retry_delay * 2).min(Self::RETRY_MAX_DELAY);
+                    }
+                    Err(e) => {
+                        log::warn!(
+                            "Dropped {} objects after {attempt} failed attempts to store them: {e}",
+                            batch.len()
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Store the objects of the batch and remove the keys without object.
+    async fn apply_batch(
+        backing_store: &Arc<dyn BackingStore>,
+        batch: &[(String, Option<Arc<Vec<u8>>>)],
+    ) -> Result<(), ClachelessError> {
+        let stores = batch
+            .iter()
+            .filter_map(|(key, value)| {
+                value
+                    .as_ref()
+                    .map(|value| (key.to_owned(), Arc::clone(value)))
+            })
+            .collect::<Vec<_>>();
+        if !stores.is_empty() {
+            backing_store.store_batch(&stores).await?;
+        }
+        for (key, _value) in batch.iter().filter(|(_key, value)| value.is_none()) {
+            backing_store.delete(key).await?;
+        }
+        Ok(())
+    }
+
+    /// Collect objects that arrive within the batch window and keep only the
+    /// most recent object (or removal) of each key.
+    async fn collect_batch(
+        first: (String, Option<Arc<Vec<u8>>>),
+        receiver: &mut mpsc::Receiver<(String, Option<Arc<Vec<u8>>>)>,
+    ) -> Vec<(String, Option<Arc<Vec<u8>>>)> {
+        let deadline = Instant::now() + Self::BATCH_WINDOW;
+        let mut batch = vec![first];
+        while batch.len() < Self::BATCH_MAX_ENTRIES {
+            match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                Ok(Some(entry)) => batch.push(entry),
+                Ok(None) | Err(_) => break,
+            }
+        }
+        let mut index_by_key = HashMap::<String, usize>::with_capacity(batch.len());
+        let mut ret: Vec<(String, Option<Arc<Vec<u8>>>)> = Vec::with_capacity(batch.len());
+        for (key, value) in batch {
+            if let Some(index) = index_by_key.get(&key) {
+                ret[*index].1 = value;
+            } else {
+                index_by_key.insert(key.to_owned(), ret.len());
+                ret.push((key, value));
+            }
+        }
+        ret
+    }
+}
@@ -0,0 +1,76 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Notifications about written cache entries.
+
+use std::sync::Arc;
+
+/// A cache entry that was written on the local node.
+///
+/// See [DistributedCache::cache_updates](crate::DistributedCache::cache_updates).
+#[derive(Clone, Debug)]
+pub struct CacheUpdate {
+    pub(super) key: String,
+    pub(super) object_bytes: Arc<Vec<u8>>,
+    pub(super) this_update_micros: u64,
+    pub(super) expires_micros: u64,
+    pub(super) bridged: bool,
+}
+
+impl CacheUpdate {
+    /// Return a new instance of an entry that was not bridged.
+    pub(crate) fn new(
+        key: String,
+        object_bytes: Arc<Vec<u8>>,
+        this_update_micros: u64,
+        expires_micros: u64,
+    ) -> Self {
+        Self {
+            key,
+            object_bytes,
+            this_update_micros,
+            expires_micros,
+            bridged: false,
+        }
+    }
+
+    /// Key of the entry.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Value of the entry.
+    pub fn object_bytes(&self) -> &Arc<Vec<u8>> {
+        &self.object_bytes
+    }
+
+    /// When the entry was written in epoch microseconds.
+    pub fn this_update_micros(&self) -> u64 {
+        self.this_update_micros
+    }
+
+    /// When the entry expires in epoch microseconds.
+    pub fn expires_micros(&self) -> u64 {
+        self.expires_micros
+    }
+
+    /// `true` if the entry was recieved from another cluster through a
+    /// [Bridge](crate::bridge::Bridge) on any node of this cluster.
+    pub fn is_bridged(&self) -> bool {
+        self.bridged
+    }
+}
@@ -0,0 +1,225 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Estimation of the clock offsets between nodes.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Estimated clock of a remote node relative to the local clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PeerClock {
+    /// How far the remote clock is ahead of the local clock (negative if
+    /// behind) in microseconds.
+    pub offset_micros: i64,
+    /// Round-trip time to the remote node in microseconds or `0` if unknown.
+    pub round_trip_micros: u64,
+    /// `true` if the offset exceeds the maximum skew.
+    pub skewed: bool,
+}
+
+/** Per peer estimates of the remote clock relative to the local clock.
+
+Estimates are based on the timestamps that are exchanged with cluster view
+updates. A round trip where the request is sent at `t0`, handled by the remote
+at `t1` and the response recieved at `t2` estimates the offset as
+`t1 - (t0 + t2) / 2` and the round-trip time as `t2 - t0`. The timestamp of a
+recieved update is corrected by half the last known round-trip time. Samples
+are smoothed with an exponential moving average.
+*/
+pub struct ClockSkew {
+    max_skew_micros: u64,
+    peers: Mutex<HashMap<u32, PeerClock>>,
+}
+
+impl ClockSkew {
+    /// Weight of the previous estimate when a new sample is added (out of
+    /// `SMOOTHING_WEIGHT + 1`).
+    const SMOOTHING_WEIGHT: i64 = 3;
+    /// Minimum number of peers with an estimate to tell if the local clock is
+    /// the odd one out.
+    const MIN_PEERS_FOR_LOCAL_SKEW: usize = 2;
+
+    /// Return a new instance that considers offsets beyond `max_skew_micros`
+    /// as skewed. `0` disables the detection.
+    pub fn new(max_skew_micros: u64) -> Self {
+        Self {
+            max_skew_micros,
+            peers: Mutex::default(),
+        }
+    }
+
+    /// Add a sample from a round trip to the remote node.
+    ///
+    /// Returns the new estimate if the node became skewed or recovered.
+    pub fn on_round_trip(
+        &self,
+        node_ordinal: u32,
+        sent_micros: u64,
+        remote_micros: u64,
+        received_micros: u64,
+    ) -> Option<PeerClock> {
+        let round_trip_micros = received_micros.saturating_sub(sent_micros);
+        let local_midpoint_micros = sent_micros + round_trip_micros / 2;
+        let offset_micros = Self::difference(remote_micros, local_midpoint_micros);
+        self.add_sample(node_ordinal, offset_micros, Some(round_trip_micros))
+    }
+
+    /// Add a sample from the timestamp of a recieved update.
+    ///
+    /// Returns the new estimate if the node became skewed or recovered.
+    pub fn on_one_way(
+        &self,
+        node_ordinal: u32,
+        remote_micros: u64,
+        received_micros: u64,
+    ) -> Option<PeerClock> {
+        let round_trip_micros = self
+            .estimate(node_ordinal)
+            .map(|peer_clock| peer_clock.round_trip_micros)
+            .unwrap_or_default();
+        let offset_micros = Self::difference(
+            remote_micros.saturating_add(round_trip_micros / 2),
+            received_micros,
+        );
+        self.add_sample(node_ordinal, offset_micros, None)
+    }
+
+    /// Forget the estimate of a node that is no longer known.
+    pub fn remove(&self, node_ordinal: u32) {
+        self.peers.lock().unwrap().remove(&node_ordinal);
+    }
+
+    /// Return the current estimate of the remote node's clock.
+    pub fn estimate(&self, node_ordinal: u32) -> Option<PeerClock> {
+        self.peers.lock().unwrap().get(&node_ordinal).copied()
+    }
+
+    /// Return `true` if the local clock is skewed compared to the majority of
+    /// (at least two) remote nodes.
+    ///
+    /// With fewer remote nodes there is no way to tell which clock is wrong.
+    pub fn is_local_clock_skewed(&self) -> bool {
+        let peers = self.peers.lock().unwrap();
+        let skewed_count = peers
+            .values()
+            .filter(|peer_clock| peer_clock.skewed)
+            .count();
+        peers.len() >= Self::MIN_PEERS_FOR_LOCAL_SKEW && skewed_count * 2 > peers.len()
+    }
+
+    fn add_sample(
+        &self,
+        node_ordinal: u32,
+        offset_micros: i64,
+        round_trip_micros: Option<u64>,
+    ) -> Option<PeerClock> {
+        let mut peers = self.peers.lock().unwrap();
+        let peer_clock = peers.entry(node_ordinal).or_insert_with(|| PeerClock {
+            offset_micros,
+            round_trip_micros: round_trip_micros.unwrap_or_default(),
+            skewed: false,
+        });
+        // Widen to avoid overflow, the weighted average always fits again
+        let smoothed_micros = (i128::from(peer_clock.offset_micros)
+            * i128::from(Self::SMOOTHING_WEIGHT)
+            + i128::from(offset_micros))
+            / i128::from(Self::SMOOTHING_WEIGHT + 1);
+        peer_clock.offset_micros = i64::try_from(smoothed_micros).unwrap_or(offset_micros);
+        if let Some(round_trip_micros) = round_trip_micros {
+            peer_clock.round_trip_micros = round_trip_micros;
+        }
+        let skewed = self.max_skew_micros > 0
+            && peer_clock.offset_micros.unsigned_abs() > self.max_skew_micros;
+        if skewed != peer_clock.skewed {
+            peer_clock.skewed = skewed;
+            return Some(*peer_clock);
+        }
+        None
+    }
+
+    /// Return `a - b` as a signed value.
+    fn difference(a: u64, b: u64) -> i64 {
+        if a >= b {
+            i64::try_from(a - b).unwrap_or(i64::MAX)
+        } else {
+            i64::try_from(b - a).map_or(i64::MIN, |difference| -difference)
+        }
+    }
+}
+
+mod test {
+    //! Clock skew estimation tests.
+
+    #[test]
+    fn test_offset_and_round_trip_estimate() {
+        use super::ClockSkew;
+
+        let clock_skew = ClockSkew::new(500_000);
+        // Remote is 2s ahead and the round trip takes 10ms
+        let changed = clock_skew.on_round_trip(1, 1_000_000, 3_005_000, 1_010_000);
+        assert!(changed.is_some_and(|peer_clock| peer_clock.skewed));
+        let estimate = clock_skew.estimate(1).unwrap();
+        assert_eq!(estimate.offset_micros, 2_000_000);
+        assert_eq!(estimate.round_trip_micros, 10_000);
+        // One way samples are corrected by half the round trip
+        assert!(clock_skew.on_one_way(1, 3_020_000, 1_025_000).is_none());
+        assert_eq!(clock_skew.estimate(1).unwrap().offset_micros, 2_000_000);
+        // Remote is slightly behind
+        clock_skew.on_round_trip(2, 1_000_000, 999_000, 1_002_000);
+        assert_eq!(clock_skew.estimate(2).unwrap().offset_micros, -2_000);
+        assert!(!clock_skew.is_local_clock_skewed());
+    }
+
+    #[test]
+    fn test_local_clock_is_skewed_against_majority() {
+        use super::ClockSkew;
+
+        let clock_skew = ClockSkew::new(500_000);
+        clock_skew.on_round_trip(1, 10_000_000, 8_000_000, 10_000_000);
+        // A single peer can't tell which clock is wrong
+        assert!(!clock_skew.is_local_clock_skewed());
+        clock_skew.on_round_trip(2, 10_000_000, 8_100_000, 10_000_000);
+        assert!(clock_skew.is_local_clock_skewed());
+        clock_skew.on_round_trip(3, 10_000_000, 10_000_000, 10_000_000);
+        assert!(clock_skew.is_local_clock_skewed());
+        clock_skew.on_round_trip(4, 10_000_000, 10_000_000, 10_000_000);
+        assert!(!clock_skew.is_local_clock_skewed());
+        // Smoothing moves the estimate gradually
+        let recovered = (0..20)
+            .filter_map(|_| clock_skew.on_round_trip(1, 10_000_000, 10_000_000, 10_000_000))
+            .collect::<Vec<_>>();
+        assert_eq!(recovered.len(), 1);
+        assert!(!recovered[0].skewed);
+    }
+
+    #[test]
+    fn test_extreme_timestamps_saturate() {
+        use super::ClockSkew;
+
+        let clock_skew = ClockSkew::new(500_000);
+        clock_skew.on_round_trip(1, 0, u64::MAX, 1_000);
+        clock_skew.on_round_trip(1, 0, u64::MAX, 1_000);
+        assert_eq!(clock_skew.estimate(1).unwrap().offset_micros, i64::MAX);
+        clock_skew.on_one_way(1, u64::MAX, 0);
+        assert_eq!(clock_skew.estimate(1).unwrap().offset_micros, i64::MAX);
+        clock_skew.on_round_trip(2, u64::MAX - 1_000, 0, u64::MAX);
+        clock_skew.on_round_trip(2, u64::MAX - 1_000, 0, u64::MAX);
+        assert_eq!(clock_skew.estimate(2).unwrap().offset_micros, i64::MIN);
+        assert!(clock_skew.estimate(2).unwrap().skewed);
+    }
+}
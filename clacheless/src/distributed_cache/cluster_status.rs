@@ -0,0 +1,195 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Point in time view of the cluster from the local node.
+
+use super::state_transfer::StateTransferProgress;
+
+/// A remote node that is known to be alive or that is down while updates are
+/// kept for it.
+#[derive(Clone, Debug)]
+pub struct PeerStatus {
+    pub(super) node_ordinal: u32,
+    pub(super) last_seen_micros: u64,
+    pub(super) down_since_micros: Option<u64>,
+    pub(super) replication_queue_depth: u64,
+    pub(super) replication_dropped_entries: u64,
+    pub(super) needs_state_transfer: bool,
+    pub(super) hinted_entries: u64,
+    pub(super) hinted_bytes: u64,
+    pub(super) clock_offset_micros: Option<i64>,
+    pub(super) round_trip_micros: Option<u64>,
+    pub(super) clock_skewed: bool,
+}
+
+impl PeerStatus {
+    /// Return the ordinal of the remote node.
+    pub fn node_ordinal(&self) -> u32 {
+        self.node_ordinal
+    }
+
+    /// Return when the remote node was last heard from in epoch microseconds.
+    ///
+    /// For a node that is no longer known to be alive, this is when it was
+    /// first considered down.
+    pub fn last_seen_micros(&self) -> u64 {
+        self.last_seen_micros
+    }
+
+    /// Return when the node was first considered down in epoch microseconds
+    /// or `None` if updates are sent to it.
+    pub fn down_since_micros(&self) -> Option<u64> {
+        self.down_since_micros
+    }
+
+    /// Return the number of updates waiting to be replicated to the node.
+    pub fn replication_queue_depth(&self) -> u64 {
+        self.replication_queue_depth
+    }
+
+    /// Return the number of updates that were not replicated to the node
+    /// because its replication queue was full.
+    pub fn replication_dropped_entries(&self) -> u64 {
+        self.replication_dropped_entries
+    }
+
+    /// Return `true` if updates were dropped and the node has not yet been
+    /// told to recover them with a state transfer.
+    pub fn needs_state_transfer(&self) -> bool {
+        self.needs_state_transfer
+    }
+
+    /// Return the number of updates kept since the node was last considered
+    /// down, waiting to be replayed.
+    pub fn hinted_entries(&self) -> u64 {
+        self.hinted_entries
+    }
+
+    /// Return the number of bytes of the updates waiting to be replayed.
+    pub fn hinted_bytes(&self) -> u64 {
+        self.hinted_bytes
+    }
+
+    /// Return the estimate of how far the node's clock is ahead of the local
+    /// clock (negative if behind) in microseconds.
+    pub fn clock_offset_micros(&self) -> Option<i64> {
+        self.clock_offset_micros
+    }
+
+    /// Return the estimated round-trip time to the node in microseconds.
+    pub fn round_trip_micros(&self) -> Option<u64> {
+        self.round_trip_micros
+    }
+
+    /// Return `true` if the clock offset exceeds the maximum skew.
+    pub fn clock_skewed(&self) -> bool {
+        self.clock_skewed
+    }
+}
+
+/// How far the local node has synchronized the updates of an origin node.
+#[derive(Clone, Debug)]
+pub struct OriginStatus {
+    pub(super) origin_node_id: u64,
+    pub(super) baseline_seq: u64,
+    pub(super) latest_seq: u64,
+}
+
+impl OriginStatus {
+    /// Return the node identifier where the updates were first recieved.
+    pub fn origin_node_id(&self) -> u64 {
+        self.origin_node_id
+    }
+
+    /// Return the sequence number up to which all updates have been recieved.
+    pub fn baseline_seq(&self) -> u64 {
+        self.baseline_seq
+    }
+
+    /// Return the latest known sequence number of the origin node.
+    ///
+    /// Updates between the baseline and the latest sequence number are either
+    /// still in flight or missing.
+    pub fn latest_seq(&self) -> u64 {
+        self.latest_seq
+    }
+}
+
+/// Snapshot of the cluster as seen by the local node.
+#[derive(Clone, Debug)]
+pub struct ClusterStatus {
+    pub(super) local_node_ordinal: u32,
+    pub(super) local_node_id: u64,
+    pub(super) peers: Vec<PeerStatus>,
+    pub(super) origins: Vec<OriginStatus>,
+    pub(super) state_transfers: Vec<StateTransferProgress>,
+    pub(super) local_entries: u64,
+    pub(super) local_bytes: u64,
+    pub(super) local_clock_skewed: bool,
+    pub(super) membership_epoch: u64,
+}
+
+impl ClusterStatus {
+    /// Return the ordinal of the local node.
+    pub fn local_node_ordinal(&self) -> u32 {
+        self.local_node_ordinal
+    }
+
+    /// Return the identifier of the local node.
+    pub fn local_node_id(&self) -> u64 {
+        self.local_node_id
+    }
+
+    /// Return the remote nodes that are known to be alive or are down while
+    /// updates are kept for them by ordinal.
+    pub fn peers(&self) -> &[PeerStatus] {
+        &self.peers
+    }
+
+    /// Return the synchronization state of each origin node by node id,
+    /// including the local node.
+    pub fn origins(&self) -> &[OriginStatus] {
+        &self.origins
+    }
+
+    /// Return the running and recently finished state transfers to this node.
+    pub fn state_transfers(&self) -> &[StateTransferProgress] {
+        &self.state_transfers
+    }
+
+    /// Return the number of entries in the local cache.
+    pub fn local_entries(&self) -> u64 {
+        self.local_entries
+    }
+
+    /// Return the number of object bytes in the local cache.
+    pub fn local_bytes(&self) -> u64 {
+        self.local_bytes
+    }
+
+    /// Return `true` if the local clock is skewed compared to the majority
+    /// of the peers.
+    pub fn local_clock_skewed(&self) -> bool {
+        self.local_clock_skewed
+    }
+
+    /// Return the local membership epoch. It increases every time a node
+    /// joins or is lost.
+    pub fn membership_epoch(&self) -> u64 {
+        self.membership_epoch
+    }
+}
@@ -19,9 +19,13 @@
 
 mod local_sequence;
 mod node_view;
+mod sequence_ranges;
+
+pub use self::sequence_ranges::SequenceRanges;
 
 use self::local_sequence::LocalSequence;
 use self::node_view::NodeView;
+use crate::time::Clock;
 use crossbeam_skiplist::SkipMap;
 use crossbeam_skiplist::map::Entry;
 use std::collections::HashMap;
@@ -31,14 +35,38 @@ use std::sync::Arc;
 pub struct ClusterStateView {
     local_sequence: LocalSequence,
     other_nodes_update_seqs: SkipMap<u64, NodeView>,
+    /// Last announced node id and own sequence number by node ordinal.
+    announced_by_ordinal: SkipMap<u32, (u64, u64)>,
+    clock: Arc<dyn Clock>,
+}
+
+/// Identity of a remote node compared to its previous announcement.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PeerIdentity {
+    /// Same node id and no regression of its sequence.
+    Unchanged,
+    /// The node restarted with a new node id.
+    NewIncarnation {
+        /// Node id of the previous incarnation.
+        previous_node_id: u64,
+    },
+    /// The node id is used by a new incarnation that started its sequence
+    /// over. What was recieved with the node id has been forgotten.
+    Reused,
 }
 
 impl ClusterStateView {
-    /// Return a new instance.
-    pub fn new(local_node_id: u64) -> Arc<Self> {
+    /// How long the local node may lag behind a remote node before the missing
+    /// updates are considered lost rather than in flight.
+    const LAG_GRACE_MICROS: u64 = 1_000_000;
+
+    /// Return a new instance that tells the age of updates by the `clock`.
+    pub fn new(local_node_id: u64, clock: &Arc<dyn Clock>) -> Arc<Self> {
         Arc::new(Self {
             local_sequence: LocalSequence::new(local_node_id),
             other_nodes_update_seqs: SkipMap::default(),
+            announced_by_ordinal: SkipMap::default(),
+            clock: Arc::clone(clock),
         })
     }
 
@@ -48,6 +76,59 @@ impl ClusterStateView {
         self.local_sequence.generate_next()
     }
 
+    /// Return the last sequence number used for locally recieved cache writes.
+    pub fn current_local_update_seq(&self) -> u64 {
+        self.local_sequence.current()
+    }
+
+    /// Register the node id and the sequence number that a remote node
+    /// announced for itself.
+    ///
+    /// A node's own sequence never decreases, so a lower sequence than in the
+    /// previous announcement means that a new incarnation reuses the node id.
+    /// The baseline of the node id is then reset, so that the updates of the
+    /// new incarnation aren't mistaken for already recieved ones.
+    pub fn on_peer_announced(
+        &self,
+        node_ordinal: u32,
+        node_id: u64,
+        announced_seq: u64,
+    ) -> PeerIdentity {
+        let previous = self
+            .announced_by_ordinal
+            .get(&node_ordinal)
+            .map(|entry| *entry.value());
+        self.announced_by_ordinal
+            .insert(node_ordinal, (node_id, announced_seq));
+        match previous {
+            Some((previous_node_id, _)) if previous_node_id != node_id => {
+                PeerIdentity::NewIncarnation { previous_node_id }
+            }
+            Some((_, previous_seq)) if previous_seq > announced_seq => {
+                self.other_nodes_update_seqs.remove(&node_id);
+                PeerIdentity::Reused
+            }
+            _ => PeerIdentity::Unchanged,
+        }
+    }
+
+    /// Return the node id that a remote node last announced for itself.
+    pub fn announced_node_id(&self, node_ordinal: u32) -> Option<u64> {
+        self.announced_by_ordinal
+            .get(&node_ordinal)
+            .map(|entry| entry.value().0)
+    }
+
+    /// Register how far a remote node has recieved updates from the local node
+    /// id.
+    ///
+    /// No node can have recieved more updates than were generated, unless an
+    /// earlier incarnation used the same node id. The local sequence then
+    /// continues after the remote's baseline and `true` is returned.
+    pub fn on_local_baseline_seen(&self, baseline_seq: u64) -> bool {
+        self.local_sequence.skip_past(baseline_seq)
+    }
+
     /// Get a map of `node_id` and the sequence baseline for each known node.
     pub async fn as_map(&self) -> HashMap<u64, u64> {
         let mut ret = HashMap::with_capacity(self.other_nodes_update_seqs.len() + 1);
@@ -63,38 +144,70 @@ impl ClusterStateView {
         ret
     }
 
-    /// Compare recieved view with local view and return a map of nodes that
-    /// require a state transfer and each node's current local baseline.
-    pub async fn get_out_of_sync_node_id_and_baselines(
+    /// Get the `node_id`, baseline and latest known sequence number of each
+    /// known node, including the local node, ordered by `node_id`.
+    pub async fn sequences(&self) -> Vec<(u64, u64, u64)> {
+        let mut ret = Vec::with_capacity(self.other_nodes_update_seqs.len() + 1);
+        let local_seq = self.local_sequence.current();
+        ret.push((self.local_sequence.node_id(), local_seq, local_seq));
+        for entry in self.other_nodes_update_seqs.iter() {
+            let (baseline_seq, latest_seq) = entry.value().get_sequences().await;
+            ret.push((*entry.key(), baseline_seq, latest_seq));
+        }
+        ret.sort_unstable();
+        ret
+    }
+
+    /// Compare recieved view with local view and return the ranges of update
+    /// sequence numbers that the local node is missing for each origin node.
+    pub async fn get_missing_ranges(
         &self,
         view: HashMap<u64, u64>,
-    ) -> HashMap<u64, u64> {
+    ) -> HashMap<u64, SequenceRanges> {
+        let now_micros = self.clock.now_micros();
         let mut ret = HashMap::new();
         // Ignore if we know more than the other node, just check if that node
         // knowns more than we do.
         for (node_id, baseline_seq) in view {
-            if node_id == self.local_sequence.node_id() {
+            if node_id == self.local_sequence.node_id() || baseline_seq == 0 {
                 // Don't compare with local state where this instance is authoritive.
                 continue;
             }
-            if let Some(node_view) = self
+            let missing_ranges = if let Some(node_view) = self
                 .other_nodes_update_seqs
                 .get(&node_id)
                 .as_ref()
                 .map(Entry::value)
                 .cloned()
             {
-                let other_baseline = node_view.get_baseline_sequence().await;
-                if other_baseline < baseline_seq {
-                    ret.insert(node_id, other_baseline);
-                }
+                node_view
+                    .get_missing_ranges(baseline_seq, now_micros, Self::LAG_GRACE_MICROS)
+                    .await
             } else {
-                ret.insert(node_id, 0);
+                // Nothing has been recieved from this node yet
+                SequenceRanges::from_iter([1..=baseline_seq])
+            };
+            if !missing_ranges.is_empty() {
+                ret.insert(node_id, missing_ranges);
             }
         }
         ret
     }
 
+    /// Mark the ranges of a completed state transfer as recieved.
+    pub async fn on_state_transfer_completed(&self, ranges: &HashMap<u64, SequenceRanges>) {
+        for (node_id, node_ranges) in ranges {
+            if *node_id == self.local_sequence.node_id() {
+                continue;
+            }
+            self.other_nodes_update_seqs
+                .get_or_insert_with(*node_id, NodeView::default)
+                .value()
+                .on_ranges_received(node_ranges)
+                .await;
+        }
+    }
+
     /// Returns `false` if we are missing updates for the `node_id`.
     pub async fn on_recieved_cache_entry_from_other(&self, node_id: u64, update_seq: u64) -> bool {
         let entry = self
@@ -103,3 +216,47 @@ impl ClusterStateView {
         entry.value().update(update_seq).await
     }
 }
+
+mod test {
+    //! Cluster view tests.
+
+    #[tokio::test]
+    async fn test_reused_node_ids_are_detected() {
+        use super::ClusterStateView;
+        use super::PeerIdentity;
+        use crate::time::Clock;
+        use crate::time::SystemClock;
+        use std::collections::HashMap;
+        use std::sync::Arc;
+
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let cluster_view = ClusterStateView::new(0x1000_0000_0000_0000, &clock);
+        let remote_node_id = 0x2000_0000_0000_0001;
+        assert!(
+            cluster_view
+                .on_recieved_cache_entry_from_other(remote_node_id, 1)
+                .await
+        );
+        assert_eq!(
+            cluster_view.on_peer_announced(1, remote_node_id, 1),
+            PeerIdentity::Unchanged
+        );
+        // Restart with the same node id: the sequence starts over
+        assert_eq!(
+            cluster_view.on_peer_announced(1, remote_node_id, 0),
+            PeerIdentity::Reused
+        );
+        let view = HashMap::from([(remote_node_id, 1)]);
+        assert!(!cluster_view.get_missing_ranges(view).await.is_empty());
+        assert_eq!(
+            cluster_view.on_peer_announced(1, 0x3000_0000_0000_0001, 0),
+            PeerIdentity::NewIncarnation {
+                previous_node_id: remote_node_id
+            }
+        );
+        // A remote has seen more of the local node id than was generated
+        assert!(cluster_view.on_local_baseline_seen(7));
+        assert!(!cluster_view.on_local_baseline_seen(3));
+        assert_eq!(cluster_view.next_local_update_seq(), 8);
+    }
+}
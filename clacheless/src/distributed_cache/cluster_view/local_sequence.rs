@@ -50,6 +50,13 @@ impl LocalSequence {
         self.seq.load(Ordering::Relaxed)
     }
 
+    /// Continue the sequence after `seq` if it is ahead of the current one.
+    ///
+    /// Returns `true` if the sequence was moved forward.
+    pub fn skip_past(&self, seq: u64) -> bool {
+        self.seq.fetch_max(seq, Ordering::Relaxed) < seq
+    }
+
     /// Return a fresh sequence number.
     pub fn generate_next(&self) -> u64 {
         self.seq.fetch_add(1, Ordering::Relaxed) + 1
@@ -17,11 +17,12 @@
 
 //! Local view of another node.
 
+use super::sequence_ranges::SequenceRanges;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
-/// Latest known sequence number of a remote node and how far the local node
-/// has synchronized.
+/// Recieved sequence numbers of a remote node and how far the local node has
+/// synchronized.
 #[derive(Default)]
 struct KnownSequences {
     /// Known baseline sequence number where the local node has recieved all
@@ -29,6 +30,27 @@ struct KnownSequences {
     baseline_seq: u64,
     /// Latest known sequence number of the remote node.
     latest_seq: u64,
+    /// Sequence numbers recieved out of order beyond the baseline.
+    received: SequenceRanges,
+    /// Sequence number the local node has been known to lag behind and since
+    /// when in epoch microseconds.
+    lagging: Option<(u64, u64)>,
+}
+
+impl KnownSequences {
+    /// Mark the range as recieved and move the baseline forward over filled
+    /// gaps.
+    fn mark_received(&mut self, first: u64, last: u64) {
+        self.latest_seq = self.latest_seq.max(last);
+        if last <= self.baseline_seq {
+            return;
+        }
+        self.received
+            .insert(first.max(self.baseline_seq + 1)..=last);
+        if let Some(contiguous) = self.received.take_starting_at(self.baseline_seq + 1) {
+            self.baseline_seq = *contiguous.end();
+        }
+    }
 }
 
 /// Synchronization state of the local node compared to what is known about the
@@ -45,16 +67,119 @@ impl NodeView {
         self.sequences.lock().await.baseline_seq
     }
 
+    /// Get the baseline and the latest known sequence number of the remote
+    /// node.
+    pub async fn get_sequences(&self) -> (u64, u64) {
+        let current = self.sequences.lock().await;
+        (current.baseline_seq, current.latest_seq)
+    }
+
+    /// Mark all sequence numbers of the ranges as recieved after the local node
+    /// has obtained them by other means (e.g. a completed state transfer).
+    pub async fn on_ranges_received(&self, ranges: &SequenceRanges) {
+        let mut current = self.sequences.lock().await;
+        for range in ranges.iter() {
+            current.mark_received(*range.start(), *range.end());
+        }
+    }
+
+    /// Return the ranges up to the remote's `baseline_seq` that the local node
+    /// has not recieved.
+    ///
+    /// Missing updates are often just still in flight, so a gap is only
+    /// reported once the local node has been lagging behind for at least
+    /// `grace_micros`.
+    pub async fn get_missing_ranges(
+        &self,
+        baseline_seq: u64,
+        now_micros: u64,
+        grace_micros: u64,
+    ) -> SequenceRanges {
+        let mut current = self.sequences.lock().await;
+        if baseline_seq <= current.baseline_seq {
+            current.lagging = None;
+            return SequenceRanges::default();
+        }
+        let Some((lagging_seq, lagging_since_micros)) = current.lagging else {
+            current.lagging = Some((baseline_seq, now_micros));
+            return SequenceRanges::default();
+        };
+        if lagging_since_micros + grace_micros > now_micros {
+            return SequenceRanges::default();
+        }
+        // Anything newer than what has been missing for long enough gets
+        // another grace period.
+        current.lagging = Some((baseline_seq, now_micros));
+        current
+            .received
+            .missing_within(current.baseline_seq + 1..=lagging_seq.min(baseline_seq))
+            .into_iter()
+            .collect()
+    }
+
     /// Update the known synchronization state compared to the remote node.
+    ///
+    /// Returns `false` if the local node is missing updates that are older
+    /// than `new_sequence`.
     pub async fn update(&self, new_sequence: u64) -> bool {
         let mut current = self.sequences.lock().await;
-        current.latest_seq = new_sequence;
-        if current.baseline_seq + 1 == current.latest_seq {
-            // In sync after this update
-            current.baseline_seq = new_sequence;
-            return true;
+        current.mark_received(new_sequence, new_sequence);
+        current.received.is_empty()
+    }
+}
+
+mod test {
+    //! Node view tests.
+
+    #[tokio::test]
+    async fn test_baseline_advances_when_gaps_are_filled() {
+        let node_view = super::NodeView::default();
+        assert!(node_view.update(1).await);
+        assert!(!node_view.update(3).await);
+        assert!(!node_view.update(5).await);
+        assert_eq!(node_view.get_baseline_sequence().await, 1);
+        assert!(!node_view.update(2).await);
+        assert_eq!(node_view.get_baseline_sequence().await, 3);
+        // Late duplicates don't move anything backwards
+        assert!(!node_view.update(2).await);
+        assert!(node_view.update(4).await);
+        assert_eq!(node_view.get_baseline_sequence().await, 5);
+        assert_eq!(node_view.sequences.lock().await.latest_seq, 5);
+    }
+
+    #[tokio::test]
+    async fn test_only_missing_ranges_are_requested_after_grace() {
+        let node_view = super::NodeView::default();
+        for seq in [1, 2, 5, 8, 9] {
+            node_view.update(seq).await;
         }
-        // No longer in sync.. we are missing update(s).
-        false
+        assert!(
+            node_view
+                .get_missing_ranges(10, 1_000, 500)
+                .await
+                .is_empty()
+        );
+        assert!(
+            node_view
+                .get_missing_ranges(12, 1_400, 500)
+                .await
+                .is_empty()
+        );
+        let missing = node_view.get_missing_ranges(12, 1_500, 500).await;
+        assert_eq!(
+            missing.iter().collect::<Vec<_>>(),
+            vec![3..=4, 6..=7, 10..=10]
+        );
+        node_view.on_ranges_received(&missing).await;
+        assert_eq!(node_view.get_baseline_sequence().await, 10);
+        // Sequence numbers 11 and 12 got a new grace period
+        assert!(
+            node_view
+                .get_missing_ranges(12, 1_900, 500)
+                .await
+                .is_empty()
+        );
+        let missing = node_view.get_missing_ranges(12, 2_000, 500).await;
+        assert_eq!(missing.iter().collect::<Vec<_>>(), vec![11..=12]);
     }
 }
@@ -0,0 +1,168 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Set of sequence numbers stored as ranges.
+
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+/// Set of sequence numbers stored as non-overlapping, non-adjacent inclusive
+/// ranges.
+#[derive(Clone, Debug, Default)]
+pub struct SequenceRanges {
+    /// First sequence number of each range mapped to the last.
+    ranges: BTreeMap<u64, u64>,
+}
+
+impl SequenceRanges {
+    /// Return `true` if the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Return `true` if the sequence number is part of the set.
+    pub fn contains(&self, seq: u64) -> bool {
+        self.ranges
+            .range(..=seq)
+            .next_back()
+            .is_some_and(|(_first, last)| seq <= *last)
+    }
+
+    /// Add all sequence numbers of the range to the set.
+    pub fn insert(&mut self, range: RangeInclusive<u64>) {
+        let (mut first, mut last) = range.into_inner();
+        if first > last {
+            return;
+        }
+        // Merge with a preceding range that overlaps or is adjacent
+        if let Some((prev_first, prev_last)) = self
+            .ranges
+            .range(..=first)
+            .next_back()
+            .map(|(prev_first, prev_last)| (*prev_first, *prev_last))
+            && prev_last.saturating_add(1) >= first
+        {
+            first = prev_first;
+            last = last.max(prev_last);
+        }
+        // Merge with following ranges that overlaps or are adjacent
+        let following = self
+            .ranges
+            .range(first..=last.saturating_add(1))
+            .map(|(next_first, next_last)| (*next_first, *next_last))
+            .collect::<Vec<_>>();
+        for (next_first, next_last) in following {
+            self.ranges.remove(&next_first);
+            last = last.max(next_last);
+        }
+        self.ranges.insert(first, last);
+    }
+
+    /// Remove and return the range that starts with `first`, if any.
+    pub fn take_starting_at(&mut self, first: u64) -> Option<RangeInclusive<u64>> {
+        self.ranges.remove(&first).map(|last| first..=last)
+    }
+
+    /// Remove all sequence numbers up to and including `seq`.
+    pub fn remove_up_to(&mut self, seq: u64) {
+        let overlapping = self
+            .ranges
+            .range(..=seq)
+            .map(|(first, last)| (*first, *last))
+            .collect::<Vec<_>>();
+        for (first, last) in overlapping {
+            self.ranges.remove(&first);
+            if last > seq {
+                self.ranges.insert(seq + 1, last);
+            }
+        }
+    }
+
+    /// Return the ranges within `range` that are not part of the set.
+    pub fn missing_within(&self, range: RangeInclusive<u64>) -> Vec<RangeInclusive<u64>> {
+        let (first, last) = range.into_inner();
+        let mut ret = vec![];
+        let mut next_missing = first;
+        for (range_first, range_last) in &self.ranges {
+            if next_missing > last {
+                break;
+            }
+            if *range_last < next_missing {
+                continue;
+            }
+            if *range_first > next_missing {
+                ret.push(next_missing..=(*range_first - 1).min(last));
+            }
+            next_missing = range_last.saturating_add(1);
+        }
+        if next_missing <= last {
+            ret.push(next_missing..=last);
+        }
+        ret
+    }
+
+    /// Return an iterator over the ranges in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = RangeInclusive<u64>> + '_ {
+        self.ranges.iter().map(|(first, last)| *first..=*last)
+    }
+}
+
+impl FromIterator<RangeInclusive<u64>> for SequenceRanges {
+    fn from_iter<T: IntoIterator<Item = RangeInclusive<u64>>>(iter: T) -> Self {
+        let mut ret = Self::default();
+        iter.into_iter().for_each(|range| ret.insert(range));
+        ret
+    }
+}
+
+mod test {
+    //! Sequence range tests.
+
+    #[test]
+    fn test_insert_merges_overlapping_and_adjacent() {
+        let mut ranges = super::SequenceRanges::default();
+        ranges.insert(5..=5);
+        ranges.insert(7..=9);
+        ranges.insert(1..=2);
+        assert_eq!(ranges.iter().collect::<Vec<_>>(), vec![1..=2, 5..=5, 7..=9]);
+        ranges.insert(6..=6);
+        assert_eq!(ranges.iter().collect::<Vec<_>>(), vec![1..=2, 5..=9]);
+        ranges.insert(3..=12);
+        assert_eq!(ranges.iter().collect::<Vec<_>>(), vec![1..=12]);
+        assert!(ranges.contains(12));
+        assert!(!ranges.contains(13));
+    }
+
+    #[test]
+    fn test_missing_within() {
+        let ranges = [3..=4, 8..=10]
+            .into_iter()
+            .collect::<super::SequenceRanges>();
+        assert_eq!(ranges.missing_within(1..=12), vec![1..=2, 5..=7, 11..=12]);
+        assert_eq!(ranges.missing_within(4..=9), vec![5..=7]);
+        assert_eq!(ranges.missing_within(8..=10), vec![]);
+    }
+
+    #[test]
+    fn test_remove_up_to() {
+        let mut ranges = [3..=4, 8..=10]
+            .into_iter()
+            .collect::<super::SequenceRanges>();
+        ranges.remove_up_to(8);
+        assert_eq!(ranges.iter().collect::<Vec<_>>(), vec![9..=10]);
+    }
+}
@@ -17,78 +17,215 @@
 
 //! GRPC client for inter-Pod communication.
 
+use super::cluster_view::SequenceRanges;
+use super::local_cache::CacheEntryAndKey;
 use super::peer_authenticator::PeerAuthenticator;
+use super::peer_transport::JoinResponse;
+use super::peer_transport::PeerClient;
+use super::peer_transport::StateTransferStream;
+use super::peer_transport::StateViewResponse;
+use super::protocol::Capability;
+use super::protocol::PROTOCOL_VERSION;
 use crate::ClachelessError;
 use crate::ClachelessErrorKind;
+use crate::proto::stateshare::AntiEntropyDigestsRequest;
+use crate::proto::stateshare::AntiEntropyVersionsRequest;
+use crate::proto::stateshare::EntryVersion;
+use crate::proto::stateshare::FetchEntriesReply;
+use crate::proto::stateshare::FetchEntriesRequest;
 use crate::proto::stateshare::InitStateTransferRequest;
+use crate::proto::stateshare::JoinRequest;
+use crate::proto::stateshare::LeaveRequest;
 use crate::proto::stateshare::PutCacheEntryRequest;
+use crate::proto::stateshare::ReplicateEntriesRequest;
+use crate::proto::stateshare::SequenceRange;
+use crate::proto::stateshare::StateTransferRequest;
 use crate::proto::stateshare::StateViewUpdateRequest;
 use crate::proto::stateshare::state_share_client::StateShareClient;
+use crate::time::Clock;
+use async_trait::async_trait;
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
+use tokio_stream::StreamExt;
+use tonic::Code;
 use tonic::Request;
 use tonic::Status;
 use tonic::metadata::MetadataValue;
+use tonic::service::Interceptor;
 use tonic::service::interceptor::InterceptedService;
 use tonic::transport::Channel;
+use tonic::transport::Endpoint;
 
-/// `tonic` interceptor function type alias.
-type TonicInterceptorFn = fn(Request<()>) -> Result<Request<()>, Status>;
+/// Return the kind of error for a failed call.
+///
+/// Failures that didn't come as a response from the remote node (the status
+/// has a local source, e.g. a refused connection or a reset stream) are
+/// [ClachelessErrorKind::Connection], so that the client is re-created.
+fn error_kind_of(status: &Status) -> ClachelessErrorKind {
+    if std::error::Error::source(status).is_some()
+        || matches!(status.code(), Code::Cancelled | Code::DeadlineExceeded)
+    {
+        return ClachelessErrorKind::Connection;
+    }
+    match status.code() {
+        Code::Unavailable => ClachelessErrorKind::Unavailable,
+        Code::NotFound => ClachelessErrorKind::NotFound,
+        _ => ClachelessErrorKind::Unspecified,
+    }
+}
+
+/// `tonic` interceptor that adds a peer authentication token to each request.
+#[derive(Clone)]
+struct AuthorizationInterceptor {
+    clock: Arc<dyn Clock>,
+}
+
+impl Interceptor for AuthorizationInterceptor {
+    /// Add token to request to prove that it is part of the same cluster.
+    fn call(&mut self, mut req: Request<()>) -> Result<Request<()>, Status> {
+        if log::log_enabled!(log::Level::Trace) {
+            log::trace!("authorization_interceptor");
+        }
+        if let Some(token) = PeerAuthenticator::instance().create_token(self.clock.as_ref()) {
+            req.metadata_mut().insert(
+                PeerAuthenticator::HEADER_NAME,
+                token.parse::<MetadataValue<_>>().unwrap(),
+            );
+            if log::log_enabled!(log::Level::Trace) {
+                log::trace!("(client) authorization_interceptor: {req:?}");
+            }
+        }
+        Ok(req)
+    }
+}
 
-/// GRPC client for inter-Pod communication.
+/** GRPC client for inter-Pod communication.
+
+The underlying [Channel] connects on first use and transparently reconnects
+after connection loss. Each call works on a (cheap) clone of the client, so
+a single instance can be shared by concurrent callers.
+*/
 pub struct GrpcClient {
-    client: Mutex<StateShareClient<InterceptedService<Channel, TonicInterceptorFn>>>,
+    client: StateShareClient<InterceptedService<Channel, AuthorizationInterceptor>>,
     address: String,
 }
 
 impl GrpcClient {
+    /// Maximum time to wait for a connection to be established.
+    const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+    /// Interval of TCP keepalive probes on idle connections.
+    const TCP_KEEPALIVE: Duration = Duration::from_secs(10);
+    /// Approximate maximum size of the entries in a single streamed message.
+    const MAX_MESSAGE_BYTES: usize = 256 * 1024;
+    /// Message size limit of tonic that is kept for small documents.
+    const DEFAULT_MAX_ENCODED_MESSAGE_BYTES: usize = 4 * 1024 * 1024;
+    /// Room for the key and meta data of an entry and the rest of a message.
+    const ENCODED_MESSAGE_HEADROOM_BYTES: usize = 1024 * 1024;
+
+    /// Return the size limit of gRPC messages between nodes.
+    ///
+    /// A single entry as large as `max_document_size` may have to be sent in
+    /// a message of its own.
+    pub fn max_message_bytes(max_document_size: usize) -> usize {
+        max_document_size
+            .max(Self::DEFAULT_MAX_ENCODED_MESSAGE_BYTES)
+            .saturating_add(Self::ENCODED_MESSAGE_HEADROOM_BYTES)
+    }
+
     /// Return a new instance.
     ///
-    /// `address` should only include fqdn and port.
-    pub async fn new(address: &str) -> Result<Arc<Self>, ClachelessError> {
+    /// `address` should only include fqdn and port. No connection is made
+    /// until the first request is sent. Authentication tokens are dated by the
+    /// `clock` and messages are limited to `max_message_bytes` (see
+    /// [Self::max_message_bytes]).
+    pub fn new(
+        address: &str,
+        clock: &Arc<dyn Clock>,
+        max_message_bytes: usize,
+    ) -> Result<Arc<Self>, ClachelessError> {
         let endpoint_string = format!("http://{address}");
-        let channel = Channel::from_shared(endpoint_string)
+        let channel = Endpoint::from_shared(endpoint_string)
             .map_err(|e| {
                 ClachelessErrorKind::Connection
                     .error_with_msg(format!("Failed to parse gRPC address '{address}': {e}"))
             })?
-            .connect()
-            .await
-            .map_err(|e| {
-                ClachelessErrorKind::Connection.error_with_msg(format!(
-                    "Failed to connect to gRPC address '{address}': {e}"
-                ))
-            })?;
+            .connect_timeout(Self::CONNECT_TIMEOUT)
+            .tcp_keepalive(Some(Self::TCP_KEEPALIVE))
+            .connect_lazy();
         let client = StateShareClient::with_interceptor(
             channel,
-            Self::authorization_interceptor as TonicInterceptorFn,
-        );
+            AuthorizationInterceptor {
+                clock: Arc::clone(clock),
+            },
+        )
+        .max_decoding_message_size(max_message_bytes)
+        .max_encoding_message_size(max_message_bytes);
         Ok(Arc::new(Self {
-            client: Mutex::new(client),
+            client,
             address: address.to_owned(),
         }))
     }
+}
 
-    /// Add token to request to prove that it is part of the same cluster.
-    fn authorization_interceptor(mut req: Request<()>) -> Result<Request<()>, Status> {
+#[async_trait]
+impl PeerClient for GrpcClient {
+    async fn stream_state_transfer(
+        &self,
+        session_id: u64,
+        reciever_node_ordinal: u32,
+        data_origin_id_and_ranges: HashMap<u64, SequenceRanges>,
+    ) -> Result<StateTransferStream, ClachelessError> {
+        let missing_ranges = data_origin_id_and_ranges
+            .iter()
+            .flat_map(|(origin_node_id, ranges)| {
+                ranges.iter().map(|range| SequenceRange {
+                    origin_node_id: *origin_node_id,
+                    first_seq: *range.start(),
+                    last_seq: *range.end(),
+                })
+            })
+            .collect();
+        let request = Request::new(StateTransferRequest {
+            session_id,
+            reciever_node_ordinal,
+            missing_ranges,
+        });
+        let mut client = self.client.clone();
+        let response = client.stream_state_transfer(request).await.map_err(|e| {
+            error_kind_of(&e).error_with_msg(format!(
+                "Requesting streamed state transfer from '{}' failed: {e}",
+                self.address
+            ))
+        })?;
+        let address = self.address.to_owned();
+        Ok(StateTransferStream::new(response.into_inner().map(
+            move |chunk| {
+                chunk.map_err(|e| {
+                    error_kind_of(&e).error_with_msg(format!(
+                        "Recieving state transfer from '{address}' failed: {e}"
+                    ))
+                })
+            },
+        )))
+    }
+
+    async fn send_update(&self, entry: CacheEntryAndKey) -> Result<(), ClachelessError> {
+        let request = Request::new(PutCacheEntryRequest::from(entry));
+        let mut client = self.client.clone();
+        let response = client.put_cache_entry(request).await.map_err(|e| {
+            error_kind_of(&e).error_with_msg(format!(
+                "Sending cache entry update to '{}' failed: {e}",
+                self.address
+            ))
+        })?;
         if log::log_enabled!(log::Level::Trace) {
-            log::trace!("authorization_interceptor");
-        }
-        if let Some(token) = PeerAuthenticator::instance().create_token() {
-            req.metadata_mut().insert(
-                PeerAuthenticator::HEADER_NAME,
-                token.parse::<MetadataValue<_>>().unwrap(),
-            );
-            if log::log_enabled!(log::Level::Trace) {
-                log::trace!("(client) authorization_interceptor: {req:?}");
-            }
+            log::trace!("send_update response: {response:?}");
         }
-        Ok(req)
+        Ok(())
     }
 
-    /// Request a state tranfer from the remote node.
-    pub async fn request_state_transfer(
+    async fn init_state_transfer(
         &self,
         reciever_node_ordinal: u32,
         data_origin_id_and_baseline: HashMap<u64, u64>,
@@ -97,63 +234,74 @@ impl GrpcClient {
             reciever_node_ordinal,
             data_origin_id_and_baseline,
         });
-        let mut client = self.client.lock().await;
-        let response = client.init_state_transfer(request).await.map_err(|e| {
-            ClachelessErrorKind::Connection.error_with_msg(format!(
+        let mut client = self.client.clone();
+        client.init_state_transfer(request).await.map_err(|e| {
+            error_kind_of(&e).error_with_msg(format!(
                 "Requesting state transfer from '{}' failed: {e}",
                 self.address
             ))
         })?;
-        if log::log_enabled!(log::Level::Trace) {
-            log::trace!("request_state_transfer response: {response:?}");
-        }
         Ok(())
     }
 
-    /// Send a cache entry update to the remote node.
-    pub async fn send_update(
+    async fn replicate_entries(
         &self,
-        key: String,
-        this_update_micros: u64,
-        expires: u64,
-        object_bytes: Vec<u8>,
-        origin_node_id: u64,
-        origin_node_update_seq: u64,
+        entries: Vec<CacheEntryAndKey>,
     ) -> Result<(), ClachelessError> {
-        let request = Request::new(PutCacheEntryRequest {
-            key,
-            this_update_micros,
-            expires,
-            object_bytes,
-            origin_node_id,
-            origin_node_update_seq,
-        });
-        let mut client = self.client.lock().await;
-        let response = client.put_cache_entry(request).await.map_err(|e| {
-            ClachelessErrorKind::Connection.error_with_msg(format!(
-                "Sending cache entry update to '{}' failed: {e}",
+        let count = entries.len();
+        let mut messages = vec![];
+        let mut message = ReplicateEntriesRequest::default();
+        let mut message_bytes = 0;
+        for entry in entries {
+            if message_bytes > 0 && message_bytes + entry.message_bytes() > Self::MAX_MESSAGE_BYTES
+            {
+                messages.push(std::mem::take(&mut message));
+                message_bytes = 0;
+            }
+            message_bytes += entry.message_bytes();
+            message.entries.push(PutCacheEntryRequest::from(entry));
+        }
+        messages.push(message);
+        let mut client = self.client.clone();
+        let response = client
+            .replicate_entries(tokio_stream::iter(messages))
+            .await
+            .map_err(|e| {
+                error_kind_of(&e).error_with_msg(format!(
+                    "Replicating entries to '{}' failed: {e}",
+                    self.address
+                ))
+            })?;
+        let applied_count = response.into_inner().applied_count;
+        if applied_count != u64::try_from(count).unwrap_or(u64::MAX) {
+            Err(ClachelessErrorKind::Unspecified.error_with_msg(format!(
+                "Only {applied_count} of {count} replicated entries were applied by '{}'.",
                 self.address
-            ))
-        })?;
-        if log::log_enabled!(log::Level::Trace) {
-            log::trace!("send_update response: {response:?}");
+            )))?;
         }
         Ok(())
     }
 
-    /// Send the local nodes cluster view to the remote.
-    pub async fn push_state_view(
+    async fn push_state_view(
         &self,
         sender_node_ordinal: u32,
+        sender_node_id: u64,
+        sender_timestamp_micros: u64,
+        sender_observer: bool,
         view: HashMap<u64, u64>,
-    ) -> Result<(), ClachelessError> {
+    ) -> Result<StateViewResponse, ClachelessError> {
         let request = Request::new(StateViewUpdateRequest {
             sender_node_ordinal,
+            sender_node_id,
+            sender_timestamp_micros,
+            sender_observer,
             view,
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: Capability::local_wire_names(),
         });
-        let mut client = self.client.lock().await;
+        let mut client = self.client.clone();
         let response = client.state_view_update(request).await.map_err(|e| {
-            ClachelessErrorKind::Connection.error_with_msg(format!(
+            error_kind_of(&e).error_with_msg(format!(
                 "Pushing state view to '{}' failed: {e}",
                 self.address
             ))
@@ -161,6 +309,150 @@ impl GrpcClient {
         if log::log_enabled!(log::Level::Trace) {
             log::trace!("push_state_view response: {response:?}");
         }
+        Ok(StateViewResponse::from(response.into_inner()))
+    }
+
+    async fn join(
+        &self,
+        sender_node_ordinal: u32,
+        sender_observer: bool,
+    ) -> Result<JoinResponse, ClachelessError> {
+        let request = Request::new(JoinRequest {
+            sender_node_ordinal,
+            sender_observer,
+        });
+        let mut client = self.client.clone();
+        let response = client.join(request).await.map_err(|e| {
+            error_kind_of(&e).error_with_msg(format!("Joining '{}' failed: {e}", self.address))
+        })?;
+        if log::log_enabled!(log::Level::Trace) {
+            log::trace!("join response: {response:?}");
+        }
+        Ok(JoinResponse::from(response.into_inner()))
+    }
+
+    async fn leave(&self, sender_node_ordinal: u32) -> Result<(), ClachelessError> {
+        let request = Request::new(LeaveRequest {
+            sender_node_ordinal,
+        });
+        let mut client = self.client.clone();
+        client.leave(request).await.map_err(|e| {
+            error_kind_of(&e).error_with_msg(format!("Leaving '{}' failed: {e}", self.address))
+        })?;
         Ok(())
     }
+
+    async fn anti_entropy_digests(
+        &self,
+        level: u32,
+        indexes: Vec<u32>,
+    ) -> Result<Vec<u64>, ClachelessError> {
+        let request = Request::new(AntiEntropyDigestsRequest { level, indexes });
+        let mut client = self.client.clone();
+        let response = client.anti_entropy_digests(request).await.map_err(|e| {
+            error_kind_of(&e).error_with_msg(format!(
+                "Requesting hash tree digests from '{}' failed: {e}",
+                self.address
+            ))
+        })?;
+        Ok(response.into_inner().digests)
+    }
+
+    async fn anti_entropy_versions(
+        &self,
+        leaf_indexes: Vec<u32>,
+    ) -> Result<Vec<EntryVersion>, ClachelessError> {
+        let request = Request::new(AntiEntropyVersionsRequest { leaf_indexes });
+        let mut client = self.client.clone();
+        let response = client.anti_entropy_versions(request).await.map_err(|e| {
+            error_kind_of(&e).error_with_msg(format!(
+                "Requesting entry versions from '{}' failed: {e}",
+                self.address
+            ))
+        })?;
+        Ok(response.into_inner().versions)
+    }
+
+    async fn fetch_entries(&self, keys: Vec<String>) -> Result<FetchEntriesReply, ClachelessError> {
+        let request = Request::new(FetchEntriesRequest { keys });
+        let mut client = self.client.clone();
+        let response = client.fetch_entries(request).await.map_err(|e| {
+            error_kind_of(&e).error_with_msg(format!(
+                "Fetching entries from '{}' failed: {e}",
+                self.address
+            ))
+        })?;
+        Ok(response.into_inner())
+    }
+}
+
+mod test {
+    //! gRPC client tests.
+
+    #[test]
+    fn test_only_local_failures_are_connection_errors() {
+        use super::error_kind_of;
+        use crate::ClachelessErrorKind;
+        use tonic::Status;
+
+        let refused = Status::from_error(Box::new(std::io::Error::from(
+            std::io::ErrorKind::ConnectionRefused,
+        )));
+        assert_eq!(error_kind_of(&refused), ClachelessErrorKind::Connection);
+        assert_eq!(
+            error_kind_of(&Status::unavailable("Node is leaving the cluster.")),
+            ClachelessErrorKind::Unavailable
+        );
+        assert_eq!(
+            error_kind_of(&Status::unauthenticated("No valid auth token.")),
+            ClachelessErrorKind::Unspecified
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_replicates_entry_of_max_document_size() {
+        use super::GrpcClient;
+        use crate::DistributedCache;
+        use crate::distributed_cache::grpc_server::run_grpc_server;
+        use crate::distributed_cache::local_cache::CacheEntry;
+        use crate::distributed_cache::local_cache::CacheEntryAndKey;
+        use crate::distributed_cache::peer_transport::PeerClient;
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        // Larger than the default message size limit of tonic
+        let address = "127.0.0.1:19471";
+        let dc = DistributedCache::new(address, 1, 30_000_000).await;
+        let dc_clone = Arc::clone(&dc);
+        tokio::spawn(async move { run_grpc_server(&dc_clone, 19471).await });
+        while !dc.is_started() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let max_document_size = dc.config.max_document_size();
+        let grpc_client = GrpcClient::new(
+            address,
+            dc.config.clock(),
+            GrpcClient::max_message_bytes(max_document_size),
+        )
+        .unwrap();
+        let now_micros = dc.config.clock().now_micros();
+        grpc_client
+            .replicate_entries(vec![CacheEntryAndKey {
+                key: "large".to_owned(),
+                ce: Arc::new(CacheEntry {
+                    this_update_micros: now_micros,
+                    origin_node_id: 0,
+                    origin_node_update_seq: 1,
+                    expires_micros: now_micros + 30_000_000,
+                    object_bytes: Arc::new(vec![7; max_document_size]),
+                    bridged: false,
+                }),
+            }])
+            .await
+            .unwrap();
+        assert_eq!(
+            dc.get_bytes("large").await.unwrap().len(),
+            max_document_size
+        );
+    }
 }
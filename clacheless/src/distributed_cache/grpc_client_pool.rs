@@ -0,0 +1,179 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Long-lived gRPC clients for each peer node.
+
+use super::DistributedCache;
+use super::grpc_client::GrpcClient;
+use super::grpc_server;
+use super::peer_transport::PeerClient;
+use super::peer_transport::PeerTransport;
+use crate::ClachelessError;
+use crate::time::Clock;
+use async_trait::async_trait;
+use crossbeam_skiplist::SkipMap;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+/// Shared client and when it was last handed out.
+struct PooledClient {
+    grpc_client: Arc<GrpcClient>,
+    last_used_micros: AtomicU64,
+}
+
+/** Pool of lazily connecting [GrpcClient]s by node ordinal.
+
+Clients are created on first use and reused for all subsequent requests to
+the same node. A client is dropped (and re-created on next use) when a request
+fails due to the connection or when it has been idle for too long.
+
+This is the [PeerTransport] used between Pods, where the gRPC server listens on
+the port of the address template.
+*/
+pub struct GrpcClientPool {
+    address_template: String,
+    clients: SkipMap<u32, Arc<PooledClient>>,
+    clock: Arc<dyn Clock>,
+    max_message_bytes: usize,
+}
+
+impl GrpcClientPool {
+    /// Return a new instance.
+    ///
+    /// `address_template` should be in the form a `fqdn:port` with the literal
+    /// string `ORDINAL` present. The `clock` tracks idle clients and dates
+    /// authentication tokens. Messages are limited to `max_message_bytes`.
+    pub fn new(address_template: &str, clock: &Arc<dyn Clock>, max_message_bytes: usize) -> Self {
+        Self {
+            address_template: address_template.to_owned(),
+            clients: SkipMap::default(),
+            clock: Arc::clone(clock),
+            max_message_bytes,
+        }
+    }
+
+    /// Extract gRPC address port from template or default to 9000.
+    fn bind_port(&self) -> u16 {
+        self.address_template
+            .match_indices(':')
+            .next_back()
+            .map(|(last_dash_index, _)| self.address_template.split_at(last_dash_index + 1).1)
+            .and_then(|ordinal_string| {
+                ordinal_string
+                    .parse::<u16>()
+                    .inspect_err(|e| log::debug!("Failed to parse ordinal '{ordinal_string}': {e}"))
+                    .ok()
+            })
+            .unwrap_or(9000)
+    }
+}
+
+#[async_trait]
+impl PeerTransport for GrpcClientPool {
+    /// Return the `fqdn:port` of the node.
+    fn address_for_node_ordinal(&self, node_ordinal: u32) -> String {
+        self.address_template
+            .replacen("ORDINAL", &node_ordinal.to_string(), 1)
+    }
+
+    fn client(&self, node_ordinal: u32) -> Result<Arc<dyn PeerClient>, ClachelessError> {
+        let now_micros = self.clock.now_micros();
+        if let Some(entry) = self.clients.get(&node_ordinal) {
+            let pooled_client = entry.value();
+            pooled_client
+                .last_used_micros
+                .store(now_micros, Ordering::Relaxed);
+            return Ok(Arc::clone(&pooled_client.grpc_client) as Arc<dyn PeerClient>);
+        }
+        let grpc_client = GrpcClient::new(
+            &self.address_for_node_ordinal(node_ordinal),
+            &self.clock,
+            self.max_message_bytes,
+        )?;
+        let entry = self.clients.get_or_insert(
+            node_ordinal,
+            Arc::new(PooledClient {
+                grpc_client,
+                last_used_micros: AtomicU64::new(now_micros),
+            }),
+        );
+        Ok(Arc::clone(&entry.value().grpc_client) as Arc<dyn PeerClient>)
+    }
+
+    fn evict(&self, node_ordinal: u32) {
+        if self.clients.remove(&node_ordinal).is_some() && log::log_enabled!(log::Level::Trace) {
+            log::trace!("Evicted gRPC client for node ordinal {node_ordinal}.");
+        }
+    }
+
+    fn evict_idle(&self, last_used_threshold_micros: u64) {
+        self.clients
+            .iter()
+            .filter(|entry| {
+                entry.value().last_used_micros.load(Ordering::Relaxed) < last_used_threshold_micros
+            })
+            .for_each(|entry| {
+                if entry.remove() && log::log_enabled!(log::Level::Debug) {
+                    log::debug!("Evicted idle gRPC client for node ordinal {}.", entry.key());
+                }
+            });
+    }
+
+    async fn serve(&self, dc: &Arc<DistributedCache>) -> Result<(), ClachelessError> {
+        grpc_server::run_grpc_server(dc, self.bind_port()).await
+    }
+}
+
+mod test {
+    //! gRPC client pool tests.
+
+    #[tokio::test]
+    async fn test_clients_are_reused_until_evicted() {
+        use super::GrpcClientPool;
+        use super::PeerTransport;
+        use crate::ClachelessErrorKind;
+        use crate::time::Clock;
+        use crate::time::ManualClock;
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        let manual_clock = ManualClock::new(1_000_000);
+        let clock: Arc<dyn Clock> = Arc::clone(&manual_clock) as Arc<dyn Clock>;
+        let pool = GrpcClientPool::new("clacheless-ORDINAL.local:9000", &clock, 4 * 1024 * 1024);
+        let client = pool.client(1).unwrap();
+        assert!(Arc::ptr_eq(&client, &pool.client(1).unwrap()));
+        // Errors returned by the node keep the client
+        pool.evict_on_connection_error(
+            1,
+            &ClachelessErrorKind::Unavailable.error_with_msg("Node is leaving the cluster."),
+        );
+        assert!(Arc::ptr_eq(&client, &pool.client(1).unwrap()));
+        pool.evict_on_connection_error(
+            1,
+            &ClachelessErrorKind::Connection.error_with_msg("Connection refused."),
+        );
+        let reconnected = pool.client(1).unwrap();
+        assert!(!Arc::ptr_eq(&client, &reconnected));
+        // Only clients that have been idle are evicted
+        manual_clock.advance(Duration::from_micros(1_000));
+        let other = pool.client(2).unwrap();
+        pool.evict_idle(1_000_500);
+        assert!(!Arc::ptr_eq(&reconnected, &pool.client(1).unwrap()));
+        assert!(Arc::ptr_eq(&other, &pool.client(2).unwrap()));
+    }
+}
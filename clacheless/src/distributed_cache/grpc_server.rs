@@ -18,64 +18,110 @@
 //! GRPC server for inter-Pod communication.
 
 use super::DistributedCache;
+use super::grpc_client::GrpcClient;
 use super::peer_authenticator::PeerAuthenticator;
+use super::peer_service::PeerService;
 use crate::ClachelessError;
 use crate::ClachelessErrorKind;
+use crate::proto::stateshare::AntiEntropyDigestsReply;
+use crate::proto::stateshare::AntiEntropyDigestsRequest;
+use crate::proto::stateshare::AntiEntropyVersionsReply;
+use crate::proto::stateshare::AntiEntropyVersionsRequest;
+use crate::proto::stateshare::FetchEntriesReply;
+use crate::proto::stateshare::FetchEntriesRequest;
 use crate::proto::stateshare::InitStateTransferReply;
 use crate::proto::stateshare::InitStateTransferRequest;
+use crate::proto::stateshare::JoinReply;
+use crate::proto::stateshare::JoinRequest;
+use crate::proto::stateshare::LeaveReply;
+use crate::proto::stateshare::LeaveRequest;
 use crate::proto::stateshare::PutCacheEntryReply;
 use crate::proto::stateshare::PutCacheEntryRequest;
+use crate::proto::stateshare::ReplicateEntriesReply;
+use crate::proto::stateshare::ReplicateEntriesRequest;
+use crate::proto::stateshare::StateTransferChunk;
+use crate::proto::stateshare::StateTransferRequest;
 use crate::proto::stateshare::StateViewUpdateReply;
 use crate::proto::stateshare::StateViewUpdateRequest;
 use crate::proto::stateshare::state_share_server::StateShare;
 use crate::proto::stateshare::state_share_server::StateShareServer;
+use crate::time::Clock;
+use std::pin::Pin;
 use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_stream::Stream;
+use tokio_stream::StreamExt;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::wrappers::TcpListenerStream;
 use tonic::Request;
 use tonic::Response;
 use tonic::Status;
+use tonic::Streaming;
 use tonic::async_trait;
+use tonic::service::interceptor::InterceptedService;
 use tonic::transport::Server;
 
 /// gRPC server implementation.
 struct StateShareImpl {
-    dc: Arc<DistributedCache>,
+    peer_service: PeerService,
+}
+
+/// Map a local error to a gRPC status.
+fn to_status(e: ClachelessError) -> Status {
+    match e.kind() {
+        ClachelessErrorKind::Unavailable => Status::unavailable(e.to_string()),
+        _ => Status::unknown(e.to_string()),
+    }
 }
 
 #[async_trait]
 impl StateShare for StateShareImpl {
+    type StreamStateTransferStream =
+        Pin<Box<dyn Stream<Item = Result<StateTransferChunk, Status>> + Send>>;
+
     /// Receive a cache entry from remote node.
     async fn put_cache_entry(
         &self,
         request: Request<PutCacheEntryRequest>,
     ) -> Result<Response<PutCacheEntryReply>, Status> {
-        let ur = request.into_inner();
-        self.dc
-            .put_raw_from_remote_origin(
-                ur.key,
-                ur.object_bytes,
-                ur.this_update_micros,
-                ur.expires,
-                ur.origin_node_id,
-                ur.origin_node_update_seq,
-            )
+        self.peer_service
+            .put_cache_entry(request.into_inner())
             .await
-            .map_err(|e| Status::unknown(e.to_string()))?;
+            .map_err(to_status)?;
         Ok(tonic::Response::new(PutCacheEntryReply::default()))
     }
 
+    /// Receive an ordered stream of cache entries from remote node.
+    async fn replicate_entries(
+        &self,
+        request: Request<Streaming<ReplicateEntriesRequest>>,
+    ) -> Result<Response<ReplicateEntriesReply>, Status> {
+        let mut stream = request.into_inner();
+        let mut applied_count = 0;
+        while let Some(rer) = stream.message().await? {
+            for ur in rer.entries {
+                self.peer_service
+                    .put_cache_entry(ur)
+                    .await
+                    .map_err(to_status)?;
+                applied_count += 1;
+            }
+        }
+        Ok(tonic::Response::new(ReplicateEntriesReply {
+            applied_count,
+        }))
+    }
+
     /// Receive remote node's view of the cluster.
     async fn state_view_update(
         &self,
         request: Request<StateViewUpdateRequest>,
     ) -> Result<Response<StateViewUpdateReply>, Status> {
-        let svr = request.into_inner();
-        if log::log_enabled!(log::Level::Trace) {
-            log::trace!("Got state update: {svr:?}");
-        }
-        self.dc
-            .on_state_view(svr.sender_node_ordinal, svr.view)
-            .await;
-        Ok(tonic::Response::new(StateViewUpdateReply {}))
+        Ok(tonic::Response::new(
+            self.peer_service
+                .state_view_update(request.into_inner())
+                .await,
+        ))
     }
 
     /// Receive a request for a state transfer
@@ -83,18 +129,72 @@ impl StateShare for StateShareImpl {
         &self,
         request: Request<InitStateTransferRequest>,
     ) -> Result<Response<InitStateTransferReply>, Status> {
-        let istr = request.into_inner();
-        if log::log_enabled!(log::Level::Trace) {
-            log::trace!("Got state transfer request: {istr:?}");
-        }
-        self.dc
-            .transfer_state(istr.reciever_node_ordinal, istr.data_origin_id_and_baseline)
+        self.peer_service
+            .init_state_transfer(request.into_inner())
             .await
-            .map_err(|e| Status::unknown(e.to_string()))?;
+            .map_err(to_status)?;
         Ok(tonic::Response::new(InitStateTransferReply {}))
     }
-}
 
+    /// Stream the entries that the requesting node is missing.
+    async fn stream_state_transfer(
+        &self,
+        request: Request<StateTransferRequest>,
+    ) -> Result<Response<Self::StreamStateTransferStream>, Status> {
+        let receiver = self
+            .peer_service
+            .stream_state_transfer(request.into_inner());
+        Ok(tonic::Response::new(Box::pin(
+            ReceiverStream::new(receiver).map(Ok),
+        )))
+    }
+
+    /// Receive an announcement from a remote node.
+    async fn join(&self, request: Request<JoinRequest>) -> Result<Response<JoinReply>, Status> {
+        self.peer_service
+            .join(request.into_inner())
+            .map(tonic::Response::new)
+            .map_err(to_status)
+    }
+
+    /// Receive a notice that a remote node is leaving.
+    async fn leave(&self, request: Request<LeaveRequest>) -> Result<Response<LeaveReply>, Status> {
+        self.peer_service.leave(request.into_inner());
+        Ok(tonic::Response::new(LeaveReply {}))
+    }
+
+    /// Return digests of the local hash tree.
+    async fn anti_entropy_digests(
+        &self,
+        request: Request<AntiEntropyDigestsRequest>,
+    ) -> Result<Response<AntiEntropyDigestsReply>, Status> {
+        Ok(tonic::Response::new(
+            self.peer_service.anti_entropy_digests(request.into_inner()),
+        ))
+    }
+
+    /// Return versions of the local entries covered by hash tree leaves.
+    async fn anti_entropy_versions(
+        &self,
+        request: Request<AntiEntropyVersionsRequest>,
+    ) -> Result<Response<AntiEntropyVersionsReply>, Status> {
+        Ok(tonic::Response::new(
+            self.peer_service
+                .anti_entropy_versions(request.into_inner())
+                .await,
+        ))
+    }
+
+    /// Return the local cache entries of the requested keys.
+    async fn fetch_entries(
+        &self,
+        request: Request<FetchEntriesRequest>,
+    ) -> Result<Response<FetchEntriesReply>, Status> {
+        Ok(tonic::Response::new(
+            self.peer_service.fetch_entries(request.into_inner()),
+        ))
+    }
+}
 /// Run gRPC server.
 ///
 /// This will not return for as long the server is running.
@@ -102,30 +202,52 @@ pub async fn run_grpc_server(
     dc: &Arc<DistributedCache>,
     bind_port: u16,
 ) -> Result<(), ClachelessError> {
-    let addr = format!("0.0.0.0:{bind_port}").parse().unwrap();
-    let state_share_impl = StateShareImpl { dc: Arc::clone(dc) };
+    let addr = format!("0.0.0.0:{bind_port}");
+    let state_share_impl = StateShareImpl {
+        peer_service: PeerService::new(dc),
+    };
+    let listener = TcpListener::bind(&addr).await.map_err(|e| {
+        ClachelessErrorKind::Unspecified
+            .error_with_msg(format!("Failed to bind gRPC server to {addr}: {e}"))
+    })?;
     log::info!("Clacheless gRPC service is listening on {addr}");
-    Server::builder()
-        .add_service(StateShareServer::with_interceptor(
-            state_share_impl,
-            authorization_interceptor,
+    dc.node_health.on_grpc_bound();
+    let token_validity_micros = dc.config.peer_token_validity_micros();
+    let clock = Arc::clone(dc.config.clock());
+    let max_message_bytes = GrpcClient::max_message_bytes(dc.config.max_document_size());
+    let res = Server::builder()
+        .add_service(InterceptedService::new(
+            StateShareServer::new(state_share_impl)
+                .max_decoding_message_size(max_message_bytes)
+                .max_encoding_message_size(max_message_bytes),
+            move |req| authorization_interceptor(req, token_validity_micros, clock.as_ref()),
         ))
-        .serve(addr)
+        .serve_with_incoming(TcpListenerStream::new(listener))
         .await
         .map_err(|e| {
             ClachelessErrorKind::Unspecified
                 .error_with_msg(format!("Failed to start gRPC server: {e}"))
-        })
+        });
+    dc.node_health.on_grpc_stopped();
+    res
 }
 
 /// Validate token of request ensure that it is part of the same cluster.
-fn authorization_interceptor(req: Request<()>) -> Result<Request<()>, Status> {
+fn authorization_interceptor(
+    req: Request<()>,
+    token_validity_micros: u64,
+    clock: &dyn Clock,
+) -> Result<Request<()>, Status> {
     if log::log_enabled!(log::Level::Trace) {
         log::trace!("(server) authorization_interceptor: {req:?}");
     }
     match req.metadata().get(PeerAuthenticator::HEADER_NAME) {
         Some(token)
-            if PeerAuthenticator::instance().is_token_valid(token.to_str().unwrap_or_default()) =>
+            if PeerAuthenticator::instance().is_token_valid(
+                token.to_str().unwrap_or_default(),
+                token_validity_micros,
+                clock,
+            ) =>
         {
             Ok(req)
         }
@@ -0,0 +1,197 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Updates kept for a peer that is down until it is heard from again.
+
+use super::local_cache::CacheEntryAndKey;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/** Hinted handoff of replication updates to a single peer.
+
+While the peer is down, updates that could not be sent are kept in order so
+that they can be replayed as soon as the peer comes back. This is cheaper than
+letting the peer request a state transfer for the gap.
+
+The hints are bounded by `max_bytes` of object data. When the limit is
+exceeded, all hints are discarded and no new ones are kept until the peer is
+back, since the peer has to recover the gap with a state transfer anyway.
+*/
+pub struct HintedHandoff {
+    max_bytes: usize,
+    state: Mutex<HintsState>,
+}
+
+#[derive(Default)]
+struct HintsState {
+    /// When the peer was first considered down in epoch microseconds.
+    peer_down_since_micros: Option<u64>,
+    entries: VecDeque<CacheEntryAndKey>,
+    bytes: usize,
+    discarded: bool,
+}
+
+impl HintsState {
+    /// Drop all hints and stop keeping new ones until the peer is back.
+    fn discard(&mut self) {
+        self.entries.clear();
+        self.bytes = 0;
+        self.discarded = true;
+    }
+}
+
+/// Return the memory accounted for the entries.
+fn size_of<'a>(entries: impl Iterator<Item = &'a CacheEntryAndKey>) -> usize {
+    entries
+        .map(|entry| entry.key.len() + entry.ce.object_bytes.len())
+        .sum()
+}
+
+impl HintedHandoff {
+    /// Return a new instance.
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Consider the peer down from `now_micros` unless it already is.
+    pub fn on_peer_down(&self, now_micros: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.peer_down_since_micros.get_or_insert(now_micros);
+    }
+
+    /// Return when the peer was first considered down or `None` if it is up.
+    pub fn peer_down_since_micros(&self) -> Option<u64> {
+        self.state.lock().unwrap().peer_down_since_micros
+    }
+
+    /// Keep the entries until the peer is back.
+    ///
+    /// Returns `false` if the entries were not kept (along with any previous
+    /// hints) due to the memory limit.
+    pub fn add(&self, entries: Vec<CacheEntryAndKey>) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.discarded {
+            return false;
+        }
+        let bytes = size_of(entries.iter());
+        if state.bytes + bytes > self.max_bytes {
+            state.discard();
+            return false;
+        }
+        state.bytes += bytes;
+        state.entries.extend(entries);
+        true
+    }
+
+    /// Consider the peer up and return the kept entries in the order they
+    /// were added.
+    pub fn take(&self) -> Vec<CacheEntryAndKey> {
+        let mut state = self.state.lock().unwrap();
+        let entries = std::mem::take(&mut state.entries);
+        *state = HintsState::default();
+        entries.into()
+    }
+
+    /// Put back entries that could not be replayed in front of any entries
+    /// added since they were taken and consider the peer down again.
+    ///
+    /// Returns `false` if the entries were not kept (along with any other
+    /// hints) due to the memory limit.
+    pub fn restore(&self, entries: Vec<CacheEntryAndKey>, now_micros: u64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.peer_down_since_micros.get_or_insert(now_micros);
+        if state.discarded {
+            return false;
+        }
+        let bytes = size_of(entries.iter());
+        if state.bytes + bytes > self.max_bytes {
+            state.discard();
+            return false;
+        }
+        state.bytes += bytes;
+        let added_since = std::mem::replace(&mut state.entries, entries.into());
+        state.entries.extend(added_since);
+        true
+    }
+
+    /// Return the number of kept entries and their size in bytes.
+    pub fn size(&self) -> (usize, usize) {
+        let state = self.state.lock().unwrap();
+        (state.entries.len(), state.bytes)
+    }
+}
+
+mod test {
+    //! Hinted handoff tests.
+
+    #[cfg(test)]
+    fn entry(key: &str, object_len: usize) -> super::CacheEntryAndKey {
+        use super::super::local_cache::CacheEntry;
+        use std::sync::Arc;
+
+        super::CacheEntryAndKey {
+            key: key.to_owned(),
+            ce: Arc::new(CacheEntry {
+                this_update_micros: 1,
+                origin_node_id: 1,
+                origin_node_update_seq: 1,
+                expires_micros: u64::MAX,
+                object_bytes: Arc::new(vec![0; object_len]),
+                bridged: false,
+            }),
+        }
+    }
+
+    #[cfg(test)]
+    fn keys(entries: &[super::CacheEntryAndKey]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.key.as_str()).collect()
+    }
+
+    #[test]
+    fn test_replay_order_survives_failed_replay() {
+        let hinted_handoff = super::HintedHandoff::new(100);
+        assert_eq!(hinted_handoff.peer_down_since_micros(), None);
+        hinted_handoff.on_peer_down(10);
+        hinted_handoff.on_peer_down(20);
+        assert_eq!(hinted_handoff.peer_down_since_micros(), Some(10));
+        assert!(hinted_handoff.add(vec![entry("a", 9), entry("b", 9)]));
+        let taken = hinted_handoff.take();
+        assert_eq!(keys(&taken), ["a", "b"]);
+        assert_eq!(hinted_handoff.peer_down_since_micros(), None);
+        assert!(hinted_handoff.add(vec![entry("c", 9)]));
+        assert!(hinted_handoff.restore(taken, 30));
+        assert_eq!(hinted_handoff.peer_down_since_micros(), Some(30));
+        assert_eq!(hinted_handoff.size(), (3, 30));
+        assert_eq!(keys(&hinted_handoff.take()), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_memory_limit_discards_until_peer_is_back() {
+        let hinted_handoff = super::HintedHandoff::new(25);
+        hinted_handoff.on_peer_down(10);
+        assert!(hinted_handoff.add(vec![entry("a", 9), entry("b", 9)]));
+        assert!(!hinted_handoff.add(vec![entry("c", 9)]));
+        assert_eq!(hinted_handoff.size(), (0, 0));
+        assert!(!hinted_handoff.add(vec![entry("d", 0)]));
+        assert!(hinted_handoff.take().is_empty());
+        assert!(hinted_handoff.add(vec![entry("e", 9)]));
+        assert_eq!(hinted_handoff.size(), (1, 10));
+    }
+}
@@ -0,0 +1,431 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Transport between nodes in the same process.
+
+use super::DistributedCache;
+use super::cluster_view::SequenceRanges;
+use super::local_cache::CacheEntryAndKey;
+use super::peer_service::PeerService;
+use super::peer_transport::JoinResponse;
+use super::peer_transport::PeerClient;
+use super::peer_transport::PeerTransport;
+use super::peer_transport::StateTransferStream;
+use super::peer_transport::StateViewResponse;
+use super::protocol::Capability;
+use super::protocol::PROTOCOL_VERSION;
+use crate::ClachelessError;
+use crate::ClachelessErrorKind;
+use crate::proto::stateshare::AntiEntropyDigestsRequest;
+use crate::proto::stateshare::AntiEntropyVersionsRequest;
+use crate::proto::stateshare::EntryVersion;
+use crate::proto::stateshare::FetchEntriesReply;
+use crate::proto::stateshare::FetchEntriesRequest;
+use crate::proto::stateshare::InitStateTransferRequest;
+use crate::proto::stateshare::JoinRequest;
+use crate::proto::stateshare::LeaveRequest;
+use crate::proto::stateshare::PutCacheEntryRequest;
+use crate::proto::stateshare::SequenceRange;
+use crate::proto::stateshare::StateTransferRequest;
+use crate::proto::stateshare::StateViewUpdateRequest;
+use async_trait::async_trait;
+use crossbeam_skiplist::SkipMap;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Weak;
+use tokio::time::Duration;
+use tokio_stream::StreamExt;
+use tokio_stream::wrappers::ReceiverStream;
+use tyst::Tyst;
+
+/** Simulated network between [DistributedCache] instances in a single process.
+
+Give each instance the same network with
+[ClachelessConfig::with_in_memory_network](crate::ClachelessConfig::with_in_memory_network)
+and a unique node ordinal. Requests between the instances are then delivered
+by direct calls instead of gRPC, which allows tests of a whole cluster without
+binding any ports.
+
+Faults can be injected at any time:
+
+* [Self::set_latency] delays each request. Random jitter lets concurrent
+  requests overtake each other.
+* [Self::set_loss_percent] fails a share of the requests.
+* [Self::partition] splits the nodes into groups that can't reach each other
+  until [Self::heal] is called.
+
+```
+use clacheless::InMemoryNetwork;
+use std::time::Duration;
+
+let network = InMemoryNetwork::new();
+network.set_latency(Duration::from_millis(1), Duration::from_millis(5));
+network.partition(&[&[0], &[1, 2]]);
+assert!(!network.is_reachable(0, 1));
+network.heal();
+assert!(network.is_reachable(0, 1));
+```
+*/
+pub struct InMemoryNetwork {
+    nodes: SkipMap<u32, Weak<DistributedCache>>,
+    faults: Mutex<Faults>,
+}
+
+/// Currently injected faults.
+#[derive(Clone, Default)]
+struct Faults {
+    latency: Duration,
+    jitter: Duration,
+    loss_percent: u8,
+    /// Partition group by node ordinal. Nodes without a group form their own
+    /// shared group.
+    partition_groups: HashMap<u32, usize>,
+}
+
+impl fmt::Debug for InMemoryNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InMemoryNetwork").finish_non_exhaustive()
+    }
+}
+
+impl InMemoryNetwork {
+    /// Return a new network without any faults.
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            nodes: SkipMap::default(),
+            faults: Mutex::default(),
+        })
+    }
+
+    /// Delay each request by `latency` plus a random part of `jitter`.
+    pub fn set_latency(&self, latency: Duration, jitter: Duration) {
+        let mut faults = self.faults.lock().unwrap();
+        faults.latency = latency;
+        faults.jitter = jitter;
+    }
+
+    /// Fail `loss_percent` (`0..=100`) of the requests at random.
+    pub fn set_loss_percent(&self, loss_percent: u8) {
+        self.faults.lock().unwrap().loss_percent = loss_percent.min(100);
+    }
+
+    /// Split the nodes into groups that can only reach nodes in the same
+    /// group.
+    ///
+    /// Nodes that are not part of any group can still reach each other.
+    pub fn partition(&self, groups: &[&[u32]]) {
+        let partition_groups = groups
+            .iter()
+            .enumerate()
+            .flat_map(|(group, node_ordinals)| {
+                node_ordinals
+                    .iter()
+                    .map(move |node_ordinal| (*node_ordinal, group + 1))
+            })
+            .collect();
+        self.faults.lock().unwrap().partition_groups = partition_groups;
+    }
+
+    /// Remove all partitions.
+    pub fn heal(&self) {
+        self.faults.lock().unwrap().partition_groups.clear();
+    }
+
+    /// Return `true` if requests from one node can reach the other node.
+    pub fn is_reachable(&self, from_node_ordinal: u32, to_node_ordinal: u32) -> bool {
+        let faults = self.faults.lock().unwrap();
+        let group_of = |node_ordinal| {
+            faults
+                .partition_groups
+                .get(&node_ordinal)
+                .copied()
+                .unwrap_or_default()
+        };
+        group_of(from_node_ordinal) == group_of(to_node_ordinal)
+    }
+
+    /// Apply the injected faults to a request and return the service of the
+    /// node it was delivered to.
+    async fn deliver(
+        &self,
+        from_node_ordinal: u32,
+        to_node_ordinal: u32,
+    ) -> Result<PeerService, ClachelessError> {
+        let faults = self.faults.lock().unwrap().clone();
+        let jitter_micros = u64::try_from(faults.jitter.as_micros()).unwrap_or(u64::MAX);
+        let delay = faults.latency + Duration::from_micros(random_below(jitter_micros + 1));
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        if !self.is_reachable(from_node_ordinal, to_node_ordinal) {
+            Err(ClachelessErrorKind::Connection.error_with_msg(format!(
+                "Node ordinal {to_node_ordinal} is unreachable from node ordinal {from_node_ordinal}."
+            )))?;
+        }
+        if random_below(100) < u64::from(faults.loss_percent) {
+            Err(ClachelessErrorKind::Connection.error_with_msg(format!(
+                "Request from node ordinal {from_node_ordinal} to node ordinal {to_node_ordinal} was lost."
+            )))?;
+        }
+        self.nodes
+            .get(&to_node_ordinal)
+            .and_then(|entry| entry.value().upgrade())
+            .map(|dc| PeerService::new(&dc))
+            .ok_or_else(|| {
+                ClachelessErrorKind::Connection.error_with_msg(format!(
+                    "No node with ordinal {to_node_ordinal} is serving."
+                ))
+            })
+    }
+}
+
+/// Return a random value in `0..max_value`.
+fn random_below(max_value: u64) -> u64 {
+    if max_value <= 1 {
+        return 0;
+    }
+    let random_bytes = Tyst::instance().prng_get_random_bytes(None, 8);
+    u64::from_be_bytes(random_bytes.try_into().unwrap_or_default()) % max_value
+}
+
+/// [PeerTransport] of a single node on an [InMemoryNetwork].
+pub struct InMemoryTransport {
+    network: Arc<InMemoryNetwork>,
+    local_node_ordinal: u32,
+}
+
+impl InMemoryTransport {
+    /// Return a new instance.
+    pub fn new(network: &Arc<InMemoryNetwork>, local_node_ordinal: u32) -> Self {
+        Self {
+            network: Arc::clone(network),
+            local_node_ordinal,
+        }
+    }
+}
+
+/// Serving registration of a node that is removed when dropped.
+struct Registration {
+    network: Arc<InMemoryNetwork>,
+    node_ordinal: u32,
+    dc: Weak<DistributedCache>,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        if let Some(entry) = self.network.nodes.get(&self.node_ordinal)
+            && Weak::ptr_eq(entry.value(), &self.dc)
+        {
+            entry.remove();
+        }
+    }
+}
+
+#[async_trait]
+impl PeerTransport for InMemoryTransport {
+    fn address_for_node_ordinal(&self, node_ordinal: u32) -> String {
+        format!("in-memory-{node_ordinal}")
+    }
+
+    fn client(&self, node_ordinal: u32) -> Result<Arc<dyn PeerClient>, ClachelessError> {
+        Ok(Arc::new(InMemoryClient {
+            network: Arc::clone(&self.network),
+            local_node_ordinal: self.local_node_ordinal,
+            node_ordinal,
+        }))
+    }
+
+    fn evict(&self, _node_ordinal: u32) {}
+
+    fn evict_idle(&self, _last_used_threshold_micros: u64) {}
+
+    async fn serve(&self, dc: &Arc<DistributedCache>) -> Result<(), ClachelessError> {
+        let dc = Arc::downgrade(dc);
+        self.network
+            .nodes
+            .insert(self.local_node_ordinal, Weak::clone(&dc));
+        let _registration = Registration {
+            network: Arc::clone(&self.network),
+            node_ordinal: self.local_node_ordinal,
+            dc: Weak::clone(&dc),
+        };
+        if let Some(dc) = dc.upgrade() {
+            dc.node_health.on_grpc_bound();
+        }
+        std::future::pending().await
+    }
+}
+
+/// [PeerClient] for a remote node on an [InMemoryNetwork].
+struct InMemoryClient {
+    network: Arc<InMemoryNetwork>,
+    local_node_ordinal: u32,
+    node_ordinal: u32,
+}
+
+impl InMemoryClient {
+    /// Deliver a request to the remote node.
+    async fn deliver(&self) -> Result<PeerService, ClachelessError> {
+        self.network
+            .deliver(self.local_node_ordinal, self.node_ordinal)
+            .await
+    }
+}
+
+#[async_trait]
+impl PeerClient for InMemoryClient {
+    async fn stream_state_transfer(
+        &self,
+        session_id: u64,
+        reciever_node_ordinal: u32,
+        data_origin_id_and_ranges: HashMap<u64, SequenceRanges>,
+    ) -> Result<StateTransferStream, ClachelessError> {
+        let missing_ranges = data_origin_id_and_ranges
+            .iter()
+            .flat_map(|(origin_node_id, ranges)| {
+                ranges.iter().map(|range| SequenceRange {
+                    origin_node_id: *origin_node_id,
+                    first_seq: *range.start(),
+                    last_seq: *range.end(),
+                })
+            })
+            .collect();
+        let receiver = self
+            .deliver()
+            .await?
+            .stream_state_transfer(StateTransferRequest {
+                session_id,
+                reciever_node_ordinal,
+                missing_ranges,
+            });
+        Ok(StateTransferStream::new(
+            ReceiverStream::new(receiver).map(Ok),
+        ))
+    }
+
+    async fn send_update(&self, entry: CacheEntryAndKey) -> Result<(), ClachelessError> {
+        self.deliver()
+            .await?
+            .put_cache_entry(PutCacheEntryRequest::from(entry))
+            .await
+    }
+
+    async fn init_state_transfer(
+        &self,
+        reciever_node_ordinal: u32,
+        data_origin_id_and_baseline: HashMap<u64, u64>,
+    ) -> Result<(), ClachelessError> {
+        self.deliver()
+            .await?
+            .init_state_transfer(InitStateTransferRequest {
+                reciever_node_ordinal,
+                data_origin_id_and_baseline,
+            })
+            .await
+    }
+
+    async fn replicate_entries(
+        &self,
+        entries: Vec<CacheEntryAndKey>,
+    ) -> Result<(), ClachelessError> {
+        let peer_service = self.deliver().await?;
+        for entry in entries {
+            peer_service
+                .put_cache_entry(PutCacheEntryRequest::from(entry))
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn push_state_view(
+        &self,
+        sender_node_ordinal: u32,
+        sender_node_id: u64,
+        sender_timestamp_micros: u64,
+        sender_observer: bool,
+        view: HashMap<u64, u64>,
+    ) -> Result<StateViewResponse, ClachelessError> {
+        let reply = self
+            .deliver()
+            .await?
+            .state_view_update(StateViewUpdateRequest {
+                sender_node_ordinal,
+                sender_node_id,
+                sender_timestamp_micros,
+                sender_observer,
+                view,
+                protocol_version: PROTOCOL_VERSION,
+                capabilities: Capability::local_wire_names(),
+            })
+            .await;
+        Ok(StateViewResponse::from(reply))
+    }
+
+    async fn join(
+        &self,
+        sender_node_ordinal: u32,
+        sender_observer: bool,
+    ) -> Result<JoinResponse, ClachelessError> {
+        self.deliver()
+            .await?
+            .join(JoinRequest {
+                sender_node_ordinal,
+                sender_observer,
+            })
+            .map(JoinResponse::from)
+    }
+
+    async fn leave(&self, sender_node_ordinal: u32) -> Result<(), ClachelessError> {
+        self.deliver().await?.leave(LeaveRequest {
+            sender_node_ordinal,
+        });
+        Ok(())
+    }
+
+    async fn anti_entropy_digests(
+        &self,
+        level: u32,
+        indexes: Vec<u32>,
+    ) -> Result<Vec<u64>, ClachelessError> {
+        Ok(self
+            .deliver()
+            .await?
+            .anti_entropy_digests(AntiEntropyDigestsRequest { level, indexes })
+            .digests)
+    }
+
+    async fn anti_entropy_versions(
+        &self,
+        leaf_indexes: Vec<u32>,
+    ) -> Result<Vec<EntryVersion>, ClachelessError> {
+        Ok(self
+            .deliver()
+            .await?
+            .anti_entropy_versions(AntiEntropyVersionsRequest { leaf_indexes })
+            .await
+            .versions)
+    }
+
+    async fn fetch_entries(&self, keys: Vec<String>) -> Result<FetchEntriesReply, ClachelessError> {
+        Ok(self
+            .deliver()
+            .await?
+            .fetch_entries(FetchEntriesRequest { keys }))
+    }
+}
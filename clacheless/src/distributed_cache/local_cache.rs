@@ -17,12 +17,16 @@
 
 //! Local copy of the distributed cache.
 
+use super::cluster_view::SequenceRanges;
 use crate::ClachelessError;
 use crate::ClachelessErrorKind;
+use crate::time::Clock;
 use crossbeam_skiplist::SkipMap;
 use crossbeam_skiplist::map::Entry;
 use std::collections::HashMap;
+use std::ops::Bound;
 use std::sync::Arc;
+use tokio::sync::mpsc;
 
 /// Cached object and meta data.
 pub struct CacheEntry {
@@ -37,25 +41,106 @@ pub struct CacheEntry {
     pub expires_micros: u64,
     /// Raw bytes of the cached object.
     pub object_bytes: Arc<Vec<u8>>,
+    /// `true` if the cache entry was recieved from another cluster through a
+    /// bridge.
+    pub bridged: bool,
+}
+
+impl CacheEntry {
+    /// Return the version of the cache entry.
+    ///
+    /// Versions are totally ordered so that all nodes pick the same winner
+    /// when two writes to the same key have identical timestamps.
+    pub fn version(&self) -> (u64, u64, u64) {
+        (
+            self.this_update_micros,
+            self.origin_node_id,
+            self.origin_node_update_seq,
+        )
+    }
+}
+
+/// How a write originated on the local node is versioned against the existing
+/// entry of the key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overwrite {
+    /// Keep the update timestamp of the write. The existing entry is kept if
+    /// it has a newer version.
+    UnlessNewer,
+    /// Skip the write if the existing entry has the same or a more recent
+    /// update timestamp.
+    OnlyIfOlder,
+    /// Move the update timestamp past the existing entry if needed, so that
+    /// the write always replaces it.
+    Always,
+}
+
+impl Overwrite {
+    /// Return the update timestamp to write with or `None` to skip the write.
+    pub fn update_micros(
+        &self,
+        this_update_micros: u64,
+        existing: Option<&CacheEntry>,
+    ) -> Option<u64> {
+        let Some(existing) = existing else {
+            return Some(this_update_micros);
+        };
+        match self {
+            Self::UnlessNewer => Some(this_update_micros),
+            Self::OnlyIfOlder => {
+                (existing.this_update_micros < this_update_micros).then_some(this_update_micros)
+            }
+            // A later timestamp supersedes the existing version regardless of
+            // the origin node ids
+            Self::Always => {
+                Some(this_update_micros.max(existing.this_update_micros.saturating_add(1)))
+            }
+        }
+    }
 }
 
 /// [CacheEntry] and the cached item's lookup key.
+#[derive(Clone)]
 pub struct CacheEntryAndKey {
     /// Lookup key the cache entry is referenced by.
     pub key: String,
+    /// The cache entry.
     pub ce: Arc<CacheEntry>,
 }
 
+impl CacheEntryAndKey {
+    /// Allowance for the meta data and field tags of an entry in a message.
+    const MESSAGE_OVERHEAD_BYTES: usize = 64;
+
+    /// Return the approximate size of the entry in a message to another node.
+    pub fn message_bytes(&self) -> usize {
+        self.key.len() + self.ce.object_bytes.len() + Self::MESSAGE_OVERHEAD_BYTES
+    }
+}
+
 /// Lock-free local copy of the distributed cache.
 pub struct LocalCache {
     cache: SkipMap<String, Arc<CacheEntry>>,
+    purge_interval_micros: u64,
+    clock: Arc<dyn Clock>,
+    expired_sender: Option<mpsc::UnboundedSender<CacheEntryAndKey>>,
 }
 
 impl LocalCache {
-    /// Return a new instance.
-    pub async fn new() -> Arc<Self> {
+    /// Return a new instance that purges expired entries every
+    /// `purge_interval_micros` according to the `clock`.
+    ///
+    /// Purged entries are sent to `expired_sender` (if any).
+    pub async fn new(
+        purge_interval_micros: u64,
+        clock: &Arc<dyn Clock>,
+        expired_sender: Option<mpsc::UnboundedSender<CacheEntryAndKey>>,
+    ) -> Arc<Self> {
         Arc::new(Self {
             cache: SkipMap::default(),
+            purge_interval_micros,
+            clock: Arc::clone(clock),
+            expired_sender,
         })
         .purge_expired()
         .await
@@ -66,35 +151,58 @@ impl LocalCache {
         let ret = Arc::clone(&self);
         tokio::spawn(async move {
             loop {
-                let now_micros = crate::time::get_timestamp_micros();
+                let now_micros = self.clock.now_micros();
                 let mut count = 0;
                 self.cache
                     .iter()
                     .filter(|entry| entry.value().expires_micros < now_micros)
                     .for_each(|entry| {
-                        entry.remove().then(|| {
+                        if entry.remove() {
                             count += 1;
-                        });
+                            if let Some(expired_sender) = &self.expired_sender {
+                                expired_sender
+                                    .send(CacheEntryAndKey {
+                                        key: entry.key().to_owned(),
+                                        ce: Arc::clone(entry.value()),
+                                    })
+                                    .ok();
+                            }
+                        }
                     });
                 if count > 0 {
                     log::info!("Purged {count} expired items from cache.");
                 }
-                tokio::time::sleep(tokio::time::Duration::from_micros(30_000_000)).await;
+                tokio::time::sleep(tokio::time::Duration::from_micros(
+                    self.purge_interval_micros,
+                ))
+                .await;
             }
         });
         ret
     }
 
+    /// Return the number of entries and the total size of the cached objects
+    /// in bytes.
+    pub fn size(&self) -> (u64, u64) {
+        self.cache.iter().fold((0, 0), |(entries, bytes), entry| {
+            (
+                entries + 1,
+                bytes + u64::try_from(entry.value().object_bytes.len()).unwrap(),
+            )
+        })
+    }
+
     /// Return an iterator over all cached items that are non-expired and
-    /// more up to date than the provided cluster view.
+    /// where the update sequence number is part of the provided ranges of the
+    /// item's origin node.
     ///
     /// Items are sorted by update origin node's update sequence to allow
     /// state transfer to send oldest items first.
     pub fn iter(
         &self,
-        data_origin_id_and_baseline: &HashMap<u64, u64>,
+        data_origin_id_and_ranges: &HashMap<u64, SequenceRanges>,
     ) -> impl Iterator<Item = CacheEntryAndKey> {
-        let now_micros = crate::time::get_timestamp_micros();
+        let now_micros = self.clock.now_micros();
         /*
         To send the oldest entries first we need to sort by
         `origin_node_update_seq`.
@@ -109,13 +217,13 @@ impl LocalCache {
             .iter()
             .filter_map(move |entry| {
                 let ce = Arc::clone(entry.value());
-                data_origin_id_and_baseline
+                data_origin_id_and_ranges
                     .get(&ce.origin_node_id)
-                    .is_some_and(|baseline| {
+                    .is_some_and(|ranges| {
                         if log::log_enabled!(log::Level::Trace) {
-                            log::trace!("ce.this_update_micros: {}, baseline: {baseline}, ce.expires_micros: {}, now_micros: {now_micros}", ce.this_update_micros, ce.expires_micros);
+                            log::trace!("ce.origin_node_update_seq: {}, ranges: {ranges:?}, ce.expires_micros: {}, now_micros: {now_micros}", ce.origin_node_update_seq, ce.expires_micros);
                         }
-                        ce.this_update_micros > *baseline && ce.expires_micros > now_micros
+                        ranges.contains(ce.origin_node_update_seq) && ce.expires_micros > now_micros
                     })
                     .then_some((entry.key().to_owned(),ce.origin_node_update_seq))
             })
@@ -129,40 +237,86 @@ impl LocalCache {
         })
     }
 
+    /// Return up to `limit` non-expired cache entries ordered by key, starting
+    /// after `after_key`.
+    ///
+    /// Allows scanning the whole cache in batches without holding on to
+    /// references into the underlying map.
+    pub fn entries_after(&self, after_key: Option<&str>, limit: usize) -> Vec<CacheEntryAndKey> {
+        let now_micros = self.clock.now_micros();
+        let lower_bound = after_key.map_or(Bound::Unbounded, Bound::Excluded);
+        self.cache
+            .range::<str, _>((lower_bound, Bound::Unbounded))
+            .filter(|entry| entry.value().expires_micros > now_micros)
+            .take(limit)
+            .map(|entry| CacheEntryAndKey {
+                key: entry.key().to_owned(),
+                ce: Arc::clone(entry.value()),
+            })
+            .collect()
+    }
+
+    /// Return up to `limit` non-expired cache entries with keys starting with
+    /// `prefix`, ordered by key and starting after `after_key`.
+    pub fn entries_with_prefix_after(
+        &self,
+        prefix: &str,
+        after_key: Option<&str>,
+        limit: usize,
+    ) -> Vec<CacheEntryAndKey> {
+        let now_micros = self.clock.now_micros();
+        let lower_bound = after_key
+            .filter(|after_key| *after_key >= prefix)
+            .map_or(Bound::Included(prefix), Bound::Excluded);
+        self.cache
+            .range::<str, _>((lower_bound, Bound::Unbounded))
+            .take_while(|entry| entry.key().starts_with(prefix))
+            .filter(|entry| entry.value().expires_micros > now_micros)
+            .take(limit)
+            .map(|entry| CacheEntryAndKey {
+                key: entry.key().to_owned(),
+                ce: Arc::clone(entry.value()),
+            })
+            .collect()
+    }
+
+    /// Get non-expired cache entry including meta data.
+    pub fn get_entry(&self, cache_key: &str) -> Option<Arc<CacheEntry>> {
+        self.cache
+            .get(cache_key)
+            .as_ref()
+            .map(Entry::value)
+            .filter(|cde| cde.expires_micros > self.clock.now_micros())
+            .cloned()
+    }
+
     /// Get non-expired cache item.
     pub fn get(&self, cache_key: &str) -> Result<Arc<Vec<u8>>, ClachelessError> {
         self.cache
             .get(cache_key)
             .as_ref()
             .map(Entry::value)
-            .filter(|cde| cde.expires_micros >= crate::time::get_timestamp_micros())
+            .filter(|cde| cde.expires_micros >= self.clock.now_micros())
             .map(|cde| Arc::clone(&cde.object_bytes))
             .ok_or_else(|| {
                 ClachelessErrorKind::NotFound.error_with_msg(format!("No entry for {cache_key}."))
             })
     }
 
-    /// Insert item in cache if it is newer than the existing one.
-    pub fn put(
+    /// Insert shared cache entry if it is newer than the existing one.
+    ///
+    /// Returns `false` if the existing entry was kept.
+    pub fn put_entry(
         &self,
         cache_key: String,
-        cache_value: Vec<u8>,
-        this_update_micros: u64,
-        origin_node_id: u64,
-        origin_node_update_seq: u64,
-        expires_micros: u64,
-    ) -> Result<(), ClachelessError> {
-        self.cache.compare_insert(
-            cache_key,
-            Arc::new(CacheEntry {
-                this_update_micros,
-                origin_node_id,
-                origin_node_update_seq,
-                expires_micros,
-                object_bytes: Arc::new(cache_value),
-            }),
-            |old_cde| old_cde.this_update_micros < this_update_micros,
-        );
-        Ok(())
+        ce: Arc<CacheEntry>,
+    ) -> Result<bool, ClachelessError> {
+        let version = ce.version();
+        let entry = self
+            .cache
+            .compare_insert(cache_key, Arc::clone(&ce), |old_cde| {
+                old_cde.version() < version
+            });
+        Ok(Arc::ptr_eq(entry.value(), &ce))
     }
 }
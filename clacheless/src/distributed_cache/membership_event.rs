@@ -0,0 +1,51 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Notifications about nodes joining and leaving the cluster.
+
+/// A change of the cluster membership as seen from the local node.
+///
+/// See [DistributedCache::membership_events](crate::DistributedCache::membership_events).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MembershipEvent {
+    /// A remote node was heard from for the first time or after it was lost.
+    Joined {
+        /// Ordinal of the remote node.
+        node_ordinal: u32,
+    },
+    /// A remote node left the cluster or could no longer be reached.
+    ///
+    /// Each `Left` follows exactly one `Joined` of the same node.
+    Left {
+        /// Ordinal of the remote node.
+        node_ordinal: u32,
+    },
+    /// The local node started to recieve missing updates from a remote node.
+    SyncStarted {
+        /// Ordinal of the node that sends the updates.
+        node_ordinal: u32,
+    },
+    /// A state transfer from a remote node has ended.
+    SyncCompleted {
+        /// Ordinal of the node that sent the updates.
+        node_ordinal: u32,
+        /// `false` if the transfer was given up before all updates were
+        /// recieved. Another transfer is started when the gap is noticed
+        /// again.
+        succeeded: bool,
+    },
+}
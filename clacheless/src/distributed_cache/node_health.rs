@@ -0,0 +1,161 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Startup, readiness and liveness of the local node.
+
+use crossbeam_skiplist::SkipMap;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+/** Health of the local node.
+
+The node is started once the gRPC server accepts connections from other nodes.
+
+The node is ready when it is started and has synchronized with at least one
+other node, either through a completed state transfer or by learning that it
+already is up to date with a node that holds data. A node that doesn't hear
+from any such node is ready after the initial synchronization timeout.
+
+The node is live for as long as the gRPC server is running and every
+background loop has reported a heartbeat recently.
+*/
+pub struct NodeHealth {
+    initial_sync_timeout_micros: u64,
+    run_started_micros: AtomicU64,
+    grpc_bound: AtomicBool,
+    grpc_stopped: AtomicBool,
+    initial_sync_done: AtomicBool,
+    /// Name of each background loop mapped to the time of the last heartbeat
+    /// and the longest allowed time between heartbeats.
+    heartbeats: SkipMap<&'static str, (u64, u64)>,
+}
+
+impl NodeHealth {
+    /// Return a new instance.
+    pub fn new(initial_sync_timeout_micros: u64) -> Self {
+        Self {
+            initial_sync_timeout_micros,
+            run_started_micros: AtomicU64::default(),
+            grpc_bound: AtomicBool::default(),
+            grpc_stopped: AtomicBool::default(),
+            initial_sync_done: AtomicBool::default(),
+            heartbeats: SkipMap::default(),
+        }
+    }
+
+    /// Invoked when the node starts to run.
+    pub fn on_run_started(&self, now_micros: u64) {
+        self.run_started_micros.store(now_micros, Ordering::Relaxed);
+    }
+
+    /// Invoked when the gRPC server has bound its port.
+    pub fn on_grpc_bound(&self) {
+        self.grpc_bound.store(true, Ordering::Relaxed);
+    }
+
+    /// Invoked when the gRPC server has stopped.
+    pub fn on_grpc_stopped(&self) {
+        self.grpc_stopped.store(true, Ordering::Relaxed);
+    }
+
+    /// Invoked when the local node has synchronized with another node.
+    pub fn on_synchronized(&self, sender_ordinal: u32) {
+        if !self.initial_sync_done.swap(true, Ordering::Relaxed) {
+            log::info!("Initial synchronization with node ordinal '{sender_ordinal}' completed.");
+        }
+    }
+
+    /// Invoked by a background loop in each iteration.
+    ///
+    /// The loop is considered stuck if the next heartbeat doesn't arrive
+    /// within `max_interval_micros`.
+    pub fn on_heartbeat(&self, loop_name: &'static str, now_micros: u64, max_interval_micros: u64) {
+        self.heartbeats
+            .insert(loop_name, (now_micros, max_interval_micros));
+    }
+
+    /// Return `true` if the node accepts connections from other nodes.
+    pub fn is_started(&self) -> bool {
+        self.grpc_bound.load(Ordering::Relaxed)
+    }
+
+    /// Return `true` if the node is started and has synchronized with other
+    /// nodes or has given up waiting for them.
+    pub fn is_ready(&self, now_micros: u64) -> bool {
+        if !self.is_started() {
+            return false;
+        }
+        if self.initial_sync_done.load(Ordering::Relaxed) {
+            return true;
+        }
+        let run_started_micros = self.run_started_micros.load(Ordering::Relaxed);
+        if now_micros < run_started_micros + self.initial_sync_timeout_micros {
+            return false;
+        }
+        if !self.initial_sync_done.swap(true, Ordering::Relaxed) {
+            log::info!("Initial synchronization timed out. Assuming this is the only node.");
+        }
+        true
+    }
+
+    /// Return `true` if the gRPC server and all background loops are running.
+    pub fn is_live(&self, now_micros: u64) -> bool {
+        if self.grpc_stopped.load(Ordering::Relaxed) {
+            return false;
+        }
+        self.heartbeats.iter().all(|entry| {
+            let (heartbeat_micros, max_interval_micros) = *entry.value();
+            let is_alive = heartbeat_micros + max_interval_micros >= now_micros;
+            if !is_alive {
+                log::warn!("Background loop '{}' has stalled.", entry.key());
+            }
+            is_alive
+        })
+    }
+}
+
+mod test {
+    //! Node health tests.
+
+    #[test]
+    fn test_ready_after_sync_or_timeout() {
+        let node_health = super::NodeHealth::new(10);
+        node_health.on_run_started(100);
+        assert!(!node_health.is_ready(200));
+        node_health.on_grpc_bound();
+        assert!(!node_health.is_ready(105));
+        assert!(node_health.is_ready(110));
+        let node_health = super::NodeHealth::new(10);
+        node_health.on_run_started(100);
+        node_health.on_grpc_bound();
+        node_health.on_synchronized(1);
+        assert!(node_health.is_ready(101));
+    }
+
+    #[test]
+    fn test_live_until_loop_stalls() {
+        let node_health = super::NodeHealth::new(10);
+        node_health.on_heartbeat("loop", 100, 5);
+        assert!(node_health.is_live(105));
+        assert!(!node_health.is_live(106));
+        node_health.on_heartbeat("loop", 106, 5);
+        assert!(node_health.is_live(106));
+        node_health.on_grpc_stopped();
+        assert!(!node_health.is_live(106));
+    }
+}
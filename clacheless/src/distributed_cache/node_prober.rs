@@ -0,0 +1,147 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Discovery of nodes that has not announced themselves.
+
+use crossbeam_skiplist::SkipMap;
+use crossbeam_skiplist::SkipSet;
+use crossbeam_skiplist::map::Entry;
+
+/// Failed probe attempts of a node ordinal.
+struct ProbeBackoff {
+    /// Number of consecutive failed probes.
+    failures: u32,
+    /// Earliest time for the next probe attempt in epoch microseconds.
+    next_attempt_micros: u64,
+}
+
+/** Keeps track of which node ordinals to probe and when.
+
+Each failed probe doubles the delay before the next attempt to the same node
+ordinal, starting from `base_delay_micros` and capped at `max_delay_micros`.
+*/
+pub struct NodeProber {
+    base_delay_micros: u64,
+    max_delay_micros: u64,
+    backoffs: SkipMap<u32, ProbeBackoff>,
+    candidates: SkipSet<u32>,
+}
+
+impl NodeProber {
+    /// Return a new instance.
+    pub fn new(base_delay_micros: u64, max_delay_micros: u64) -> Self {
+        Self {
+            base_delay_micros,
+            max_delay_micros,
+            backoffs: SkipMap::default(),
+            candidates: SkipSet::default(),
+        }
+    }
+
+    /// Return `true` if the node ordinal isn't backing off from a previous
+    /// failed probe.
+    pub fn is_due(&self, node_ordinal: u32, now_micros: u64) -> bool {
+        self.backoffs
+            .get(&node_ordinal)
+            .as_ref()
+            .map(Entry::value)
+            .is_none_or(|backoff| backoff.next_attempt_micros <= now_micros)
+    }
+
+    /// Add a node ordinal reported by another node to the next probe round.
+    pub fn add_candidate(&self, node_ordinal: u32) {
+        self.candidates.insert(node_ordinal);
+    }
+
+    /// Remove and return node ordinals reported by other nodes since the last
+    /// probe round.
+    pub fn take_candidates(&self) -> Vec<u32> {
+        std::iter::from_fn(|| self.candidates.pop_front())
+            .map(|entry| *entry.value())
+            .collect()
+    }
+
+    /// Forget about any backoff once the node ordinal has been reached.
+    pub fn on_success(&self, node_ordinal: u32) {
+        self.backoffs.remove(&node_ordinal);
+    }
+
+    /// Delay the next probe of the node ordinal.
+    pub fn on_failure(&self, node_ordinal: u32, now_micros: u64) {
+        let failures = self
+            .backoffs
+            .get(&node_ordinal)
+            .as_ref()
+            .map(Entry::value)
+            .map_or(0, |backoff| backoff.failures)
+            .saturating_add(1);
+        let delay_micros = self
+            .base_delay_micros
+            .saturating_mul(1 << (failures - 1).min(16))
+            .min(self.max_delay_micros);
+        if log::log_enabled!(log::Level::Trace) {
+            log::trace!(
+                "Probe of node ordinal {node_ordinal} failed {failures} time(s). Next attempt in {delay_micros} micros."
+            );
+        }
+        self.backoffs.insert(
+            node_ordinal,
+            ProbeBackoff {
+                failures,
+                next_attempt_micros: now_micros.saturating_add(delay_micros),
+            },
+        );
+    }
+}
+
+mod test {
+    //! Node prober tests.
+
+    #[test]
+    fn test_failed_probes_back_off_exponentially() {
+        use super::NodeProber;
+
+        let node_prober = NodeProber::new(1_000, 5_000);
+        assert!(node_prober.is_due(3, 0));
+        node_prober.on_failure(3, 0);
+        assert!(!node_prober.is_due(3, 999));
+        assert!(node_prober.is_due(3, 1_000));
+        node_prober.on_failure(3, 1_000);
+        assert!(!node_prober.is_due(3, 2_999));
+        assert!(node_prober.is_due(3, 3_000));
+        node_prober.on_failure(3, 3_000);
+        node_prober.on_failure(3, 3_000);
+        // Capped at the max delay
+        assert!(node_prober.is_due(3, 8_000));
+        // Other ordinals are not affected
+        assert!(node_prober.is_due(4, 0));
+        node_prober.on_success(3);
+        assert!(node_prober.is_due(3, 3_000));
+    }
+
+    #[test]
+    fn test_candidates_are_taken_once() {
+        use super::NodeProber;
+
+        let node_prober = NodeProber::new(1_000, 5_000);
+        node_prober.add_candidate(5);
+        node_prober.add_candidate(4);
+        node_prober.add_candidate(5);
+        assert_eq!(node_prober.take_candidates(), vec![4, 5]);
+        assert!(node_prober.take_candidates().is_empty());
+    }
+}
@@ -0,0 +1,136 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Reports of healed network partitions.
+
+/// How a key that was written on both sides of a partition was resolved.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictResolution {
+    /// The local write was newer and was kept.
+    LocalWon,
+    /// The remote write was newer and replaced the local one.
+    RemoteWon,
+}
+
+/// A key that was written on both sides of a partition.
+#[derive(Clone, Debug)]
+pub struct KeyConflict {
+    pub(super) key: String,
+    pub(super) resolution: ConflictResolution,
+    pub(super) local_update_micros: u64,
+    pub(super) local_origin_node_id: u64,
+    pub(super) remote_update_micros: u64,
+    pub(super) remote_origin_node_id: u64,
+}
+
+impl KeyConflict {
+    /// Return the key that was written on both sides.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Return which write was kept. The newest write wins.
+    pub fn resolution(&self) -> ConflictResolution {
+        self.resolution
+    }
+
+    /// Return when the write on the local side happened in epoch
+    /// microseconds.
+    pub fn local_update_micros(&self) -> u64 {
+        self.local_update_micros
+    }
+
+    /// Return the node id where the write on the local side was recieved.
+    pub fn local_origin_node_id(&self) -> u64 {
+        self.local_origin_node_id
+    }
+
+    /// Return when the write on the remote side happened in epoch
+    /// microseconds.
+    pub fn remote_update_micros(&self) -> u64 {
+        self.remote_update_micros
+    }
+
+    /// Return the node id where the write on the remote side was recieved.
+    pub fn remote_origin_node_id(&self) -> u64 {
+        self.remote_origin_node_id
+    }
+}
+
+/** A partition between the local node and a remote node that has healed.
+
+Both sides of the partition accepted writes while they couldn't reach each
+other. When the partition healed, the local node ran an anti-entropy round
+with the remote node to merge the two sides.
+*/
+#[derive(Clone, Debug)]
+pub struct PartitionReport {
+    pub(super) node_ordinal: u32,
+    pub(super) node_id: u64,
+    pub(super) split_micros: u64,
+    pub(super) healed_micros: u64,
+    pub(super) split_epoch: u64,
+    pub(super) healed_epoch: u64,
+    pub(super) conflict_count: usize,
+    pub(super) conflicts: Vec<KeyConflict>,
+}
+
+impl PartitionReport {
+    /// Return the ordinal of the remote node.
+    pub fn node_ordinal(&self) -> u32 {
+        self.node_ordinal
+    }
+
+    /// Return the identifier of the remote node.
+    pub fn node_id(&self) -> u64 {
+        self.node_id
+    }
+
+    /// Return when the remote node was last heard from before the partition
+    /// in epoch microseconds.
+    pub fn split_micros(&self) -> u64 {
+        self.split_micros
+    }
+
+    /// Return when the sides were merged in epoch microseconds.
+    pub fn healed_micros(&self) -> u64 {
+        self.healed_micros
+    }
+
+    /// Return the local membership epoch when the remote node was lost.
+    pub fn split_epoch(&self) -> u64 {
+        self.split_epoch
+    }
+
+    /// Return the local membership epoch when the remote node rejoined.
+    pub fn healed_epoch(&self) -> u64 {
+        self.healed_epoch
+    }
+
+    /// Return the number of keys that were written on both sides.
+    pub fn conflict_count(&self) -> usize {
+        self.conflict_count
+    }
+
+    /// Return the keys that were written on both sides, ordered by key.
+    ///
+    /// Only a limited number of keys are kept for large partitions, so this
+    /// may be fewer than [Self::conflict_count].
+    pub fn conflicts(&self) -> &[KeyConflict] {
+        &self.conflicts
+    }
+}
@@ -0,0 +1,330 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Detection of network partitions where both sides accepted writes.
+
+use super::partition_report::ConflictResolution;
+use super::partition_report::KeyConflict;
+use super::partition_report::PartitionReport;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+/// A remote node that the local node has lost connectivity to.
+struct Separation {
+    node_id: u64,
+    since_micros: u64,
+    split_epoch: u64,
+    view_at_split: HashMap<u64, u64>,
+    conflicts: BTreeMap<String, KeyConflict>,
+    healing: bool,
+}
+
+/** Tracks membership epochs and separations from remote nodes.
+
+The membership epoch is increased every time a node joins or is lost. When a
+lost node is heard from again, the cluster views of both sides tell if both
+sides accepted writes while they were separated. Such a partition is healed by
+merging the two sides, and every key that was written on both sides is
+reported as a conflict.
+
+Keys are only considered conflicting when the local entry was written on this
+side of the partition and the recieved entry on the other side, both after
+the remote node was last heard from.
+*/
+#[derive(Default)]
+pub struct PartitionTracker {
+    membership_epoch: AtomicU64,
+    separations: Mutex<HashMap<u32, Separation>>,
+    reports: Mutex<VecDeque<PartitionReport>>,
+}
+
+impl PartitionTracker {
+    /// Number of healed partitions to keep reports for.
+    const MAX_REPORTS: usize = 16;
+    /// Maximum number of conflicting keys to list in a report.
+    const MAX_REPORTED_CONFLICTS: usize = 1024;
+
+    /// Return the current membership epoch.
+    pub fn membership_epoch(&self) -> u64 {
+        self.membership_epoch.load(Ordering::Relaxed)
+    }
+
+    /// Increase the membership epoch when a node joins or is lost.
+    pub fn on_membership_changed(&self) {
+        self.membership_epoch.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Register that the local node lost connectivity to a remote node that
+    /// was last heard from at `since_micros`.
+    ///
+    /// `view_at_split` is the local cluster view at the time.
+    pub fn on_peer_lost(
+        &self,
+        node_ordinal: u32,
+        node_id: u64,
+        since_micros: u64,
+        view_at_split: HashMap<u64, u64>,
+    ) {
+        let split_epoch = self.membership_epoch();
+        let mut separations = self.separations.lock().unwrap();
+        if let Some(separation) = separations.get_mut(&node_ordinal) {
+            // Lost again while healing; keep the original split
+            separation.healing = false;
+            return;
+        }
+        separations.insert(
+            node_ordinal,
+            Separation {
+                node_id,
+                since_micros,
+                split_epoch,
+                view_at_split,
+                conflicts: BTreeMap::new(),
+                healing: false,
+            },
+        );
+    }
+
+    /// Compare the version of a local entry with the version of an entry for
+    /// the same key from a remote origin and record a conflict if they were
+    /// written on different sides of a partition.
+    ///
+    /// Versions are `(this_update_micros, origin_node_id, origin_node_update_seq)`.
+    pub fn on_versions_compared(
+        &self,
+        key: &str,
+        local_version: (u64, u64, u64),
+        remote_version: (u64, u64, u64),
+    ) {
+        if local_version == remote_version {
+            return;
+        }
+        let mut separations = self.separations.lock().unwrap();
+        if separations.is_empty() || separations.contains_key(&Self::ordinal_of(local_version.1)) {
+            return;
+        }
+        let Some(separation) = separations.get_mut(&Self::ordinal_of(remote_version.1)) else {
+            return;
+        };
+        if local_version.0 < separation.since_micros || remote_version.0 < separation.since_micros {
+            return;
+        }
+        let resolution = if local_version > remote_version {
+            ConflictResolution::LocalWon
+        } else {
+            ConflictResolution::RemoteWon
+        };
+        separation.conflicts.insert(
+            key.to_owned(),
+            KeyConflict {
+                key: key.to_owned(),
+                resolution,
+                local_update_micros: local_version.0,
+                local_origin_node_id: local_version.1,
+                remote_update_micros: remote_version.0,
+                remote_origin_node_id: remote_version.1,
+            },
+        );
+    }
+
+    /// Return `true` if the local node has lost connectivity to the remote node
+    /// and the separation hasn't been resolved yet.
+    pub fn is_separated(&self, node_ordinal: u32) -> bool {
+        self.separations.lock().unwrap().contains_key(&node_ordinal)
+    }
+
+    /// Invoked when a node is heard from with its cluster view.
+    ///
+    /// Returns `true` if the node was separated from the local node and both
+    /// sides accepted writes in the meantime. The caller should then merge the
+    /// two sides and call [Self::finish_healing].
+    pub fn on_peer_view(
+        &self,
+        node_ordinal: u32,
+        node_id: u64,
+        remote_view: &HashMap<u64, u64>,
+        local_view: &HashMap<u64, u64>,
+    ) -> bool {
+        let mut separations = self.separations.lock().unwrap();
+        let Some(separation) = separations.get(&node_ordinal) else {
+            return false;
+        };
+        if separation.healing {
+            return false;
+        }
+        if node_id != 0 && separation.node_id != 0 && node_id != separation.node_id {
+            // The node restarted and could not have accepted writes
+            separations.remove(&node_ordinal);
+            return false;
+        }
+        let advanced_since_split = |view: &HashMap<u64, u64>, separated: bool| {
+            view.iter().any(|(origin_node_id, seq)| {
+                separations.contains_key(&Self::ordinal_of(*origin_node_id)) == separated
+                    && *seq
+                        > separation
+                            .view_at_split
+                            .get(origin_node_id)
+                            .copied()
+                            .unwrap_or_default()
+            })
+        };
+        let is_partition = !separation.conflicts.is_empty()
+            || (advanced_since_split(remote_view, true) && advanced_since_split(local_view, false));
+        if is_partition {
+            separations.get_mut(&node_ordinal).unwrap().healing = true;
+        } else {
+            separations.remove(&node_ordinal);
+        }
+        is_partition
+    }
+
+    /// Complete the healing of a partition with a remote node and return the
+    /// report.
+    pub fn finish_healing(&self, node_ordinal: u32, healed_micros: u64) -> Option<PartitionReport> {
+        let separation = {
+            let mut separations = self.separations.lock().unwrap();
+            if !separations
+                .get(&node_ordinal)
+                .is_some_and(|separation| separation.healing)
+            {
+                return None;
+            }
+            separations.remove(&node_ordinal)?
+        };
+        let report = PartitionReport {
+            node_ordinal,
+            node_id: separation.node_id,
+            split_micros: separation.since_micros,
+            healed_micros,
+            split_epoch: separation.split_epoch,
+            healed_epoch: self.membership_epoch(),
+            conflict_count: separation.conflicts.len(),
+            conflicts: separation
+                .conflicts
+                .into_values()
+                .take(Self::MAX_REPORTED_CONFLICTS)
+                .collect(),
+        };
+        let mut reports = self.reports.lock().unwrap();
+        if reports.len() == Self::MAX_REPORTS {
+            reports.pop_front();
+        }
+        reports.push_back(report.clone());
+        Some(report)
+    }
+
+    /// Forget a remote node that seems to be gone for good.
+    pub fn discard(&self, node_ordinal: u32) {
+        self.separations.lock().unwrap().remove(&node_ordinal);
+    }
+
+    /// Return the reports of the most recently healed partitions, oldest
+    /// first.
+    pub fn reports(&self) -> Vec<PartitionReport> {
+        self.reports.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn ordinal_of(node_id: u64) -> u32 {
+        (node_id & 0xffff_ffff) as u32
+    }
+}
+
+mod test {
+    //! Partition detection tests.
+
+    #[test]
+    fn test_conflicts_are_reported_when_partition_heals() {
+        use super::ConflictResolution;
+        use super::PartitionTracker;
+        use std::collections::HashMap;
+
+        let tracker = PartitionTracker::default();
+        let (local_id, remote_id) = (1 << 32, 7 << 32 | 1);
+        tracker.on_membership_changed();
+        tracker.on_peer_lost(
+            1,
+            remote_id,
+            1_000,
+            HashMap::from([(local_id, 5), (remote_id, 9)]),
+        );
+        tracker.on_membership_changed();
+        // Written on both sides after the split
+        tracker.on_versions_compared("a", (2_000, local_id, 6), (3_000, remote_id, 10));
+        tracker.on_versions_compared("b", (4_000, local_id, 7), (3_500, remote_id, 11));
+        // Written before the split on the local side
+        tracker.on_versions_compared("c", (500, local_id, 2), (3_000, remote_id, 12));
+        assert!(tracker.on_peer_view(
+            1,
+            remote_id,
+            &HashMap::from([(local_id, 5), (remote_id, 12)]),
+            &HashMap::from([(local_id, 7), (remote_id, 9)]),
+        ));
+        let report = tracker.finish_healing(1, 5_000).unwrap();
+        assert_eq!(report.split_epoch(), 1);
+        assert_eq!(report.healed_epoch(), 2);
+        assert_eq!(report.conflict_count(), 2);
+        assert_eq!(report.conflicts()[0].key(), "a");
+        assert_eq!(
+            report.conflicts()[0].resolution(),
+            ConflictResolution::RemoteWon
+        );
+        assert_eq!(
+            report.conflicts()[1].resolution(),
+            ConflictResolution::LocalWon
+        );
+        assert_eq!(tracker.reports().len(), 1);
+    }
+
+    #[test]
+    fn test_separation_without_writes_on_both_sides_is_not_a_partition() {
+        use super::PartitionTracker;
+        use std::collections::HashMap;
+
+        let tracker = PartitionTracker::default();
+        let (local_id, remote_id) = (1 << 32, 7 << 32 | 1);
+        tracker.on_peer_lost(
+            1,
+            remote_id,
+            1_000,
+            HashMap::from([(local_id, 5), (remote_id, 9)]),
+        );
+        assert!(!tracker.on_peer_view(
+            1,
+            remote_id,
+            &HashMap::from([(local_id, 5), (remote_id, 9)]),
+            &HashMap::from([(local_id, 8), (remote_id, 9)]),
+        ));
+        // A restarted node is not a partition either
+        tracker.on_peer_lost(
+            1,
+            remote_id,
+            1_000,
+            HashMap::from([(local_id, 5), (remote_id, 9)]),
+        );
+        assert!(!tracker.on_peer_view(
+            1,
+            8 << 32 | 1,
+            &HashMap::from([(8 << 32 | 1, 3)]),
+            &HashMap::from([(local_id, 8)]),
+        ));
+        assert!(tracker.finish_healing(1, 5_000).is_none());
+    }
+}
@@ -17,6 +17,7 @@
 
 //! Simplistic authentication for distributed cache communication.
 
+use crate::time::Clock;
 use std::sync::Arc;
 use std::sync::OnceLock;
 use tyst::Tyst;
@@ -47,8 +48,6 @@ pub struct PeerAuthenticator {
 impl PeerAuthenticator {
     /// Recommended header name
     pub const HEADER_NAME: &str = "internal-auth";
-    /// Authorization ticket validity duration
-    const TOKEN_VALIDITY: u64 = 1_000_000;
 
     fn new() -> Arc<Self> {
         // Read secret from file (136 bytes for HMAC-SHA3-256)
@@ -86,9 +85,9 @@ impl PeerAuthenticator {
         AUTHENTICATOR.get_or_init(Self::new).clone()
     }
 
-    /// Get short-lived peer authentication token.
-    pub fn create_token(&self) -> Option<String> {
-        let now_micros = crate::time::get_timestamp_micros();
+    /// Get short-lived peer authentication token dated by the `clock`.
+    pub fn create_token(&self, clock: &dyn Clock) -> Option<String> {
+        let now_micros = clock.now_micros();
         let mut time_and_mac = now_micros.to_be_bytes().to_vec();
         self.create_mac(&time_and_mac)
             .map(|mac| {
@@ -98,8 +97,14 @@ impl PeerAuthenticator {
             .map(|time_and_mac| tyst::encdec::base64::encode_url(&time_and_mac, false))
     }
 
-    /// Validate peer authentication token.
-    pub fn is_token_valid(&self, b64urlenc: &str) -> bool {
+    /// Validate peer authentication token that was created at most
+    /// `token_validity_micros` ago according to the `clock`.
+    pub fn is_token_valid(
+        &self,
+        b64urlenc: &str,
+        token_validity_micros: u64,
+        clock: &dyn Clock,
+    ) -> bool {
         let time_and_mac = tyst::encdec::base64::decode_url(b64urlenc).unwrap_or_default();
         if time_and_mac.is_empty() {
             return false;
@@ -107,9 +112,9 @@ impl PeerAuthenticator {
         let mut time_bytes = [0u8; 8];
         time_bytes.copy_from_slice(&time_and_mac[0..8]);
         let ts_micros = u64::from_be_bytes(time_bytes);
-        let now_micros = crate::time::get_timestamp_micros();
+        let now_micros = clock.now_micros();
         let mac = self.create_mac(&time_and_mac[0..8]).unwrap_or_default();
-        mac.eq(&time_and_mac[8..]) && ts_micros > now_micros - Self::TOKEN_VALIDITY
+        mac.eq(&time_and_mac[8..]) && ts_micros > now_micros.saturating_sub(token_validity_micros)
     }
 
     /// Create a HMAC-SHA3-256 message authenctication code of message.
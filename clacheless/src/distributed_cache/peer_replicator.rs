@@ -0,0 +1,530 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Ordered and batched replication of cache entries to a single peer.
+
+use super::hinted_handoff::HintedHandoff;
+use super::local_cache::CacheEntryAndKey;
+use super::peer_transport::PeerTransport;
+use super::protocol::Capability;
+use super::protocol::PeerProtocols;
+use super::retry_backoff::RetryBackoff;
+use crate::ClachelessConfig;
+use crate::ClachelessError;
+use crate::ClachelessErrorKind;
+use crate::time::Clock;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use tokio::sync::Notify;
+use tokio::sync::mpsc;
+use tokio::sync::oneshot;
+use tokio::time::Duration;
+use tokio::time::Instant;
+
+/** Outbound replication queue of a single peer node.
+
+A dedicated task drains the bounded queue and sends the entries in the order
+they were enqueued. Entries that arrive within a short time window (or until
+the byte budget is exhausted) are coalesced into a single `ReplicateEntries`
+call, or sent one by one to peers that don't support it.
+
+Entries that don't fit in a full queue can be dropped, which marks the peer as
+needing a state transfer of the skipped updates.
+
+Failed sends are retried with exponential backoff and jitter. When the retries
+are exhausted, the peer is considered down and further entries are kept as
+hints (see [HintedHandoff]) instead of being sent. The hints are replayed in
+order once the peer is heard from again (see [Self::on_peer_alive]).
+
+Dropping the instance lets the task finish sending what is already queued.
+*/
+pub struct PeerReplicator {
+    sender: mpsc::Sender<Queued>,
+    shared: Arc<SharedState>,
+}
+
+/// State shared between the instance and its sending task.
+struct SharedState {
+    node_ordinal: u32,
+    peer_transport: Arc<dyn PeerTransport>,
+    peer_protocols: Arc<PeerProtocols>,
+    dropped_entries: AtomicU64,
+    needs_state_transfer: AtomicBool,
+    hinted_handoff: HintedHandoff,
+    peer_alive: Notify,
+    clock: Arc<dyn Clock>,
+}
+
+/// Reserved room for one entry in the replication queue of a peer.
+pub struct QueueSlot(mpsc::OwnedPermit<Queued>);
+
+impl QueueSlot {
+    /// Queue the entry in the reserved room without waiting.
+    pub fn send(self, entry: CacheEntryAndKey) {
+        self.0.send(Queued::Entry(entry));
+    }
+}
+
+/// Item of the replication queue.
+enum Queued {
+    /// Entry to replicate.
+    Entry(CacheEntryAndKey),
+    /// Marker that is acknowledged once all entries queued before it have
+    /// been sent.
+    Flush(oneshot::Sender<()>),
+}
+
+impl PeerReplicator {
+    /// How long to wait for more entries before sending a batch.
+    const BATCH_WINDOW: Duration = Duration::from_millis(5);
+    /// Approximate maximum size of a batch, well below the gRPC message limit.
+    /// A single larger entry is sent in a batch of its own.
+    pub const BATCH_MAX_BYTES: usize = 1024 * 1024;
+
+    /// Return a new instance and start the sending task.
+    pub fn new(
+        node_ordinal: u32,
+        peer_transport: &Arc<dyn PeerTransport>,
+        peer_protocols: &Arc<PeerProtocols>,
+        config: &ClachelessConfig,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel(config.replication_queue_capacity());
+        let shared = Arc::new(SharedState {
+            node_ordinal,
+            peer_transport: Arc::clone(peer_transport),
+            peer_protocols: Arc::clone(peer_protocols),
+            dropped_entries: AtomicU64::default(),
+            needs_state_transfer: AtomicBool::default(),
+            hinted_handoff: HintedHandoff::new(config.hinted_handoff_max_bytes()),
+            peer_alive: Notify::default(),
+            clock: Arc::clone(config.clock()),
+        });
+        let shared_clone = Arc::clone(&shared);
+        tokio::spawn(async move { Self::run(shared_clone, receiver).await });
+        Self { sender, shared }
+    }
+
+    /// Return the number of entries (and flush markers) waiting to be sent.
+    pub fn queue_depth(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
+    }
+
+    /// Return `true` if there is no room for more entries in the queue.
+    pub fn is_full(&self) -> bool {
+        self.sender.capacity() == 0
+    }
+
+    /// Return the number of entries that were dropped because the queue or
+    /// the hinted handoff was full.
+    pub fn dropped_entries(&self) -> u64 {
+        self.shared.dropped_entries.load(Ordering::Relaxed)
+    }
+
+    /// Return `true` if entries were dropped since the last time the peer was
+    /// told about the local cluster view.
+    pub fn needs_state_transfer(&self) -> bool {
+        self.shared.needs_state_transfer.load(Ordering::Relaxed)
+    }
+
+    /// Clear the state transfer mark once the peer has recieved a cluster view
+    /// that reveals the skipped updates.
+    pub fn on_state_view_pushed(&self) {
+        self.shared
+            .needs_state_transfer
+            .store(false, Ordering::Relaxed);
+    }
+
+    /// Return the number of entries kept for the peer while it is down and
+    /// their size in bytes.
+    pub fn hinted_size(&self) -> (usize, usize) {
+        self.shared.hinted_handoff.size()
+    }
+
+    /// Return when the peer was first considered down or `None` if it is up.
+    pub fn peer_down_since_micros(&self) -> Option<u64> {
+        self.shared.hinted_handoff.peer_down_since_micros()
+    }
+
+    /// Keep further entries as hints instead of trying to send them, since
+    /// the peer hasn't been heard from in a while.
+    pub fn on_peer_lost(&self, now_micros: u64) {
+        self.shared.hinted_handoff.on_peer_down(now_micros);
+    }
+
+    /// Replay the kept hints if the peer was considered down.
+    pub fn on_peer_alive(&self) {
+        if self.peer_down_since_micros().is_some() {
+            self.shared.peer_alive.notify_one();
+        }
+    }
+
+    /// Wait for room for one more entry in the queue.
+    ///
+    /// The room is held until the returned slot is used or dropped, so that
+    /// entries can be queued in order without waiting while holding a lock.
+    pub async fn reserve(&self) -> Result<QueueSlot, ClachelessError> {
+        self.sender
+            .clone()
+            .reserve_owned()
+            .await
+            .map(QueueSlot)
+            .map_err(|_e| {
+                ClachelessErrorKind::Unspecified.error_with_msg("Replication queue is closed.")
+            })
+    }
+
+    /// Queue the entry for replication to the peer or drop it if the queue is
+    /// full.
+    ///
+    /// Returns `false` if the entry was dropped and the peer now needs a state
+    /// transfer.
+    pub fn enqueue_or_drop(&self, entry: CacheEntryAndKey) -> Result<bool, ClachelessError> {
+        match self.sender.try_send(Queued::Entry(entry)) {
+            Ok(()) => Ok(true),
+            Err(mpsc::error::TrySendError::Full(_queued)) => {
+                self.shared.on_dropped(1, "queue");
+                Ok(false)
+            }
+            Err(mpsc::error::TrySendError::Closed(_queued)) => {
+                Err(ClachelessErrorKind::Unspecified.error_with_msg("Replication queue is closed."))
+            }
+        }
+    }
+
+    /// Wait until all entries queued so far have been sent (or failed to be
+    /// sent) to the peer.
+    pub async fn flush(&self) -> Result<(), ClachelessError> {
+        let (flushed_sender, flushed_receiver) = oneshot::channel();
+        self.sender
+            .send(Queued::Flush(flushed_sender))
+            .await
+            .map_err(|_e| {
+                ClachelessErrorKind::Unspecified.error_with_msg("Replication queue is closed.")
+            })?;
+        flushed_receiver.await.map_err(|_e| {
+            ClachelessErrorKind::Unspecified.error_with_msg("Replication queue was dropped.")
+        })
+    }
+
+    /// Send batches of queued entries until the queue is closed and replay
+    /// hints when the peer is back.
+    async fn run(shared: Arc<SharedState>, mut receiver: mpsc::Receiver<Queued>) {
+        // Entry that didn't fit in the previous batch
+        let mut next_entry = None;
+        loop {
+            let first = match next_entry.take() {
+                Some(entry) => Queued::Entry(entry),
+                None => tokio::select! {
+                    biased;
+                    () = shared.peer_alive.notified() => {
+                        shared.replay_hints().await;
+                        continue;
+                    }
+                    queued = receiver.recv() => {
+                        let Some(first) = queued else {
+                            break;
+                        };
+                        first
+                    }
+                },
+            };
+            let (batch, flushed_sender) = match first {
+                Queued::Entry(first) => {
+                    let (batch, flushed_sender, rest) =
+                        Self::collect_batch(first, &mut receiver).await;
+                    next_entry = rest;
+                    (batch, flushed_sender)
+                }
+                Queued::Flush(flushed_sender) => (vec![], Some(flushed_sender)),
+            };
+            if !batch.is_empty() {
+                shared.replicate(batch).await;
+            }
+            if let Some(flushed_sender) = flushed_sender {
+                flushed_sender.send(()).ok();
+            }
+        }
+        if log::log_enabled!(log::Level::Trace) {
+            log::trace!(
+                "Replication to node ordinal {} stopped.",
+                shared.node_ordinal
+            );
+        }
+    }
+
+    /// Collect entries that arrive within the batch window or until the byte
+    /// budget is exhausted.
+    ///
+    /// A flush marker ends the batch early and is returned with it. An entry
+    /// that would exceed the budget is returned to start the next batch.
+    async fn collect_batch(
+        first: CacheEntryAndKey,
+        receiver: &mut mpsc::Receiver<Queued>,
+    ) -> (
+        Vec<CacheEntryAndKey>,
+        Option<oneshot::Sender<()>>,
+        Option<CacheEntryAndKey>,
+    ) {
+        let deadline = Instant::now() + Self::BATCH_WINDOW;
+        let mut batch_bytes = first.message_bytes();
+        let mut batch = vec![first];
+        while batch_bytes < Self::BATCH_MAX_BYTES {
+            match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                Ok(Some(Queued::Entry(entry))) => {
+                    if batch_bytes + entry.message_bytes() > Self::BATCH_MAX_BYTES {
+                        return (batch, None, Some(entry));
+                    }
+                    batch_bytes += entry.message_bytes();
+                    batch.push(entry);
+                }
+                Ok(Some(Queued::Flush(flushed_sender))) => {
+                    return (batch, Some(flushed_sender), None);
+                }
+                Ok(None) | Err(_) => break,
+            }
+        }
+        (batch, None, None)
+    }
+
+    /// Return how many of the leading `entries` fit in a batch of at most
+    /// `max_entries`, but at least one.
+    pub fn batch_len(entries: &[CacheEntryAndKey], max_entries: usize) -> usize {
+        let mut batch_bytes = 0;
+        entries
+            .iter()
+            .take(max_entries)
+            .take_while(|entry| {
+                batch_bytes += entry.message_bytes();
+                batch_bytes <= Self::BATCH_MAX_BYTES
+            })
+            .count()
+            .max(1)
+            .min(entries.len())
+    }
+}
+
+impl SharedState {
+    /// Maximum number of attempts to send a batch before the peer is
+    /// considered down.
+    const SEND_MAX_ATTEMPTS: u32 = 3;
+    /// Delays between attempts to send a batch.
+    const SEND_RETRY_BACKOFF: RetryBackoff =
+        RetryBackoff::new(Duration::from_millis(100), Duration::from_millis(1000));
+    /// Number of hints to replay in a single batch.
+    const REPLAY_BATCH_SIZE: usize = 256;
+
+    /// Send a batch of entries to the peer or keep them as hints if the peer
+    /// is down.
+    async fn replicate(&self, batch: Vec<CacheEntryAndKey>) {
+        if self.hinted_handoff.peer_down_since_micros().is_some() {
+            self.keep_hints(batch);
+            return;
+        }
+        let count = batch.len();
+        match self.send_with_retry(&batch).await {
+            Ok(()) => {
+                if log::log_enabled!(log::Level::Trace) {
+                    log::trace!(
+                        "Replicated {count} entries to node ordinal {}.",
+                        self.node_ordinal
+                    );
+                }
+            }
+            Err(e) => {
+                log::info!(
+                    "Node ordinal {} appears to be down. Keeping updates until it is back: {e}",
+                    self.node_ordinal
+                );
+                self.hinted_handoff.on_peer_down(self.clock.now_micros());
+                self.keep_hints(batch);
+            }
+        }
+    }
+
+    /// Keep the entries until the peer is back.
+    fn keep_hints(&self, batch: Vec<CacheEntryAndKey>) {
+        let (previous_count, _previous_bytes) = self.hinted_handoff.size();
+        let count = batch.len();
+        if !self.hinted_handoff.add(batch) {
+            self.on_dropped((previous_count + count) as u64, "hinted handoff");
+        }
+    }
+
+    /// Send the hints that were kept while the peer was down.
+    ///
+    /// Hints that could not be sent are kept until the next time the peer is
+    /// heard from.
+    async fn replay_hints(&self) {
+        let now_micros = self.clock.now_micros();
+        let hints = self
+            .hinted_handoff
+            .take()
+            .into_iter()
+            .filter(|entry| entry.ce.expires_micros > now_micros)
+            .collect::<Vec<_>>();
+        let mut remaining = hints.as_slice();
+        while !remaining.is_empty() {
+            let (batch, rest) = remaining.split_at(PeerReplicator::batch_len(
+                remaining,
+                Self::REPLAY_BATCH_SIZE,
+            ));
+            if let Err(e) = self.send_with_retry(batch).await {
+                log::debug!(
+                    "Failed to replay hints to node ordinal {}: {e}",
+                    self.node_ordinal
+                );
+                if !self.hinted_handoff.restore(remaining.to_vec(), now_micros) {
+                    self.on_dropped(remaining.len() as u64, "hinted handoff");
+                }
+                return;
+            }
+            remaining = rest;
+        }
+        if !hints.is_empty() {
+            log::info!(
+                "Replayed {} updates that were kept while node ordinal {} was down.",
+                hints.len(),
+                self.node_ordinal
+            );
+        }
+    }
+
+    /// Send a batch of entries to the peer and retry with backoff on failure.
+    async fn send_with_retry(&self, batch: &[CacheEntryAndKey]) -> Result<(), ClachelessError> {
+        let mut failed_attempts = 0;
+        loop {
+            match self.send_batch(batch.to_vec()).await {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    self.peer_transport
+                        .evict_on_connection_error(self.node_ordinal, &e);
+                    failed_attempts += 1;
+                    if failed_attempts >= Self::SEND_MAX_ATTEMPTS {
+                        return Err(e);
+                    }
+                    log::debug!(
+                        "Failed to replicate {} entries to node ordinal {} (attempt {failed_attempts}): {e}",
+                        batch.len(),
+                        self.node_ordinal
+                    );
+                    Self::SEND_RETRY_BACKOFF.sleep(failed_attempts).await;
+                }
+            }
+        }
+    }
+
+    /// Send a batch of entries to the peer.
+    async fn send_batch(&self, batch: Vec<CacheEntryAndKey>) -> Result<(), ClachelessError> {
+        let grpc_client = self.peer_transport.client(self.node_ordinal)?;
+        if self
+            .peer_protocols
+            .supports(self.node_ordinal, Capability::ReplicateEntries)
+        {
+            grpc_client.replicate_entries(batch).await
+        } else {
+            grpc_client.send_updates(batch).await
+        }
+    }
+
+    /// Count entries that were not replicated and mark the peer as needing a
+    /// state transfer.
+    fn on_dropped(&self, count: u64, full_buffer: &str) {
+        self.dropped_entries.fetch_add(count, Ordering::Relaxed);
+        if !self.needs_state_transfer.swap(true, Ordering::Relaxed) {
+            log::info!(
+                "Replication {full_buffer} of node ordinal {} is full. Dropping updates until the node has been told to catch up.",
+                self.node_ordinal
+            );
+        }
+    }
+}
+
+mod test {
+    //! Peer replicator tests.
+
+    #[tokio::test]
+    async fn test_full_queue_drops_entries() {
+        use super::PeerReplicator;
+        use crate::ClachelessConfig;
+        use crate::distributed_cache::in_memory_network::InMemoryNetwork;
+        use crate::distributed_cache::in_memory_network::InMemoryTransport;
+        use crate::distributed_cache::local_cache::CacheEntry;
+        use crate::distributed_cache::local_cache::CacheEntryAndKey;
+        use crate::distributed_cache::peer_transport::PeerTransport;
+        use std::sync::Arc;
+
+        // No node is serving ordinal 1, so the queue is never drained
+        let network = InMemoryNetwork::new();
+        let peer_transport: Arc<dyn PeerTransport> = Arc::new(InMemoryTransport::new(&network, 0));
+        let config = ClachelessConfig::default().with_replication_queue_capacity(2);
+        let peer_replicator = PeerReplicator::new(1, &peer_transport, &Arc::default(), &config);
+        for update_seq in 1..=5 {
+            let queued = peer_replicator
+                .enqueue_or_drop(CacheEntryAndKey {
+                    key: format!("key-{update_seq}"),
+                    ce: Arc::new(CacheEntry {
+                        this_update_micros: 1,
+                        origin_node_id: 0,
+                        origin_node_update_seq: update_seq,
+                        expires_micros: u64::MAX,
+                        object_bytes: Arc::default(),
+                        bridged: false,
+                    }),
+                })
+                .unwrap();
+            assert_eq!(queued, update_seq <= 2);
+        }
+        assert!(peer_replicator.is_full());
+        assert_eq!(peer_replicator.queue_depth(), 2);
+        assert_eq!(peer_replicator.dropped_entries(), 3);
+        assert!(peer_replicator.needs_state_transfer());
+        peer_replicator.on_state_view_pushed();
+        assert!(!peer_replicator.needs_state_transfer());
+    }
+
+    #[test]
+    fn test_batches_stay_within_byte_budget() {
+        use super::PeerReplicator;
+        use crate::distributed_cache::local_cache::CacheEntry;
+        use crate::distributed_cache::local_cache::CacheEntryAndKey;
+        use std::sync::Arc;
+
+        let entry_of_size = |object_size| CacheEntryAndKey {
+            key: "key".to_owned(),
+            ce: Arc::new(CacheEntry {
+                this_update_micros: 1,
+                origin_node_id: 0,
+                origin_node_update_seq: 1,
+                expires_micros: u64::MAX,
+                object_bytes: Arc::new(vec![0; object_size]),
+                bridged: false,
+            }),
+        };
+        let small = vec![entry_of_size(10); 100];
+        assert_eq!(PeerReplicator::batch_len(&small, 64), 64);
+        assert_eq!(PeerReplicator::batch_len(&small[..10], 64), 10);
+        // Keys and meta data count as well, so exactly filling the budget
+        // with object bytes doesn't fit
+        let half = vec![entry_of_size(PeerReplicator::BATCH_MAX_BYTES / 2); 3];
+        assert_eq!(PeerReplicator::batch_len(&half, 64), 1);
+        // A large entry is sent on its own
+        let large = vec![entry_of_size(2 * PeerReplicator::BATCH_MAX_BYTES); 2];
+        assert_eq!(PeerReplicator::batch_len(&large, 64), 1);
+    }
+}
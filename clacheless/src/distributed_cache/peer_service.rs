@@ -0,0 +1,292 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Handling of requests from other nodes, independent of the transport.
+
+use super::DistributedCache;
+use super::cluster_view::SequenceRanges;
+use super::local_cache::CacheEntryAndKey;
+use super::protocol::Capability;
+use super::protocol::PROTOCOL_VERSION;
+use super::protocol::PeerProtocol;
+use crate::ClachelessError;
+use crate::ClachelessErrorKind;
+use crate::proto::stateshare::AntiEntropyDigestsReply;
+use crate::proto::stateshare::AntiEntropyDigestsRequest;
+use crate::proto::stateshare::AntiEntropyVersionsReply;
+use crate::proto::stateshare::AntiEntropyVersionsRequest;
+use crate::proto::stateshare::EntryVersion;
+use crate::proto::stateshare::FetchEntriesReply;
+use crate::proto::stateshare::FetchEntriesRequest;
+use crate::proto::stateshare::InitStateTransferRequest;
+use crate::proto::stateshare::JoinReply;
+use crate::proto::stateshare::JoinRequest;
+use crate::proto::stateshare::LeaveRequest;
+use crate::proto::stateshare::PutCacheEntryRequest;
+use crate::proto::stateshare::StateTransferChunk;
+use crate::proto::stateshare::StateTransferRequest;
+use crate::proto::stateshare::StateViewUpdateReply;
+use crate::proto::stateshare::StateViewUpdateRequest;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// Approximate maximum size of the object bytes in a state transfer chunk.
+const STATE_TRANSFER_CHUNK_MAX_BYTES: usize = 256 * 1024;
+/// Approximate maximum size of the entries in a reply to a fetch of entries.
+const FETCH_ENTRIES_REPLY_MAX_BYTES: usize = 1024 * 1024;
+
+/** Serves the `StateShare` requests of other nodes from the local node.
+
+Each [PeerTransport](super::peer_transport::PeerTransport) decodes requests
+into the protocol messages and hands them to this service.
+*/
+pub struct PeerService {
+    dc: Arc<DistributedCache>,
+}
+
+impl PeerService {
+    /// Return a new instance.
+    pub fn new(dc: &Arc<DistributedCache>) -> Self {
+        Self { dc: Arc::clone(dc) }
+    }
+
+    /// Receive a cache entry from remote node.
+    pub async fn put_cache_entry(&self, ur: PutCacheEntryRequest) -> Result<(), ClachelessError> {
+        self.dc
+            .put_raw_from_remote_origin(CacheEntryAndKey::from(ur))
+            .await
+    }
+
+    /// Receive remote node's view of the cluster.
+    pub async fn state_view_update(&self, svr: StateViewUpdateRequest) -> StateViewUpdateReply {
+        // Processing the view may take a while, so the time it was recieved is
+        // the closest to the sender's midpoint of the round trip
+        let responder_timestamp_micros = self.dc.config().clock().now_micros();
+        if log::log_enabled!(log::Level::Trace) {
+            log::trace!("Got state update: {svr:?}");
+        }
+        self.dc.peer_protocols.on_advertised(
+            svr.sender_node_ordinal,
+            PeerProtocol::from_wire(svr.protocol_version, &svr.capabilities),
+        );
+        self.dc
+            .on_state_view(
+                svr.sender_node_ordinal,
+                svr.sender_node_id,
+                svr.sender_timestamp_micros,
+                svr.sender_observer,
+                svr.view,
+            )
+            .await;
+        StateViewUpdateReply {
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: Capability::local_wire_names(),
+            responder_timestamp_micros,
+        }
+    }
+
+    /// Receive a request for a state transfer
+    pub async fn init_state_transfer(
+        &self,
+        istr: InitStateTransferRequest,
+    ) -> Result<(), ClachelessError> {
+        if log::log_enabled!(log::Level::Trace) {
+            log::trace!("Got state transfer request: {istr:?}");
+        }
+        self.dc
+            .transfer_state(istr.reciever_node_ordinal, istr.data_origin_id_and_baseline)
+            .await
+    }
+
+    /// Return a channel of chunks with the entries that the requesting node
+    /// is missing.
+    ///
+    /// The chunks are produced by a background task that stops if the
+    /// reciever is dropped.
+    pub fn stream_state_transfer(
+        &self,
+        tr: StateTransferRequest,
+    ) -> mpsc::Receiver<StateTransferChunk> {
+        if log::log_enabled!(log::Level::Debug) {
+            log::debug!(
+                "Starting state transfer session {} to node ordinal {}: {:?}",
+                tr.session_id,
+                tr.reciever_node_ordinal,
+                tr.missing_ranges
+            );
+        }
+        let (sender, receiver) = mpsc::channel(4);
+        let dc = Arc::clone(&self.dc);
+        tokio::spawn(async move {
+            let session_id = tr.session_id;
+            let mut data_origin_id_and_ranges = HashMap::<u64, SequenceRanges>::new();
+            for range in tr.missing_ranges {
+                data_origin_id_and_ranges
+                    .entry(range.origin_node_id)
+                    .or_default()
+                    .insert(range.first_seq..=range.last_seq);
+            }
+            let mut chunk = StateTransferChunk {
+                session_id,
+                ..StateTransferChunk::default()
+            };
+            let mut chunk_bytes = 0;
+            for fcde in dc.local_cache.iter(&data_origin_id_and_ranges) {
+                if chunk_bytes > 0
+                    && chunk_bytes + fcde.ce.object_bytes.len() > STATE_TRANSFER_CHUNK_MAX_BYTES
+                {
+                    let full_chunk = std::mem::replace(
+                        &mut chunk,
+                        StateTransferChunk {
+                            session_id,
+                            ..StateTransferChunk::default()
+                        },
+                    );
+                    if sender.send(full_chunk).await.is_err() {
+                        log::debug!("State transfer session {session_id} was aborted by reciever.");
+                        return;
+                    }
+                    chunk_bytes = 0;
+                }
+                chunk_bytes += fcde.ce.object_bytes.len();
+                chunk.entries.push(PutCacheEntryRequest::from(fcde));
+            }
+            chunk.completed = true;
+            if sender.send(chunk).await.is_err() {
+                log::debug!("State transfer session {session_id} was aborted by reciever.");
+            }
+        });
+        receiver
+    }
+
+    /// Receive an announcement from a remote node.
+    pub fn join(&self, jr: JoinRequest) -> Result<JoinReply, ClachelessError> {
+        if log::log_enabled!(log::Level::Trace) {
+            log::trace!("Got join request: {jr:?}");
+        }
+        if self.dc.is_leaving() {
+            Err(ClachelessErrorKind::Unavailable.error_with_msg("Node is leaving the cluster."))?;
+        }
+        let member_node_ordinals = self.dc.on_join(jr.sender_node_ordinal, jr.sender_observer);
+        Ok(JoinReply {
+            member_node_ordinals,
+            responder_observer: self.dc.is_observer(),
+        })
+    }
+
+    /// Receive a notice that a remote node is leaving.
+    pub fn leave(&self, lr: LeaveRequest) {
+        if log::log_enabled!(log::Level::Trace) {
+            log::trace!("Got leave request: {lr:?}");
+        }
+        self.dc.on_leave(lr.sender_node_ordinal);
+    }
+
+    /// Return digests of the local hash tree.
+    pub fn anti_entropy_digests(&self, adr: AntiEntropyDigestsRequest) -> AntiEntropyDigestsReply {
+        let digests = self
+            .dc
+            .anti_entropy
+            .tree()
+            .map(|tree| tree.digests(adr.level, &adr.indexes))
+            .unwrap_or_default();
+        AntiEntropyDigestsReply { digests }
+    }
+
+    /// Return versions of the local entries covered by hash tree leaves.
+    pub async fn anti_entropy_versions(
+        &self,
+        avr: AntiEntropyVersionsRequest,
+    ) -> AntiEntropyVersionsReply {
+        let versions = self
+            .dc
+            .anti_entropy
+            .entries_in_leaves(&self.dc.local_cache, &avr.leaf_indexes)
+            .await
+            .into_iter()
+            .map(|entry| EntryVersion {
+                key: entry.key,
+                this_update_micros: entry.ce.this_update_micros,
+                origin_node_id: entry.ce.origin_node_id,
+                origin_node_update_seq: entry.ce.origin_node_update_seq,
+            })
+            .collect();
+        AntiEntropyVersionsReply { versions }
+    }
+
+    /// Return the local cache entries of the leading requested keys that fit
+    /// in a reply.
+    ///
+    /// At least one key is always handled, even if its entry alone exceeds
+    /// [FETCH_ENTRIES_REPLY_MAX_BYTES].
+    pub fn fetch_entries(&self, fer: FetchEntriesRequest) -> FetchEntriesReply {
+        let mut reply = FetchEntriesReply::default();
+        let mut reply_bytes = 0;
+        for key in fer.keys {
+            if let Some(ce) = self.dc.local_cache.get_entry(&key) {
+                let entry = CacheEntryAndKey { key, ce };
+                if reply_bytes > 0
+                    && reply_bytes + entry.message_bytes() > FETCH_ENTRIES_REPLY_MAX_BYTES
+                {
+                    break;
+                }
+                reply_bytes += entry.message_bytes();
+                reply.entries.push(PutCacheEntryRequest::from(entry));
+            }
+            reply.handled_count += 1;
+        }
+        reply
+    }
+}
+
+mod test {
+    //! Peer service tests.
+
+    #[tokio::test]
+    async fn test_fetched_entries_fit_in_reply() {
+        use super::FETCH_ENTRIES_REPLY_MAX_BYTES;
+        use super::PeerService;
+        use crate::DistributedCache;
+        use crate::proto::stateshare::FetchEntriesRequest;
+
+        let dc = DistributedCache::new("clacheless-ORDINAL.local:9000", 0, 30_000_000).await;
+        let object = vec![7; FETCH_ENTRIES_REPLY_MAX_BYTES / 3];
+        for index in 0..3 {
+            dc.put_bytes(&format!("key-{index}"), &object)
+                .await
+                .unwrap();
+        }
+        dc.put_bytes("large", &vec![7; 2 * FETCH_ENTRIES_REPLY_MAX_BYTES])
+            .await
+            .unwrap();
+        let peer_service = PeerService::new(&dc);
+        let fetch = |keys: &[&str]| {
+            peer_service.fetch_entries(FetchEntriesRequest {
+                keys: keys.iter().map(|key| (*key).to_owned()).collect(),
+            })
+        };
+        // Missing keys are handled without taking room in the reply
+        let reply = fetch(&["missing", "key-0", "key-1", "key-2"]);
+        assert_eq!(reply.handled_count, 3);
+        assert_eq!(reply.entries.len(), 2);
+        // An entry larger than a reply is still fetched on its own
+        let reply = fetch(&["large", "key-0"]);
+        assert_eq!(reply.handled_count, 1);
+        assert_eq!(reply.entries[0].key, "large");
+    }
+}
@@ -0,0 +1,243 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Abstraction of how nodes reach each other.
+
+use super::DistributedCache;
+use super::cluster_view::SequenceRanges;
+use super::local_cache::CacheEntry;
+use super::local_cache::CacheEntryAndKey;
+use super::protocol::PeerProtocol;
+use crate::ClachelessError;
+use crate::ClachelessErrorKind;
+use crate::proto::stateshare::EntryVersion;
+use crate::proto::stateshare::FetchEntriesReply;
+use crate::proto::stateshare::JoinReply;
+use crate::proto::stateshare::PutCacheEntryRequest;
+use crate::proto::stateshare::StateTransferChunk;
+use crate::proto::stateshare::StateViewUpdateReply;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio_stream::Stream;
+use tokio_stream::StreamExt;
+
+/** Transport between the nodes of a cluster.
+
+The transport hands out a [PeerClient] for each remote node and serves the
+requests of remote nodes to the local node. Over the network this is gRPC (see
+[GrpcClientPool](super::grpc_client_pool::GrpcClientPool)), while tests can
+run several nodes in one process (see
+[InMemoryNetwork](super::in_memory_network::InMemoryNetwork)).
+*/
+#[async_trait]
+pub trait PeerTransport: Send + Sync {
+    /// Return a human readable address of the node.
+    fn address_for_node_ordinal(&self, node_ordinal: u32) -> String;
+
+    /// Return a client for the node, creating one if needed.
+    fn client(&self, node_ordinal: u32) -> Result<Arc<dyn PeerClient>, ClachelessError>;
+
+    /// Drop the client for the node so the next request will use a fresh
+    /// connection.
+    fn evict(&self, node_ordinal: u32);
+
+    /// Drop the client for the node if the request failed due to the
+    /// connection. Errors returned by the node itself keep the client.
+    fn evict_on_connection_error(&self, node_ordinal: u32, e: &ClachelessError) {
+        if e.kind() == &ClachelessErrorKind::Connection {
+            self.evict(node_ordinal);
+        }
+    }
+
+    /// Drop clients that has not been used since `last_used_threshold_micros`.
+    fn evict_idle(&self, last_used_threshold_micros: u64);
+
+    /// Serve requests from other nodes to `dc`.
+    ///
+    /// This will not return for as long the transport is serving.
+    async fn serve(&self, dc: &Arc<DistributedCache>) -> Result<(), ClachelessError>;
+}
+
+/// Requests from the local node to a single remote node.
+#[async_trait]
+pub trait PeerClient: Send + Sync {
+    /// Start (or resume) a streamed state transfer from the remote node.
+    async fn stream_state_transfer(
+        &self,
+        session_id: u64,
+        reciever_node_ordinal: u32,
+        data_origin_id_and_ranges: HashMap<u64, SequenceRanges>,
+    ) -> Result<StateTransferStream, ClachelessError>;
+
+    /// Send a cache entry update to the remote node.
+    async fn send_update(&self, entry: CacheEntryAndKey) -> Result<(), ClachelessError>;
+
+    /// Send cache entries to the remote node in order, one request per entry.
+    ///
+    /// Used with nodes that don't support `ReplicateEntries`.
+    async fn send_updates(&self, entries: Vec<CacheEntryAndKey>) -> Result<(), ClachelessError> {
+        for entry in entries {
+            self.send_update(entry).await?;
+        }
+        Ok(())
+    }
+
+    /// Request the remote node to push all entries newer than the baselines.
+    ///
+    /// Used with nodes that don't support `StreamStateTransfer`.
+    async fn init_state_transfer(
+        &self,
+        reciever_node_ordinal: u32,
+        data_origin_id_and_baseline: HashMap<u64, u64>,
+    ) -> Result<(), ClachelessError>;
+
+    /// Stream cache entries to the remote node in order.
+    async fn replicate_entries(
+        &self,
+        entries: Vec<CacheEntryAndKey>,
+    ) -> Result<(), ClachelessError>;
+
+    /// Send the local nodes cluster view to the remote and return the
+    /// protocol the remote advertised in response.
+    async fn push_state_view(
+        &self,
+        sender_node_ordinal: u32,
+        sender_node_id: u64,
+        sender_timestamp_micros: u64,
+        sender_observer: bool,
+        view: HashMap<u64, u64>,
+    ) -> Result<StateViewResponse, ClachelessError>;
+
+    /// Announce the local node to the remote and return the node ordinals of
+    /// the members the remote knows about.
+    async fn join(
+        &self,
+        sender_node_ordinal: u32,
+        sender_observer: bool,
+    ) -> Result<JoinResponse, ClachelessError>;
+
+    /// Announce to the remote that the local node is leaving the cluster.
+    async fn leave(&self, sender_node_ordinal: u32) -> Result<(), ClachelessError>;
+
+    /// Return the remote's digests of the hash tree nodes at `level`.
+    ///
+    /// The result is empty if the remote has not built a hash tree yet.
+    async fn anti_entropy_digests(
+        &self,
+        level: u32,
+        indexes: Vec<u32>,
+    ) -> Result<Vec<u64>, ClachelessError>;
+
+    /// Return the versions of the remote's entries covered by the hash tree
+    /// leaves.
+    async fn anti_entropy_versions(
+        &self,
+        leaf_indexes: Vec<u32>,
+    ) -> Result<Vec<EntryVersion>, ClachelessError>;
+
+    /// Return the remote's cache entries of the leading keys that fit in a
+    /// reply.
+    async fn fetch_entries(&self, keys: Vec<String>) -> Result<FetchEntriesReply, ClachelessError>;
+}
+
+/// Response of a remote node to a cluster view update.
+pub struct StateViewResponse {
+    /// Protocol that the remote advertised.
+    pub peer_protocol: PeerProtocol,
+    /// Time the remote handled the update in epoch microseconds or `0` if the
+    /// remote doesn't tell.
+    pub timestamp_micros: u64,
+}
+
+impl From<StateViewUpdateReply> for StateViewResponse {
+    fn from(value: StateViewUpdateReply) -> Self {
+        Self {
+            peer_protocol: PeerProtocol::from_wire(value.protocol_version, &value.capabilities),
+            timestamp_micros: value.responder_timestamp_micros,
+        }
+    }
+}
+
+/// Response of a remote node to a join request.
+pub struct JoinResponse {
+    /// Ordinals of the members that the remote knows about.
+    pub member_node_ordinals: Vec<u32>,
+    /// `true` if the remote is a read-only observer.
+    pub observer: bool,
+}
+
+impl From<JoinReply> for JoinResponse {
+    fn from(value: JoinReply) -> Self {
+        Self {
+            member_node_ordinals: value.member_node_ordinals,
+            observer: value.responder_observer,
+        }
+    }
+}
+
+/// Chunks of a streamed state transfer from a remote node.
+pub struct StateTransferStream {
+    chunks: Pin<Box<dyn Stream<Item = Result<StateTransferChunk, ClachelessError>> + Send>>,
+}
+
+impl StateTransferStream {
+    /// Return a new instance.
+    pub fn new(
+        chunks: impl Stream<Item = Result<StateTransferChunk, ClachelessError>> + Send + 'static,
+    ) -> Self {
+        Self {
+            chunks: Box::pin(chunks),
+        }
+    }
+
+    /// Return the next chunk or `None` if the remote closed the stream.
+    pub async fn next_chunk(&mut self) -> Result<Option<StateTransferChunk>, ClachelessError> {
+        self.chunks.next().await.transpose()
+    }
+}
+
+impl From<PutCacheEntryRequest> for CacheEntryAndKey {
+    fn from(value: PutCacheEntryRequest) -> Self {
+        Self {
+            key: value.key,
+            ce: Arc::new(CacheEntry {
+                this_update_micros: value.this_update_micros,
+                origin_node_id: value.origin_node_id,
+                origin_node_update_seq: value.origin_node_update_seq,
+                expires_micros: value.expires,
+                object_bytes: Arc::new(value.object_bytes),
+                bridged: value.bridged,
+            }),
+        }
+    }
+}
+
+impl From<CacheEntryAndKey> for PutCacheEntryRequest {
+    fn from(value: CacheEntryAndKey) -> Self {
+        Self {
+            key: value.key,
+            this_update_micros: value.ce.this_update_micros,
+            expires: value.ce.expires_micros,
+            object_bytes: value.ce.object_bytes.to_vec(),
+            origin_node_id: value.ce.origin_node_id,
+            origin_node_update_seq: value.ce.origin_node_update_seq,
+            bridged: value.ce.bridged,
+        }
+    }
+}
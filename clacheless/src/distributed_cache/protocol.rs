@@ -0,0 +1,248 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Wire protocol version and optional features negotiated between nodes.
+
+use crossbeam_skiplist::SkipMap;
+
+/// Version of the wire protocol spoken by this node.
+///
+/// Nodes that don't advertise a version (older releases) are at version `0`.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Optional protocol feature that a node has to advertise before other nodes
+/// use it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capability {
+    /// `StreamStateTransfer` instead of `InitStateTransfer`.
+    StreamStateTransfer,
+    /// `ReplicateEntries` instead of one `PutCacheEntry` per entry.
+    ReplicateEntries,
+    /// `AntiEntropyDigests`, `AntiEntropyVersions` and `FetchEntries`.
+    AntiEntropy,
+    /// `Leave`.
+    Leave,
+}
+
+impl Capability {
+    /// All capabilities supported by this node.
+    pub const ALL: [Self; 4] = [
+        Self::StreamStateTransfer,
+        Self::ReplicateEntries,
+        Self::AntiEntropy,
+        Self::Leave,
+    ];
+
+    /// Return the name used on the wire.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::StreamStateTransfer => "stream-state-transfer",
+            Self::ReplicateEntries => "replicate-entries",
+            Self::AntiEntropy => "anti-entropy",
+            Self::Leave => "leave",
+        }
+    }
+
+    /// Return the capability with the wire name or `None` if it is unknown to
+    /// this node.
+    pub fn from_wire(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|capability| capability.as_str() == name)
+    }
+
+    /// Return the wire names of all capabilities supported by this node.
+    pub fn local_wire_names() -> Vec<String> {
+        Self::ALL
+            .iter()
+            .map(|capability| capability.as_str().to_string())
+            .collect()
+    }
+
+    fn bit(&self) -> u32 {
+        1 << (*self as u32)
+    }
+}
+
+/// Protocol version and capabilities advertised by a remote node.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PeerProtocol {
+    version: u32,
+    capabilities: u32,
+}
+
+impl PeerProtocol {
+    /// Return an instance from the advertised values where capabilities
+    /// unknown to this node are ignored.
+    pub fn from_wire(version: u32, capabilities: &[String]) -> Self {
+        Self {
+            version,
+            capabilities: capabilities
+                .iter()
+                .filter_map(|name| Capability::from_wire(name))
+                .fold(0, |bits, capability| bits | capability.bit()),
+        }
+    }
+
+    /// Return the advertised protocol version.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Return `true` if the remote node has advertised the capability.
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities & capability.bit() != 0
+    }
+}
+
+/** Protocols advertised by remote nodes by node ordinal.
+
+Nodes advertise their protocol in every `StateViewUpdate` request and reply.
+Until a node has advertised anything, only the features of the initial
+protocol version are used with it.
+*/
+#[derive(Default)]
+pub struct PeerProtocols {
+    by_node_ordinal: SkipMap<u32, PeerProtocol>,
+}
+
+impl PeerProtocols {
+    /// Return the protocol advertised by the remote node.
+    pub fn get(&self, node_ordinal: u32) -> PeerProtocol {
+        self.by_node_ordinal
+            .get(&node_ordinal)
+            .map(|entry| *entry.value())
+            .unwrap_or_default()
+    }
+
+    /// Return `true` if the remote node has advertised the capability.
+    pub fn supports(&self, node_ordinal: u32, capability: Capability) -> bool {
+        self.get(node_ordinal).supports(capability)
+    }
+
+    /// Record what the remote node has advertised.
+    pub fn on_advertised(&self, node_ordinal: u32, peer_protocol: PeerProtocol) {
+        let previous = self
+            .by_node_ordinal
+            .get(&node_ordinal)
+            .map(|entry| *entry.value());
+        if previous != Some(peer_protocol) {
+            self.by_node_ordinal.insert(node_ordinal, peer_protocol);
+            if peer_protocol.version() < PROTOCOL_VERSION {
+                log::info!(
+                    "Node ordinal '{node_ordinal}' speaks protocol version {} and will be served with reduced features.",
+                    peer_protocol.version()
+                );
+            }
+        }
+    }
+
+    /// Forget what the remote node has advertised when it is gone, so that a
+    /// replacement starts out with the initial protocol.
+    pub fn remove(&self, node_ordinal: u32) {
+        self.by_node_ordinal.remove(&node_ordinal);
+    }
+}
+
+mod test {
+    //! Protocol negotiation tests where messages of the initial protocol are
+    //! exchanged with messages of the current protocol.
+
+    #[cfg(test)]
+    mod initial {
+        //! Messages as defined before protocol negotiation was introduced.
+
+        #[derive(Clone, PartialEq, prost::Message)]
+        pub struct StateViewUpdateRequest {
+            #[prost(uint32, tag = "1")]
+            pub sender_node_ordinal: u32,
+            #[prost(map = "uint64, uint64", tag = "2")]
+            pub view: std::collections::HashMap<u64, u64>,
+        }
+
+        #[derive(Clone, PartialEq, prost::Message)]
+        pub struct StateViewUpdateReply {}
+    }
+
+    #[test]
+    fn test_initial_node_reads_current_request() {
+        use crate::proto::stateshare::StateViewUpdateRequest;
+        use prost::Message;
+
+        let request = StateViewUpdateRequest {
+            sender_node_ordinal: 3,
+            view: [(1, 2)].into(),
+            protocol_version: super::PROTOCOL_VERSION,
+            capabilities: super::Capability::local_wire_names(),
+            sender_node_id: 0x1234_5678_0000_0003,
+            sender_timestamp_micros: 1_700_000_000_000_000,
+            sender_observer: true,
+        };
+        let decoded =
+            initial::StateViewUpdateRequest::decode(request.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(decoded.sender_node_ordinal, 3);
+        assert_eq!(decoded.view, request.view);
+    }
+
+    #[test]
+    fn test_current_node_downgrades_for_initial_messages() {
+        use crate::proto::stateshare::StateViewUpdateReply;
+        use crate::proto::stateshare::StateViewUpdateRequest;
+        use prost::Message;
+
+        let initial_request = initial::StateViewUpdateRequest {
+            sender_node_ordinal: 3,
+            view: [(1, 2)].into(),
+        };
+        let decoded =
+            StateViewUpdateRequest::decode(initial_request.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(decoded.view, initial_request.view);
+        assert!(!decoded.sender_observer);
+        let peer_protocol =
+            super::PeerProtocol::from_wire(decoded.protocol_version, &decoded.capabilities);
+        assert_eq!(peer_protocol.version(), 0);
+        assert!(
+            super::Capability::ALL
+                .iter()
+                .all(|capability| !peer_protocol.supports(*capability))
+        );
+        let decoded = StateViewUpdateReply::decode(
+            initial::StateViewUpdateReply {}.encode_to_vec().as_slice(),
+        )
+        .unwrap();
+        assert_eq!(
+            super::PeerProtocol::from_wire(decoded.protocol_version, &decoded.capabilities),
+            super::PeerProtocol::default()
+        );
+    }
+
+    #[test]
+    fn test_unknown_capabilities_are_ignored() {
+        let peer_protocol = super::PeerProtocol::from_wire(
+            super::PROTOCOL_VERSION + 1,
+            &["leave".to_string(), "compression".to_string()],
+        );
+        assert!(peer_protocol.supports(super::Capability::Leave));
+        assert!(!peer_protocol.supports(super::Capability::AntiEntropy));
+        let peer_protocols = super::PeerProtocols::default();
+        assert!(!peer_protocols.supports(1, super::Capability::ReplicateEntries));
+        peer_protocols.on_advertised(1, peer_protocol);
+        assert!(peer_protocols.supports(1, super::Capability::Leave));
+        peer_protocols.remove(1);
+        assert!(!peer_protocols.supports(1, super::Capability::Leave));
+    }
+}
@@ -0,0 +1,89 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Exponential backoff with jitter for retrying failed peer requests.
+
+use tokio::time::Duration;
+use tyst::Tyst;
+
+/** Delays between attempts of a retried operation.
+
+The delay doubles with each failed attempt, starting from `base_delay` and
+capped at `max_delay`. Up to half of each delay is replaced by random jitter,
+so that nodes that failed at the same time don't retry in lockstep.
+*/
+pub struct RetryBackoff {
+    base_delay: Duration,
+    max_delay: Duration,
+}
+
+impl RetryBackoff {
+    /// Return a new instance.
+    pub const fn new(base_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            base_delay,
+            max_delay,
+        }
+    }
+
+    /// Return the delay before the next attempt after `failed_attempts`
+    /// consecutive failures.
+    pub fn delay(&self, failed_attempts: u32) -> Duration {
+        let delay = self.max_delay_after(failed_attempts);
+        let half_delay_micros = u64::try_from(delay.as_micros() / 2).unwrap_or(u64::MAX);
+        Duration::from_micros(half_delay_micros.saturating_add(Self::jitter(half_delay_micros)))
+    }
+
+    /// Sleep for the delay before the next attempt.
+    pub async fn sleep(&self, failed_attempts: u32) {
+        tokio::time::sleep(self.delay(failed_attempts)).await;
+    }
+
+    /// Return the delay without jitter.
+    fn max_delay_after(&self, failed_attempts: u32) -> Duration {
+        self.base_delay
+            .saturating_mul(1 << failed_attempts.saturating_sub(1).min(16))
+            .min(self.max_delay)
+    }
+
+    /// Return a random value in `0..=max_value`.
+    fn jitter(max_value: u64) -> u64 {
+        let random_bytes = Tyst::instance().prng_get_random_bytes(None, 8);
+        let random_value = u64::from_be_bytes(random_bytes.try_into().unwrap_or_default());
+        random_value % max_value.saturating_add(1)
+    }
+}
+
+mod test {
+    //! Retry backoff tests.
+
+    #[test]
+    fn test_delay_grows_and_is_capped() {
+        use tokio::time::Duration;
+
+        let retry_backoff =
+            super::RetryBackoff::new(Duration::from_millis(100), Duration::from_millis(1000));
+        for (failed_attempts, max_delay_millis) in [(1, 100), (2, 200), (3, 400), (5, 1000)] {
+            let max_delay = Duration::from_millis(max_delay_millis);
+            for _ in 0..16 {
+                let delay = retry_backoff.delay(failed_attempts);
+                assert!(delay >= max_delay / 2, "{delay:?} < {max_delay:?} / 2");
+                assert!(delay <= max_delay, "{delay:?} > {max_delay:?}");
+            }
+        }
+    }
+}
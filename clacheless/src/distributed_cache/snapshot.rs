@@ -0,0 +1,461 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Versioned binary snapshots of the local cache.
+
+use super::local_cache::CacheEntry;
+use super::local_cache::CacheEntryAndKey;
+use crate::ClachelessError;
+use crate::ClachelessErrorKind;
+use crate::proto::snapshot::SnapshotEntry;
+use crate::proto::snapshot::SnapshotHeader;
+use crate::proto::snapshot::SnapshotRecord;
+use crate::proto::snapshot::SnapshotTrailer;
+use crate::proto::snapshot::snapshot_record::Record;
+use prost::Message;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+
+/** Point in time copy of the local cache and the cluster state baselines.
+
+The binary format is
+
+* the magic bytes `CLACHSNP`,
+* the format version as a big-endian `u32`,
+* a length-delimited `SnapshotHeader` protobuf message,
+* a length-delimited `SnapshotRecord` protobuf message with the entry per
+  cache entry and
+* a length-delimited `SnapshotRecord` protobuf message with the trailer that
+  holds the number of entries.
+
+Input that ends without the trailer is rejected as truncated.
+*/
+pub struct Snapshot {
+    /// When the snapshot was taken in epoch microseconds.
+    pub created_micros: u64,
+    /// Sequence number per origin node id up to which the snapshot holds all
+    /// (non-superseded) updates.
+    pub data_origin_id_and_baseline: HashMap<u64, u64>,
+    /// The cache entries.
+    pub entries: Vec<CacheEntryAndKey>,
+}
+
+impl Snapshot {
+    /// Current version of the binary format.
+    pub const FORMAT_VERSION: u32 = 2;
+    const MAGIC: &[u8; 8] = b"CLACHSNP";
+
+    /// Write the snapshot in the binary format.
+    pub fn encode(&self, writer: &mut impl Write) -> Result<(), ClachelessError> {
+        writer
+            .write_all(&Self::encode_header(
+                self.created_micros,
+                &self.data_origin_id_and_baseline,
+            ))
+            .map_err(Self::io_error)?;
+        for entry in &self.entries {
+            writer
+                .write_all(&Self::encode_entry(entry))
+                .map_err(Self::io_error)?;
+        }
+        writer
+            .write_all(&Self::encode_trailer(self.entries.len()))
+            .map_err(Self::io_error)
+    }
+
+    /// Return the magic bytes, format version and header of the binary format.
+    pub fn encode_header(
+        created_micros: u64,
+        data_origin_id_and_baseline: &HashMap<u64, u64>,
+    ) -> Vec<u8> {
+        let header = SnapshotHeader {
+            created_micros,
+            data_origin_id_and_baseline: data_origin_id_and_baseline.clone(),
+        };
+        let mut ret = Self::MAGIC.to_vec();
+        ret.extend_from_slice(&Self::FORMAT_VERSION.to_be_bytes());
+        ret.extend_from_slice(&header.encode_length_delimited_to_vec());
+        ret
+    }
+
+    /// Return a single entry in the binary format.
+    pub fn encode_entry(entry: &CacheEntryAndKey) -> Vec<u8> {
+        SnapshotRecord {
+            record: Some(Record::Entry(SnapshotEntry {
+                key: entry.key.to_owned(),
+                this_update_micros: entry.ce.this_update_micros,
+                expires: entry.ce.expires_micros,
+                object_bytes: entry.ce.object_bytes.to_vec(),
+                origin_node_id: entry.ce.origin_node_id,
+                origin_node_update_seq: entry.ce.origin_node_update_seq,
+                bridged: entry.ce.bridged,
+            })),
+        }
+        .encode_length_delimited_to_vec()
+    }
+
+    /// Return the trailer that ends a snapshot of `entry_count` entries in the
+    /// binary format.
+    pub fn encode_trailer(entry_count: usize) -> Vec<u8> {
+        SnapshotRecord {
+            record: Some(Record::Trailer(SnapshotTrailer {
+                entry_count: entry_count as u64,
+            })),
+        }
+        .encode_length_delimited_to_vec()
+    }
+
+    /// Parse a snapshot in the binary format and skip entries that have
+    /// expired at `now_micros`.
+    pub fn decode(bytes: &[u8], now_micros: u64) -> Result<Self, ClachelessError> {
+        let (preamble, mut buf) = bytes
+            .split_first_chunk::<12>()
+            .ok_or_else(Self::truncated_error)?;
+        Self::check_preamble(preamble)?;
+        let header =
+            SnapshotHeader::decode_length_delimited(&mut buf).map_err(Self::decode_error)?;
+        let mut entries = vec![];
+        let mut entry_count = 0;
+        loop {
+            if buf.is_empty() {
+                return Err(Self::truncated_error());
+            }
+            let record =
+                SnapshotRecord::decode_length_delimited(&mut buf).map_err(Self::decode_error)?;
+            match record.record {
+                Some(Record::Entry(snapshot_entry)) => {
+                    entry_count += 1;
+                    if snapshot_entry.expires > now_micros {
+                        entries.push(Self::entry_from(snapshot_entry));
+                    }
+                }
+                Some(Record::Trailer(trailer)) => {
+                    Self::check_trailer(&trailer, entry_count)?;
+                    if !buf.is_empty() {
+                        return Err(ClachelessErrorKind::Malformed
+                            .error_with_msg("Unexpected data after snapshot trailer."));
+                    }
+                    break;
+                }
+                None => return Err(Self::empty_record_error()),
+            }
+        }
+        Ok(Self {
+            created_micros: header.created_micros,
+            data_origin_id_and_baseline: header.data_origin_id_and_baseline,
+            entries,
+        })
+    }
+
+    /// Write the snapshot to a temporary file next to `path` and atomically
+    /// replace `path` with it.
+    pub async fn write_to_file(self, path: &Path) -> Result<(), ClachelessError> {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            let mut tmp_path = path.clone().into_os_string();
+            tmp_path.push(".tmp");
+            let tmp_path = PathBuf::from(tmp_path);
+            let file = std::fs::File::create(&tmp_path).map_err(Self::io_error)?;
+            let mut writer = std::io::BufWriter::new(file);
+            self.encode(&mut writer)?;
+            writer
+                .into_inner()
+                .map_err(|e| Self::io_error(e.into_error()))?
+                .sync_all()
+                .map_err(Self::io_error)?;
+            std::fs::rename(&tmp_path, &path).map_err(Self::io_error)
+        })
+        .await
+        .map_err(|e| ClachelessErrorKind::Unspecified.error_with_msg(e.to_string()))?
+    }
+
+    /// Read a snapshot from file and skip entries that have expired at
+    /// `now_micros`.
+    ///
+    /// Returns `None` if there is no such file.
+    pub async fn read_from_file(
+        path: &Path,
+        now_micros: u64,
+    ) -> Result<Option<Self>, ClachelessError> {
+        let bytes = match tokio::fs::read(path).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Self::io_error(e)),
+        };
+        Self::decode(&bytes, now_micros).map(Some)
+    }
+
+    /// Check the magic bytes and the format version.
+    fn check_preamble(preamble: &[u8; 12]) -> Result<(), ClachelessError> {
+        let (magic, version) = preamble.split_at(Self::MAGIC.len());
+        if magic != Self::MAGIC {
+            return Err(ClachelessErrorKind::Malformed.error_with_msg("Not a snapshot."));
+        }
+        let version = u32::from_be_bytes(version.try_into().unwrap());
+        if version != Self::FORMAT_VERSION {
+            return Err(ClachelessErrorKind::Malformed
+                .error_with_msg(format!("Unsupported snapshot format version {version}.")));
+        }
+        Ok(())
+    }
+
+    /// Check that the trailer matches the number of read entries.
+    fn check_trailer(trailer: &SnapshotTrailer, entry_count: u64) -> Result<(), ClachelessError> {
+        if trailer.entry_count != entry_count {
+            return Err(ClachelessErrorKind::Malformed.error_with_msg(format!(
+                "Snapshot trailer expects {} entries, but {entry_count} were read.",
+                trailer.entry_count
+            )));
+        }
+        Ok(())
+    }
+
+    fn entry_from(snapshot_entry: SnapshotEntry) -> CacheEntryAndKey {
+        CacheEntryAndKey {
+            key: snapshot_entry.key,
+            ce: Arc::new(CacheEntry {
+                this_update_micros: snapshot_entry.this_update_micros,
+                origin_node_id: snapshot_entry.origin_node_id,
+                origin_node_update_seq: snapshot_entry.origin_node_update_seq,
+                expires_micros: snapshot_entry.expires,
+                object_bytes: Arc::new(snapshot_entry.object_bytes),
+                bridged: snapshot_entry.bridged,
+            }),
+        }
+    }
+
+    fn io_error(e: std::io::Error) -> ClachelessError {
+        ClachelessErrorKind::Unspecified.error_with_msg(format!("Snapshot I/O failed: {e}"))
+    }
+
+    fn decode_error(e: prost::DecodeError) -> ClachelessError {
+        ClachelessErrorKind::Malformed.error_with_msg(format!("Corrupt snapshot: {e}"))
+    }
+
+    fn truncated_error() -> ClachelessError {
+        ClachelessErrorKind::Malformed.error_with_msg("Snapshot is truncated.")
+    }
+
+    fn empty_record_error() -> ClachelessError {
+        ClachelessErrorKind::Malformed.error_with_msg("Corrupt snapshot: Empty record.")
+    }
+}
+
+/// Incremental parser of the binary snapshot format from an async source.
+pub struct SnapshotReader<R> {
+    reader: R,
+    entry_count: u64,
+    complete: bool,
+}
+
+impl<R: AsyncRead + Unpin> SnapshotReader<R> {
+    /// Maximum size of a single encoded message.
+    const MAX_MESSAGE_BYTES: usize = 64 * 1024 * 1024;
+
+    /// Parse the preamble and header and return a reader positioned at the
+    /// first entry and the header's baselines.
+    pub async fn new(mut reader: R) -> Result<(Self, HashMap<u64, u64>), ClachelessError> {
+        let mut preamble = [0u8; 12];
+        reader
+            .read_exact(&mut preamble)
+            .await
+            .map_err(Self::read_error)?;
+        Snapshot::check_preamble(&preamble)?;
+        let mut ret = Self {
+            reader,
+            entry_count: 0,
+            complete: false,
+        };
+        let header = ret
+            .next_message::<SnapshotHeader>()
+            .await?
+            .ok_or_else(Snapshot::truncated_error)?;
+        Ok((ret, header.data_origin_id_and_baseline))
+    }
+
+    /// Return the next entry (including expired ones) or `None` after the
+    /// trailer.
+    ///
+    /// Fails if the source ends before the trailer or the trailer does not
+    /// match the number of read entries.
+    pub async fn next_entry(&mut self) -> Result<Option<CacheEntryAndKey>, ClachelessError> {
+        if self.complete {
+            return Ok(None);
+        }
+        let record = self
+            .next_message::<SnapshotRecord>()
+            .await?
+            .ok_or_else(Snapshot::truncated_error)?;
+        match record.record {
+            Some(Record::Entry(snapshot_entry)) => {
+                self.entry_count += 1;
+                Ok(Some(Snapshot::entry_from(snapshot_entry)))
+            }
+            Some(Record::Trailer(trailer)) => {
+                Snapshot::check_trailer(&trailer, self.entry_count)?;
+                let mut byte = [0u8; 1];
+                let read = self
+                    .reader
+                    .read(&mut byte)
+                    .await
+                    .map_err(Self::read_error)?;
+                if read != 0 {
+                    return Err(ClachelessErrorKind::Malformed
+                        .error_with_msg("Unexpected data after snapshot trailer."));
+                }
+                self.complete = true;
+                Ok(None)
+            }
+            None => Err(Snapshot::empty_record_error()),
+        }
+    }
+
+    /// Read the next length-delimited message or return `None` if the source
+    /// ends at a message boundary.
+    async fn next_message<M: Message + Default>(&mut self) -> Result<Option<M>, ClachelessError> {
+        // Varint length prefix
+        let mut len = 0usize;
+        for shift in (0..64).step_by(7) {
+            let mut byte = [0u8; 1];
+            let read = self
+                .reader
+                .read(&mut byte)
+                .await
+                .map_err(Self::read_error)?;
+            if read == 0 {
+                if shift == 0 {
+                    return Ok(None);
+                }
+                return Err(Snapshot::truncated_error());
+            }
+            len |= usize::from(byte[0] & 0x7f) << shift;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        if len > Self::MAX_MESSAGE_BYTES {
+            return Err(ClachelessErrorKind::Malformed
+                .error_with_msg(format!("Snapshot message of {len} bytes is too large.")));
+        }
+        let mut buf = vec![0u8; len];
+        self.reader
+            .read_exact(&mut buf)
+            .await
+            .map_err(Self::read_error)?;
+        M::decode(buf.as_slice())
+            .map(Some)
+            .map_err(Snapshot::decode_error)
+    }
+
+    fn read_error(e: std::io::Error) -> ClachelessError {
+        if e.kind() == std::io::ErrorKind::UnexpectedEof {
+            Snapshot::truncated_error()
+        } else {
+            Snapshot::io_error(e)
+        }
+    }
+}
+
+mod test {
+    //! Snapshot format tests.
+
+    #[test]
+    fn test_round_trip_skips_expired_entries() {
+        let entry = |key: &str, expires_micros| super::CacheEntryAndKey {
+            key: key.to_string(),
+            ce: std::sync::Arc::new(super::CacheEntry {
+                this_update_micros: 10,
+                origin_node_id: 1 << 32,
+                origin_node_update_seq: 7,
+                expires_micros,
+                object_bytes: std::sync::Arc::new(b"value".to_vec()),
+                bridged: false,
+            }),
+        };
+        let snapshot = super::Snapshot {
+            created_micros: 100,
+            data_origin_id_and_baseline: [(1 << 32, 7)].into_iter().collect(),
+            entries: vec![entry("expired", 150), entry("live", 250)],
+        };
+        let mut bytes = vec![];
+        snapshot.encode(&mut bytes).unwrap();
+        let decoded = super::Snapshot::decode(&bytes, 200).unwrap();
+        assert_eq!(decoded.created_micros, 100);
+        assert_eq!(
+            decoded.data_origin_id_and_baseline,
+            snapshot.data_origin_id_and_baseline
+        );
+        assert_eq!(decoded.entries.len(), 1);
+        assert_eq!(decoded.entries[0].key, "live");
+        assert_eq!(decoded.entries[0].ce.object_bytes.as_slice(), b"value");
+        assert!(super::Snapshot::decode(&bytes[..bytes.len() - 1], 200).is_err());
+        let trailer_len = super::Snapshot::encode_trailer(2).len();
+        let without_trailer = &bytes[..bytes.len() - trailer_len];
+        assert!(super::Snapshot::decode(without_trailer, 200).is_err());
+        let mut fewer_entries = super::Snapshot::encode_header(100, &Default::default());
+        fewer_entries.extend_from_slice(&super::Snapshot::encode_entry(&entry("live", 250)));
+        fewer_entries.extend_from_slice(&super::Snapshot::encode_trailer(2));
+        assert!(super::Snapshot::decode(&fewer_entries, 200).is_err());
+        bytes[9] = 0xff;
+        assert!(super::Snapshot::decode(&bytes, 200).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_streamed_read() {
+        let entry = super::CacheEntryAndKey {
+            key: "key".to_string(),
+            ce: std::sync::Arc::new(super::CacheEntry {
+                this_update_micros: 10,
+                origin_node_id: 1 << 32,
+                origin_node_update_seq: 7,
+                expires_micros: 20,
+                object_bytes: std::sync::Arc::new(vec![0xab; 300]),
+                bridged: false,
+            }),
+        };
+        let mut bytes = super::Snapshot::encode_header(100, &Default::default());
+        bytes.extend_from_slice(&super::Snapshot::encode_entry(&entry));
+        bytes.extend_from_slice(&super::Snapshot::encode_entry(&entry));
+        let without_trailer_len = bytes.len();
+        bytes.extend_from_slice(&super::Snapshot::encode_trailer(2));
+        let (mut reader, baselines) = super::SnapshotReader::new(bytes.as_slice()).await.unwrap();
+        assert!(baselines.is_empty());
+        for _ in 0..2 {
+            let read_entry = reader.next_entry().await.unwrap().unwrap();
+            assert_eq!(read_entry.key, "key");
+            assert_eq!(read_entry.ce.object_bytes.len(), 300);
+        }
+        assert!(reader.next_entry().await.unwrap().is_none());
+        assert!(reader.next_entry().await.unwrap().is_none());
+        // A stream cut at an entry boundary lacks the trailer
+        let without_trailer = &bytes[..without_trailer_len];
+        let (mut reader, _) = super::SnapshotReader::new(without_trailer).await.unwrap();
+        for _ in 0..2 {
+            assert!(reader.next_entry().await.unwrap().is_some());
+        }
+        assert!(reader.next_entry().await.is_err());
+        let truncated = &bytes[..without_trailer_len - 1];
+        let (mut reader, _) = super::SnapshotReader::new(truncated).await.unwrap();
+        assert!(reader.next_entry().await.unwrap().is_some());
+        assert!(reader.next_entry().await.is_err());
+    }
+}
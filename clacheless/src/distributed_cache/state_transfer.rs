@@ -0,0 +1,394 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Tracking of state transfers requested by the local node.
+
+use super::cluster_view::SequenceRanges;
+use crate::time::Clock;
+use crossbeam_skiplist::SkipMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicU8;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+/// State of a state transfer session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateTransferState {
+    /// Entries are still being recieved or the transfer is about to be
+    /// resumed.
+    Running,
+    /// All requested entries have been recieved.
+    Completed,
+    /// The transfer was abandoned after repeated failures.
+    Failed,
+}
+
+impl StateTransferState {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Self::Running,
+            1 => Self::Completed,
+            _ => Self::Failed,
+        }
+    }
+
+    fn as_u8(self) -> u8 {
+        match self {
+            Self::Running => 0,
+            Self::Completed => 1,
+            Self::Failed => 2,
+        }
+    }
+}
+
+/// Snapshot of the progress of a state transfer session.
+#[derive(Clone, Debug)]
+pub struct StateTransferProgress {
+    session_id: u64,
+    sender_node_ordinal: u32,
+    state: StateTransferState,
+    attempts: u32,
+    started_micros: u64,
+    updated_micros: u64,
+    received_entries: u64,
+    received_bytes: u64,
+    checkpoints: HashMap<u64, u64>,
+}
+
+impl StateTransferProgress {
+    /// Return the locally unique identifier of the session.
+    pub fn session_id(&self) -> u64 {
+        self.session_id
+    }
+
+    /// Return the ordinal of the node that sends the entries.
+    pub fn sender_node_ordinal(&self) -> u32 {
+        self.sender_node_ordinal
+    }
+
+    /// Return the current state of the session.
+    pub fn state(&self) -> StateTransferState {
+        self.state
+    }
+
+    /// Return how many times the transfer has been started or resumed.
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Return when the session started in epoch microseconds.
+    pub fn started_micros(&self) -> u64 {
+        self.started_micros
+    }
+
+    /// Return when the session last made progress or changed state in epoch
+    /// microseconds.
+    pub fn updated_micros(&self) -> u64 {
+        self.updated_micros
+    }
+
+    /// Return the number of entries recieved so far.
+    pub fn received_entries(&self) -> u64 {
+        self.received_entries
+    }
+
+    /// Return the number of object bytes recieved so far.
+    pub fn received_bytes(&self) -> u64 {
+        self.received_bytes
+    }
+
+    /// Return the highest recieved sequence number for each origin node id
+    /// that the transfer covers. An interrupted transfer resumes from here.
+    pub fn checkpoints(&self) -> &HashMap<u64, u64> {
+        &self.checkpoints
+    }
+}
+
+/// A state transfer from a single remote node.
+pub struct StateTransferSession {
+    session_id: u64,
+    sender_node_ordinal: u32,
+    started_micros: u64,
+    state: AtomicU8,
+    attempts: AtomicU32,
+    updated_micros: AtomicU64,
+    received_entries: AtomicU64,
+    received_bytes: AtomicU64,
+    ranges: HashMap<u64, SequenceRanges>,
+    checkpoints: Mutex<HashMap<u64, u64>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl StateTransferSession {
+    /// Return the locally unique identifier of the session.
+    pub fn session_id(&self) -> u64 {
+        self.session_id
+    }
+
+    /// Return the ordinal of the node that sends the entries.
+    pub fn sender_node_ordinal(&self) -> u32 {
+        self.sender_node_ordinal
+    }
+
+    /// Return the highest recieved sequence number of each origin node id.
+    pub fn checkpoints(&self) -> HashMap<u64, u64> {
+        self.checkpoints.lock().unwrap().clone()
+    }
+
+    /// Return the requested ranges of each origin node id.
+    pub fn ranges(&self) -> &HashMap<u64, SequenceRanges> {
+        &self.ranges
+    }
+
+    /// Return the requested ranges of each origin node id that are beyond the
+    /// checkpoint, to request (or resume) the transfer with.
+    pub fn remaining_ranges(&self) -> HashMap<u64, SequenceRanges> {
+        let checkpoints = self.checkpoints();
+        self.ranges
+            .iter()
+            .map(|(origin_node_id, ranges)| {
+                let mut remaining = ranges.clone();
+                if let Some(checkpoint) = checkpoints.get(origin_node_id) {
+                    remaining.remove_up_to(*checkpoint);
+                }
+                (*origin_node_id, remaining)
+            })
+            .filter(|(_origin_node_id, remaining)| !remaining.is_empty())
+            .collect()
+    }
+
+    /// Register a new attempt and return the number of attempts so far.
+    pub fn on_attempt(&self) -> u32 {
+        self.touch();
+        self.attempts.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Register that an entry has been recieved and applied.
+    pub fn on_entry_applied(&self, origin_node_id: u64, origin_node_update_seq: u64, bytes: usize) {
+        self.checkpoints
+            .lock()
+            .unwrap()
+            .entry(origin_node_id)
+            .and_modify(|checkpoint| *checkpoint = (*checkpoint).max(origin_node_update_seq));
+        self.received_entries.fetch_add(1, Ordering::Relaxed);
+        self.received_bytes
+            .fetch_add(u64::try_from(bytes).unwrap_or(u64::MAX), Ordering::Relaxed);
+        self.touch();
+    }
+
+    /// Mark the session as finished.
+    pub fn finish(&self, state: StateTransferState) {
+        self.state.store(state.as_u8(), Ordering::Relaxed);
+        self.touch();
+    }
+
+    /// Return the current state of the session.
+    pub fn state(&self) -> StateTransferState {
+        StateTransferState::from_u8(self.state.load(Ordering::Relaxed))
+    }
+
+    fn touch(&self) {
+        self.updated_micros
+            .store(self.clock.now_micros(), Ordering::Relaxed);
+    }
+
+    fn progress(&self) -> StateTransferProgress {
+        StateTransferProgress {
+            session_id: self.session_id,
+            sender_node_ordinal: self.sender_node_ordinal,
+            state: self.state(),
+            attempts: self.attempts.load(Ordering::Relaxed),
+            started_micros: self.started_micros,
+            updated_micros: self.updated_micros.load(Ordering::Relaxed),
+            received_entries: self.received_entries.load(Ordering::Relaxed),
+            received_bytes: self.received_bytes.load(Ordering::Relaxed),
+            checkpoints: self.checkpoints(),
+        }
+    }
+}
+
+/** Running and recently finished state transfer sessions.
+
+At most one running session covers each origin node id, so repeated detection
+of the same lag does not lead to concurrent transfers of the same entries.
+*/
+pub struct StateTransfers {
+    start_lock: Mutex<()>,
+    last_session_id: AtomicU64,
+    sessions: SkipMap<u64, Arc<StateTransferSession>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl StateTransfers {
+    /// Number of finished sessions to keep for progress reporting.
+    const MAX_FINISHED_SESSIONS: usize = 16;
+
+    /// Return a new instance that timestamps sessions with the `clock`.
+    pub fn new(clock: &Arc<dyn Clock>) -> Self {
+        Self {
+            start_lock: Mutex::default(),
+            last_session_id: AtomicU64::default(),
+            sessions: SkipMap::default(),
+            clock: Arc::clone(clock),
+        }
+    }
+
+    /// Start a new session for the origin node ids that aren't already
+    /// covered by a running session.
+    ///
+    /// Returns `None` if there is nothing left to transfer.
+    pub fn start(
+        &self,
+        sender_node_ordinal: u32,
+        data_origin_id_and_ranges: HashMap<u64, SequenceRanges>,
+    ) -> Option<Arc<StateTransferSession>> {
+        let _start_guard = self.start_lock.lock().unwrap();
+        let in_progress = self
+            .sessions
+            .iter()
+            .filter(|entry| entry.value().state() == StateTransferState::Running)
+            .flat_map(|entry| entry.value().ranges().keys().copied().collect::<Vec<_>>())
+            .collect::<HashSet<_>>();
+        let ranges = data_origin_id_and_ranges
+            .into_iter()
+            .filter(|(origin_node_id, _ranges)| !in_progress.contains(origin_node_id))
+            .collect::<HashMap<_, _>>();
+        if ranges.is_empty() {
+            return None;
+        }
+        let checkpoints = ranges
+            .keys()
+            .map(|origin_node_id| (*origin_node_id, 0))
+            .collect();
+        self.purge_finished();
+        let now_micros = self.clock.now_micros();
+        let session_id = self.last_session_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let session = Arc::new(StateTransferSession {
+            session_id,
+            sender_node_ordinal,
+            started_micros: now_micros,
+            state: AtomicU8::new(StateTransferState::Running.as_u8()),
+            attempts: AtomicU32::default(),
+            updated_micros: AtomicU64::new(now_micros),
+            received_entries: AtomicU64::default(),
+            received_bytes: AtomicU64::default(),
+            ranges,
+            checkpoints: Mutex::new(checkpoints),
+            clock: Arc::clone(&self.clock),
+        });
+        self.sessions.insert(session_id, Arc::clone(&session));
+        Some(session)
+    }
+
+    /// Return the progress of running and recently finished sessions.
+    pub fn progress(&self) -> Vec<StateTransferProgress> {
+        self.sessions
+            .iter()
+            .map(|entry| entry.value().progress())
+            .collect()
+    }
+
+    /// Forget about the oldest finished sessions.
+    fn purge_finished(&self) {
+        let finished = self
+            .sessions
+            .iter()
+            .filter(|entry| entry.value().state() != StateTransferState::Running)
+            .map(|entry| *entry.key())
+            .collect::<Vec<_>>();
+        finished
+            .iter()
+            .take(finished.len().saturating_sub(Self::MAX_FINISHED_SESSIONS))
+            .for_each(|session_id| {
+                self.sessions.remove(session_id);
+            });
+    }
+}
+
+mod test {
+    //! State transfer session tests.
+
+    #[test]
+    fn test_concurrent_transfers_are_deduplicated() {
+        use super::StateTransferState;
+        use super::StateTransfers;
+        use crate::distributed_cache::cluster_view::SequenceRanges;
+        use crate::time::Clock;
+        use crate::time::ManualClock;
+        use std::collections::HashMap;
+        use std::sync::Arc;
+
+        let clock: Arc<dyn Clock> = ManualClock::new(1_000_000);
+        let state_transfers = StateTransfers::new(&clock);
+        let ranges = |origin_node_ids: &[u64]| {
+            origin_node_ids
+                .iter()
+                .map(|origin_node_id| (*origin_node_id, [1..=10].into_iter().collect()))
+                .collect::<HashMap<u64, SequenceRanges>>()
+        };
+        let first = state_transfers.start(1, ranges(&[100, 200])).unwrap();
+        // Origins covered by a running session are not transferred again
+        assert!(state_transfers.start(2, ranges(&[100, 200])).is_none());
+        let second = state_transfers.start(2, ranges(&[200, 300])).unwrap();
+        assert_eq!(
+            second.ranges().keys().copied().collect::<Vec<_>>(),
+            vec![300]
+        );
+        first.finish(StateTransferState::Completed);
+        assert!(state_transfers.start(2, ranges(&[100])).is_some());
+        assert_eq!(state_transfers.progress().len(), 3);
+    }
+
+    #[test]
+    fn test_interrupted_transfer_resumes_after_checkpoint() {
+        use super::StateTransfers;
+        use crate::distributed_cache::cluster_view::SequenceRanges;
+        use crate::time::Clock;
+        use crate::time::ManualClock;
+        use std::collections::HashMap;
+        use std::sync::Arc;
+
+        let clock: Arc<dyn Clock> = ManualClock::new(1_000_000);
+        let state_transfers = StateTransfers::new(&clock);
+        let session = state_transfers
+            .start(
+                1,
+                HashMap::from([
+                    (100, [1..=5, 8..=10].into_iter().collect::<SequenceRanges>()),
+                    (200, [1..=3].into_iter().collect::<SequenceRanges>()),
+                ]),
+            )
+            .unwrap();
+        assert_eq!(session.on_attempt(), 1);
+        session.on_entry_applied(100, 1, 10);
+        session.on_entry_applied(100, 2, 10);
+        session.on_entry_applied(100, 8, 10);
+        session.on_entry_applied(200, 3, 10);
+        assert_eq!(session.on_attempt(), 2);
+        let remaining = session.remaining_ranges();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[&100].iter().collect::<Vec<_>>(), vec![9..=10]);
+        let progress = &state_transfers.progress()[0];
+        assert_eq!(progress.attempts(), 2);
+        assert_eq!(progress.received_entries(), 4);
+        assert_eq!(progress.received_bytes(), 40);
+    }
+}
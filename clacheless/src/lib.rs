@@ -19,19 +19,46 @@
 #![warn(missing_docs)]
 #![doc = include_str!("../README.md")]
 
+pub mod backing_store;
+pub mod bridge;
+mod clacheless_config;
 mod clacheless_error;
 mod distributed_cache;
 pub(crate) mod proto {
     //! Includes gRPC code generated by `build.rs`
 
+    pub mod bridge {
+        //! Stream format between the bridges of two clusters.
+
+        tonic::include_proto!("bridge");
+    }
+
+    pub mod snapshot {
+        //! Binary format of cache snapshots.
+
+        tonic::include_proto!("snapshot");
+    }
+
     pub mod stateshare {
         //! gRPC package for sharing a cache over network.
 
         tonic::include_proto!("stateshare");
     }
 }
-pub(crate) mod time;
+pub mod time;
 pub mod util;
 
+pub use self::distributed_cache::CacheUpdate;
+pub use self::distributed_cache::ClusterStatus;
+pub use self::distributed_cache::ConflictResolution;
 pub use self::distributed_cache::DistributedCache;
+pub use self::distributed_cache::InMemoryNetwork;
+pub use self::distributed_cache::KeyConflict;
+pub use self::distributed_cache::MembershipEvent;
+pub use self::distributed_cache::OriginStatus;
+pub use self::distributed_cache::PartitionReport;
+pub use self::distributed_cache::PeerStatus;
+pub use self::distributed_cache::StateTransferProgress;
+pub use self::distributed_cache::StateTransferState;
+pub use clacheless_config::*;
 pub use clacheless_error::*;
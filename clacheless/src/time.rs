@@ -15,7 +15,19 @@
     limitations under the License.
 */
 
-//! Time related utility functions.
+//! Time sources.
+//!
+//! All timing decisions of a [DistributedCache](crate::DistributedCache) are
+//! based on the [Clock] of its
+//! [ClachelessConfig](crate::ClachelessConfig::with_clock). Tests can use a
+//! [ManualClock] to control the passing of time.
+
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::time::Instant;
 
 /// Microseconds since UNIX epoch
 pub fn get_timestamp_micros() -> u64 {
@@ -27,3 +39,80 @@ pub fn get_timestamp_micros() -> u64 {
     )
     .expect("Current epoch time in microseconds did not fit inside a 64-bit unsigned.")
 }
+
+/// Source of the current wall clock time.
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Microseconds since UNIX epoch
+    fn now_micros(&self) -> u64;
+}
+
+/// [Clock] of the operating system.
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_micros(&self) -> u64 {
+        get_timestamp_micros()
+    }
+}
+
+/** [Clock] for tests.
+
+The clock follows the (monotonic) time of the `tokio` runtime and can be moved
+forward with [Self::advance]. With paused `tokio` time
+(`#[tokio::test(start_paused = true)]`) time only passes when all tasks are
+waiting on timers, which makes tests both fast and deterministic.
+
+Advancing the clock does not wake sleeping tasks. This allows e.g. cache
+entries to expire without running the background loops.
+
+```
+# #[tokio::main(flavor = "current_thread")]
+# async fn main() {
+# tokio::time::pause();
+use clacheless::time::Clock;
+use clacheless::time::ManualClock;
+use std::time::Duration;
+
+let clock = ManualClock::new(1_000_000);
+clock.advance(Duration::from_secs(2));
+assert_eq!(clock.now_micros(), 3_000_000);
+# }
+```
+*/
+#[derive(Debug)]
+pub struct ManualClock {
+    start: Instant,
+    start_micros: u64,
+    advanced_micros: AtomicU64,
+}
+
+impl ManualClock {
+    /// Return a new instance that starts at `start_micros` since UNIX epoch.
+    ///
+    /// Must be called from within a `tokio` runtime.
+    pub fn new(start_micros: u64) -> Arc<Self> {
+        Arc::new(Self {
+            start: Instant::now(),
+            start_micros,
+            advanced_micros: AtomicU64::default(),
+        })
+    }
+
+    /// Move the clock forward.
+    pub fn advance(&self, duration: Duration) {
+        self.advanced_micros.fetch_add(
+            u64::try_from(duration.as_micros()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_micros(&self) -> u64 {
+        let elapsed_micros = u64::try_from(self.start.elapsed().as_micros()).unwrap_or(u64::MAX);
+        self.start_micros
+            .saturating_add(elapsed_micros)
+            .saturating_add(self.advanced_micros.load(Ordering::Relaxed))
+    }
+}
@@ -0,0 +1,121 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Integration tests of [DistributedCache] in front of a [BackingStore].
+
+use clacheless::ClachelessConfig;
+use clacheless::DistributedCache;
+use clacheless::backing_store::BackingStore;
+use clacheless::backing_store::FileSystemBackingStore;
+use clacheless::backing_store::InMemoryBackingStore;
+use clacheless::backing_store::WritePolicy;
+use clacheless::time::ManualClock;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn write_through_and_read_through() {
+    let backing_store = Arc::new(InMemoryBackingStore::default());
+    let config = ClachelessConfig::default()
+        .with_backing_store(Arc::clone(&backing_store) as _, WritePolicy::WriteThrough);
+    let dc =
+        DistributedCache::new_with_config("clacheless-ORDINAL.local:9000", 0, 30_000_000, config)
+            .await
+            .unwrap();
+    dc.put_string("written", "value")
+        .await
+        .expect("Failed to update cache.");
+    assert_eq!(
+        backing_store.load("written").await.unwrap(),
+        Some(b"value".to_vec())
+    );
+    backing_store.store("preloaded", b"stored").await.unwrap();
+    assert_eq!(dc.get_string("preloaded").await.unwrap(), "stored");
+    // Objects loaded from the backing store are not written back
+    assert_eq!(backing_store.store_count(), 2);
+    assert!(dc.get_string("missing").await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn write_behind_to_file_system() {
+    let dir = std::env::temp_dir().join(format!("clacheless-test-{}", std::process::id()));
+    let backing_store = Arc::new(FileSystemBackingStore::new(&dir).unwrap());
+    let config = ClachelessConfig::default()
+        .with_backing_store(Arc::clone(&backing_store) as _, WritePolicy::WriteBehind);
+    let dc =
+        DistributedCache::new_with_config("clacheless-ORDINAL.local:9000", 0, 30_000_000, config)
+            .await
+            .unwrap();
+    let cache_key = "key/with/../separators";
+    dc.put_string(cache_key, "first").await.unwrap();
+    dc.put_string(cache_key, "second").await.unwrap();
+    let mut stored = None;
+    for _ in 0..50 {
+        stored = backing_store.load(cache_key).await.unwrap();
+        if stored.is_some() {
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+    }
+    std::fs::remove_dir_all(&dir).ok();
+    assert_eq!(stored, Some(b"second".to_vec()));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn leaving_node_does_not_cache_loaded_objects() {
+    let backing_store = Arc::new(InMemoryBackingStore::default());
+    let config = ClachelessConfig::default()
+        .with_backing_store(Arc::clone(&backing_store) as _, WritePolicy::WriteThrough);
+    let dc =
+        DistributedCache::new_with_config("clacheless-ORDINAL.local:9000", 0, 30_000_000, config)
+            .await
+            .unwrap();
+    backing_store.store("preloaded", b"stored").await.unwrap();
+    dc.leave(1_000_000).await.unwrap();
+    assert_eq!(dc.get_string("preloaded").await.unwrap(), "stored");
+    backing_store.delete("preloaded").await.unwrap();
+    assert!(dc.get_string("preloaded").await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn expired_objects_are_removed() {
+    let backing_store = Arc::new(InMemoryBackingStore::default());
+    let clock = ManualClock::new(1_700_000_000_000_000);
+    let config = ClachelessConfig::default()
+        .with_clock(clock.clone())
+        .with_purge_interval_micros(10_000)
+        .with_backing_store(Arc::clone(&backing_store) as _, WritePolicy::WriteThrough);
+    let dc =
+        DistributedCache::new_with_config("clacheless-ORDINAL.local:9000", 0, 30_000_000, config)
+            .await
+            .unwrap();
+    dc.put_bytes_with_ttl("short", b"lived", 1_000_000)
+        .await
+        .unwrap();
+    dc.put_string("long", "lived").await.unwrap();
+    clock.advance(Duration::from_secs(2));
+    let mut stored = Some(vec![]);
+    for _ in 0..50 {
+        stored = backing_store.load("short").await.unwrap();
+        if stored.is_none() {
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(stored, None);
+    assert!(backing_store.load("long").await.unwrap().is_some());
+}
@@ -0,0 +1,456 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Convergence tests of a cluster of [DistributedCache] instances on an
+//! [InMemoryNetwork].
+
+use clacheless::ClachelessConfig;
+use clacheless::ClachelessErrorKind;
+use clacheless::ConflictResolution;
+use clacheless::DistributedCache;
+use clacheless::InMemoryNetwork;
+use clacheless::MembershipEvent;
+use clacheless::NodeRole;
+use clacheless::PartitionReport;
+use clacheless::bridge::Bridge;
+use clacheless::bridge::BridgeConfig;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+const CLUSTER_SIZE: u32 = 3;
+const CONVERGENCE_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Start a cluster of `CLUSTER_SIZE` nodes on the network and wait until all
+/// nodes know each other.
+async fn start_cluster(
+    network: &Arc<InMemoryNetwork>,
+) -> (Vec<Arc<DistributedCache>>, Vec<JoinHandle<()>>) {
+    start_cluster_with_config(network, ClachelessConfig::default()).await
+}
+
+/// Return a node on the network with short timings applied on top of
+/// `config`.
+async fn new_node(
+    network: &Arc<InMemoryNetwork>,
+    node_ordinal: u32,
+    config: ClachelessConfig,
+) -> Arc<DistributedCache> {
+    let config = config
+        .with_state_broadcast_interval_micros(100_000)
+        .with_alive_margin_micros(50_000)
+        .with_initial_sync_timeout_micros(1_000_000)
+        .with_in_memory_network(Arc::clone(network))
+        .validate()
+        .unwrap();
+    DistributedCache::new_with_config(
+        "clacheless-ORDINAL.local:9000",
+        node_ordinal,
+        60_000_000,
+        config,
+    )
+    .await
+    .unwrap()
+}
+
+/// Run the node until the returned task is aborted.
+fn run_node(dc: &Arc<DistributedCache>) -> JoinHandle<()> {
+    let dc_clone = Arc::clone(dc);
+    tokio::spawn(async move {
+        dc_clone.run().await.unwrap();
+    })
+}
+
+/// Start a cluster like [start_cluster] from `config`.
+async fn start_cluster_with_config(
+    network: &Arc<InMemoryNetwork>,
+    config: ClachelessConfig,
+) -> (Vec<Arc<DistributedCache>>, Vec<JoinHandle<()>>) {
+    let mut nodes = Vec::new();
+    let mut tasks = Vec::new();
+    for node_ordinal in 0..CLUSTER_SIZE {
+        let dc = new_node(network, node_ordinal, config.clone()).await;
+        tasks.push(run_node(&dc));
+        nodes.push(dc);
+    }
+    let deadline = Instant::now() + CONVERGENCE_TIMEOUT;
+    for dc in &nodes {
+        while dc.cluster_status().await.peers().len() + 1 < nodes.len() {
+            assert!(Instant::now() < deadline, "Cluster did not form.");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+    (nodes, tasks)
+}
+
+/// Wait until all nodes return the expected value for each key.
+async fn assert_converges(nodes: &[Arc<DistributedCache>], expected: &[(String, String)]) {
+    let deadline = Instant::now() + CONVERGENCE_TIMEOUT;
+    loop {
+        let mut missing = 0;
+        for dc in nodes {
+            for (key, value) in expected {
+                if dc.get_string(key).await.ok().as_ref() != Some(value) {
+                    missing += 1;
+                }
+            }
+        }
+        if missing == 0 {
+            return;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "{missing} entries did not converge within {CONVERGENCE_TIMEOUT:?}."
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Write `count` entries round-robin over the nodes.
+async fn put_entries(
+    nodes: &[Arc<DistributedCache>],
+    prefix: &str,
+    count: usize,
+) -> Vec<(String, String)> {
+    let mut expected = Vec::new();
+    for i in 0..count {
+        let key = format!("{prefix}-{i}");
+        let value = format!("value-{i}");
+        nodes[i % nodes.len()]
+            .put_string(&key, &value)
+            .await
+            .unwrap();
+        expected.push((key, value));
+    }
+    expected
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn replicates_to_all_nodes() {
+    let network = InMemoryNetwork::new();
+    let (nodes, tasks) = start_cluster(&network).await;
+    let expected = put_entries(&nodes, "key", 30).await;
+    assert_converges(&nodes, &expected).await;
+    tasks.iter().for_each(JoinHandle::abort);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn converges_with_loss_and_reordering() {
+    let network = InMemoryNetwork::new();
+    let (nodes, tasks) = start_cluster(&network).await;
+    network.set_latency(Duration::from_millis(1), Duration::from_millis(20));
+    network.set_loss_percent(20);
+    let expected = put_entries(&nodes, "lossy", 30).await;
+    network.set_loss_percent(0);
+    assert_converges(&nodes, &expected).await;
+    tasks.iter().for_each(JoinHandle::abort);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn converges_after_partition_heals() {
+    let network = InMemoryNetwork::new();
+    let (nodes, tasks) = start_cluster(&network).await;
+    let mut expected = put_entries(&nodes, "before", 10).await;
+    assert_converges(&nodes, &expected).await;
+    network.partition(&[&[0], &[1, 2]]);
+    expected.extend(put_entries(&nodes[..1], "minority", 10).await);
+    expected.extend(put_entries(&nodes[1..], "majority", 10).await);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(nodes[1].get_string("minority-0").await.is_err());
+    network.heal();
+    assert_converges(&nodes, &expected).await;
+    tasks.iter().for_each(JoinHandle::abort);
+}
+
+/// Wait until the node has seen at least `changes` membership changes after
+/// `epoch`.
+async fn wait_for_membership_changes(dc: &DistributedCache, epoch: u64, changes: u64) {
+    let deadline = Instant::now() + CONVERGENCE_TIMEOUT;
+    while dc.cluster_status().await.membership_epoch() < epoch + changes {
+        assert!(Instant::now() < deadline, "Membership did not change.");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn reports_conflicts_when_partition_heals() {
+    let network = InMemoryNetwork::new();
+    let healed = Arc::new(Mutex::new(Vec::<PartitionReport>::new()));
+    let healed_clone = Arc::clone(&healed);
+    let config = ClachelessConfig::default().with_partition_healed_callback(Arc::new(
+        move |report: &PartitionReport| healed_clone.lock().unwrap().push(report.clone()),
+    ));
+    let (nodes, tasks) = start_cluster_with_config(&network, config).await;
+    let epoch_before = nodes[0].cluster_status().await.membership_epoch();
+    let other_epoch_before = nodes[1].cluster_status().await.membership_epoch();
+    network.partition(&[&[0], &[1, 2]]);
+    wait_for_membership_changes(&nodes[0], epoch_before, 2).await;
+    wait_for_membership_changes(&nodes[1], other_epoch_before, 1).await;
+    nodes[0].put_string("contested", "minority").await.unwrap();
+    nodes[1].put_string("contested", "majority").await.unwrap();
+    network.heal();
+    let expected = vec![("contested".to_string(), "majority".to_string())];
+    assert_converges(&nodes, &expected).await;
+    let deadline = Instant::now() + CONVERGENCE_TIMEOUT;
+    let report = loop {
+        if let Some(report) = nodes[0]
+            .partition_reports()
+            .into_iter()
+            .find(|report| report.conflict_count() > 0)
+        {
+            break report;
+        }
+        assert!(Instant::now() < deadline, "No partition was reported.");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+    assert!(report.healed_epoch() > report.split_epoch());
+    assert!(report.split_epoch() > epoch_before);
+    let conflict = &report.conflicts()[0];
+    assert_eq!(conflict.key(), "contested");
+    assert_eq!(conflict.resolution(), ConflictResolution::RemoteWon);
+    assert!(!healed.lock().unwrap().is_empty());
+    tasks.iter().for_each(JoinHandle::abort);
+}
+
+/// Recieve membership events until `expected` has been seen.
+async fn wait_for_membership_event(
+    membership_events: &mut broadcast::Receiver<MembershipEvent>,
+    expected: MembershipEvent,
+) {
+    tokio::time::timeout(CONVERGENCE_TIMEOUT, async {
+        while membership_events.recv().await.unwrap() != expected {}
+    })
+    .await
+    .unwrap_or_else(|_| panic!("Did not recieve {expected:?}."));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn publishes_membership_events() {
+    let network = InMemoryNetwork::new();
+    let (nodes, tasks) = start_cluster(&network).await;
+    let mut membership_events = nodes[0].membership_events();
+    assert_eq!(nodes[0].members(), vec![0, 1, 2]);
+    network.partition(&[&[0], &[1, 2]]);
+    for node_ordinal in [1, 2] {
+        wait_for_membership_event(
+            &mut membership_events,
+            MembershipEvent::Left { node_ordinal },
+        )
+        .await;
+    }
+    assert_eq!(nodes[0].members(), vec![0]);
+    network.heal();
+    wait_for_membership_event(
+        &mut membership_events,
+        MembershipEvent::Joined { node_ordinal: 1 },
+    )
+    .await;
+    // A new node has to catch up with what was written before it joined
+    nodes[1].put_string("before-join", "value").await.unwrap();
+    let new_dc = new_node(&network, CLUSTER_SIZE, ClachelessConfig::default()).await;
+    let mut new_membership_events = new_dc.membership_events();
+    let new_task = run_node(&new_dc);
+    let node_ordinal = tokio::time::timeout(CONVERGENCE_TIMEOUT, async {
+        loop {
+            if let MembershipEvent::SyncStarted { node_ordinal } =
+                new_membership_events.recv().await.unwrap()
+            {
+                return node_ordinal;
+            }
+        }
+    })
+    .await
+    .expect("No sync was started.");
+    wait_for_membership_event(
+        &mut new_membership_events,
+        MembershipEvent::SyncCompleted {
+            node_ordinal,
+            succeeded: true,
+        },
+    )
+    .await;
+    assert_eq!(new_dc.get_string("before-join").await.unwrap(), "value");
+    wait_for_membership_event(
+        &mut membership_events,
+        MembershipEvent::Joined {
+            node_ordinal: CLUSTER_SIZE,
+        },
+    )
+    .await;
+    new_task.abort();
+    tasks.iter().for_each(JoinHandle::abort);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn observer_replicates_without_joining() {
+    let network = InMemoryNetwork::new();
+    let (nodes, mut tasks) = start_cluster(&network).await;
+    let mut expected = put_entries(&nodes, "before", 10).await;
+    let observer_ordinal = 10;
+    let observer = new_node(
+        &network,
+        observer_ordinal,
+        ClachelessConfig::default().with_node_role(NodeRole::Observer),
+    )
+    .await;
+    tasks.push(run_node(&observer));
+    expected.extend(put_entries(&nodes, "after", 10).await);
+    assert_converges(std::slice::from_ref(&observer), &expected).await;
+    assert!(observer.put_string("rejected", "value").await.is_err());
+    assert_eq!(observer.members(), vec![0, 1, 2]);
+    for dc in &nodes {
+        let cluster_status = dc.cluster_status().await;
+        assert_eq!(dc.members(), vec![0, 1, 2]);
+        assert!(
+            cluster_status
+                .peers()
+                .iter()
+                .all(|peer| peer.node_ordinal() != observer_ordinal)
+        );
+        assert!(
+            cluster_status
+                .origins()
+                .iter()
+                .all(|origin| origin.origin_node_id() & 0xffff_ffff != u64::from(observer_ordinal))
+        );
+    }
+    tasks.iter().for_each(JoinHandle::abort);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn leaving_node_hands_off_data() {
+    let network = InMemoryNetwork::new();
+    let (nodes, tasks) = start_cluster(&network).await;
+    let expected = put_entries(&nodes[..1], "handoff", 20).await;
+    nodes[0].leave(5_000_000).await.unwrap();
+    assert!(!nodes[0].is_ready());
+    assert_eq!(
+        nodes[0]
+            .put_string("late", "value")
+            .await
+            .unwrap_err()
+            .kind(),
+        &ClachelessErrorKind::Unavailable
+    );
+    assert_converges(&nodes[1..], &expected).await;
+    tasks.iter().for_each(JoinHandle::abort);
+}
+
+/// Run the bridges of two clusters over an in-memory connection until the
+/// returned task is aborted.
+fn run_bridges(bridge_a: &Arc<Bridge>, bridge_b: &Arc<Bridge>) -> JoinHandle<()> {
+    let (stream_a, mut proxy_a) = tokio::io::duplex(64 * 1024);
+    let (stream_b, mut proxy_b) = tokio::io::duplex(64 * 1024);
+    for (bridge, stream) in [(bridge_a, stream_a), (bridge_b, stream_b)] {
+        let bridge = Arc::clone(bridge);
+        // The bridge returns when the connection is cut
+        tokio::spawn(async move { bridge.run(stream).await.ok() });
+    }
+    tokio::spawn(async move {
+        tokio::io::copy_bidirectional(&mut proxy_a, &mut proxy_b)
+            .await
+            .ok();
+    })
+}
+
+/// Wait until the bridges are connected or disconnected.
+async fn wait_for_bridges(bridges: &[&Arc<Bridge>], connected: bool) {
+    let deadline = Instant::now() + CONVERGENCE_TIMEOUT;
+    while bridges
+        .iter()
+        .any(|bridge| bridge.is_connected() != connected)
+    {
+        assert!(Instant::now() < deadline, "Bridges did not (dis)connect.");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn bridge_replicates_prefixes_between_clusters() {
+    let network_east = InMemoryNetwork::new();
+    let network_west = InMemoryNetwork::new();
+    let (east, mut tasks) = start_cluster(&network_east).await;
+    let (west, west_tasks) = start_cluster(&network_west).await;
+    tasks.extend(west_tasks);
+    let mut expected = put_entries(&east, "shared/before", 10).await;
+    let bridge_config =
+        |cluster_id| BridgeConfig::new(cluster_id, b"secret").with_key_prefix("shared/");
+    let bridge_east = Bridge::new(&east[0], bridge_config("east"));
+    let bridge_west = Bridge::new(&west[0], bridge_config("west"));
+    let bridges = run_bridges(&bridge_east, &bridge_west);
+    expected.extend(put_entries(&east, "shared/east", 10).await);
+    expected.extend(put_entries(&west, "shared/west", 10).await);
+    put_entries(&west, "local/west", 10).await;
+    // Concurrent writes of the same key resolve to the most recent write
+    west[1]
+        .put_string("shared/conflict", "older")
+        .await
+        .unwrap();
+    east[1]
+        .put_string("shared/conflict", "newer")
+        .await
+        .unwrap();
+    expected.push(("shared/conflict".to_owned(), "newer".to_owned()));
+    assert_converges(&east, &expected).await;
+    assert_converges(&west, &expected).await;
+    for dc in &east {
+        assert!(dc.get_string("local/west-0").await.is_err());
+    }
+    // Recieved entries are not sent back
+    let sent_east = bridge_east.status().sent_entries();
+    let sent_west = bridge_west.status().sent_entries();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(bridge_east.status().sent_entries(), sent_east);
+    assert_eq!(bridge_west.status().sent_entries(), sent_west);
+    assert_eq!(bridge_east.status().remote_cluster_id(), Some("west"));
+    // Reconnecting only sends the entries written in each cluster
+    bridges.abort();
+    wait_for_bridges(&[&bridge_east, &bridge_west], false).await;
+    let sent_east = bridge_east.status().sent_entries();
+    let sent_west = bridge_west.status().sent_entries();
+    let bridges = run_bridges(&bridge_east, &bridge_west);
+    wait_for_bridges(&[&bridge_east, &bridge_west], true).await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(bridge_east.status().sent_entries(), sent_east + 21);
+    assert_eq!(bridge_west.status().sent_entries(), sent_west + 10);
+    bridges.abort();
+    tasks.iter().for_each(JoinHandle::abort);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn bridge_rejects_wrong_secret() {
+    let network = InMemoryNetwork::new();
+    let east = new_node(&network, 0, ClachelessConfig::default()).await;
+    let west = new_node(&network, 1, ClachelessConfig::default()).await;
+    let bridge_east = Bridge::new(&east, BridgeConfig::new("east", b"secret"));
+    let bridge_west = Bridge::new(&west, BridgeConfig::new("west", b"guessed"));
+    let (stream_east, stream_west) = tokio::io::duplex(64 * 1024);
+    let (res_east, res_west) =
+        tokio::join!(bridge_east.run(stream_east), bridge_west.run(stream_west));
+    assert_eq!(
+        res_east.unwrap_err().kind(),
+        &ClachelessErrorKind::Connection
+    );
+    assert_eq!(
+        res_west.unwrap_err().kind(),
+        &ClachelessErrorKind::Connection
+    );
+    assert!(!bridge_east.is_connected());
+}
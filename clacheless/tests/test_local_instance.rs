@@ -17,6 +17,8 @@
 
 //! Integration tests of [InterPodCache].
 
+use clacheless::ClachelessConfig;
+use clacheless::ClachelessErrorKind;
 use clacheless::DistributedCache;
 use std::sync::Arc;
 
@@ -31,7 +33,65 @@ async fn run_local_instance() {
         .await
         .expect("Failed to update local-only cache.");
     let read_result = dc
-        .get_string(&cache_key)
+        .get_string(cache_key)
+        .await
         .expect("Locally cached item should always be available.");
     assert_eq!(read_result, cache_value);
 }
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn local_instance_cluster_status() {
+    let dc = DistributedCache::new("clacheless-ORDINAL.local:9000", 0, 30_000_000).await;
+    let cache_value = "cache_value";
+    dc.put_string("cache_key", cache_value)
+        .await
+        .expect("Failed to update local-only cache.");
+    let cluster_status = dc.cluster_status().await;
+    assert!(cluster_status.peers().is_empty());
+    assert_eq!(cluster_status.local_entries(), 1);
+    assert_eq!(cluster_status.local_bytes(), cache_value.len() as u64);
+    let local_origin = cluster_status
+        .origins()
+        .iter()
+        .find(|origin| origin.origin_node_id() == cluster_status.local_node_id())
+        .expect("Local node should be an origin.");
+    assert_eq!(local_origin.latest_seq(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn invalid_config_is_rejected() {
+    let config = ClachelessConfig::default().with_purge_interval_micros(0);
+    let res =
+        DistributedCache::new_with_config("clacheless-ORDINAL.local:9000", 0, 30_000_000, config)
+            .await;
+    let e = res
+        .err()
+        .expect("Invalid configuration should be rejected.");
+    assert_eq!(e.kind(), &ClachelessErrorKind::Malformed);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn import_applies_entries_as_they_are_read() {
+    let source = DistributedCache::new("clacheless-ORDINAL.local:9000", 0, 30_000_000).await;
+    for index in 0..3 {
+        source
+            .put_string(&format!("key{index}"), "value")
+            .await
+            .unwrap();
+    }
+    let mut exported = Vec::new();
+    assert_eq!(source.export(&mut exported).await.unwrap(), 3);
+    // Entries read before the input ends are kept
+    let target = DistributedCache::new("clacheless-ORDINAL.local:9000", 0, 30_000_000).await;
+    let mut truncated = &exported[..exported.len() - 1];
+    let e = target.import(&mut truncated).await.unwrap_err();
+    assert_eq!(e.kind(), &ClachelessErrorKind::Malformed);
+    for index in 0..3 {
+        assert_eq!(
+            target.get_string(&format!("key{index}")).await.unwrap(),
+            "value"
+        );
+    }
+    // Importing again skips the entries that are already present
+    assert_eq!(target.import(&mut exported.as_slice()).await.unwrap(), 0);
+}
@@ -0,0 +1,551 @@
+/*
+    Copyright 2025 MydriaTech AB
+
+    Licensed under the Apache License 2.0 with Free world makers exception
+    1.0.0 (the "License"); you may not use this file except in compliance with
+    the License. You should have obtained a copy of the License with the source
+    or binary distribution in file named
+
+        LICENSE-Apache-2.0-with-FWM-Exception-1.0.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Deterministic tests of [DistributedCache] timing with a [ManualClock] and
+//! paused `tokio` time.
+
+use clacheless::ClachelessConfig;
+use clacheless::ClachelessErrorKind;
+use clacheless::DistributedCache;
+use clacheless::InMemoryNetwork;
+use clacheless::MembershipEvent;
+use clacheless::ReplicationOverflowPolicy;
+use clacheless::backing_store::BackingStore;
+use clacheless::backing_store::InMemoryBackingStore;
+use clacheless::backing_store::WritePolicy;
+use clacheless::bridge::Bridge;
+use clacheless::bridge::BridgeConfig;
+use clacheless::bridge::BridgeConflictPolicy;
+use clacheless::time::ManualClock;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// Arbitrary fixed start of the manual clock.
+const START_MICROS: u64 = 1_700_000_000_000_000;
+
+#[tokio::test(start_paused = true)]
+async fn entries_expire_when_clock_advances() {
+    let clock = ManualClock::new(START_MICROS);
+    let config = ClachelessConfig::default().with_clock(clock.clone());
+    let dc =
+        DistributedCache::new_with_config("clacheless-ORDINAL.local:9000", 0, 30_000_000, config)
+            .await
+            .unwrap();
+    dc.put_bytes_with_ttl("short", b"lived", 1_000_000)
+        .await
+        .unwrap();
+    dc.put_string("default", "ttl").await.unwrap();
+    clock.advance(Duration::from_millis(999));
+    assert!(dc.get_bytes("short").await.is_ok());
+    clock.advance(Duration::from_secs(2));
+    assert!(dc.get_bytes("short").await.is_err());
+    assert_eq!(dc.get_string("default").await.unwrap(), "ttl");
+    clock.advance(Duration::from_secs(30));
+    assert!(dc.get_string("default").await.is_err());
+}
+
+#[tokio::test(start_paused = true)]
+async fn lost_peer_is_detected_and_rejoins() {
+    let clock = ManualClock::new(START_MICROS);
+    let network = InMemoryNetwork::new();
+    let mut nodes = Vec::new();
+    for node_ordinal in 0..2 {
+        let config = ClachelessConfig::default()
+            .with_clock(clock.clone())
+            .with_in_memory_network(Arc::clone(&network));
+        let dc = DistributedCache::new_with_config(
+            "clacheless-ORDINAL.local:9000",
+            node_ordinal,
+            30_000_000,
+            config,
+        )
+        .await
+        .unwrap();
+        let dc_clone = Arc::clone(&dc);
+        tokio::spawn(async move { dc_clone.run().await });
+        nodes.push(dc);
+    }
+    let peer_count = async |dc: &DistributedCache| dc.cluster_status().await.peers().len();
+    // Paused time advances instantly whenever all tasks wait on timers
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    assert_eq!(peer_count(&nodes[0]).await, 1);
+    network.partition(&[&[0], &[1]]);
+    // Lost after the broadcast interval plus the alive margin
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    assert_eq!(peer_count(&nodes[0]).await, 0);
+    assert_eq!(peer_count(&nodes[1]).await, 0);
+    network.heal();
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    assert_eq!(peer_count(&nodes[0]).await, 1);
+    assert_eq!(peer_count(&nodes[1]).await, 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn skewed_clock_is_detected() {
+    let clock = ManualClock::new(START_MICROS);
+    let skewed_clock = ManualClock::new(START_MICROS + 2_000_000);
+    let network = InMemoryNetwork::new();
+    let mut nodes = Vec::new();
+    for node_ordinal in 0..3 {
+        let config = ClachelessConfig::default()
+            .with_clock(if node_ordinal == 2 {
+                skewed_clock.clone()
+            } else {
+                clock.clone()
+            })
+            .with_in_memory_network(Arc::clone(&network));
+        let dc = DistributedCache::new_with_config(
+            "clacheless-ORDINAL.local:9000",
+            node_ordinal,
+            30_000_000,
+            config,
+        )
+        .await
+        .unwrap();
+        let dc_clone = Arc::clone(&dc);
+        tokio::spawn(async move { dc_clone.run().await });
+        nodes.push(dc);
+    }
+    tokio::time::sleep(Duration::from_secs(15)).await;
+    let cluster_status = nodes[0].cluster_status().await;
+    let skewed_ordinals = cluster_status
+        .peers()
+        .iter()
+        .filter(|peer_status| peer_status.clock_skewed())
+        .map(|peer_status| peer_status.node_ordinal())
+        .collect::<Vec<_>>();
+    assert_eq!(skewed_ordinals, [2]);
+    let offset_micros = cluster_status.peers()[1].clock_offset_micros().unwrap();
+    assert!((1_900_000..=2_100_000).contains(&offset_micros));
+    assert!(!cluster_status.local_clock_skewed());
+    assert!(nodes[0].is_ready());
+    assert!(nodes[2].cluster_status().await.local_clock_skewed());
+    assert!(!nodes[2].is_ready());
+}
+
+#[tokio::test(start_paused = true)]
+async fn peers_without_data_dont_make_a_node_ready() {
+    let clock = ManualClock::new(START_MICROS);
+    let network = InMemoryNetwork::new();
+    let mut nodes = Vec::new();
+    for node_ordinal in 0..2 {
+        let config = ClachelessConfig::default()
+            .with_clock(clock.clone())
+            .with_initial_sync_timeout_micros(60_000_000)
+            .with_in_memory_network(Arc::clone(&network));
+        let dc = DistributedCache::new_with_config(
+            "clacheless-ORDINAL.local:9000",
+            node_ordinal,
+            30_000_000,
+            config,
+        )
+        .await
+        .unwrap();
+        let dc_clone = Arc::clone(&dc);
+        tokio::spawn(async move { dc_clone.run().await });
+        nodes.push(dc);
+    }
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    assert!(!nodes[0].is_ready());
+    assert!(!nodes[1].is_ready());
+    nodes[0].put_string("key", "value").await.unwrap();
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    assert!(nodes[1].is_ready());
+}
+
+/// Start two nodes with the `config` on a network of their own and wait until
+/// they know each other.
+async fn start_pair(
+    config: ClachelessConfig,
+) -> (Vec<Arc<DistributedCache>>, Arc<InMemoryNetwork>) {
+    let network = InMemoryNetwork::new();
+    let mut nodes = Vec::new();
+    for node_ordinal in 0..2 {
+        let config = config.clone().with_in_memory_network(Arc::clone(&network));
+        let dc = DistributedCache::new_with_config(
+            "clacheless-ORDINAL.local:9000",
+            node_ordinal,
+            30_000_000,
+            config,
+        )
+        .await
+        .unwrap();
+        let dc_clone = Arc::clone(&dc);
+        tokio::spawn(async move { dc_clone.run().await });
+        nodes.push(dc);
+    }
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    assert_eq!(nodes[0].cluster_status().await.peers().len(), 1);
+    (nodes, network)
+}
+
+/// Start two nodes with the `config` and partition them once they know each
+/// other.
+async fn start_partitioned_pair(
+    config: ClachelessConfig,
+) -> (Vec<Arc<DistributedCache>>, Arc<InMemoryNetwork>) {
+    let (nodes, network) = start_pair(config).await;
+    network.partition(&[&[0], &[1]]);
+    (nodes, network)
+}
+
+#[tokio::test(start_paused = true)]
+async fn fail_put_rejects_writes_when_queue_is_full() {
+    let clock = ManualClock::new(START_MICROS);
+    let backing_store = Arc::new(InMemoryBackingStore::default());
+    let config = ClachelessConfig::default()
+        .with_clock(clock.clone())
+        .with_replication_queue_capacity(1)
+        .with_replication_overflow_policy(ReplicationOverflowPolicy::FailPut)
+        .with_backing_store(Arc::clone(&backing_store) as _, WritePolicy::WriteThrough);
+    let (nodes, _network) = start_partitioned_pair(config).await;
+    let mut results = Vec::new();
+    for index in 0..3 {
+        results.push(nodes[0].put_string(&format!("key{index}"), "value").await);
+    }
+    let error = results
+        .into_iter()
+        .find_map(Result::err)
+        .expect("A write should fail when the replication queue is full.");
+    assert_eq!(error.kind(), &ClachelessErrorKind::Unavailable);
+    // Reads of stored objects still succeed although they can't be cached
+    backing_store.store("stored", b"object").await.unwrap();
+    assert_eq!(nodes[0].get_string("stored").await.unwrap(), "object");
+}
+
+#[tokio::test(start_paused = true)]
+async fn fail_put_checks_queues_of_peers_that_seem_gone() {
+    let clock = ManualClock::new(START_MICROS);
+    let config = ClachelessConfig::default()
+        .with_clock(clock.clone())
+        .with_replication_queue_capacity(1)
+        .with_replication_overflow_policy(ReplicationOverflowPolicy::FailPut);
+    let (nodes, _network) = start_partitioned_pair(config).await;
+    // The peer is no longer a member, but still gets the updates until it is
+    // detected as lost
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(nodes[0].members(), vec![0]);
+    let mut results = Vec::new();
+    for index in 0..3 {
+        results.push(nodes[0].put_string(&format!("key{index}"), "value").await);
+    }
+    let error = results
+        .into_iter()
+        .find_map(Result::err)
+        .expect("A write should fail when the replication queue is full.");
+    assert_eq!(error.kind(), &ClachelessErrorKind::Unavailable);
+}
+
+#[tokio::test(start_paused = true)]
+async fn drop_and_resync_recovers_dropped_writes() {
+    let clock = ManualClock::new(START_MICROS);
+    let config = ClachelessConfig::default()
+        .with_clock(clock.clone())
+        .with_replication_queue_capacity(1)
+        .with_replication_overflow_policy(ReplicationOverflowPolicy::DropAndResync);
+    let (nodes, network) = start_partitioned_pair(config).await;
+    for index in 0..3 {
+        nodes[0]
+            .put_string(&format!("key{index}"), "value")
+            .await
+            .unwrap();
+    }
+    let dropped_entries = nodes[0].cluster_status().await.peers()[0].replication_dropped_entries();
+    assert!(dropped_entries > 0);
+    network.heal();
+    tokio::time::sleep(Duration::from_secs(10)).await;
+    for index in 0..3 {
+        assert_eq!(
+            nodes[1].get_string(&format!("key{index}")).await.unwrap(),
+            "value"
+        );
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn block_waits_for_room_in_queue() {
+    let clock = ManualClock::new(START_MICROS);
+    let config = ClachelessConfig::default()
+        .with_clock(clock.clone())
+        .with_replication_queue_capacity(1)
+        .with_replication_overflow_policy(ReplicationOverflowPolicy::Block);
+    let (nodes, network) = start_partitioned_pair(config).await;
+    for index in 0..3 {
+        nodes[0]
+            .put_string(&format!("key{index}"), "value")
+            .await
+            .unwrap();
+    }
+    // The updates were kept for the unreachable peer instead of dropped
+    let peer_status = nodes[0].cluster_status().await.peers()[0].clone();
+    assert_eq!(peer_status.replication_dropped_entries(), 0);
+    network.heal();
+    tokio::time::sleep(Duration::from_secs(10)).await;
+    for index in 0..3 {
+        assert_eq!(
+            nodes[1].get_string(&format!("key{index}")).await.unwrap(),
+            "value"
+        );
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn hints_are_replayed_when_peer_is_back() {
+    let clock = ManualClock::new(START_MICROS);
+    let config = ClachelessConfig::default().with_clock(clock.clone());
+    let (nodes, network) = start_partitioned_pair(config).await;
+    nodes[0].put_string("key", "value").await.unwrap();
+    // Sending gives up after a few retries and keeps the update
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    let peer_status = nodes[0].cluster_status().await.peers()[0].clone();
+    assert!(peer_status.down_since_micros().is_some());
+    assert_eq!(peer_status.hinted_entries(), 1);
+    assert!(nodes[1].get_string("key").await.is_err());
+    network.heal();
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    let peer_status = nodes[0].cluster_status().await.peers()[0].clone();
+    assert!(peer_status.down_since_micros().is_none());
+    assert_eq!(peer_status.hinted_entries(), 0);
+    assert_eq!(nodes[1].get_string("key").await.unwrap(), "value");
+}
+
+#[tokio::test(start_paused = true)]
+async fn hints_are_dropped_after_max_age() {
+    let clock = ManualClock::new(START_MICROS);
+    let config = ClachelessConfig::default()
+        .with_clock(clock.clone())
+        .with_hinted_handoff_max_age_micros(60_000_000);
+    let (nodes, _network) = start_partitioned_pair(config).await;
+    nodes[0].put_string("key", "value").await.unwrap();
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    let hinted_entries = async || nodes[0].cluster_status().await.peers()[0].hinted_entries();
+    assert_eq!(hinted_entries().await, 1);
+    clock.advance(Duration::from_secs(30));
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    assert_eq!(hinted_entries().await, 1);
+    clock.advance(Duration::from_secs(30));
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    assert!(nodes[0].cluster_status().await.peers().is_empty());
+    // Updates are no longer kept for the node that is gone
+    nodes[0].put_string("other", "value").await.unwrap();
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    assert!(nodes[0].cluster_status().await.peers().is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn clock_near_epoch_start() {
+    // Time windows reaching back before the epoch must not underflow
+    let clock = ManualClock::new(1_000);
+    let network = InMemoryNetwork::new();
+    let mut nodes = Vec::new();
+    for node_ordinal in 0..2 {
+        let config = ClachelessConfig::default()
+            .with_clock(clock.clone())
+            .with_in_memory_network(Arc::clone(&network));
+        let dc = DistributedCache::new_with_config(
+            "clacheless-ORDINAL.local:9000",
+            node_ordinal,
+            30_000_000,
+            config,
+        )
+        .await
+        .unwrap();
+        let dc_clone = Arc::clone(&dc);
+        tokio::spawn(async move { dc_clone.run().await });
+        nodes.push(dc);
+    }
+    nodes[0].put_string("key", "value").await.unwrap();
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    assert_eq!(nodes[0].cluster_status().await.peers().len(), 1);
+    assert_eq!(nodes[1].get_string("key").await.unwrap(), "value");
+}
+
+#[tokio::test(start_paused = true)]
+async fn joined_and_left_events_are_paired() {
+    let clock = ManualClock::new(START_MICROS);
+    let network = InMemoryNetwork::new();
+    let mut nodes = Vec::new();
+    for node_ordinal in 0..2 {
+        let mut config = ClachelessConfig::default()
+            .with_clock(clock.clone())
+            .with_in_memory_network(Arc::clone(&network));
+        if node_ordinal == 1 {
+            // Heard from more often than the other node looks for lost nodes
+            config = config
+                .with_state_broadcast_interval_micros(500_000)
+                .with_alive_margin_micros(400_000);
+        }
+        let dc = DistributedCache::new_with_config(
+            "clacheless-ORDINAL.local:9000",
+            node_ordinal,
+            30_000_000,
+            config,
+        )
+        .await
+        .unwrap();
+        let dc_clone = Arc::clone(&dc);
+        tokio::spawn(async move { dc_clone.run().await });
+        nodes.push(dc);
+    }
+    let mut membership_events = nodes[0].membership_events();
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    // Let the peer look lost right before it is heard from again
+    for _ in 0..10 {
+        clock.advance(Duration::from_secs(3));
+        tokio::time::sleep(Duration::from_millis(700)).await;
+    }
+    network.partition(&[&[0], &[1]]);
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    network.heal();
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    let mut joined = Vec::new();
+    while let Ok(membership_event) = membership_events.try_recv() {
+        match membership_event {
+            MembershipEvent::Joined { node_ordinal: 1 } => joined.push(true),
+            MembershipEvent::Left { node_ordinal: 1 } => joined.push(false),
+            _ => {}
+        }
+    }
+    assert!(joined.len() >= 3);
+    assert!(
+        joined
+            .iter()
+            .enumerate()
+            .all(|(index, joined)| *joined == (index % 2 == 0)),
+        "Unpaired events: {joined:?}"
+    );
+    assert_eq!(joined.last(), Some(&true));
+}
+
+/// Write `key` in both clusters of two nodes each, `west` `west_delay` after
+/// `east`, then bridge the clusters with the conflict policy of each side.
+///
+/// Returns the value of the key on each node, east first.
+async fn bridge_conflict(
+    east_policy: BridgeConflictPolicy,
+    west_policy: BridgeConflictPolicy,
+    west_delay: Duration,
+) -> Vec<String> {
+    let clock = ManualClock::new(START_MICROS);
+    let config = ClachelessConfig::default().with_clock(clock.clone());
+    let (east, _network_east) = start_pair(config.clone()).await;
+    let (west, _network_west) = start_pair(config).await;
+    // Written on the nodes without the bridge to get different origin node ids
+    east[1].put_string("shared/key", "east").await.unwrap();
+    clock.advance(west_delay);
+    west[1].put_string("shared/key", "west").await.unwrap();
+    // Replicated to the nodes with the bridge before the bridge connects
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let bridge_config = |cluster_id, conflict_policy| {
+        BridgeConfig::new(cluster_id, b"secret")
+            .with_key_prefix("shared/")
+            .with_conflict_policy(conflict_policy)
+    };
+    let bridge_east = Bridge::new(&east[0], bridge_config("east", east_policy));
+    let bridge_west = Bridge::new(&west[0], bridge_config("west", west_policy));
+    let (stream_east, stream_west) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move { bridge_east.run(stream_east).await });
+    tokio::spawn(async move { bridge_west.run(stream_west).await });
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    let mut values = Vec::new();
+    for dc in east.iter().chain(&west) {
+        values.push(dc.get_string("shared/key").await.unwrap());
+    }
+    values
+}
+
+#[tokio::test(start_paused = true)]
+async fn bridge_newest_wins_decides_ties_by_cluster_id() {
+    let values = bridge_conflict(
+        BridgeConflictPolicy::NewestWins,
+        BridgeConflictPolicy::NewestWins,
+        Duration::ZERO,
+    )
+    .await;
+    assert_eq!(values, vec!["west"; 4]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn bridge_local_wins_keeps_local_entries() {
+    let values = bridge_conflict(
+        BridgeConflictPolicy::LocalWins,
+        BridgeConflictPolicy::LocalWins,
+        Duration::from_secs(1),
+    )
+    .await;
+    assert_eq!(values, vec!["east", "east", "west", "west"]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn bridge_remote_wins_replaces_newer_local_entries() {
+    let values = bridge_conflict(
+        BridgeConflictPolicy::LocalWins,
+        BridgeConflictPolicy::RemoteWins,
+        Duration::from_secs(1),
+    )
+    .await;
+    assert_eq!(values, vec!["east"; 4]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn bridge_doesnt_send_repaired_entries_back() {
+    let clock = ManualClock::new(START_MICROS);
+    let config = ClachelessConfig::default().with_clock(clock.clone());
+    let (east, _network_east) = start_pair(config.clone()).await;
+    let (west, network_west) = start_partitioned_pair(config).await;
+    east[1].put_string("shared/key", "old").await.unwrap();
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let bridge_config = |cluster_id, conflict_policy| {
+        BridgeConfig::new(cluster_id, b"secret")
+            .with_key_prefix("shared/")
+            .with_conflict_policy(conflict_policy)
+    };
+    let run_bridges = |dc_east, dc_west| {
+        let bridge_east = Bridge::new(
+            dc_east,
+            bridge_config("east", BridgeConflictPolicy::RemoteWins),
+        );
+        let bridge_west = Bridge::new(
+            dc_west,
+            bridge_config("west", BridgeConflictPolicy::NewestWins),
+        );
+        let (stream_east, stream_west) = tokio::io::duplex(64 * 1024);
+        [
+            tokio::spawn(async move { bridge_east.run(stream_east).await.ok() }),
+            tokio::spawn(async move { bridge_west.run(stream_west).await.ok() }),
+        ]
+    };
+    let bridges = run_bridges(&east[0], &west[0]);
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    bridges.iter().for_each(JoinHandle::abort);
+    clock.advance(Duration::from_secs(1));
+    east[1].put_string("shared/key", "new").await.unwrap();
+    // The bridged entry reaches the other west node once the partition heals
+    assert!(west[1].get_string("shared/key").await.is_err());
+    network_west.heal();
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    assert_eq!(west[1].get_string("shared/key").await.unwrap(), "old");
+    // A bridge on that node must not send it back to replace the newer write
+    let _bridges = run_bridges(&east[0], &west[1]);
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    let mut values = Vec::new();
+    for dc in east.iter().chain(&west) {
+        values.push(dc.get_string("shared/key").await.unwrap());
+    }
+    assert_eq!(values, vec!["new"; 4]);
+}